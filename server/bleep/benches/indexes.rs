@@ -1,7 +1,11 @@
 use bleep::{
     indexes::{reader::ContentReader, DocumentRead, File},
     intelligence::TreeSitterFile,
-    semantic::Semantic,
+    semantic::{
+        embedder::{self, Embedder, Onnx, Pooling},
+        store::Qdrant,
+        Semantic,
+    },
     symbol::SymbolLocations,
     Application, Configuration, Environment,
 };
@@ -54,13 +58,22 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         .await
         .unwrap();
 
+        let tokenizer = embedder::load_tokenizer(&model_dir).unwrap();
+        let embedder = Onnx::new(&model_dir, tokenizer, None, Pooling::Mean).unwrap();
+        let store = Qdrant::connect(
+            "http://127.0.0.1:6334",
+            embedder.dimension(),
+            &embedder.fingerprint(),
+        )
+        .await
+        .unwrap();
         let file = File::new(
             app.sql.clone(),
-            Some(
-                Semantic::initialize(&model_dir, "http://127.0.0.1:6334", Arc::clone(&app.config))
-                    .await
-                    .unwrap(),
-            ),
+            Some(Semantic::new(
+                Arc::new(embedder),
+                Arc::new(store),
+                Arc::clone(&app.config),
+            )),
         );
 
         // Get the symbols for the `js-sample-big-symbols.js` file in this directory.
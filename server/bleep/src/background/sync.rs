@@ -103,22 +103,7 @@ impl SyncHandle {
                 if reporef.is_local() {
                     Repository::local_from(&reporef)
                 } else {
-                    let name = reporef.to_string();
-                    let remote = reporef.as_ref().into();
-                    let disk_path = app
-                        .config
-                        .source
-                        .repo_path_for_name(&name.replace('/', "_"));
-
-                    Repository {
-                        disk_path,
-                        remote,
-                        sync_status: SyncStatus::Queued,
-                        last_index_unix_secs: 0,
-                        last_commit_unix_secs: 0,
-                        most_common_lang: None,
-                        branch_filter: None,
-                    }
+                    Repository::remote_from(&reporef, &app.config.source)
                 }
             });
 
@@ -1,17 +1,18 @@
 use anyhow::Result;
 use bleep::{Application, Configuration, Environment};
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     Application::install_logging();
-    let app = Application::initialize(
-        Environment::server(),
-        Configuration::cli_overriding_config_file()?,
-        None,
-        None,
-    )
-    .await?;
+    let config = Configuration::cli_overriding_config_file()?;
+    Application::export_network_settings(&config)?;
 
-    app.initialize_sentry();
-    app.run().await
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(async {
+            let app = Application::initialize(Environment::server(), config, None, None).await?;
+
+            app.initialize_sentry();
+            app.run().await
+        })
 }
@@ -1,16 +1,16 @@
 use std::sync::{Arc, RwLock};
 
-use qdrant_client::{
-    prelude::QdrantClient,
-    qdrant::{PointId, PointStruct},
-};
 use sqlx::Sqlite;
 use tracing::trace;
 use uuid::Uuid;
 
 use crate::{
     repo::RepoRef,
-    semantic::{self, Embedding, Payload},
+    semantic::{
+        embedder::Embedder,
+        store::{Point, VectorStore},
+        Payload,
+    },
 };
 
 use super::db::SqlDb;
@@ -154,7 +154,7 @@ impl<'a> FileCache<'a> {
     }
 }
 
-/// Manage both the SQL cache and the underlying qdrant database to
+/// Manage both the SQL cache and the underlying vector store to
 /// ensure consistency.
 ///
 /// Operates on a single file's level.
@@ -164,7 +164,7 @@ pub struct ChunkCache<'a> {
     file_cache_key: &'a str,
     cache: scc::HashMap<String, FreshValue<String>>,
     update: scc::HashMap<(Vec<String>, String), Vec<String>>,
-    new: RwLock<Vec<PointStruct>>,
+    new: RwLock<Vec<Point>>,
     new_sql: RwLock<Vec<(String, String)>>,
 }
 
@@ -198,82 +198,117 @@ impl<'a> ChunkCache<'a> {
         }
     }
 
-    pub fn update_or_embed(
+    /// Update the branches of chunks that are already cached, and
+    /// embed the new ones in a batch.
+    ///
+    /// Chunks are given as the data to embed, along with their
+    /// payload.
+    pub async fn update_or_embed(
         &self,
-        data: &'a str,
-        embedder: impl FnOnce(&'a str) -> anyhow::Result<Embedding>,
-        payload: Payload,
+        chunks: Vec<(String, Payload)>,
+        embedder: &dyn Embedder,
     ) -> anyhow::Result<()> {
-        let id = self.cache_key(data);
-        let branches_hash = blake3::hash(payload.branches.join("\n").as_ref()).to_string();
-
-        match self.cache.entry(id) {
-            scc::hash_map::Entry::Occupied(mut existing) => {
-                let key = existing.key();
-                trace!(?key, "found; not upserting new");
-                if existing.get().value != branches_hash {
-                    self.update
-                        .entry((payload.branches, branches_hash.clone()))
-                        .or_insert_with(Vec::new)
-                        .get_mut()
-                        .push(existing.key().to_owned());
+        let mut new = vec![];
+
+        for (data, payload) in chunks {
+            let id = self.cache_key(&data);
+            let branches_hash = blake3::hash(payload.branches.join("\n").as_ref()).to_string();
+
+            match self.cache.entry(id) {
+                scc::hash_map::Entry::Occupied(mut existing) => {
+                    let key = existing.key();
+                    trace!(?key, "found; not upserting new");
+                    if existing.get().value != branches_hash {
+                        self.update
+                            .entry((payload.branches, branches_hash.clone()))
+                            .or_insert_with(Vec::new)
+                            .get_mut()
+                            .push(existing.key().to_owned());
+                    }
+                    *existing.get_mut() = branches_hash.into();
+                }
+                scc::hash_map::Entry::Vacant(vacant) => {
+                    let key = vacant.key();
+                    trace!(?key, "inserting new");
+                    new.push((
+                        vacant.key().to_owned(),
+                        branches_hash.clone(),
+                        data,
+                        payload,
+                    ));
+                    vacant.insert_entry(branches_hash.into());
                 }
-                *existing.get_mut() = branches_hash.into();
             }
-            scc::hash_map::Entry::Vacant(vacant) => {
-                let key = vacant.key();
-                trace!(?key, "inserting new");
-                self.new_sql
-                    .write()
-                    .unwrap()
-                    .push((vacant.key().to_owned(), branches_hash.clone()));
-
-                self.new.write().unwrap().push(PointStruct {
-                    id: Some(PointId::from(vacant.key().clone())),
-                    vectors: Some(embedder(data)?.into()),
-                    payload: payload.into_qdrant(),
-                });
-
-                vacant.insert_entry(branches_hash.into());
+        }
+
+        if new.is_empty() {
+            return Ok(());
+        }
+
+        let embeddings = embedder
+            .batch_embed(new.iter().map(|(_, _, data, _)| data.as_str()).collect())
+            .await;
+
+        let embeddings = match embeddings {
+            Ok(embeddings) => embeddings,
+            Err(err) => {
+                // nothing was embedded, so nothing new is cached
+                for (id, ..) in &new {
+                    self.cache.remove(id);
+                }
+                return Err(err);
             }
+        };
+
+        for ((id, branches_hash, _, payload), vector) in new.into_iter().zip(embeddings) {
+            self.new_sql
+                .write()
+                .unwrap()
+                .push((id.clone(), branches_hash));
+
+            self.new.write().unwrap().push(Point {
+                id,
+                vector,
+                payload,
+            });
         }
 
         Ok(())
     }
 
-    /// Commit both qdrant and cache changes to the respective databases.
+    /// Commit both vector store and cache changes to the respective databases.
     ///
-    /// The SQLite operations mirror qdrant changes 1:1, so any
+    /// The SQLite operations mirror vector store changes 1:1, so any
     /// discrepancy between the 2 should be minimized.
     ///
     /// In addition, the SQLite cache is committed only AFTER all
-    /// qdrant writes have successfully completed, meaning they're in
-    /// qdrant's pipelines.
+    /// vector store writes have successfully completed, meaning
+    /// they're in qdrant's pipelines, or logged by the embedded store.
     ///
     /// Since qdrant changes are pipelined on their end, data written
     /// here is not necessarily available for querying when the
     /// commit's completed.
-    pub async fn commit(self, qdrant: &QdrantClient) -> anyhow::Result<(usize, usize, usize)> {
+    pub async fn commit(self, store: &dyn VectorStore) -> anyhow::Result<(usize, usize, usize)> {
         let mut tx = self.sql.begin().await?;
 
-        let update_size = self.commit_branch_updates(&mut tx, qdrant).await?;
-        let delete_size = self.commit_deletes(&mut tx, qdrant).await?;
-        let new_size = self.commit_inserts(&mut tx, qdrant).await?;
+        let update_size = self.commit_branch_updates(&mut tx, store).await?;
+        let delete_size = self.commit_deletes(&mut tx, store).await?;
+        let new_size = self.commit_inserts(&mut tx, store).await?;
 
         tx.commit().await?;
 
         Ok((new_size, update_size, delete_size))
     }
 
-    /// Insert new additions to both qdrant and sqlite.
+    /// Insert new additions to both the vector store and sqlite.
     ///
-    /// The qdrant write uses `upsert`, because we simply want to
-    /// express "these points should be in this state", without
+    /// The vector store write uses `upsert`, because we simply want
+    /// to express "these points should be in this state", without
     /// being pedantic.
     async fn commit_inserts(
         &self,
         tx: &mut sqlx::Transaction<'_, Sqlite>,
-        qdrant: &QdrantClient,
+        store: &dyn VectorStore,
     ) -> Result<usize, anyhow::Error> {
         let new: Vec<_> = std::mem::take(self.new.write().unwrap().as_mut());
         let new_sql = std::mem::take(&mut *self.new_sql.write().unwrap());
@@ -290,12 +325,7 @@ impl<'a> ChunkCache<'a> {
             .await?;
         }
 
-        // qdrant doesn't like empty payloads.
-        if !new.is_empty() {
-            qdrant
-                .upsert_points_blocking(semantic::COLLECTION_NAME, new, None)
-                .await?;
-        }
+        store.upsert(new).await?;
         Ok(new_size)
     }
 
@@ -303,7 +333,7 @@ impl<'a> ChunkCache<'a> {
     async fn commit_deletes(
         &self,
         tx: &mut sqlx::Transaction<'_, Sqlite>,
-        qdrant: &QdrantClient,
+        store: &dyn VectorStore,
     ) -> Result<usize, anyhow::Error> {
         let mut to_delete = vec![];
         self.cache
@@ -326,19 +356,7 @@ impl<'a> ChunkCache<'a> {
             .await?;
         }
 
-        if !to_delete.is_empty() {
-            qdrant
-                .delete_points(
-                    semantic::COLLECTION_NAME,
-                    &to_delete
-                        .into_iter()
-                        .map(PointId::from)
-                        .collect::<Vec<_>>()
-                        .into(),
-                    None,
-                )
-                .await?;
-        }
+        store.delete(to_delete).await?;
         Ok(delete_size)
     }
 
@@ -347,10 +365,10 @@ impl<'a> ChunkCache<'a> {
     async fn commit_branch_updates(
         &self,
         tx: &mut sqlx::Transaction<'_, Sqlite>,
-        qdrant: &QdrantClient,
+        store: &dyn VectorStore,
     ) -> Result<usize, anyhow::Error> {
         let mut update_size = 0;
-        let mut qdrant_updates = vec![];
+        let mut store_updates = vec![];
 
         let mut next = self.update.first_occupied_entry();
         while let Some(entry) = next {
@@ -369,22 +387,7 @@ impl<'a> ChunkCache<'a> {
                 .await?;
             }
 
-            let id = points
-                .iter()
-                .cloned()
-                .map(PointId::from)
-                .collect::<Vec<_>>()
-                .into();
-
-            let payload = qdrant_client::client::Payload::new_from_hashmap(
-                [("branches".to_string(), branches_list.to_owned().into())].into(),
-            );
-
-            qdrant_updates.push(async move {
-                qdrant
-                    .set_payload_blocking(semantic::COLLECTION_NAME, &id, payload, None)
-                    .await
-            });
+            store_updates.push(store.set_branches(points.clone(), branches_list.to_owned()));
             next = entry.next();
         }
 
@@ -393,7 +396,7 @@ impl<'a> ChunkCache<'a> {
         //
         // This should be fine since the number of updates would be
         // reasonably small.
-        futures::future::join_all(qdrant_updates.into_iter())
+        futures::future::join_all(store_updates.into_iter())
             .await
             .into_iter()
             .collect::<Result<Vec<_>, _>>()?;
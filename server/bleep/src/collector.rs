@@ -1,5 +1,5 @@
 mod bytes_filter;
 mod frequency;
 
-pub use bytes_filter::BytesFilterCollector;
+pub use bytes_filter::{BytesFilterCollector, MultiBytesFilterCollector};
 pub use frequency::FrequencyCollector;
@@ -1,5 +1,6 @@
 // a version of tantivy::collector::FilterCollector that works on byte fast fields
 
+use smallvec::SmallVec;
 use tantivy::collector::{Collector, SegmentCollector};
 use tantivy::fastfield::BytesFastFieldReader;
 use tantivy::schema::Field;
@@ -111,3 +112,120 @@ where
         self.segment_collector.harvest()
     }
 }
+
+/// A `BytesFilterCollector` that checks the values of several byte fast fields at once.
+///
+/// The predicate is given the values of `fields`, in the same order.
+pub struct MultiBytesFilterCollector<TCollector, TPredicate>
+where
+    TPredicate: 'static + Clone,
+{
+    fields: Vec<Field>,
+    collector: TCollector,
+    predicate: TPredicate,
+}
+
+impl<TCollector, TPredicate> MultiBytesFilterCollector<TCollector, TPredicate>
+where
+    TCollector: Collector + Send + Sync,
+    TPredicate: Fn(&[&[u8]]) -> bool + Send + Sync + Clone,
+{
+    /// Create a new MultiBytesFilterCollector.
+    pub fn new(
+        fields: Vec<Field>,
+        predicate: TPredicate,
+        collector: TCollector,
+    ) -> MultiBytesFilterCollector<TCollector, TPredicate> {
+        MultiBytesFilterCollector {
+            fields,
+            predicate,
+            collector,
+        }
+    }
+}
+
+impl<TCollector, TPredicate> Collector for MultiBytesFilterCollector<TCollector, TPredicate>
+where
+    TCollector: Collector + Send + Sync,
+    TPredicate: 'static + Fn(&[&[u8]]) -> bool + Send + Sync + Clone,
+{
+    type Fruit = TCollector::Fruit;
+
+    type Child = MultiBytesFilterSegmentCollector<TCollector::Child, TPredicate>;
+
+    fn for_segment(
+        &self,
+        segment_local_id: u32,
+        segment_reader: &SegmentReader,
+    ) -> tantivy::Result<MultiBytesFilterSegmentCollector<TCollector::Child, TPredicate>> {
+        let schema = segment_reader.schema();
+        let mut fast_field_readers = Vec::with_capacity(self.fields.len());
+
+        for &field in &self.fields {
+            let field_entry = schema.get_field_entry(field);
+            if !field_entry.is_fast() {
+                return Err(TantivyError::SchemaError(format!(
+                    "Field {:?} is not a fast field.",
+                    field_entry.name()
+                )));
+            }
+
+            fast_field_readers.push(segment_reader.fast_fields().bytes(field)?);
+        }
+
+        let segment_collector = self
+            .collector
+            .for_segment(segment_local_id, segment_reader)?;
+
+        Ok(MultiBytesFilterSegmentCollector {
+            fast_field_readers,
+            segment_collector,
+            predicate: self.predicate.clone(),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        self.collector.requires_scoring()
+    }
+
+    fn merge_fruits(
+        &self,
+        segment_fruits: Vec<<TCollector::Child as SegmentCollector>::Fruit>,
+    ) -> tantivy::Result<TCollector::Fruit> {
+        self.collector.merge_fruits(segment_fruits)
+    }
+}
+
+pub struct MultiBytesFilterSegmentCollector<TSegmentCollector, TPredicate>
+where
+    TPredicate: 'static,
+{
+    fast_field_readers: Vec<BytesFastFieldReader>,
+    segment_collector: TSegmentCollector,
+    predicate: TPredicate,
+}
+
+impl<TSegmentCollector, TPredicate> SegmentCollector
+    for MultiBytesFilterSegmentCollector<TSegmentCollector, TPredicate>
+where
+    TSegmentCollector: SegmentCollector,
+    TPredicate: 'static + Fn(&[&[u8]]) -> bool + Send + Sync,
+{
+    type Fruit = TSegmentCollector::Fruit;
+
+    fn collect(&mut self, doc: u32, score: Score) {
+        let values = self
+            .fast_field_readers
+            .iter()
+            .map(|reader| reader.get_bytes(doc))
+            .collect::<SmallVec<[_; 4]>>();
+
+        if (self.predicate)(&values) {
+            self.segment_collector.collect(doc, score)
+        }
+    }
+
+    fn harvest(self) -> <TSegmentCollector as SegmentCollector>::Fruit {
+        self.segment_collector.harvest()
+    }
+}
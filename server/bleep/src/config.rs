@@ -1,4 +1,7 @@
-use crate::{semantic::chunk::OverlapStrategy, state::StateSource};
+use crate::{
+    semantic::{chunk::OverlapStrategy, embedder::Pooling},
+    state::StateSource,
+};
 use anyhow::{Context, Result};
 use clap::Parser;
 
@@ -66,6 +69,24 @@ pub struct Configuration {
     /// Bind the webserver to `<host>`
     pub port: u16,
 
+    #[clap(long)]
+    /// Proxy for all outbound HTTP(S) connections, as in `http://proxy.example.com:3128`
+    pub http_proxy: Option<String>,
+
+    #[clap(long)]
+    /// Comma-separated hosts to reach without the proxy, as in `localhost,.example.com`
+    pub no_proxy: Option<String>,
+
+    #[clap(long = "ca-bundle")]
+    #[serde(default)]
+    /// PEM files with CA certificates to trust on top of the bundled ones. Can be repeated.
+    pub ca_bundles: Vec<PathBuf>,
+
+    #[clap(long, default_value_os_t = default_ssh_key_dir())]
+    #[serde(default = "default_ssh_key_dir")]
+    /// Directory that private keys for SSH git remotes have to be in
+    pub ssh_key_dir: PathBuf,
+
     //
     // External dependencies
     //
@@ -105,18 +126,48 @@ pub struct Configuration {
     /// URL for the qdrant server
     pub qdrant_url: Option<String>,
 
+    #[clap(long, default_value_t = false)]
+    #[serde(default)]
+    /// Keep embeddings in the index directory instead of a qdrant server. Ignored if
+    /// `qdrant_url` is set.
+    pub embedded_vector_store: bool,
+
     #[clap(long, default_value_os_t = default_model_dir())]
     #[serde(default = "default_model_dir")]
-    /// Path to the embedding model directory
+    /// Path to the embedding model directory. Its tokenizer measures chunks even when
+    /// embeddings come from `embedding_url`
     pub model_dir: PathBuf,
 
+    #[clap(long)]
+    /// Size of the embeddings the model produces. Found by embedding some text if not set
+    pub embedding_dim: Option<usize>,
+
+    #[clap(long, value_enum)]
+    /// How the ONNX model's token embeddings are pooled, `mean` by default
+    pub embedding_pooling: Option<Pooling>,
+
+    #[clap(long)]
+    /// Base URL of an OpenAI-compatible API to get embeddings from instead of the ONNX model,
+    /// e.g. `https://api.openai.com/v1`
+    pub embedding_url: Option<String>,
+
+    #[clap(long)]
+    /// Model to request from `embedding_url`
+    pub embedding_model: Option<String>,
+
+    #[clap(long)]
+    #[serde(serialize_with = "serialize_secret_opt_str", default)]
+    /// API key for `embedding_url`
+    pub embedding_api_key: Option<SecretString>,
+
     #[clap(long, default_value_t = default_max_chunk_tokens())]
     #[serde(default = "default_max_chunk_tokens")]
     /// Maximum number of tokens in a chunk (should be the model's input size)
     pub max_chunk_tokens: usize,
 
     #[clap(long)]
-    /// Chunking strategy
+    /// Chunking strategy: lines or a percentage of tokens to overlap chunks by, or `syntax` to
+    /// chunk along definitions
     pub overlap: Option<OverlapStrategy>,
 
     //
@@ -149,6 +200,11 @@ pub struct Configuration {
     /// Bot secret token
     pub bot_secret: Option<SecretString>,
 
+    #[clap(long)]
+    #[serde(serialize_with = "serialize_secret_opt_str", default)]
+    /// Secret for verifying the signature of push webhooks. Webhooks are disabled without it
+    pub webhook_secret: Option<SecretString>,
+
     //
     // Cloud deployment values
     //
@@ -240,8 +296,26 @@ impl Configuration {
 
             port: right_if_default!(b.port, a.port, default_port()),
 
+            http_proxy: b.http_proxy.or(a.http_proxy),
+
+            no_proxy: b.no_proxy.or(a.no_proxy),
+
+            ca_bundles: right_if_default!(b.ca_bundles, a.ca_bundles, Vec::<PathBuf>::new()),
+
+            ssh_key_dir: right_if_default!(b.ssh_key_dir, a.ssh_key_dir, default_ssh_key_dir()),
+
             model_dir: right_if_default!(b.model_dir, a.model_dir, default_model_dir()),
 
+            embedding_dim: b.embedding_dim.or(a.embedding_dim),
+
+            embedding_pooling: b.embedding_pooling.or(a.embedding_pooling),
+
+            embedding_url: b.embedding_url.or(a.embedding_url),
+
+            embedding_model: b.embedding_model.or(a.embedding_model),
+
+            embedding_api_key: b.embedding_api_key.or(a.embedding_api_key),
+
             max_chunk_tokens: right_if_default!(
                 b.max_chunk_tokens,
                 a.max_chunk_tokens,
@@ -254,6 +328,8 @@ impl Configuration {
 
             qdrant_url: b.qdrant_url.or(a.qdrant_url),
 
+            embedded_vector_store: b.embedded_vector_store | a.embedded_vector_store,
+
             answer_api_url: right_if_default!(
                 b.answer_api_url,
                 a.answer_api_url,
@@ -274,6 +350,8 @@ impl Configuration {
 
             bot_secret: b.bot_secret.or(a.bot_secret),
 
+            webhook_secret: b.webhook_secret.or(a.webhook_secret),
+
             analytics_key: b.analytics_key.or(a.analytics_key),
             analytics_key_fe: b.analytics_key_fe.or(a.analytics_key_fe),
 
@@ -318,6 +396,13 @@ fn default_index_dir() -> PathBuf {
     }
 }
 
+fn default_ssh_key_dir() -> PathBuf {
+    match directories::BaseDirs::new() {
+        Some(dirs) => dirs.home_dir().join(".ssh"),
+        None => ".ssh".into(),
+    }
+}
+
 fn default_model_dir() -> PathBuf {
     "model".into()
 }
@@ -19,6 +19,14 @@ pub(crate) enum Feature {
     /// Use GitHub App permission system scoped to a single
     /// installation. Cloud instances use this.
     GithubInstallation = 1 << 4,
+
+    /// Allow connecting to a GitLab instance with an access token. The server makes requests to
+    /// whichever URL it's given, so this is only for local installations
+    GitlabTokenAuth = 1 << 5,
+
+    /// Allow adding repositories by any git URL, including `file://` URLs and SSH keys on this
+    /// machine
+    GitRemotes = 1 << 6,
 }
 
 #[rustfmt::skip]
@@ -59,7 +67,9 @@ enum EnvironmentInner {
     /// Enables scanning arbitrary user-specified locations through a Web-endpoint.
     InsecureLocal =
 	AnyPathScan as u64
-	| GithubDeviceFlow as u64,
+	| GithubDeviceFlow as u64
+	| GitlabTokenAuth as u64
+	| GitRemotes as u64,
 }
 
 #[derive(Debug, Clone)]
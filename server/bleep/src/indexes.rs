@@ -2,20 +2,22 @@ use std::{fs, ops::Deref, path::Path, sync::Arc};
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use smallvec::SmallVec;
 use tantivy::{
     collector::{Collector, MultiFruit},
+    query::EmptyQuery,
     schema::Schema,
     tokenizer::NgramTokenizer,
-    DocAddress, Document, IndexReader, IndexWriter, Score,
+    DocAddress, Document, IndexReader, IndexWriter, Score, Searcher,
 };
 use tokio::sync::RwLock;
 
+pub mod commit;
 pub mod file;
 pub mod reader;
 pub mod repo;
 mod schema;
 
+pub use commit::Commit;
 pub use file::File;
 pub use repo::Repo;
 use tracing::debug;
@@ -24,7 +26,7 @@ use crate::{
     background::{SyncHandle, SyncPipes},
     cache::FileCache,
     db::SqlDb,
-    query::parser::Query,
+    query::parser::QueryTree,
     repo::{RepoError, RepoMetadata, RepoRef, Repository},
     semantic::Semantic,
     state::RepositoryPool,
@@ -84,6 +86,7 @@ impl<'a> GlobalWriteHandle<'a> {
 pub struct Indexes {
     pub repo: Indexer<Repo>,
     pub file: Indexer<File>,
+    pub commit: Indexer<Commit>,
     write_mutex: tokio::sync::Mutex<()>,
 }
 
@@ -94,17 +97,30 @@ impl Indexes {
         sql: SqlDb,
         semantic: Option<Semantic>,
     ) -> Result<Self> {
-        if config.source.index_version_mismatch() {
+        // a vector store that was just created has none of the
+        // embeddings our file caches point to
+        let new_vector_store = semantic.as_ref().map_or(false, Semantic::store_is_new);
+
+        if config.source.index_version_mismatch() || new_vector_store {
             // we don't support old schemas, and tantivy will hard
             // error if we try to open a db with a different schema.
-            std::fs::remove_dir_all(config.index_path("repo"))?;
-            std::fs::remove_dir_all(config.index_path("content"))?;
+            //
+            // the file caches also decide what goes in the content
+            // index, so that has to be rebuilt along with the vectors.
+            for index in ["repo", "content", "commit"] {
+                // older versions didn't have a commit index, and
+                // there's nothing to remove on the first run
+                if config.index_path(index).as_ref().exists() {
+                    std::fs::remove_dir_all(config.index_path(index))?;
+                }
+            }
 
             let mut refs = vec![];
             // knocking out our current file caches will force re-indexing qdrant
             repo_pool.for_each(|reporef, repo| {
                 refs.push(reporef.to_owned());
                 repo.last_index_unix_secs = 0;
+                repo.indexed_branches.clear();
             });
 
             for reporef in refs {
@@ -126,6 +142,12 @@ impl Indexes {
                 config.buffer_size,
                 config.max_threads,
             )?,
+            commit: Indexer::create(
+                Commit::new(),
+                config.index_path("commit").as_ref(),
+                config.repo_buffer_size,
+                config.max_threads,
+            )?,
             write_mutex: Default::default(),
         })
     }
@@ -137,7 +159,11 @@ impl Indexes {
         debug!(id, "lock acquired");
 
         Ok(GlobalWriteHandle {
-            handles: vec![self.repo.write_handle()?, self.file.write_handle()?],
+            handles: vec![
+                self.repo.write_handle()?,
+                self.file.write_handle()?,
+                self.commit.write_handle()?,
+            ],
             _write_lock,
         })
     }
@@ -151,6 +177,7 @@ pub trait Indexable: Send + Sync {
         reporef: &RepoRef,
         repo: &Repository,
         metadata: &RepoMetadata,
+        searcher: &Searcher,
         writer: &IndexWriter,
         pipes: &SyncPipes,
     ) -> Result<()>;
@@ -167,17 +194,21 @@ pub trait DocumentRead: Send + Sync {
     type Document;
 
     /// Return whether this reader can process this query.
-    fn query_matches(&self, query: &Query<'_>) -> bool;
+    fn query_matches(&self, query: &QueryTree<'_>) -> bool;
+
+    /// Narrow a query down to the alternatives this reader can process, so that it can run
+    /// `repo:bar` in `foo or repo:bar`. Returns `None` if it can't process any of them.
+    fn select<'a>(&self, query: &QueryTree<'a>) -> Option<QueryTree<'a>> {
+        query.filter_disjuncts(|q| self.query_matches(q))
+    }
 
-    /// Compile a set of parsed queries into a single `tantivy` query.
-    fn compile<'a, I>(
+    /// Compile a parsed query into a single `tantivy` query.
+    fn compile(
         &self,
         schema: &Self::Schema,
-        queries: I,
+        query: &QueryTree<'_>,
         index: &tantivy::Index,
-    ) -> Result<Box<dyn tantivy::query::Query>>
-    where
-        I: Iterator<Item = &'a Query<'a>>;
+    ) -> Result<Box<dyn tantivy::query::Query>>;
 
     /// Read a tantivy document into the specified output type.
     fn read_document(&self, schema: &Self::Schema, doc: Document) -> Self::Document;
@@ -207,8 +238,9 @@ impl<'a> IndexWriteHandle<'a> {
         metadata: &RepoMetadata,
         progress: &SyncPipes,
     ) -> Result<()> {
+        let searcher = self.reader.read().await.searcher();
         self.source
-            .index_repository(reporef, repo, metadata, &self.writer, progress)
+            .index_repository(reporef, repo, metadata, &searcher, &self.writer, progress)
             .await
     }
 
@@ -278,23 +310,25 @@ impl<T: Indexable> Indexer<T> {
         Ok(instance)
     }
 
-    pub async fn query<'a, R, I, C>(
+    pub async fn query<'a, R, C>(
         &'a self,
-        queries: I,
+        query: &QueryTree<'_>,
         doc_reader: &'a R,
         collector: C,
     ) -> Result<SearchResults<'_, R::Document>>
     where
-        I: Iterator<Item = &'a Query<'a>> + Send,
         C: Collector<Fruit = (Vec<(Score, DocAddress)>, MultiFruit)>,
         R: DocumentRead<Schema = T>,
     {
         let searcher = self.reader.read().await.searcher();
-        let queries = queries
-            .filter(|q| doc_reader.query_matches(q))
-            .collect::<SmallVec<[_; 2]>>();
-        let compiled_query =
-            doc_reader.compile(&self.source, queries.iter().copied(), &self.index)?;
+
+        // Readers that can't process this query produce no results, but still run the collector
+        // so that callers get empty metadata.
+        let compiled_query = if doc_reader.query_matches(query) {
+            doc_reader.compile(&self.source, query, &self.index)?
+        } else {
+            Box::new(EmptyQuery)
+        };
 
         let (top_k, metadata) = searcher
             .search(&compiled_query, &collector)
@@ -310,6 +344,29 @@ impl<T: Indexable> Indexer<T> {
             metadata,
         })
     }
+
+    /// Count the documents that match a query, and pass through `collector`.
+    pub async fn count<R, C>(
+        &self,
+        query: &QueryTree<'_>,
+        doc_reader: &R,
+        collector: C,
+    ) -> Result<usize>
+    where
+        C: Collector<Fruit = usize>,
+        R: DocumentRead<Schema = T>,
+    {
+        if !doc_reader.query_matches(query) {
+            return Ok(0);
+        }
+
+        let searcher = self.reader.read().await.searcher();
+        let compiled_query = doc_reader.compile(&self.source, query, &self.index)?;
+
+        searcher
+            .search(&compiled_query, &collector)
+            .context("failed to execute count query")
+    }
 }
 
 pub struct SearchResults<'a, T> {
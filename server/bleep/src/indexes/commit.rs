@@ -0,0 +1,89 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use tantivy::{doc, schema::Schema, IndexWriter, Searcher, Term};
+use tracing::info;
+
+pub use super::schema::Commit;
+use super::Indexable;
+use crate::{
+    background::SyncPipes,
+    repo::{
+        iterator::{commit_history, HgWalker},
+        RepoMetadata, RepoRef, Repository,
+    },
+};
+
+impl Default for Commit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Indexable for Commit {
+    async fn index_repository(
+        &self,
+        reporef: &RepoRef,
+        repo: &Repository,
+        metadata: &RepoMetadata,
+        _searcher: &Searcher,
+        writer: &IndexWriter,
+        pipes: &SyncPipes,
+    ) -> Result<()> {
+        // The history is small compared to the files in a repository, so it is indexed from
+        // scratch on every sync.
+        self.delete_by_repo(writer, repo);
+
+        // Only git and Mercurial repositories have a history
+        if metadata.last_commit_unix_secs.is_none() {
+            return Ok(());
+        }
+
+        let filter = repo.branch_filter.as_ref().map(Into::into);
+        let history = if HgWalker::is_repository(&repo.disk_path) {
+            HgWalker::commit_history(&repo.disk_path, filter)?
+        } else {
+            commit_history(reporef, &repo.disk_path, filter)?
+        };
+
+        let repo_name = reporef.indexed_name();
+        let count = history.len();
+
+        for commit in history {
+            if pipes.is_cancelled() {
+                bail!("cancelled");
+            }
+
+            let changed_paths = commit.changed_paths.join("\n");
+
+            writer.add_document(doc!(
+                self.repo_disk_path => repo.disk_path.to_string_lossy().as_ref(),
+                self.repo_ref => reporef.to_string(),
+                self.repo_name => repo_name.as_str(),
+                self.raw_repo_name => repo_name.as_bytes(),
+                self.id => commit.id,
+                self.author => commit.author,
+                self.raw_message => commit.message.as_bytes(),
+                self.message => commit.message,
+                self.raw_changed_paths => changed_paths.as_bytes(),
+                self.changed_paths => changed_paths,
+                self.commit_unix_seconds => commit.unix_secs,
+            ))?;
+        }
+
+        info!(?repo.disk_path, count, "finished indexing commits");
+
+        Ok(())
+    }
+
+    fn delete_by_repo(&self, writer: &IndexWriter, repo: &Repository) {
+        writer.delete_term(Term::from_field_text(
+            self.repo_disk_path,
+            &repo.disk_path.to_string_lossy(),
+        ));
+    }
+
+    fn schema(&self) -> Schema {
+        self.schema.clone()
+    }
+}
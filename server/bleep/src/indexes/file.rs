@@ -12,11 +12,11 @@ use async_trait::async_trait;
 use rayon::prelude::*;
 use scc::hash_map::Entry;
 use tantivy::{
-    collector::TopDocs,
+    collector::{DocSetCollector, TopDocs},
     doc,
     query::{BooleanQuery, Query, QueryParser, TermQuery},
     schema::{IndexRecordOption, Schema, Term},
-    IndexWriter,
+    IndexWriter, Searcher,
 };
 use tokenizers as _;
 use tokio::runtime::Handle;
@@ -36,7 +36,7 @@ use crate::{
     cache::{FileCache, FileCacheSnapshot},
     intelligence::TreeSitterFile,
     query::compiler::{case_permutations, trigrams},
-    repo::{iterator::*, RepoMetadata, RepoRef, Repository},
+    repo::{iterator::*, RepoMetadata, RepoRef, Repository, WalkedBranches},
     symbol::SymbolLocations,
 };
 
@@ -57,6 +57,7 @@ impl Indexable for File {
         reporef: &RepoRef,
         repo: &Repository,
         repo_metadata: &RepoMetadata,
+        searcher: &Searcher,
         writer: &IndexWriter,
         pipes: &SyncPipes,
     ) -> Result<()> {
@@ -93,13 +94,47 @@ impl Indexable for File {
         let start = std::time::Instant::now();
 
         // If we could determine the time of the last commit, proceed
-        // with a Git Walker, otherwise use a FS walker
-        if repo_metadata.last_commit_unix_secs.is_some() {
-            let walker = GitWalker::open_repository(
+        // with a Git or Mercurial Walker, otherwise use a FS walker
+        if repo_metadata.last_commit_unix_secs.is_some() && HgWalker::is_repository(&repo.disk_path)
+        {
+            let walker = HgWalker::open_repository(
+                &repo.disk_path,
+                repo.branch_filter.as_ref().map(Into::into),
+            )?;
+
+            _ = repo_metadata.walked.set(WalkedBranches {
+                branches: walker.branches().clone(),
+                incremental: false,
+            });
+
+            let count = walker.len();
+            walker.for_each(pipes, file_worker(count));
+        } else if repo_metadata.last_commit_unix_secs.is_some() {
+            let walker = GitWalker::open_incremental(
                 reporef,
                 &repo.disk_path,
                 repo.branch_filter.as_ref().map(Into::into),
+                &repo.indexed_branches,
+                repo.index_submodules,
+                repo.index_working_tree,
             )?;
+
+            // Only the documents of paths that changed since the last index can be out of
+            // date, everything else stays in the index as it is.
+            if let Some(changed) = walker.changed_paths() {
+                info!(changed = changed.len(), "indexing changed paths only");
+                let outdated = self.unique_hashes_at(searcher, changed)?;
+                cache_snapshot.retain(|k, v| {
+                    v.fresh = !outdated.contains(k);
+                    true
+                });
+            }
+
+            _ = repo_metadata.walked.set(WalkedBranches {
+                branches: walker.branches().clone(),
+                incremental: walker.changed_paths().is_some(),
+            });
+
             let count = walker.len();
             walker.for_each(pipes, file_worker(count));
         } else {
@@ -407,6 +442,27 @@ impl Indexer<File> {
 }
 
 impl File {
+    /// The unique hashes of every document for the given paths on disk.
+    fn unique_hashes_at(&self, searcher: &Searcher, paths: &[String]) -> Result<HashSet<String>> {
+        let mut hashes = HashSet::new();
+
+        for path in paths {
+            let query = TermQuery::new(
+                Term::from_field_text(self.entry_disk_path, path),
+                IndexRecordOption::Basic,
+            );
+
+            for addr in searcher.search(&query, &DocSetCollector)? {
+                let doc = searcher.doc(addr)?;
+                if let Some(hash) = doc.get_first(self.unique_hash).and_then(|v| v.as_text()) {
+                    hashes.insert(hash.to_owned());
+                }
+            }
+        }
+
+        Ok(hashes)
+    }
+
     #[tracing::instrument(fields(repo=%workload.repo_ref, entry_disk_path=?workload.dir_entry.path()), skip_all)]
     fn worker(&self, workload: Workload<'_>, writer: &IndexWriter) -> Result<()> {
         let Workload {
@@ -451,7 +507,10 @@ impl File {
             hash.finalize().to_hex().to_string()
         };
 
-        let last_commit = repo_metadata.last_commit_unix_secs.unwrap_or(0);
+        let last_commit = dir_entry
+            .last_commit_unix_secs()
+            .or(repo_metadata.last_commit_unix_secs)
+            .unwrap_or(0);
 
         match dir_entry {
             _ if is_cache_fresh(cache_snapshot, &tantivy_hash, &entry_pathbuf) => {
@@ -544,13 +603,16 @@ impl RepoDir {
         doc!(
                 schema.raw_repo_name => repo_name.as_bytes(),
                 schema.raw_relative_path => relative_path_str.as_bytes(),
+                schema.raw_branches => branches.as_bytes(),
                 schema.repo_disk_path => repo_disk_path.to_string_lossy().as_ref(),
+                schema.entry_disk_path => repo_disk_path.join(relative_path).to_string_lossy().as_ref(),
                 schema.relative_path => relative_path_str,
                 schema.repo_ref => repo_ref,
                 schema.repo_name => repo_name,
                 schema.last_commit_unix_seconds => last_commit,
                 schema.branches => branches,
                 schema.is_directory => true,
+                schema.submodule => self.submodule.unwrap_or_default(),
                 schema.unique_hash => tantivy_cache_key,
 
                 // nulls
@@ -594,10 +656,14 @@ impl RepoFile {
                 ""
             });
 
+        // build a syntax aware representation of the file
+        let file_tree = TreeSitterFile::try_build(self.buffer.as_bytes(), lang_str);
+
+        // kept for chunking the file along its syntax, so it isn't parsed twice
+        let syntax_tree = file_tree.as_ref().ok().map(|file| file.tree().clone());
+
         let symbol_locations = {
-            // build a syntax aware representation of the file
-            let scope_graph = TreeSitterFile::try_build(self.buffer.as_bytes(), lang_str)
-                .and_then(TreeSitterFile::scope_graph);
+            let scope_graph = file_tree.and_then(TreeSitterFile::scope_graph);
 
             match scope_graph {
                 // we have a graph, use that
@@ -610,9 +676,10 @@ impl RepoFile {
             }
         };
 
+        let symbol_list = symbol_locations.list();
+
         // flatten the list of symbols into a string with just text
-        let symbols = symbol_locations
-            .list()
+        let symbols = symbol_list
             .iter()
             .map(|sym| self.buffer[sym.range.start.byte..sym.range.end.byte].to_owned())
             .collect::<HashSet<_>>()
@@ -620,6 +687,12 @@ impl RepoFile {
             .collect::<Vec<_>>()
             .join("\n");
 
+        // the kinds of symbols defined in this file, for `kind:` filters
+        let symbol_kinds = symbol_list
+            .into_iter()
+            .map(|sym| sym.kind)
+            .collect::<HashSet<_>>();
+
         // add an NL if this file is not NL-terminated
         if !self.buffer.ends_with('\n') {
             self.buffer += "\n";
@@ -649,6 +722,7 @@ impl RepoFile {
                             &relative_path_str,
                             &self.buffer,
                             lang_str,
+                            syntax_tree.as_ref(),
                             &self.branches,
                             file_cache.chunks_for_file(&semantic_cache_key).await,
                         )
@@ -657,12 +731,14 @@ impl RepoFile {
             });
         }
 
-        Some(doc!(
+        let mut doc = doc!(
             schema.raw_content => self.buffer.as_bytes(),
             schema.raw_repo_name => repo_name.as_bytes(),
             schema.raw_relative_path => relative_path_str.as_bytes(),
+            schema.raw_branches => branches.as_bytes(),
             schema.unique_hash => tantivy_cache_key,
             schema.repo_disk_path => repo_disk_path.to_string_lossy().as_ref(),
+            schema.entry_disk_path => entry_pathbuf.to_string_lossy().as_ref(),
             schema.relative_path => relative_path_str,
             schema.repo_ref => repo_ref,
             schema.repo_name => repo_name,
@@ -675,7 +751,14 @@ impl RepoFile {
             schema.symbols => symbols,
             schema.branches => branches,
             schema.is_directory => false,
-        ))
+            schema.submodule => self.submodule.unwrap_or_default(),
+        );
+
+        for kind in symbol_kinds {
+            doc.add_bytes(schema.symbol_kinds, kind.into_bytes());
+        }
+
+        Some(doc)
     }
 }
 
@@ -5,12 +5,12 @@ use tantivy::{
     Index,
 };
 
-use super::{file::File, repo::Repo, DocumentRead};
+use super::{commit::Commit, file::File, repo::Repo, DocumentRead};
 use crate::{
-    intelligence::TreeSitterFile,
+    intelligence::{StructuralQueries, TreeSitterFile},
     query::{
         compiler::Compiler,
-        parser::{self, Query, Target},
+        parser::{self, Query, QueryTree, Target},
     },
     symbol::SymbolLocations,
     text_range::TextRange,
@@ -26,6 +26,8 @@ pub struct ContentDocument {
     pub line_end_indices: Vec<u32>,
     pub symbol_locations: SymbolLocations,
     pub branches: Option<String>,
+    /// The path of the submodule this file comes from
+    pub submodule: Option<String>,
 }
 
 impl ContentDocument {
@@ -34,6 +36,17 @@ impl ContentDocument {
             .and_then(TreeSitterFile::hoverable_ranges)
             .ok()
     }
+
+    /// Run a tree-sitter query against this document, returning the ranges of captured nodes.
+    pub fn structural_matches(
+        &self,
+        query: &str,
+        queries: &mut StructuralQueries,
+    ) -> Option<Vec<TextRange>> {
+        TreeSitterFile::try_build(self.content.as_bytes(), self.lang.as_ref()?)
+            .ok()?
+            .structural_matches(query, queries)
+    }
 }
 
 #[derive(Debug)]
@@ -43,6 +56,8 @@ pub struct FileDocument {
     pub repo_ref: String,
     pub lang: Option<String>,
     pub branches: String,
+    /// The path of the submodule this file comes from
+    pub submodule: Option<String>,
 }
 
 pub struct RepoDocument {
@@ -51,6 +66,16 @@ pub struct RepoDocument {
     pub repo_ref: String,
 }
 
+pub struct CommitDocument {
+    pub id: String,
+    pub repo_name: String,
+    pub repo_ref: String,
+    pub author: String,
+    pub message: String,
+    pub changed_paths: Vec<String>,
+    pub commit_unix_secs: u64,
+}
+
 pub struct ContentReader;
 
 #[async_trait]
@@ -58,39 +83,51 @@ impl DocumentRead for ContentReader {
     type Schema = File;
     type Document = ContentDocument;
 
-    fn query_matches(&self, query: &Query<'_>) -> bool {
-        matches!(
-            query,
-            Query {
-                open: Some(false) | None,
-                target: Some(Target::Content(..) | Target::Symbol(..)),
-                ..
-            }
-        )
+    fn query_matches(&self, query: &QueryTree<'_>) -> bool {
+        // There has to be at least one target to highlight. Symbol kinds on their own highlight
+        // every symbol of that kind. Commit messages are not part of any file.
+        !is_open(query)
+            && query
+                .positive_terms()
+                .any(|q| q.target.is_some() || q.kind.is_some())
+            && query
+                .terms()
+                .all(|q| q.target.as_ref().and_then(Target::commit).is_none())
     }
 
-    fn compile<'a, I>(
+    fn compile(
         &self,
         schema: &File,
-        queries: I,
+        query: &QueryTree<'_>,
         tantivy_index: &Index,
-    ) -> Result<Box<dyn tantivy::query::Query>>
-    where
-        I: Iterator<Item = &'a Query<'a>>,
-    {
+    ) -> Result<Box<dyn tantivy::query::Query>> {
         Compiler::new()
             .priority(&[schema.relative_path])
+            // Excluded terms are filtered out in the collector instead, as a file is likely to
+            // contain all trigrams of an excluded term without containing the term itself.
+            .approximate(&[
+                schema.content,
+                schema.symbols,
+                schema.relative_path,
+                schema.repo_name,
+                schema.branches,
+            ])
             .literal(schema.relative_path, |q| q.path.clone())
             .literal(schema.repo_name, |q| q.repo.clone())
             .literal(schema.branches, |q| q.branch.clone())
             .byte_string(schema.lang, |q| q.lang.as_ref())
+            .byte_string(schema.symbol_kinds, |q| q.kind.as_ref())
+            .range(schema.last_commit_unix_seconds, |q| q.commit_range())
             .literal(schema.symbols, |q| {
                 q.target.as_ref().and_then(Target::symbol).cloned()
             })
             .literal(schema.content, |q| {
                 q.target.as_ref().and_then(Target::content).cloned()
             })
-            .compile(queries, tantivy_index)
+            .structural(schema.content, |q| {
+                q.target.as_ref().and_then(Target::structural)
+            })
+            .compile(query, tantivy_index)
     }
 
     fn read_document(&self, schema: &File, doc: tantivy::Document) -> Self::Document {
@@ -100,6 +137,7 @@ impl DocumentRead for ContentReader {
         let content = read_text_field(&doc, schema.content);
         let lang = read_lang_field(&doc, schema.lang);
         let branches = read_lang_field(&doc, schema.branches);
+        let submodule = read_submodule_field(&doc, schema.submodule);
 
         let line_end_indices = doc
             .get_first(schema.line_end_indices)
@@ -127,6 +165,7 @@ impl DocumentRead for ContentReader {
             line_end_indices,
             lang,
             branches,
+            submodule,
         }
     }
 }
@@ -138,42 +177,46 @@ impl DocumentRead for FileReader {
     type Document = FileDocument;
     type Schema = File;
 
-    fn query_matches(&self, query: &Query<'_>) -> bool {
-        matches!(
-            query,
-            // Match both language or filename searches. Handles searches like:
-            //   lang:Rust
-            //   path:server
-            //   lang:Rust path:server
-            Query {
-                open: Some(false) | None,
-                target: None,
-                lang: Some(..),
-                ..
-            } | Query {
-                open: Some(false) | None,
-                target: None,
-                path: Some(..),
-                ..
-            }
-        )
+    fn query_matches(&self, query: &QueryTree<'_>) -> bool {
+        // Match language, filename or commit date searches, including ones that only exclude
+        // files. Handles searches like:
+        //   lang:Rust
+        //   path:server
+        //   lang:Rust path:server
+        //   -path:tests
+        //   after:2023-01-01
+        !is_open(query)
+            && query.positive_terms().all(|q| q.target.is_none())
+            && query
+                .terms()
+                .any(|q| q.path.is_some() || q.lang.is_some() || q.commit_range().is_some())
     }
 
-    fn compile<'a, I>(
+    fn compile(
         &self,
         schema: &Self::Schema,
-        queries: I,
+        query: &QueryTree<'_>,
         tantivy_index: &Index,
-    ) -> Result<Box<dyn tantivy::query::Query>>
-    where
-        I: Iterator<Item = &'a Query<'a>>,
-    {
+    ) -> Result<Box<dyn tantivy::query::Query>> {
         Compiler::new()
+            // Content only ever appears in exclusions here, but it still has to be extracted so
+            // that terms like `not (foo path:src)` don't exclude all of `src`. Excluded terms are
+            // filtered out in the collector.
+            .approximate(&[
+                schema.content,
+                schema.relative_path,
+                schema.repo_name,
+                schema.branches,
+            ])
             .literal(schema.relative_path, |q| q.path.clone())
             .literal(schema.repo_name, |q| q.repo.clone())
             .literal(schema.branches, |q| q.branch.clone())
             .byte_string(schema.lang, |q| q.lang.as_ref())
-            .compile(queries, tantivy_index)
+            .range(schema.last_commit_unix_seconds, |q| q.commit_range())
+            .literal(schema.content, |q| {
+                q.target.as_ref().and_then(Target::content).cloned()
+            })
+            .compile(query, tantivy_index)
     }
 
     fn read_document(&self, schema: &Self::Schema, doc: tantivy::Document) -> Self::Document {
@@ -182,6 +225,7 @@ impl DocumentRead for FileReader {
         let repo_name = read_text_field(&doc, schema.repo_name);
         let lang = read_lang_field(&doc, schema.lang);
         let branches = read_text_field(&doc, schema.branches);
+        let submodule = read_submodule_field(&doc, schema.submodule);
 
         FileDocument {
             relative_path,
@@ -189,6 +233,7 @@ impl DocumentRead for FileReader {
             repo_ref,
             lang,
             branches,
+            submodule,
         }
     }
 }
@@ -200,31 +245,29 @@ impl DocumentRead for RepoReader {
     type Document = RepoDocument;
     type Schema = Repo;
 
-    fn query_matches(&self, query: &Query<'_>) -> bool {
-        matches!(
-            query,
-            Query {
-                open: Some(false) | None,
-                repo: Some(..),
-                path: None,
-                target: None,
-                ..
-            }
-        )
+    fn query_matches(&self, query: &QueryTree<'_>) -> bool {
+        // Repositories can only be filtered by name, so we can't exclude anything else.
+        !is_open(query)
+            && query.positive_terms().any(|q| q.repo.is_some())
+            && query.terms().all(|q| {
+                q.path.is_none()
+                    && q.target.is_none()
+                    && q.kind.is_none()
+                    && q.commit_range().is_none()
+            })
+            && query.negated_terms().all(|q| q.lang.is_none())
     }
 
-    fn compile<'a, I>(
+    fn compile(
         &self,
         schema: &Repo,
-        queries: I,
+        query: &QueryTree<'_>,
         tantivy_index: &Index,
-    ) -> Result<Box<dyn tantivy::query::Query>>
-    where
-        I: Iterator<Item = &'a Query<'a>>,
-    {
+    ) -> Result<Box<dyn tantivy::query::Query>> {
         Compiler::new()
+            .approximate(&[schema.name])
             .literal(schema.name, |q| q.repo.clone())
-            .compile(queries, tantivy_index)
+            .compile(query, tantivy_index)
     }
 
     fn read_document(&self, schema: &Repo, doc: tantivy::Document) -> Self::Document {
@@ -240,6 +283,74 @@ impl DocumentRead for RepoReader {
     }
 }
 
+pub struct CommitReader;
+
+#[async_trait]
+impl DocumentRead for CommitReader {
+    type Document = CommitDocument;
+    type Schema = Commit;
+
+    fn query_matches(&self, query: &QueryTree<'_>) -> bool {
+        // Commits can only be narrowed down by repository, changed path and date, on top of the
+        // message itself. Handles searches like:
+        //   commit:retry
+        //   commit:retry path:src/backoff.rs after:2023-01-01
+        !is_open(query)
+            && query
+                .positive_terms()
+                .any(|q| q.target.as_ref().and_then(Target::commit).is_some())
+            && query.terms().all(|q| {
+                matches!(q.target, None | Some(Target::Commit(_)))
+                    && q.lang.is_none()
+                    && q.branch.is_none()
+                    && q.kind.is_none()
+            })
+    }
+
+    fn compile(
+        &self,
+        schema: &Commit,
+        query: &QueryTree<'_>,
+        tantivy_index: &Index,
+    ) -> Result<Box<dyn tantivy::query::Query>> {
+        Compiler::new()
+            .approximate(&[schema.message, schema.repo_name, schema.changed_paths])
+            .literal(schema.repo_name, |q| q.repo.clone())
+            .literal(schema.changed_paths, |q| q.path.clone())
+            .range(schema.commit_unix_seconds, |q| q.commit_range())
+            .literal(schema.message, |q| {
+                q.target.as_ref().and_then(Target::commit).cloned()
+            })
+            .compile(query, tantivy_index)
+    }
+
+    fn read_document(&self, schema: &Commit, doc: tantivy::Document) -> Self::Document {
+        let id = read_text_field(&doc, schema.id);
+        let repo_name = read_text_field(&doc, schema.repo_name);
+        let repo_ref = read_text_field(&doc, schema.repo_ref);
+        let author = read_text_field(&doc, schema.author);
+        let message = read_text_field(&doc, schema.message);
+        let changed_paths = read_text_field(&doc, schema.changed_paths)
+            .lines()
+            .map(ToOwned::to_owned)
+            .collect();
+        let commit_unix_secs = doc
+            .get_first(schema.commit_unix_seconds)
+            .and_then(Value::as_u64)
+            .unwrap_or_default();
+
+        CommitDocument {
+            id,
+            repo_name,
+            repo_ref,
+            author,
+            message,
+            changed_paths,
+            commit_unix_secs,
+        }
+    }
+}
+
 pub struct OpenReader;
 
 #[derive(Debug)]
@@ -257,34 +368,36 @@ impl DocumentRead for OpenReader {
     type Document = OpenDocument;
     type Schema = File;
 
-    fn query_matches(&self, query: &Query<'_>) -> bool {
-        matches!(
-            query,
-            Query {
-                open: Some(true),
-
-                // All open queries must specify at least the repository name. We don't accept regex
-                // inputs for this type of query.
-                repo: Some(parser::Literal::Plain(..)),
-                path: None | Some(parser::Literal::Plain(..)),
-
-                // We want to make sure this query isn't a symbol or content search, which doesn't
-                // make sense for a file open.
-                target: None,
-                ..
-            }
-        )
+    fn query_matches(&self, query: &QueryTree<'_>) -> bool {
+        is_open(query)
+            && query.negated_terms().next().is_none()
+            && query.terms().all(|q| {
+                matches!(
+                    q,
+                    Query {
+                        // All open queries must specify at least the repository name. We don't
+                        // accept regex inputs for this type of query.
+                        repo: Some(parser::Literal::Plain(..)),
+                        path: None | Some(parser::Literal::Plain(..)),
+
+                        // We want to make sure this query isn't a symbol or content search, which
+                        // doesn't make sense for a file open.
+                        target: None,
+                        kind: None,
+                        before: None,
+                        after: None,
+                        ..
+                    }
+                )
+            })
     }
 
-    fn compile<'a, I>(
+    fn compile(
         &self,
         schema: &File,
-        queries: I,
+        query: &QueryTree<'_>,
         tantivy_index: &Index,
-    ) -> Result<Box<dyn tantivy::query::Query>>
-    where
-        I: Iterator<Item = &'a Query<'a>>,
-    {
+    ) -> Result<Box<dyn tantivy::query::Query>> {
         Compiler::new()
             .literal(schema.repo_name, |q| q.repo.clone())
             .literal(schema.branches, |q| q.branch.clone())
@@ -300,7 +413,7 @@ impl DocumentRead for OpenReader {
                 _ => None,
             })
             .byte_string(schema.lang, |q| q.lang.as_ref())
-            .compile(queries, tantivy_index)
+            .compile(query, tantivy_index)
     }
 
     fn read_document(&self, schema: &File, doc: tantivy::Document) -> Self::Document {
@@ -329,6 +442,11 @@ impl DocumentRead for OpenReader {
     }
 }
 
+/// Whether a query asks to open a file or directory, e.g. `open:true repo:bloop path:src/`.
+fn is_open(query: &QueryTree<'_>) -> bool {
+    query.terms().any(|q| q.open == Some(true))
+}
+
 /// Get the basename of a path, returning an empty string if the path contains no separators.
 ///
 /// ## Examples
@@ -344,6 +462,13 @@ fn read_text_field(doc: &tantivy::Document, field: Field) -> String {
     doc.get_first(field).unwrap().as_text().unwrap().to_owned()
 }
 
+fn read_submodule_field(doc: &tantivy::Document, field: Field) -> Option<String> {
+    doc.get_first(field)
+        .and_then(Value::as_text)
+        .filter(|path| !path.is_empty())
+        .map(ToOwned::to_owned)
+}
+
 fn read_lang_field(doc: &tantivy::Document, lang: Field) -> Option<String> {
     let lang_str = crate::query::languages::proper_case(
         doc.get_first(lang)
@@ -371,4 +496,24 @@ mod test {
         assert_eq!(base_name(&format!("bar/")), format!("bar/"));
         assert_eq!(base_name("foo.txt"), "");
     }
+
+    #[test]
+    fn select_alternatives() {
+        let query = parser::parse("foo or repo:bar").unwrap();
+
+        assert_eq!(
+            ContentReader.select(&query),
+            Some(parser::parse("foo").unwrap())
+        );
+        assert_eq!(
+            RepoReader.select(&query),
+            Some(parser::parse("repo:bar").unwrap())
+        );
+        assert_eq!(CommitReader.select(&query), None);
+
+        // groups that are joined with other terms can't be split up
+        let query = parser::parse("(foo or repo:bar) path:src").unwrap();
+        assert_eq!(RepoReader.select(&query), None);
+        assert_eq!(ContentReader.select(&query), Some(query));
+    }
 }
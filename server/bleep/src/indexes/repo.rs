@@ -1,6 +1,6 @@
 use anyhow::Result;
 use async_trait::async_trait;
-use tantivy::{doc, schema::Schema, IndexWriter, Term};
+use tantivy::{doc, schema::Schema, IndexWriter, Searcher, Term};
 use tracing::info;
 
 pub use super::schema::Repo;
@@ -23,6 +23,7 @@ impl Indexable for Repo {
         repo_ref: &RepoRef,
         repo: &Repository,
         _metadata: &RepoMetadata,
+        _searcher: &Searcher,
         writer: &IndexWriter,
         _pipes: &SyncPipes,
     ) -> Result<()> {
@@ -1,6 +1,6 @@
 use tantivy::schema::{
     BytesOptions, Field, IndexRecordOption, Schema, SchemaBuilder, TextFieldIndexing, TextOptions,
-    FAST, STORED, STRING,
+    FAST, INDEXED, STORED, STRING,
 };
 
 use crate::{db::SqlDb, semantic::Semantic};
@@ -29,6 +29,8 @@ pub struct File {
     pub repo_disk_path: Field,
     /// Path to the file, relative to the repo root
     pub relative_path: Field,
+    /// Path to the file on disk, for finding every document of a path
+    pub entry_disk_path: Field,
 
     /// Unique repo identifier, of the form:
     ///  local: local//path/to/repo
@@ -48,6 +50,10 @@ pub struct File {
     pub symbols: Field,
     pub symbol_locations: Field,
 
+    /// the kinds of symbols defined in this file, one value per kind, e.g.:
+    /// ["struct", "function"]
+    pub symbol_kinds: Field,
+
     /// fast fields for scoring
     pub lang: Field,
     pub avg_line_length: Field,
@@ -57,12 +63,16 @@ pub struct File {
     pub raw_content: Field,
     pub raw_repo_name: Field,
     pub raw_relative_path: Field,
+    pub raw_branches: Field,
 
     /// list of branches in which this file can be found
     pub branches: Field,
 
     /// Whether this entry is a file or a directory
     pub is_directory: Field,
+
+    /// The path of the submodule this entry comes from, or empty
+    pub submodule: Field,
 }
 
 impl File {
@@ -77,6 +87,7 @@ impl File {
         let unique_hash = builder.add_text_field("unique_hash", STRING | STORED);
 
         let repo_disk_path = builder.add_text_field("repo_disk_path", STRING);
+        let entry_disk_path = builder.add_text_field("entry_disk_path", STRING);
         let repo_ref = builder.add_text_field("repo_ref", STRING | STORED);
         let repo_name = builder.add_text_field("repo_name", trigram.clone());
         let relative_path = builder.add_text_field("relative_path", trigram.clone());
@@ -88,6 +99,8 @@ impl File {
         let symbols = builder.add_text_field("symbols", trigram.clone());
         let symbol_locations =
             builder.add_bytes_field("symbol_locations", BytesOptions::default().set_stored());
+        let symbol_kinds =
+            builder.add_bytes_field("symbol_kinds", BytesOptions::default().set_indexed());
 
         let branches = builder.add_text_field("branches", trigram);
 
@@ -96,17 +109,22 @@ impl File {
             BytesOptions::default().set_stored().set_indexed() | FAST,
         );
         let avg_line_length = builder.add_f64_field("line_length", FAST);
-        let last_commit_unix_seconds = builder.add_u64_field("last_commit_unix_seconds", FAST);
+        // indexed for `before:` and `after:` range queries
+        let last_commit_unix_seconds =
+            builder.add_u64_field("last_commit_unix_seconds", FAST | INDEXED);
 
         let raw_content = builder.add_bytes_field("raw_content", FAST);
         let raw_repo_name = builder.add_bytes_field("raw_repo_name", FAST);
         let raw_relative_path = builder.add_bytes_field("raw_relative_path", FAST);
+        let raw_branches = builder.add_bytes_field("raw_branches", FAST);
 
         let is_directory = builder.add_bool_field("is_directory", FAST);
+        let submodule = builder.add_text_field("submodule", STRING | STORED);
 
         Self {
             repo_disk_path,
             relative_path,
+            entry_disk_path,
             unique_hash,
             repo_ref,
             repo_name,
@@ -114,6 +132,7 @@ impl File {
             line_end_indices,
             symbols,
             symbol_locations,
+            symbol_kinds,
             lang,
             avg_line_length,
             last_commit_unix_seconds,
@@ -122,8 +141,10 @@ impl File {
             raw_content,
             raw_repo_name,
             raw_relative_path,
+            raw_branches,
             branches,
             is_directory,
+            submodule,
             sql,
 
             #[cfg(feature = "debug")]
@@ -180,3 +201,80 @@ impl Repo {
         }
     }
 }
+
+/// An index of the commits in the history of each repository, to search commit messages
+pub struct Commit {
+    pub(super) schema: Schema,
+
+    /// Path to the root of the repo on disk
+    pub repo_disk_path: Field,
+
+    /// Unique repo identifier, of the form:
+    ///  local: local//path/to/repo
+    /// github: github.com/org/repo
+    pub repo_ref: Field,
+
+    /// Indexed repo name, of the form:
+    ///  local: repo
+    /// github: github.com/org/repo
+    pub repo_name: Field,
+
+    /// The full SHA of the commit
+    pub id: Field,
+
+    /// Name and email of the author, e.g. `Jane Doe <jane@example.com>`
+    pub author: Field,
+    pub message: Field,
+
+    /// Files changed by the commit, relative to the repo root, one per line
+    pub changed_paths: Field,
+
+    /// Time of the commit, for sorting and `before:`/`after:` range queries
+    pub commit_unix_seconds: Field,
+
+    /// fast byte versions of certain fields for collector-level filtering
+    pub raw_repo_name: Field,
+    pub raw_message: Field,
+    pub raw_changed_paths: Field,
+}
+
+impl Commit {
+    pub fn new() -> Self {
+        let mut builder = SchemaBuilder::new();
+        let trigram = TextOptions::default().set_stored().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_tokenizer("default")
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+        );
+
+        let repo_disk_path = builder.add_text_field("repo_disk_path", STRING);
+        let repo_ref = builder.add_text_field("repo_ref", STRING | STORED);
+        let repo_name = builder.add_text_field("repo_name", trigram.clone());
+
+        let id = builder.add_text_field("id", STRING | STORED);
+        let author = builder.add_text_field("author", trigram.clone());
+        let message = builder.add_text_field("message", trigram.clone());
+        let changed_paths = builder.add_text_field("changed_paths", trigram);
+        let commit_unix_seconds =
+            builder.add_u64_field("commit_unix_seconds", FAST | INDEXED | STORED);
+
+        let raw_repo_name = builder.add_bytes_field("raw_repo_name", FAST);
+        let raw_message = builder.add_bytes_field("raw_message", FAST);
+        let raw_changed_paths = builder.add_bytes_field("raw_changed_paths", FAST);
+
+        Self {
+            repo_disk_path,
+            repo_ref,
+            repo_name,
+            id,
+            author,
+            message,
+            changed_paths,
+            commit_unix_seconds,
+            raw_repo_name,
+            raw_message,
+            raw_changed_paths,
+            schema: builder.build(),
+        }
+    }
+}
@@ -10,6 +10,7 @@ pub use {
 };
 
 use scope_resolution::ResolutionMethod;
+use std::collections::HashMap;
 use tree_sitter::{Parser, Tree};
 
 /// A tree-sitter representation of a file
@@ -65,6 +66,16 @@ impl<'a> TreeSitterFile<'a> {
         })
     }
 
+    /// The root of the syntax tree of this file.
+    pub fn root_node(&self) -> tree_sitter::Node<'_> {
+        self.tree.root_node()
+    }
+
+    /// The syntax tree of this file.
+    pub fn tree(&self) -> &Tree {
+        &self.tree
+    }
+
     pub fn hoverable_ranges(
         self,
     ) -> Result<Vec<crate::text_range::TextRange>, TreeSitterFileError> {
@@ -82,6 +93,29 @@ impl<'a> TreeSitterFile<'a> {
             .collect::<Vec<_>>())
     }
 
+    /// Run an arbitrary tree-sitter query against this file, returning the ranges of all nodes
+    /// captured by its matches.
+    ///
+    /// Queries are written against the grammar of a single language, so the query is compiled
+    /// for the language of this file through `queries`. This returns `None` if the query refers
+    /// to nodes or fields that this language doesn't have.
+    pub fn structural_matches(
+        self,
+        query: &str,
+        queries: &mut StructuralQueries,
+    ) -> Option<Vec<crate::text_range::TextRange>> {
+        let query = queries.compile(self.language, query)?;
+        let root_node = self.tree.root_node();
+        let mut cursor = tree_sitter::QueryCursor::new();
+        Some(
+            cursor
+                .matches(query, root_node, self.src)
+                .flat_map(|m| m.captures)
+                .map(|c| c.node.range().into())
+                .collect::<Vec<_>>(),
+        )
+    }
+
     /// Produce a lexical scope-graph for this TreeSitterFile.
     pub fn scope_graph(self) -> Result<ScopeGraph, TreeSitterFileError> {
         let query = self
@@ -94,3 +128,25 @@ impl<'a> TreeSitterFile<'a> {
         Ok(ResolutionMethod::Generic.build_scope(query, root_node, self.src, self.language))
     }
 }
+
+/// Tree-sitter queries compiled for each language they're run against, so that running a query
+/// against many files only compiles it once per language.
+#[derive(Default)]
+pub struct StructuralQueries {
+    compiled: HashMap<(&'static str, String), Option<tree_sitter::Query>>,
+}
+
+impl StructuralQueries {
+    /// Compile `query` for `language`, or get it from an earlier call. Queries that don't compile
+    /// for this language are remembered as `None`.
+    fn compile(
+        &mut self,
+        language: &'static TSLanguageConfig,
+        query: &str,
+    ) -> Option<&tree_sitter::Query> {
+        self.compiled
+            .entry((language.language_ids[0], query.to_owned()))
+            .or_insert_with(|| tree_sitter::Query::new((language.grammar)(), query).ok())
+            .as_ref()
+    }
+}
@@ -42,6 +42,7 @@ mod collector;
 mod config;
 mod db;
 mod env;
+mod network;
 mod remotes;
 mod repo;
 mod webserver;
@@ -49,6 +50,9 @@ mod webserver;
 #[cfg(feature = "ee")]
 mod ee;
 
+#[cfg(test)]
+mod test_utils;
+
 pub mod analytics;
 pub mod indexes;
 pub mod intelligence;
@@ -99,6 +103,12 @@ pub struct Application {
     /// Store for user profiles
     user_profiles: PersistedState<scc::HashMap<String, UserProfile>>,
 
+    /// Rules for adding the repositories of GitHub organizations automatically
+    github_discovery: PersistedState<remotes::discovery::DiscoveryRules>,
+
+    /// When repositories aren't synced in the background
+    quiet_hours: PersistedState<std::sync::RwLock<Option<periodic::QuietHours>>>,
+
     /// SQL database for persistent storage
     pub sql: SqlDb,
 
@@ -124,22 +134,21 @@ impl Application {
         let config = Arc::new(config);
         debug!(?config, "effective configuration");
 
+        network::initialize(&config)?;
+
         let sqlite = Arc::new(db::init(&config).await?);
 
-        // Initialise Semantic index if `qdrant_url` set in config
-        let semantic = match config.qdrant_url {
-            Some(ref url) => {
-                match Semantic::initialize(&config.model_dir, url, Arc::clone(&config)).await {
-                    Ok(semantic) => Some(semantic),
-                    Err(e) => {
-                        bail!("Qdrant initialization failed: {}", e);
-                    }
+        // Initialise Semantic index if a vector store is set in config
+        let semantic = if semantic::store::is_configured(&config) {
+            match Semantic::initialize(Arc::clone(&config)).await {
+                Ok(semantic) => Some(semantic),
+                Err(e) => {
+                    bail!("Semantic initialization failed: {}", e);
                 }
             }
-            None => {
-                warn!("Semantic search disabled because `qdrant_url` is not provided. Starting without.");
-                None
-            }
+        } else {
+            warn!("Semantic search disabled because neither `qdrant_url` nor `embedded_vector_store` is provided. Starting without.");
+            None
         };
 
         let env = if config.github_app_id.is_some() {
@@ -172,6 +181,8 @@ impl Application {
             cookie_key: config.source.initialize_cookie_key()?,
             credentials: config.source.initialize_credentials()?.into(),
             user_profiles: config.source.load_or_default("user_profiles")?,
+            github_discovery: config.source.load_or_default("github_discovery")?,
+            quiet_hours: config.source.load_or_default("quiet_hours")?,
             sql: sqlite,
             repo_pool,
             analytics,
@@ -197,6 +208,12 @@ impl Application {
             dsn.to_string(),
             sentry::ClientOptions {
                 release: sentry::release_name!(),
+                transport: Some(Arc::new(|options: &sentry::ClientOptions| {
+                    Arc::new(sentry::transports::ReqwestHttpTransport::with_client(
+                        options,
+                        network::client(),
+                    )) as Arc<dyn sentry::Transport>
+                })),
                 ..Default::default()
             },
         ));
@@ -234,6 +251,15 @@ impl Application {
         LOGGER_INSTALLED.set(true).unwrap();
     }
 
+    /// Export the proxy and certificate settings to the environment, for clients that only read
+    /// them from there, like analytics.
+    ///
+    /// Changing the environment isn't thread-safe, so this has to be called before the async
+    /// runtime starts. Otherwise these clients only see the environment the process started with.
+    pub fn export_network_settings(config: &Configuration) -> Result<()> {
+        network::set_env(config)
+    }
+
     pub fn user(&self) -> Option<String> {
         self.credentials.user()
     }
@@ -248,6 +274,7 @@ impl Application {
         } else {
             if !self.config.disable_background {
                 tokio::spawn(periodic::sync_github_status(self.clone()));
+                tokio::spawn(periodic::sync_gitlab_status(self.clone()));
                 tokio::spawn(periodic::check_repo_updates(self.clone()));
                 tokio::spawn(periodic::log_and_branch_rotate(self.clone()));
             }
@@ -1,5 +1,7 @@
 mod logrotate;
 mod remotes;
+mod schedule;
 
 pub(crate) use logrotate::*;
 pub(crate) use remotes::*;
+pub(crate) use schedule::*;
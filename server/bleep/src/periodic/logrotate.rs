@@ -62,12 +62,13 @@ fn collect_branches_for_repos(queries: Vec<String>) -> scc::HashMap<String, Hash
     queries.par_iter().for_each(|q| {
         let parsed = parser::parse_nl(q);
         match parsed {
-            Ok(ParsedQuery::Grep(list)) => {
-                for q in list {
+            Ok(ParsedQuery::Grep(tree)) => {
+                for q in tree.positive_terms() {
                     if let Some((r, b)) = q
                         .repo
+                        .as_ref()
                         .and_then(|r| r.as_plain())
-                        .zip(q.branch.and_then(|b| b.as_plain()))
+                        .zip(q.branch.as_ref().and_then(|b| b.as_plain()))
                     {
                         record_branch(&map, r, b);
                     }
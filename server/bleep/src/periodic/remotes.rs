@@ -1,13 +1,15 @@
 use std::{
     ops::Not,
+    path::{Path, PathBuf},
     sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use chrono::Utc;
+use ignore::{gitignore::GitignoreBuilder, Match};
 use notify_debouncer_mini::{
     new_debouncer_opt,
-    notify::{Config, RecommendedWatcher, RecursiveMode},
+    notify::{self, Config, RecommendedWatcher, RecursiveMode, Watcher},
     DebounceEventResult, Debouncer,
 };
 use rand::{distributions, thread_rng, Rng};
@@ -17,7 +19,7 @@ use tracing::{debug, error, info, warn};
 use crate::{
     env::Feature,
     remotes,
-    repo::{Backend, RepoRef, SyncStatus},
+    repo::{Backend, RepoRef, SyncSchedule, SyncStatus},
     Application,
 };
 
@@ -81,6 +83,8 @@ pub(crate) async fn sync_github_status(app: Application) {
 	};
         debug!("repo list updated");
 
+        remotes::discovery::apply(&app, &app.github_discovery, &repos).await;
+
         let updated = app.credentials.github_updated().unwrap();
         let new = github.update_repositories(repos);
 
@@ -137,8 +141,43 @@ async fn update_credentials(app: &Application) {
     }
 }
 
+pub(crate) async fn sync_gitlab_status(app: Application) {
+    const POLL_PERIOD: Duration = POLL_INTERVAL_MINUTE[1];
+    const LIVENESS: Duration = Duration::from_secs(1);
+
+    loop {
+        let (Some(gitlab), Some(updated)) =
+            (app.credentials.gitlab(), app.credentials.gitlab_updated())
+        else {
+            sleep(LIVENESS).await;
+            continue;
+        };
+
+        match gitlab.current_repo_list().await {
+            Ok(repos) => {
+                app.credentials
+                    .set_gitlab(gitlab.update_repositories(repos));
+                debug!("gitlab repo list updated");
+
+                // swallow the event that's generated from this update
+                _ = updated.recv_async().await;
+            }
+            Err(err) => warn!(?err, "failed to list GitLab projects"),
+        }
+
+        tokio::select! {
+            _ = sleep(POLL_PERIOD) => {
+                debug!("timeout expired; refreshing gitlab repositories");
+            },
+            _ = updated.recv_async() => {
+                debug!("gitlab credentials changed; refreshing repositories");
+            }
+        }
+    }
+}
+
 pub(crate) async fn check_repo_updates(app: Application) {
-    while app.credentials.github().is_none() {
+    while app.credentials.github().is_none() && app.credentials.gitlab().is_none() {
         sleep(Duration::from_millis(100)).await
     }
 
@@ -152,7 +191,7 @@ pub(crate) async fn check_repo_updates(app: Application) {
                     }
                 }
                 scc::hash_map::Entry::Vacant(vacant) => {
-                    if repo.sync_status.indexable() {
+                    if repo.sync_status.indexable() && repo.sync_schedule != SyncSchedule::Manual {
                         vacant.insert_entry(tokio::spawn(periodic_repo_poll(
                             app.clone(),
                             reporef.to_owned(),
@@ -176,12 +215,26 @@ async fn periodic_repo_poll(app: Application, reporef: RepoRef) -> Option<()> {
 
     loop {
         use SyncStatus::*;
-        let (last_updated, status) = check_repo(&app, &reporef)?;
+        let (last_updated, status, schedule) = check_repo(&app, &reporef)?;
         if status.indexable().not() {
             warn!(?status, "skipping indexing of repo");
             return None;
         }
 
+        if schedule == SyncSchedule::Manual {
+            debug!(
+                ?reporef,
+                "repo is only synced manually; stopping monitoring"
+            );
+            return None;
+        }
+
+        if let Some(remaining) = quiet_hours_remaining(&app) {
+            debug!(?reporef, ?remaining, "waiting for quiet hours to end");
+            sleep(remaining + poller.jitter(remaining)).await;
+            continue;
+        }
+
         debug!("starting sync");
         if let Err(err) = app.write_index().block_until_synced(reporef.clone()).await {
             error!(?err, ?reporef, "failed to sync & index repo");
@@ -189,12 +242,23 @@ async fn periodic_repo_poll(app: Application, reporef: RepoRef) -> Option<()> {
         }
 
         debug!("sync done");
-        let (updated, status) = check_repo(&app, &reporef)?;
+        let (updated, status, schedule) = check_repo(&app, &reporef)?;
         if status.indexable().not() {
             warn!(?status, ?reporef, "terminating monitoring for repo");
             return None;
         }
 
+        if app.repo_pool.read(&reporef, |_, v| v.index_working_tree)? != poller.working_tree {
+            // monitoring starts over with the right paths watched
+            debug!(
+                ?reporef,
+                "working tree setting changed; restarting monitoring"
+            );
+            return None;
+        }
+
+        poller.fixed_interval = schedule.interval();
+
         if last_updated == updated && status == Done {
             let poll_interval = poller.increase_interval();
 
@@ -232,6 +296,12 @@ async fn periodic_repo_poll(app: Application, reporef: RepoRef) -> Option<()> {
 struct Poller {
     poll_interval_index: usize,
     minimum_interval_index: usize,
+    /// Overrides the backoff ladder, when the repository is polled on a fixed schedule
+    fixed_interval: Option<Duration>,
+    /// Whether the whole working tree is watched, rather than just the git directory
+    working_tree: bool,
+    /// The working tree whose directories are watched one by one, if it's watched
+    watched_tree: Option<PathBuf>,
     git_events: flume::Receiver<()>,
     debouncer: Option<Debouncer<RecommendedWatcher>>,
 }
@@ -243,22 +313,36 @@ impl Poller {
 
         let (tx, rx) = flume::bounded(10);
 
+        let working_tree = app.repo_pool.read(reporef, |_, v| v.index_working_tree)?;
+
         let mut _debouncer = None;
+        let mut watched_tree = None;
         if app.config.disable_fsevents.not() && reporef.backend() == Backend::Local {
-            let git_path = app
-                .repo_pool
-                .read(reporef, |_, v| v.disk_path.join(".git"))?;
-
-            let mut debouncer = debounced_events(tx);
-            debouncer
-                .watcher()
-                .watch(&git_path, RecursiveMode::Recursive)
-                .map_err(|e| {
-                    let d = git_path.display();
-                    error!(error = %e, path = %d, "path does not exist anymore");
-                })
-                .ok()?;
+            let (git_path, tree) = app.repo_pool.read(reporef, |_, v| {
+                // Mercurial commits change the `.hg` directory instead
+                let hg_path = v.disk_path.join(".hg");
+                if hg_path.is_dir() {
+                    (hg_path, None)
+                } else if working_tree {
+                    (v.disk_path.clone(), Some(v.disk_path.clone()))
+                } else {
+                    (v.disk_path.join(".git"), None)
+                }
+            })?;
+
+            let mut debouncer = debounced_events(tx, tree.clone());
+            let watcher = debouncer.watcher();
+            match tree {
+                Some(ref root) => tokio::task::block_in_place(|| watch_working_tree(watcher, root)),
+                None => watcher.watch(&git_path, RecursiveMode::Recursive),
+            }
+            .map_err(|e| {
+                let d = git_path.display();
+                error!(error = %e, path = %d, "path does not exist anymore");
+            })
+            .ok()?;
             _debouncer = Some(debouncer);
+            watched_tree = tree;
 
             info!(?reporef, ?git_path, "will reindex repo on git changes");
 
@@ -269,6 +353,9 @@ impl Poller {
         Some(Self {
             poll_interval_index,
             minimum_interval_index,
+            fixed_interval: None,
+            working_tree,
+            watched_tree,
             debouncer: _debouncer,
             git_events: rx,
         })
@@ -286,24 +373,40 @@ impl Poller {
     }
 
     fn interval(&self) -> Duration {
-        POLL_INTERVAL_MINUTE[self.poll_interval_index]
+        self.fixed_interval
+            .unwrap_or(POLL_INTERVAL_MINUTE[self.poll_interval_index])
     }
 
     fn jittery_interval(&self) -> Duration {
         let poll_interval = self.interval();
+        poll_interval + self.jitter(poll_interval)
+    }
 
-        // add random jitter to avoid contention when jobs start at the same time
+    /// Random jitter to avoid contention when jobs start at the same time
+    ///
+    /// Long waits get no more jitter than the longest poll interval.
+    fn jitter(&self, interval: Duration) -> Duration {
+        let longest = POLL_INTERVAL_MINUTE[POLL_INTERVAL_MINUTE.len() - 1];
         let jitter = thread_rng().sample(distributions::Uniform::new(
             10,
-            30 + poll_interval.as_secs() / 2,
+            30 + interval.min(longest).as_secs() / 2,
         ));
-        poll_interval + Duration::from_secs(jitter)
+        Duration::from_secs(jitter)
     }
 
     async fn git_change(&mut self) {
-        if self.debouncer.is_some() {
+        if let Some(ref mut debouncer) = self.debouncer {
             _ = self.git_events.recv_async().await;
             _ = self.git_events.drain().collect::<Vec<_>>();
+
+            // directories created since the last walk aren't watched yet
+            if let Some(ref root) = self.watched_tree {
+                let watcher = debouncer.watcher();
+                if let Err(err) = tokio::task::block_in_place(|| watch_working_tree(watcher, root))
+                {
+                    warn!(?err, ?root, "failed to watch new directories");
+                }
+            }
         } else {
             loop {
                 futures::pending!()
@@ -312,23 +415,108 @@ impl Poller {
     }
 }
 
-fn check_repo(app: &Application, reporef: &RepoRef) -> Option<(u64, SyncStatus)> {
+fn check_repo(app: &Application, reporef: &RepoRef) -> Option<(u64, SyncStatus, SyncSchedule)> {
     app.repo_pool.read(reporef, |_, repo| {
-        (repo.last_commit_unix_secs, repo.sync_status.clone())
+        (
+            repo.last_commit_unix_secs,
+            repo.sync_status.clone(),
+            repo.sync_schedule.clone(),
+        )
     })
 }
 
-fn debounced_events(tx: flume::Sender<()>) -> Debouncer<RecommendedWatcher> {
+fn quiet_hours_remaining(app: &Application) -> Option<Duration> {
+    app.quiet_hours.read().unwrap().and_then(|q| q.remaining())
+}
+
+/// Watch the git directory of the working tree at `root`, and every directory in it that isn't
+/// ignored, the way the walker finds them. Build outputs like `target/` and `node_modules/` are
+/// never watched.
+///
+/// Directories are watched one by one, so this has to run again to watch new ones.
+fn watch_working_tree(watcher: &mut dyn Watcher, root: &Path) -> notify::Result<()> {
+    watcher.watch(&root.join(".git"), RecursiveMode::Recursive)?;
+
+    let dirs = ignore::WalkBuilder::new(root)
+        .standard_filters(true)
+        .hidden(false)
+        .filter_entry(|entry| {
+            entry.depth() == 0
+                || (entry.file_name() != ".git" && !entry.path().join(".git").exists())
+        })
+        .build()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().map_or(false, |t| t.is_dir()));
+
+    for dir in dirs {
+        // directories can be removed while they're walked
+        if let Err(err) = watcher.watch(dir.path(), RecursiveMode::NonRecursive) {
+            debug!(?err, path = ?dir.path(), "failed to watch directory");
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether git ignores `path` in the working tree at `root`, going by the `.gitignore` of every
+/// directory up to it, then `.git/info/exclude` and the global excludes file
+fn is_ignored(root: &Path, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return false;
+    };
+
+    // rules in deeper directories take precedence
+    let gitignores = relative.ancestors().skip(1).map(|dir| {
+        let dir = root.join(dir);
+        let file = dir.join(".gitignore");
+        (dir, file)
+    });
+    let excludes = std::iter::once(root.join(".git/info/exclude"))
+        .chain(ignore::gitignore::gitconfig_excludes_path())
+        .map(|file| (root.to_owned(), file));
+
+    let is_dir = path.is_dir();
+    for (dir, file) in gitignores.chain(excludes) {
+        if !file.is_file() {
+            continue;
+        }
+
+        let mut builder = GitignoreBuilder::new(dir);
+        _ = builder.add(file);
+        let Ok(rules) = builder.build() else {
+            continue;
+        };
+
+        match rules.matched_path_or_any_parents(path, is_dir) {
+            Match::Ignore(_) => return true,
+            Match::Whitelist(_) => return false,
+            Match::None => {}
+        }
+    }
+
+    false
+}
+
+fn debounced_events(
+    tx: flume::Sender<()>,
+    working_tree: Option<PathBuf>,
+) -> Debouncer<RecommendedWatcher> {
+    let is_ignored = move |path: &Path| {
+        working_tree
+            .as_deref()
+            .map_or(false, |root| is_ignored(root, path))
+    };
+
     new_debouncer_opt(
         Duration::from_secs(5),
         None,
         move |event: DebounceEventResult| match event {
-            Ok(events) if events.is_empty().not() => {
+            Ok(events) if events.iter().any(|e| !is_ignored(&e.path)) => {
                 if let Err(e) = tx.send(()) {
                     error!("{e}");
                 }
             }
-            Ok(_) => debug!("no events received from debouncer"),
+            Ok(_) => debug!("no relevant events received from debouncer"),
             Err(err) => {
                 error!(?err, "repository monitoring");
             }
@@ -337,3 +525,32 @@ fn debounced_events(tx: flume::Sender<()>) -> Debouncer<RecommendedWatcher> {
     )
     .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn nested_ignore_rules() {
+        let tmpdir = TempDir::new("test-ignore-rules").unwrap();
+        let root = tmpdir.path();
+        std::fs::create_dir_all(root.join(".git/info")).unwrap();
+        std::fs::create_dir_all(root.join("web/node_modules/lib")).unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+
+        std::fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+        std::fs::write(root.join("web/.gitignore"), "node_modules/\n!keep.log\n").unwrap();
+        std::fs::write(root.join(".git/info/exclude"), "scratch.rs\n").unwrap();
+
+        assert!(is_ignored(
+            root,
+            &root.join("web/node_modules/lib/index.js")
+        ));
+        assert!(is_ignored(root, &root.join("src/debug.log")));
+        assert!(is_ignored(root, &root.join("src/scratch.rs")));
+        assert!(!is_ignored(root, &root.join("web/keep.log")));
+        assert!(!is_ignored(root, &root.join("src/main.rs")));
+        assert!(!is_ignored(root, &root.join("web/index.js")));
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use chrono::{NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// A daily window, in UTC, when repositories aren't synced in the background
+///
+/// The window wraps around midnight when `end` is earlier than `start`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct QuietHours {
+    /// As in `"08:00:00"`
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    /// How long until the quiet hours end, if they're on now
+    pub(crate) fn remaining(&self) -> Option<Duration> {
+        self.remaining_at(Utc::now().time())
+    }
+
+    fn remaining_at(&self, now: NaiveTime) -> Option<Duration> {
+        let Self { start, end } = *self;
+
+        let quiet = if start <= end {
+            start <= now && now < end
+        } else {
+            start <= now || now < end
+        };
+
+        if !quiet {
+            return None;
+        }
+
+        let mut remaining = end - now;
+        if remaining < chrono::Duration::zero() {
+            remaining = remaining + chrono::Duration::days(1);
+        }
+
+        remaining.to_std().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    #[test]
+    fn quiet_hours() {
+        let daytime = QuietHours {
+            start: time(8, 0),
+            end: time(18, 0),
+        };
+        assert_eq!(
+            daytime.remaining_at(time(17, 30)),
+            Some(Duration::from_secs(30 * 60))
+        );
+        assert_eq!(daytime.remaining_at(time(18, 0)), None);
+        assert_eq!(daytime.remaining_at(time(7, 59)), None);
+
+        let overnight = QuietHours {
+            start: time(22, 0),
+            end: time(2, 0),
+        };
+        assert_eq!(
+            overnight.remaining_at(time(23, 0)),
+            Some(Duration::from_secs(3 * 60 * 60))
+        );
+        assert_eq!(
+            overnight.remaining_at(time(1, 0)),
+            Some(Duration::from_secs(60 * 60))
+        );
+        assert_eq!(overnight.remaining_at(time(12, 0)), None);
+
+        let empty = QuietHours {
+            start: time(8, 0),
+            end: time(8, 0),
+        };
+        assert_eq!(empty.remaining_at(time(8, 0)), None);
+    }
+}
@@ -1,22 +1,18 @@
-use std::{
-    borrow::Cow,
-    collections::{HashMap, HashSet},
-    mem,
-};
+use std::{borrow::Cow, collections::HashSet, mem, ops::Range};
 
 use anyhow::{Context, Result};
 use compact_str::CompactString;
 use either::Either;
 use smallvec::SmallVec;
 use tantivy::{
-    query::{AllQuery, BooleanQuery, BoostQuery, TermQuery},
+    query::{AllQuery, BooleanQuery, BoostQuery, EmptyQuery, Occur, RangeQuery, TermQuery},
     schema::{Field, IndexRecordOption},
     Index, Term,
 };
 
 use crate::query::{
-    parser::{Literal, Query},
-    planner,
+    parser::{Literal, Query, QueryTree, Tree},
+    planner, structural,
 };
 
 type DynQuery = Box<dyn tantivy::query::Query>;
@@ -27,6 +23,12 @@ enum Extraction<'a> {
 
     /// Match a string against a tantivy `bytes` field.
     ByteString(&'a Cow<'a, str>),
+
+    /// Match the text required by a tree-sitter query against a tantivy `text` field.
+    Structural(&'a Cow<'a, str>),
+
+    /// Match a range of values in an indexed tantivy `u64` field.
+    Range(Range<u64>),
 }
 
 /// A closure that tries to pull out an `Extraction` variant, given a `Query` reference.
@@ -35,7 +37,8 @@ type Extractor = dyn for<'a> FnMut(&'a Query<'a>) -> Option<Extraction<'a>>;
 #[derive(Default)]
 pub struct Compiler {
     priority: HashSet<Field>,
-    extractors: HashMap<Field, Box<Extractor>>,
+    approximate: HashSet<Field>,
+    extractors: Vec<(Field, Box<Extractor>)>,
 }
 
 impl Compiler {
@@ -50,6 +53,16 @@ impl Compiler {
         self
     }
 
+    /// Mark a list of fields as matching too loosely to exclude documents with.
+    ///
+    /// The index only tells us that a document contains all trigrams of a term, not the term
+    /// itself. When a term on one of these fields is negated, the compiler doesn't exclude any
+    /// documents based on it, and callers are expected to filter the results themselves.
+    pub fn approximate(mut self, fields: &[Field]) -> Self {
+        self.approximate = fields.iter().copied().collect();
+        self
+    }
+
     /// Add a literal field to the compiler.
     ///
     /// This takes a Tantivy `Field`, alongside a closure that returns an `Option<&Literal>` when
@@ -59,10 +72,10 @@ impl Compiler {
     where
         F: for<'b> FnMut(&'b Query<'b>) -> Option<Literal<'b>> + 'static,
     {
-        self.extractors.insert(
+        self.extractors.push((
             tantivy_field,
             Box::new(move |q| extractor(q).map(Extraction::Literal)),
-        );
+        ));
 
         self
     }
@@ -74,88 +87,214 @@ impl Compiler {
     where
         F: for<'b> FnMut(&'b Query<'b>) -> Option<&'b Cow<'b, str>> + 'static,
     {
-        self.extractors.insert(
+        self.extractors.push((
             tantivy_field,
             Box::new(move |q| extractor(q).map(Extraction::ByteString)),
-        );
+        ));
         self
     }
 
-    /// Compile a list of queries into a single Tantivy query that matches any
-    /// of them.
-    pub fn compile<'a, I>(mut self, queries: I, index: &Index) -> Result<DynQuery>
+    /// Add a structural query field to the compiler.
+    ///
+    /// Tree-sitter queries can't be matched against the index directly, so this only narrows
+    /// down the documents to those containing the text that every match of the query requires.
+    /// The query itself has to be run by the caller. A field can have a structural extractor
+    /// alongside a literal one.
+    pub fn structural<F>(mut self, tantivy_field: Field, mut extractor: F) -> Self
     where
-        I: Iterator<Item = &'a Query<'a>>,
+        F: for<'b> FnMut(&'b Query<'b>) -> Option<&'b Cow<'b, str>> + 'static,
     {
-        let mut sub_queries: SmallVec<[DynQuery; 2]> = SmallVec::new();
-
-        for query in queries {
-            let mut intersection = Vec::new();
-
-            for (field, extractor) in &mut self.extractors {
-                let Some(extraction) = extractor(query) else {
-                    continue
-                };
-
-                let field_query = match extraction {
-                    Extraction::Literal(Literal::Plain(text)) => {
-                        let tokenizer = index
-                            .tokenizer_for_field(*field)
-                            .context("field is missing tokenizer")?;
-
-                        let mut token_stream = tokenizer.token_stream(&text);
-                        let tokens = std::iter::from_fn(move || {
-                            token_stream.next().map(|tok| CompactString::new(&tok.text))
-                        });
-
-                        let terms = if query.is_case_sensitive() {
-                            tokens.map(|s| str_to_query(*field, &s)).collect::<Vec<_>>()
-                        } else {
-                            tokens
-                                .map(|s| {
-                                    let terms = case_permutations(&s)
-                                        .map(|s| str_to_query(*field, &s))
-                                        .collect();
-
-                                    Box::new(BooleanQuery::union(terms)) as DynQuery
-                                })
-                                .collect()
-                        };
-
-                        let mut field_query: DynQuery = Box::new(BooleanQuery::intersection(terms));
-
-                        if self.priority.contains(field) {
-                            field_query = Box::new(BoostQuery::new(field_query, 10.0));
+        self.extractors.push((
+            tantivy_field,
+            Box::new(move |q| extractor(q).map(Extraction::Structural)),
+        ));
+        self
+    }
+
+    /// Add a `u64` range field to the compiler.
+    ///
+    /// The field must be indexed, rather than just being a fast field.
+    pub fn range<F>(mut self, tantivy_field: Field, mut extractor: F) -> Self
+    where
+        F: for<'b> FnMut(&'b Query<'b>) -> Option<Range<u64>> + 'static,
+    {
+        self.extractors.push((
+            tantivy_field,
+            Box::new(move |q| extractor(q).map(Extraction::Range)),
+        ));
+        self
+    }
+
+    /// Compile a query tree into a single Tantivy query.
+    pub fn compile(mut self, tree: &QueryTree<'_>, index: &Index) -> Result<DynQuery> {
+        self.compile_tree(tree, false, index)
+    }
+
+    /// Compile a subtree, keeping track of whether it is used to exclude documents.
+    fn compile_tree(
+        &mut self,
+        tree: &QueryTree<'_>,
+        negated: bool,
+        index: &Index,
+    ) -> Result<DynQuery> {
+        Ok(match tree {
+            Tree::And(trees) => {
+                let mut clauses = Vec::with_capacity(trees.len());
+
+                for tree in trees {
+                    clauses.push(match tree {
+                        Tree::Not(tree) => {
+                            (Occur::MustNot, self.compile_tree(tree, !negated, index)?)
                         }
+                        tree => (Occur::Must, self.compile_tree(tree, negated, index)?),
+                    });
+                }
 
-                        field_query
-                    }
-                    Extraction::Literal(Literal::Regex(regex)) => {
-                        let plan = planner::plan(&regex)?;
-                        plan_to_query(plan, *field, query.is_case_sensitive())
-                    }
+                // A boolean query without any `Must` clauses matches nothing, so an intersection
+                // that only excludes documents has to start out by matching all of them.
+                if !clauses
+                    .iter()
+                    .any(|(occur, _)| matches!(occur, Occur::Must))
+                {
+                    clauses.insert(0, (Occur::Must, Box::new(AllQuery)));
+                }
 
-                    Extraction::ByteString(bs) => {
-                        let term = Term::from_field_bytes(*field, bs.as_bytes());
-                        let q = TermQuery::new(term, IndexRecordOption::Basic);
-                        Box::new(q) as DynQuery
+                Box::new(BooleanQuery::new(clauses))
+            }
+
+            Tree::Or(trees) => {
+                let mut queries = Vec::with_capacity(trees.len());
+
+                for tree in trees {
+                    queries.push(self.compile_tree(tree, negated, index)?);
+                }
+
+                Box::new(BooleanQuery::union(queries))
+            }
+
+            Tree::Not(tree) => Box::new(BooleanQuery::new(vec![
+                (Occur::Must, Box::new(AllQuery) as DynQuery),
+                (Occur::MustNot, self.compile_tree(tree, !negated, index)?),
+            ])),
+
+            Tree::Term(query) => self.compile_term(query, negated, index)?,
+        })
+    }
+
+    fn compile_term(
+        &mut self,
+        query: &Query<'_>,
+        negated: bool,
+        index: &Index,
+    ) -> Result<DynQuery> {
+        let case_sensitive = query.is_case_sensitive();
+        let mut intersection = Vec::new();
+
+        for &mut (field, ref mut extractor) in &mut self.extractors {
+            let Some(extraction) = extractor(query) else {
+                continue
+            };
+
+            if negated {
+                // Excluding documents based on an approximate match would also exclude documents
+                // that don't actually match, so we can't tell anything about this term.
+                if self.approximate.contains(&field) {
+                    return Ok(unknown(negated));
+                }
+
+                // A regex without any literal parts can't be excluded using trigrams either, as
+                // that would exclude every document.
+                if let Extraction::Literal(Literal::Regex(regex)) = &extraction {
+                    if let planner::Fragment::Break = planner::plan(regex)? {
+                        return Ok(unknown(negated));
                     }
-                };
+                }
+            }
+
+            let boost = self.priority.contains(&field)
+                && matches!(extraction, Extraction::Literal(Literal::Plain(_)));
 
-                intersection.push(field_query);
+            let mut field_query = extraction_to_query(extraction, field, case_sensitive, index)?;
+
+            if boost {
+                field_query = Box::new(BoostQuery::new(field_query, 10.0));
             }
 
-            sub_queries.push(Box::new(BooleanQuery::intersection(intersection)));
+            intersection.push(field_query);
         }
 
-        Ok(if sub_queries.len() == 1 {
-            sub_queries.pop().unwrap()
+        Ok(if intersection.is_empty() {
+            // None of this term's labels apply to the fields we know about.
+            unknown(negated)
         } else {
-            Box::new(BooleanQuery::union(sub_queries.into_vec()))
+            Box::new(BooleanQuery::intersection(intersection))
         })
     }
 }
 
+/// A stand-in for a term that the index can't tell anything about.
+///
+/// This matches every document, unless the term is used to exclude documents, in which case it
+/// matches none. Either way, the term doesn't narrow down the results on its own.
+fn unknown(negated: bool) -> DynQuery {
+    if negated {
+        Box::new(EmptyQuery)
+    } else {
+        Box::new(AllQuery)
+    }
+}
+
+fn extraction_to_query(
+    extraction: Extraction<'_>,
+    field: Field,
+    case_sensitive: bool,
+    index: &Index,
+) -> Result<DynQuery> {
+    Ok(match extraction {
+        Extraction::Literal(Literal::Plain(text)) => {
+            let tokenizer = index
+                .tokenizer_for_field(field)
+                .context("field is missing tokenizer")?;
+
+            let mut token_stream = tokenizer.token_stream(&text);
+            let tokens = std::iter::from_fn(move || {
+                token_stream.next().map(|tok| CompactString::new(&tok.text))
+            });
+
+            let terms = if case_sensitive {
+                tokens.map(|s| str_to_query(field, &s)).collect::<Vec<_>>()
+            } else {
+                tokens
+                    .map(|s| {
+                        let terms = case_permutations(&s)
+                            .map(|s| str_to_query(field, &s))
+                            .collect();
+
+                        Box::new(BooleanQuery::union(terms)) as DynQuery
+                    })
+                    .collect()
+            };
+
+            Box::new(BooleanQuery::intersection(terms))
+        }
+        Extraction::Literal(Literal::Regex(regex)) => {
+            let plan = planner::plan(&regex)?;
+            plan_to_query(plan, field, case_sensitive)
+        }
+
+        Extraction::ByteString(bs) => {
+            let term = Term::from_field_bytes(field, bs.as_bytes());
+            let q = TermQuery::new(term, IndexRecordOption::Basic);
+            Box::new(q) as DynQuery
+        }
+
+        // tree-sitter predicates compare text exactly, regardless of the `case:` flag
+        Extraction::Structural(src) => plan_to_query(structural::plan(src), field, true),
+
+        Extraction::Range(range) => Box::new(RangeQuery::new_u64(field, range)),
+    })
+}
+
 fn plan_to_query(plan: planner::Fragment, field: Field, case_sensitive: bool) -> DynQuery {
     match plan {
         planner::Fragment::Literal(s) => {
@@ -307,6 +446,7 @@ mod tests {
     use tantivy::query::Occur;
 
     use super::*;
+    use crate::query::parser::{self, Target};
 
     #[test]
     fn test_trigrams() {
@@ -379,4 +519,56 @@ mod tests {
             assert_eq!(term.term().as_str().unwrap(), expected);
         }
     }
+
+    #[test]
+    fn test_compile_tree() {
+        let mut builder = tantivy::schema::Schema::builder();
+        let path = builder.add_text_field("path", tantivy::schema::TEXT);
+        let content = builder.add_text_field("content", tantivy::schema::TEXT);
+        let index = Index::create_in_ram(builder.build());
+
+        let compile = |query: &str| {
+            Compiler::new()
+                .approximate(&[content])
+                .literal(path, |q| q.path.clone())
+                .literal(content, |q| {
+                    q.target.as_ref().and_then(Target::content).cloned()
+                })
+                .compile(&parser::parse(query).unwrap(), &index)
+                .unwrap()
+        };
+
+        fn clauses(query: &DynQuery) -> &[(Occur, DynQuery)] {
+            query.downcast_ref::<BooleanQuery>().unwrap().clauses()
+        }
+
+        fn occurs(query: &DynQuery) -> Vec<Occur> {
+            clauses(query).iter().map(|(occur, _)| *occur).collect()
+        }
+
+        // Groups are compiled to nested queries, rather than being expanded.
+        let query = compile("(path:a or path:b) (path:c or path:d)");
+        assert_eq!(occurs(&query), [Occur::Must, Occur::Must]);
+        for (_, group) in clauses(&query) {
+            assert_eq!(occurs(group), [Occur::Should, Occur::Should]);
+        }
+
+        let query = compile("path:src -path:tests");
+        assert_eq!(occurs(&query), [Occur::Must, Occur::MustNot]);
+
+        // Queries made up only of exclusions must still match the remaining documents.
+        let query = compile("-path:tests");
+        assert_eq!(occurs(&query), [Occur::Must, Occur::MustNot]);
+        assert!(clauses(&query)[0].1.is::<AllQuery>());
+
+        // Regexes without any literals can't be excluded with trigrams.
+        let query = compile("path:src -path:/.*/");
+        assert_eq!(occurs(&query), [Occur::Must, Occur::MustNot]);
+        assert!(clauses(&query)[1].1.is::<EmptyQuery>());
+
+        // Neither can terms on approximate fields.
+        let query = compile("path:src not (path:tests foo)");
+        assert_eq!(occurs(&query), [Occur::Must, Occur::MustNot]);
+        assert!(clauses(&query)[1].1.is::<EmptyQuery>());
+    }
 }
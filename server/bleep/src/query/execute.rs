@@ -1,24 +1,32 @@
 use std::{
+    borrow::Cow,
     collections::{HashMap, HashSet},
+    ops::ControlFlow,
     sync::Arc,
 };
 
-use super::{parser, ranking::DocumentTweaker};
+use super::{parser, planner, ranking::DocumentTweaker, structural};
 use crate::{
-    collector::{BytesFilterCollector, FrequencyCollector},
+    collector::{BytesFilterCollector, FrequencyCollector, MultiBytesFilterCollector},
     indexes::{
-        reader::{base_name, ContentReader, FileReader, OpenReader, RepoReader},
-        DocumentRead, File, Indexable, Indexer, Indexes, Repo,
+        reader::{
+            base_name, CommitReader, ContentDocument, ContentReader, FileReader, OpenReader,
+            RepoReader,
+        },
+        Commit, DocumentRead, File, Indexable, Indexer, Indexes, Repo,
     },
+    intelligence::StructuralQueries,
     snippet::{HighlightedString, SnippedFile, Snipper},
 };
 
 use anyhow::{bail, Result};
 use async_trait::async_trait;
+use futures::Stream;
 use regex::{bytes::RegexBuilder as ByteRegexBuilder, RegexBuilder};
 use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
-use tantivy::collector::{MultiCollector, TopDocs};
+use tantivy::collector::{Collector, Count, MultiCollector, TopDocs};
+use tokio_stream::wrappers::ReceiverStream;
 
 const fn default_page_size() -> usize {
     100
@@ -84,6 +92,78 @@ pub struct QueryResponse {
 
 impl crate::webserver::ApiResponse for QueryResponse {}
 
+impl QueryResponse {
+    fn new(summary: QuerySummary, data: Vec<QueryResult>) -> Self {
+        Self {
+            count: summary.count,
+            metadata: summary.metadata,
+            data,
+            stats: summary.stats,
+        }
+    }
+}
+
+/// Everything in a response apart from the results themselves, which is only known once all
+/// results have been produced
+#[derive(Serialize)]
+pub struct QuerySummary {
+    /// Number of search results produced
+    pub count: usize,
+    /// Paging metadata
+    pub(crate) metadata: PagingMetadata,
+    /// Stats for nerds
+    pub stats: ResultStats,
+}
+
+/// An event in a streamed query response
+pub enum QueryEvent {
+    /// A single search result, sent as soon as it is produced
+    Result(QueryResult),
+
+    /// Sent once after every result, unless the query fails part-way through
+    Done(QuerySummary),
+
+    /// The query failed after the response started
+    Error(String),
+}
+
+/// An explanation of how a query is run, to help tune slow queries or ones without results.
+#[derive(Serialize)]
+pub struct Explanation {
+    /// The parsed query
+    query: parser::QueryTree<'static>,
+
+    /// The trigram plan of every target that is planned, rather than tokenized
+    plans: Vec<TargetPlan>,
+
+    /// The readers that can process this query, in the order they are tried. The first one is
+    /// used to execute the query.
+    readers: Vec<ReaderExplanation>,
+}
+
+impl crate::webserver::ApiResponse for Explanation {}
+
+#[derive(Serialize)]
+pub struct TargetPlan {
+    target: parser::Target<'static>,
+    plan: planner::Fragment,
+}
+
+#[derive(Serialize)]
+pub struct ReaderExplanation {
+    reader: &'static str,
+    index: &'static str,
+
+    /// The tantivy query built by the compiler
+    compiled: String,
+
+    /// Number of documents matched by the compiled query
+    candidates: usize,
+
+    /// Number of documents left after the collector-level filters
+    filtered: usize,
+}
+
 /// Metadata pertaining to the query response, such as paging info
 #[derive(Default, Serialize)]
 #[non_exhaustive]
@@ -127,6 +207,9 @@ pub enum QueryResult {
     #[serde(rename = "dir")]
     Directory(DirectoryData),
 
+    #[serde(rename = "commit")]
+    Commit(CommitResultData),
+
     // Only returned by autocomplete
     #[serde(rename = "flag")]
     Flag(String),
@@ -148,6 +231,8 @@ pub struct FileResultData {
     repo_ref: String,
     lang: Option<String>,
     branches: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    submodule: Option<String>,
 }
 
 #[derive(Serialize, Debug)]
@@ -171,6 +256,17 @@ pub struct DirectoryData {
     entries: Vec<DirEntry>,
 }
 
+#[derive(Serialize)]
+pub struct CommitResultData {
+    repo_name: String,
+    repo_ref: String,
+    id: String,
+    author: String,
+    commit_unix_secs: u64,
+    message: HighlightedString,
+    changed_paths: Vec<String>,
+}
+
 #[derive(Serialize, PartialEq, Eq, Hash, Clone, Debug)]
 pub struct DirEntry {
     name: String,
@@ -183,18 +279,70 @@ enum EntryData {
     File { lang: Option<String> },
 }
 
+/// Receives search results one at a time, as a reader produces them.
+///
+/// Readers stop producing results once the sink breaks, e.g. when a streaming client disconnects.
+pub type ResultSink<'a> = dyn FnMut(QueryResult) -> ControlFlow<()> + Send + 'a;
+
 #[async_trait]
-pub trait ExecuteQuery {
+pub trait ExecuteQuery: DocumentRead {
     type Index: Indexable;
 
+    /// Run a query, passing every result to `sink` as soon as it is ready.
+    async fn execute_with(
+        &self,
+        indexer: &Indexer<Self::Index>,
+        query: &parser::QueryTree<'_>,
+        q: &ApiQuery,
+        sink: &mut ResultSink<'_>,
+    ) -> Result<QuerySummary>;
+
+    /// Run the alternatives of a query that this reader can process, see
+    /// [`DocumentRead::select`].
+    async fn execute_selected(
+        &self,
+        indexer: &Indexer<Self::Index>,
+        query: &parser::QueryTree<'_>,
+        q: &ApiQuery,
+        sink: &mut ResultSink<'_>,
+    ) -> Result<QuerySummary> {
+        // Readers that can't process any alternative are still run with the whole query, which
+        // they don't match, so that callers get empty results with metadata.
+        let query = self.select(query).unwrap_or_else(|| query.clone());
+        self.execute_with(indexer, &query, q, sink).await
+    }
+
     async fn execute(
         &self,
         indexer: &Indexer<Self::Index>,
-        queries: &[parser::Query<'_>],
+        query: &parser::QueryTree<'_>,
         q: &ApiQuery,
-    ) -> Result<QueryResponse>;
+    ) -> Result<QueryResponse> {
+        let mut data = Vec::new();
+        let summary = self
+            .execute_selected(indexer, query, q, &mut |result| {
+                data.push(result);
+                ControlFlow::Continue(())
+            })
+            .await?;
+
+        Ok(QueryResponse::new(summary, data))
+    }
+
+    /// Explain how this reader runs a query against its index.
+    async fn explain(
+        &self,
+        indexer: &Indexer<Self::Index>,
+        query: &parser::QueryTree<'_>,
+    ) -> Result<ReaderExplanation>;
 }
 
+/// How many streamed events can be waiting for a slow client before readers are held up.
+const STREAM_BUFFER_SIZE: usize = 16;
+
+/// How many candidates of a deferred query are snipped at most, as each one is loaded and parsed.
+const MAX_DEFERRED_CANDIDATES: usize = 5000;
+
 impl ApiQuery {
     pub async fn query(self: Arc<Self>, indexes: Arc<Indexes>) -> Result<QueryResponse> {
         let query = self.q.clone();
@@ -206,18 +354,79 @@ impl ApiQuery {
     pub async fn query_with(
         self: Arc<Self>,
         indexes: Arc<Indexes>,
-        queries: Vec<parser::Query<'_>>,
+        query: parser::QueryTree<'_>,
     ) -> Result<QueryResponse> {
-        // FIXME: this for-loop prevents us from ever producing heterogenous
+        let mut data = Vec::new();
+        let summary = self
+            .run(&indexes, &query, &mut |result| {
+                data.push(result);
+                ControlFlow::Continue(())
+            })
+            .await?;
+
+        Ok(QueryResponse::new(summary, data))
+    }
+
+    /// Run a query in the background, sending every result as soon as it's produced, followed by
+    /// a summary.
+    ///
+    /// The query is parsed before returning, so that syntax errors can be reported up front.
+    pub fn stream(
+        self: Arc<Self>,
+        indexes: Arc<Indexes>,
+    ) -> Result<impl Stream<Item = QueryEvent>> {
+        let query = parser::parse(&self.q)?.map(&mut |q| q.clone().into_owned());
+        let (tx, rx) = tokio::sync::mpsc::channel(STREAM_BUFFER_SIZE);
+
+        tokio::spawn(async move {
+            // the receiver is gone if the client disconnected, in which case there is nobody
+            // left to send results to
+            if tx.is_closed() {
+                return;
+            }
+
+            let results = tx.clone();
+            let summary = self
+                .run(&indexes, &query, &mut |result| {
+                    // readers produce results synchronously, so wait for room in the buffer
+                    // without holding up the other tasks on this worker
+                    let event = QueryEvent::Result(result);
+                    match tokio::task::block_in_place(|| results.blocking_send(event)) {
+                        Ok(()) => ControlFlow::Continue(()),
+                        Err(_) => ControlFlow::Break(()),
+                    }
+                })
+                .await;
+
+            if tx.is_closed() {
+                return;
+            }
+
+            _ = tx
+                .send(match summary {
+                    Ok(summary) => QueryEvent::Done(summary),
+                    Err(err) => QueryEvent::Error(err.to_string()),
+                })
+                .await;
+        });
+
+        Ok(ReceiverStream::new(rx))
+    }
+
+    async fn run(
+        &self,
+        indexes: &Indexes,
+        query: &parser::QueryTree<'_>,
+        sink: &mut ResultSink<'_>,
+    ) -> Result<QuerySummary> {
+        // FIXME: picking a single reader prevents us from ever producing heterogenous
         // results.
         //
-        // It picks up the first query in the query list and produces results only
-        // for that query. A query containing an `or` operator; such as:
+        // A query containing an `or` operator; such as:
         //
         //     symbol:foo or repo:bar
         //
-        //
-        // is actually broken down into two separate queries:
+        // has terms that operate on different indices:
         //
         //  - `symbol:foo` which operates on the `File` index
         //  - `repo:bar` which operates on the `Repo` index
@@ -229,28 +438,95 @@ impl ApiQuery {
         //    results?
         //  - how do we rank these results?
         //
-        // For the time-being, we take the easy way out by prioritizing the first
-        // target of the query, in this case `symbol:foo`. Queries that produce
-        // homogenous results will work as expected: `repo:foo or repo:bar`.
-        for q in &queries {
-            if ContentReader.query_matches(q) {
+        // For the time-being, we take the easy way out by picking the reader of
+        // the first alternative, in this case `symbol:foo`, and running only the
+        // alternatives that reader can process. Queries that produce homogenous
+        // results will work as expected: `repo:foo or repo:bar`.
+        for alternative in query.disjuncts() {
+            if ContentReader.query_matches(alternative) {
                 tracing::trace!("executing with ContentReader");
-                return ContentReader.execute(&indexes.file, &queries, &self).await;
-            } else if RepoReader.query_matches(q) {
+                return ContentReader
+                    .execute_selected(&indexes.file, query, self, sink)
+                    .await;
+            } else if CommitReader.query_matches(alternative) {
+                tracing::trace!("executing with CommitReader");
+                return CommitReader
+                    .execute_selected(&indexes.commit, query, self, sink)
+                    .await;
+            } else if RepoReader.query_matches(alternative) {
                 tracing::trace!("executing with RepoReader");
-                return RepoReader.execute(&indexes.repo, &queries, &self).await;
-            } else if FileReader.query_matches(q) {
+                return RepoReader
+                    .execute_selected(&indexes.repo, query, self, sink)
+                    .await;
+            } else if FileReader.query_matches(alternative) {
                 tracing::trace!("executing with FileReader");
-                return FileReader.execute(&indexes.file, &queries, &self).await;
-            } else if OpenReader.query_matches(q) {
+                return FileReader
+                    .execute_selected(&indexes.file, query, self, sink)
+                    .await;
+            } else if OpenReader.query_matches(alternative) {
                 tracing::trace!("executing with OpenReader");
-                return OpenReader.execute(&indexes.file, &queries, &self).await;
+                return OpenReader
+                    .execute_selected(&indexes.file, query, self, sink)
+                    .await;
             }
         }
 
         bail!("mangled query")
     }
 
+    /// Explain how a query is parsed, planned, and run against each index, without reading any
+    /// documents.
+    pub async fn explain(self: Arc<Self>, indexes: Arc<Indexes>) -> Result<Explanation> {
+        let query = parser::parse(&self.q)?;
+
+        let plans = query
+            .terms()
+            .filter_map(|q| q.target.as_ref())
+            .filter_map(|target| {
+                let plan = match (target, target.literal()) {
+                    (parser::Target::Structural(_), _) => {
+                        Ok(structural::plan(target.structural()?))
+                    }
+                    (_, parser::Literal::Regex(regex)) => planner::plan(regex),
+                    (_, parser::Literal::Plain(_)) => return None,
+                };
+
+                Some(plan.map(|plan| TargetPlan {
+                    target: target.clone().into_owned(),
+                    plan,
+                }))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut readers = Vec::new();
+
+        if let Some(query) = ContentReader.select(&query) {
+            readers.push(ContentReader.explain(&indexes.file, &query).await?);
+        }
+
+        if let Some(query) = CommitReader.select(&query) {
+            readers.push(CommitReader.explain(&indexes.commit, &query).await?);
+        }
+
+        if let Some(query) = RepoReader.select(&query) {
+            readers.push(RepoReader.explain(&indexes.repo, &query).await?);
+        }
+
+        if let Some(query) = FileReader.select(&query) {
+            readers.push(FileReader.explain(&indexes.file, &query).await?);
+        }
+
+        if let Some(query) = OpenReader.select(&query) {
+            readers.push(OpenReader.explain(&indexes.file, &query).await?);
+        }
+
+        Ok(Explanation {
+            query: query.map(&mut |q| q.clone().into_owned()),
+            plans,
+            readers,
+        })
+    }
+
     fn limit(&self) -> usize {
         // do not permit a page-size of 0
         self.page_size.max(1)
@@ -299,29 +575,236 @@ impl ResultStats {
     }
 }
 
-#[async_trait]
-impl ExecuteQuery for ContentReader {
-    type Index = File;
+/// A raw document field that query terms can be checked against exactly.
+#[derive(Clone, Copy)]
+enum RawField {
+    Content,
+    Path,
+    RepoName,
+    Branches,
+    Lang,
+    CommitMessage,
+}
 
-    async fn execute(
-        &self,
-        indexer: &Indexer<Self::Index>,
-        queries: &[parser::Query<'_>],
+impl RawField {
+    /// The regex that this field has to match, if the term has a label for it.
+    fn regex_str<'a>(self, term: &'a parser::Query<'a>) -> Option<Cow<'a, str>> {
+        let literal = match self {
+            Self::Content => term.target.as_ref().and_then(parser::Target::content),
+            Self::Path => term.path.as_ref(),
+            Self::RepoName => term.repo.as_ref(),
+            Self::Branches => term.branch.as_ref(),
+            // languages are indexed in lowercase, and matched as a whole
+            Self::Lang => {
+                return term
+                    .lang
+                    .as_ref()
+                    .map(|lang| format!("^{}$", regex::escape(&lang.to_lowercase())).into())
+            }
+            Self::CommitMessage => term.target.as_ref().and_then(parser::Target::commit),
+        };
+
+        literal.map(parser::Literal::regex_str)
+    }
+
+    /// Build a filter that checks each term of a query against several fields of a document.
+    ///
+    /// This is used to get rid of documents that contain the trigrams of a term, but not the
+    /// term itself. It also takes care of excluded terms, e.g. `-content:foo` or
+    /// `not (foo lang:rust)`, as the compiled query can't exclude documents by their trigrams.
+    /// Their other labels, like the language, have to be checked here as well.
+    ///
+    /// The checks expect the values of `fields` in the same order.
+    fn filter(fields: &[Self], query: &parser::QueryTree<'_>) -> parser::Tree<TermCheck> {
+        query.map(&mut |term| {
+            let regexes = fields
+                .iter()
+                .enumerate()
+                .filter_map(|(i, field)| {
+                    let regex = ByteRegexBuilder::new(&field.regex_str(term)?)
+                        .multi_line(true)
+                        .case_insensitive(!term.is_case_sensitive())
+                        .build()
+                        .ok()?;
+
+                    Some((i, regex))
+                })
+                .collect::<SmallVec<[_; 2]>>();
+
+            TermCheck {
+                exact: !regexes.is_empty() && regexes.len() == term.label_count(),
+                regexes,
+            }
+        })
+    }
+}
+
+/// An exact check of a single query term against the contents of some `RawField`s.
+#[derive(Clone)]
+struct TermCheck {
+    /// A regex for each label of the term that one of the fields holds, with that field's index.
+    regexes: SmallVec<[(usize, regex::bytes::Regex); 2]>,
+
+    /// Whether the term has no other labels, so that it matches if the regexes do.
+    exact: bool,
+}
+
+impl TermCheck {
+    fn check(&self, values: &[&[u8]]) -> Option<bool> {
+        if self
+            .regexes
+            .iter()
+            .any(|(i, regex)| !regex.is_match(values[*i]))
+        {
+            Some(false)
+        } else if self.exact {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+/// Compile a query with a reader, and count the documents it matches before and after they pass
+/// through a filtering collector.
+async fn count_candidates<R, C>(
+    reader: &R,
+    name: &'static str,
+    index: &'static str,
+    indexer: &Indexer<R::Schema>,
+    query: &parser::QueryTree<'_>,
+    filtered: C,
+) -> Result<ReaderExplanation>
+where
+    R: DocumentRead,
+    C: Collector<Fruit = usize>,
+{
+    let searcher = indexer.reader.read().await.searcher();
+    let compiled = reader.compile(&indexer.source, query, &indexer.index)?;
+    let (candidates, filtered) = searcher.search(&compiled, &(Count, filtered))?;
+
+    Ok(ReaderExplanation {
+        reader: name,
+        index,
+        compiled: format!("{compiled:?}"),
+        candidates,
+        filtered,
+    })
+}
+
+impl ContentReader {
+    /// Whether some terms of a query can only be checked against a file while snipping, where
+    /// files without any matches are dropped:
+    /// - tree-sitter queries need the language of a file
+    /// - symbol kinds are only indexed per file, so `symbol:foo kind:struct` matches files with a
+    ///   `foo` symbol and some struct, even if `foo` isn't one
+    fn is_deferred(query: &parser::QueryTree<'_>) -> bool {
+        query.positive_terms().any(|q| {
+            q.kind.is_some()
+                || q.target
+                    .as_ref()
+                    .and_then(parser::Target::structural)
+                    .is_some()
+        })
+    }
+
+    /// Snip candidate documents in order, passing the files on the page requested by `q` to
+    /// `sink`.
+    ///
+    /// This is used for deferred queries, where candidates are only known to match once they
+    /// are snipped, so that pages only include files that have matches. Snipping stops at the
+    /// first match past the requested page, so the total count and stats are a lower bound that
+    /// only says whether there's another page.
+    fn snip_pages(
+        query: &parser::QueryTree<'_>,
+        docs: impl Iterator<Item = ContentDocument>,
         q: &ApiQuery,
-    ) -> Result<QueryResponse> {
-        // queries that produce content results
-        let relevant_queries = queries.iter().filter(|q| self.query_matches(q));
+        sink: &mut ResultSink<'_>,
+    ) -> Result<QuerySummary> {
+        let snipper = Snipper::default().context(q.context_before, q.context_after);
+        let mut queries = StructuralQueries::default();
+        let page = q.offset()..q.offset() + q.limit();
+
+        let mut count = 0;
+        let mut total_count = 0;
+        let mut lang_freqs = HashMap::new();
+        let mut repo_freqs = HashMap::new();
+
+        for doc in docs {
+            let Some(snippets) = snipper.all_for_query_with(query, &doc, &mut queries)? else {
+                continue;
+            };
+
+            let lang = doc.lang.unwrap_or_default().to_ascii_lowercase();
+            *lang_freqs.entry(lang.into_bytes()).or_default() += 1;
+            *repo_freqs.entry(doc.repo_name.into_bytes()).or_default() += 1;
+
+            if page.contains(&total_count) {
+                if sink(QueryResult::Snippets(snippets)).is_break() {
+                    break;
+                }
+                count += 1;
+            }
+
+            total_count += 1;
+            if total_count > page.end {
+                break;
+            }
+        }
+
+        let stats = ResultStats::default()
+            .with_lang_freqs(lang_freqs)
+            .with_repo_freqs(repo_freqs);
 
+        let metadata = PagingMetadata::new(q.page, q.page_size, Some(total_count));
+
+        Ok(QuerySummary {
+            count,
+            metadata,
+            stats,
+        })
+    }
+
+    /// Wrap a collector so that it only sees documents that contain at least one target, and may
+    /// match the query.
+    fn filter<C: Collector>(
+        &self,
+        source: &File,
+        query: &parser::QueryTree<'_>,
+        collector: C,
+    ) -> impl Collector<Fruit = C::Fruit> {
         // a list of targets, for a query of the form `symbol:foo or bar`, this is:
         // - a symbol target: foo
         // - a content target: bar
-        let targets = relevant_queries
+        let targets = query
+            .positive_terms()
             .filter_map(|q| Some((q.target.as_ref()?, q.is_case_sensitive())))
             .collect::<SmallVec<[_; 2]>>();
 
+        // the content, path, repo, branches and language of each term are matched exactly here,
+        // rather than by the compiled query
+        let term_filter = RawField::filter(
+            &[
+                RawField::Content,
+                RawField::Path,
+                RawField::RepoName,
+                RawField::Branches,
+                RawField::Lang,
+            ],
+            query,
+        );
+
+        // files don't have to contain any text for terms without a textual target, i.e.
+        // tree-sitter queries and symbol kinds on their own
+        let untargeted = query.positive_terms().any(|q| match &q.target {
+            Some(target) => target.structural().is_some(),
+            None => q.kind.is_some(),
+        });
+
         // a regex filter to get rid of docs that contain the trigrams but not the text
         let byte_regexes = targets
             .iter()
+            .filter(|(target, _)| target.structural().is_none())
             .filter_map(|(target, case)| {
                 ByteRegexBuilder::new(&target.literal().regex_str())
                     .multi_line(true)
@@ -331,7 +814,190 @@ impl ExecuteQuery for ContentReader {
             })
             .collect::<Vec<_>>();
 
-        let raw_content = indexer.source.raw_content;
+        MultiBytesFilterCollector::new(
+            vec![
+                source.raw_content,
+                source.raw_relative_path,
+                source.raw_repo_name,
+                source.raw_branches,
+                source.lang,
+            ],
+            move |values| {
+                (untargeted || byte_regexes.iter().any(|r| r.is_match(values[0])))
+                    && term_filter.matches(&mut |check| check.check(values))
+            },
+            collector,
+        )
+    }
+}
+
+impl FileReader {
+    /// Wrap a collector so that it only sees documents whose path, content, repo, branches and
+    /// language may all match the query.
+    fn filter<C: Collector>(
+        &self,
+        source: &File,
+        query: &parser::QueryTree<'_>,
+        collector: C,
+    ) -> impl Collector<Fruit = C::Fruit> {
+        let term_filter = RawField::filter(
+            &[
+                RawField::Path,
+                RawField::Content,
+                RawField::RepoName,
+                RawField::Branches,
+                RawField::Lang,
+            ],
+            query,
+        );
+
+        MultiBytesFilterCollector::new(
+            vec![
+                source.raw_relative_path,
+                source.raw_content,
+                source.raw_repo_name,
+                source.raw_branches,
+                source.lang,
+            ],
+            move |values| term_filter.matches(&mut |check| check.check(values)),
+            collector,
+        )
+    }
+}
+
+impl RepoReader {
+    /// Wrap a collector so that it only sees repositories whose name may match the query.
+    fn filter<C: Collector>(
+        &self,
+        source: &Repo,
+        query: &parser::QueryTree<'_>,
+        collector: C,
+    ) -> impl Collector<Fruit = C::Fruit> {
+        let name_filter = RawField::filter(&[RawField::RepoName], query);
+
+        BytesFilterCollector::new(
+            source.raw_name,
+            move |b| name_filter.matches(&mut |check| check.check(&[b])),
+            collector,
+        )
+    }
+}
+
+impl CommitReader {
+    /// Wrap a collector so that it only sees commits whose message, changed paths and repo may
+    /// all match the query.
+    fn filter<C: Collector>(
+        &self,
+        source: &Commit,
+        query: &parser::QueryTree<'_>,
+        collector: C,
+    ) -> impl Collector<Fruit = C::Fruit> {
+        let term_filter = RawField::filter(
+            &[RawField::CommitMessage, RawField::Path, RawField::RepoName],
+            query,
+        );
+
+        MultiBytesFilterCollector::new(
+            vec![
+                source.raw_message,
+                source.raw_changed_paths,
+                source.raw_repo_name,
+            ],
+            move |values| term_filter.matches(&mut |check| check.check(values)),
+            collector,
+        )
+    }
+}
+
+#[derive(Debug)]
+struct OpenDirective {
+    relative_path: String,
+    repo_name: String,
+}
+
+impl OpenDirective {
+    fn from_query(query: &parser::QueryTree<'_>) -> SmallVec<[Self; 2]> {
+        query
+            .positive_terms()
+            .filter_map(|q| {
+                Some(Self {
+                    relative_path: match q.path.as_ref() {
+                        None => "".into(),
+                        Some(parser::Literal::Plain(p)) => p.to_string(),
+                        Some(parser::Literal::Regex(..)) => return None,
+                    },
+                    repo_name: q.repo.as_ref()?.as_plain()?.into(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl OpenReader {
+    /// Wrap a collector so that it only sees documents directly under an opened path.
+    fn filter<C: Collector>(
+        &self,
+        source: &File,
+        query: &parser::QueryTree<'_>,
+        collector: C,
+    ) -> impl Collector<Fruit = C::Fruit> {
+        let relative_paths = OpenDirective::from_query(query)
+            .into_iter()
+            .map(|d| d.relative_path)
+            .collect::<Vec<_>>();
+
+        BytesFilterCollector::new(
+            source.raw_relative_path,
+            move |b| {
+                let Ok(relative_path) = std::str::from_utf8(b) else {
+                    return false;
+                };
+
+                // Check if *any* of the relative paths match. We can't compare repositories here
+                // because the `BytesFilterCollector` operates on one field. So we sort through this
+                // later. It's unlikely that a search will use more than one open query.
+                relative_paths.iter().any(|rp| {
+                    let rp = rp.trim_end_matches(|c| c != '/');
+
+                    matches!(
+                        // Trim trailing suffix and avoid returning results for an empty string
+                        // (this means that the document we are looking at is the folder itself; a
+                        // redundant result).
+                        relative_path.strip_prefix(rp).map(|p| p.trim_end_matches('/')),
+                        Some(p) if !p.is_empty() && !p.contains('/')
+                    )
+                })
+            },
+            collector,
+        )
+    }
+}
+
+#[async_trait]
+impl ExecuteQuery for ContentReader {
+    type Index = File;
+
+    async fn execute_with(
+        &self,
+        indexer: &Indexer<Self::Index>,
+        query: &parser::QueryTree<'_>,
+        q: &ApiQuery,
+        sink: &mut ResultSink<'_>,
+    ) -> Result<QuerySummary> {
+        if Self::is_deferred(query) {
+            // count the candidates first, so that they can be snipped in order until the page is
+            // full
+            let candidates = self.filter(&indexer.source, query, Count);
+            let candidates = indexer.count(query, self, candidates).await?;
+
+            let top_k = TopDocs::with_limit(candidates.clamp(1, MAX_DEFERRED_CANDIDATES))
+                .tweak_score(DocumentTweaker(indexer.source.clone()));
+            let collector = self.filter(&indexer.source, query, (top_k, MultiCollector::new()));
+            let results = indexer.query(query, self, collector).await?;
+
+            return Self::snip_pages(query, results.docs, q, sink);
+        }
+
         let repo_field = indexer.source.raw_repo_name;
         let lang_field = indexer.source.lang;
 
@@ -352,42 +1018,24 @@ impl ExecuteQuery for ContentReader {
 
         // our final search results contain top-k, total count, language stats, repo stats,
         // filtered by the target regex
-        let collector = BytesFilterCollector::new(
-            raw_content,
-            move |b| byte_regexes.iter().any(|r| r.is_match(b)), // a doc is accepted if it contains at least 1 target
-            (top_k, metadata_collector),
-        );
+        let collector = self.filter(&indexer.source, query, (top_k, metadata_collector));
 
-        let mut results = indexer.query(queries.iter(), self, collector).await?;
-        let data = results
-            .docs
-            .filter_map(|doc| {
-                let snipper = Snipper::default().context(q.context_before, q.context_after);
-                let mut all_snippets = None::<SnippedFile>;
-
-                for (target, case_sensitive) in &targets {
-                    let (is_symbol, lit) = match target {
-                        parser::Target::Symbol(lit) => (true, lit),
-                        parser::Target::Content(lit) => (false, lit),
-                    };
+        let mut results = indexer.query(query, self, collector).await?;
+        let snipper = Snipper::default().context(q.context_before, q.context_after);
+        let mut queries = StructuralQueries::default();
 
-                    if let Some(snippets) = snipper
-                        .find_symbols(is_symbol)
-                        .case_sensitive(*case_sensitive)
-                        .all_for_doc(&lit.regex_str(), &doc)
-                        .unwrap()
-                    {
-                        all_snippets = if let Some(data) = all_snippets {
-                            Some(data.merge(snippets))
-                        } else {
-                            Some(snippets)
-                        };
-                    }
+        let mut count = 0;
+        for doc in results.docs {
+            if let Some(snippets) = snipper
+                .all_for_query_with(query, &doc, &mut queries)
+                .unwrap()
+            {
+                if sink(QueryResult::Snippets(snippets)).is_break() {
+                    break;
                 }
-
-                Some(QueryResult::Snippets(all_snippets?))
-            })
-            .collect::<Vec<QueryResult>>();
+                count += 1;
+            }
+        }
 
         let total_count = total_count_handle.extract(&mut results.metadata);
 
@@ -397,14 +1045,20 @@ impl ExecuteQuery for ContentReader {
 
         let metadata = PagingMetadata::new(q.page, q.page_size, Some(total_count));
 
-        let count = data.len();
-        let response = QueryResponse {
+        Ok(QuerySummary {
             count,
             metadata,
-            data,
             stats,
-        };
-        Ok(response)
+        })
+    }
+
+    async fn explain(
+        &self,
+        indexer: &Indexer<Self::Index>,
+        query: &parser::QueryTree<'_>,
+    ) -> Result<ReaderExplanation> {
+        let filtered = self.filter(&indexer.source, query, Count);
+        count_candidates(self, "content", "file", indexer, query, filtered).await
     }
 }
 
@@ -412,33 +1066,25 @@ impl ExecuteQuery for ContentReader {
 impl ExecuteQuery for FileReader {
     type Index = File;
 
-    async fn execute(
+    async fn execute_with(
         &self,
         indexer: &Indexer<File>,
-        queries: &[parser::Query<'_>],
+        query: &parser::QueryTree<'_>,
         q: &ApiQuery,
-    ) -> Result<QueryResponse> {
-        let (filter_regexes, byte_filter_regexes): (Vec<_>, Vec<_>) = queries
-            .iter()
-            .filter(|q| self.query_matches(q))
+        sink: &mut ResultSink<'_>,
+    ) -> Result<QuerySummary> {
+        let filter_regexes = query
+            .positive_terms()
             .filter_map(|q| {
-                let regex_str = q.path.as_ref()?.regex_str();
-                let case_insensitive = !q.is_case_sensitive();
-                let regex = RegexBuilder::new(&regex_str)
-                    .case_insensitive(case_insensitive)
-                    .build()
-                    .ok()?;
-                let byte_regex = ByteRegexBuilder::new(&regex_str)
-                    .case_insensitive(case_insensitive)
+                RegexBuilder::new(&q.path.as_ref()?.regex_str())
+                    .case_insensitive(!q.is_case_sensitive())
                     .build()
-                    .ok()?;
-                Some((regex, byte_regex))
+                    .ok()
             })
-            .unzip();
+            .collect::<Vec<_>>();
 
         let top_k = TopDocs::with_limit(q.limit()).and_offset(q.offset());
 
-        let path_field = indexer.source.raw_relative_path;
         let repo_field = indexer.source.raw_repo_name;
         let lang_field = indexer.source.lang;
 
@@ -451,32 +1097,32 @@ impl ExecuteQuery for FileReader {
         let lang_stats_handle = metadata_collector.add_collector(lang_stats_collector);
         let repo_stats_handle = metadata_collector.add_collector(repo_stats_collector);
 
-        let collector = BytesFilterCollector::new(
-            path_field,
-            move |b| byte_filter_regexes.iter().any(|r| r.is_match(b)), // a doc is accepted if it contains at least 1 target
-            (top_k, metadata_collector),
-        );
+        let collector = self.filter(&indexer.source, query, (top_k, metadata_collector));
 
-        let mut results = indexer.query(queries.iter(), self, collector).await?;
+        let mut results = indexer.query(query, self, collector).await?;
 
-        let data = results
-            .docs
-            .map(|f| {
-                let mut relative_path = HighlightedString::new(f.relative_path);
+        let mut count = 0;
+        for f in results.docs {
+            let mut relative_path = HighlightedString::new(f.relative_path);
 
-                for regex in &filter_regexes {
-                    relative_path.apply_regex(regex);
-                }
+            for regex in &filter_regexes {
+                relative_path.apply_regex(regex);
+            }
 
-                QueryResult::FileResult(FileResultData {
-                    relative_path,
-                    repo_name: f.repo_name,
-                    repo_ref: f.repo_ref,
-                    lang: f.lang,
-                    branches: f.branches,
-                })
-            })
-            .collect::<Vec<QueryResult>>();
+            let result = QueryResult::FileResult(FileResultData {
+                relative_path,
+                repo_name: f.repo_name,
+                repo_ref: f.repo_ref,
+                lang: f.lang,
+                branches: f.branches,
+                submodule: f.submodule,
+            });
+
+            if sink(result).is_break() {
+                break;
+            }
+            count += 1;
+        }
 
         let total_count = total_count_handle.extract(&mut results.metadata);
 
@@ -486,14 +1132,20 @@ impl ExecuteQuery for FileReader {
 
         let metadata = PagingMetadata::new(q.page, q.page_size, Some(total_count));
 
-        let response = QueryResponse {
-            count: data.len(),
-            data,
+        Ok(QuerySummary {
+            count,
             metadata,
             stats,
-        };
+        })
+    }
 
-        Ok(response)
+    async fn explain(
+        &self,
+        indexer: &Indexer<Self::Index>,
+        query: &parser::QueryTree<'_>,
+    ) -> Result<ReaderExplanation> {
+        let filtered = self.filter(&indexer.source, query, Count);
+        count_candidates(self, "file", "file", indexer, query, filtered).await
     }
 }
 
@@ -501,29 +1153,22 @@ impl ExecuteQuery for FileReader {
 impl ExecuteQuery for RepoReader {
     type Index = Repo;
 
-    async fn execute(
+    async fn execute_with(
         &self,
         indexer: &Indexer<Self::Index>,
-        queries: &[parser::Query<'_>],
+        query: &parser::QueryTree<'_>,
         q: &ApiQuery,
-    ) -> Result<QueryResponse> {
-        let (filter_regexes, byte_filter_regexes): (Vec<_>, Vec<_>) = queries
-            .iter()
-            .filter(|q| self.query_matches(q))
+        sink: &mut ResultSink<'_>,
+    ) -> Result<QuerySummary> {
+        let filter_regexes = query
+            .positive_terms()
             .filter_map(|q| {
-                let regex_str = q.path.as_ref()?.regex_str();
-                let case_insensitive = !q.is_case_sensitive();
-                let regex = RegexBuilder::new(&regex_str)
-                    .case_insensitive(case_insensitive)
-                    .build()
-                    .ok()?;
-                let byte_regex = ByteRegexBuilder::new(&regex_str)
-                    .case_insensitive(case_insensitive)
+                RegexBuilder::new(&q.repo.as_ref()?.regex_str())
+                    .case_insensitive(!q.is_case_sensitive())
                     .build()
-                    .ok()?;
-                Some((regex, byte_regex))
+                    .ok()
             })
-            .unzip();
+            .collect::<Vec<_>>();
 
         let top_k = TopDocs::with_limit(q.limit()).and_offset(q.offset());
 
@@ -535,29 +1180,112 @@ impl ExecuteQuery for RepoReader {
         let repo_stats_handle = metadata_collector.add_collector(repo_stats_collector);
         let total_count_handle = metadata_collector.add_collector(total_count_collector);
 
-        let collector = BytesFilterCollector::new(
-            name_field,
-            move |b| byte_filter_regexes.iter().any(|r| r.is_match(b)), // a doc is accepted if it contains at least 1 target
-            (top_k, metadata_collector),
-        );
+        let collector = self.filter(&indexer.source, query, (top_k, metadata_collector));
 
-        let mut results = indexer.query(queries.iter(), self, collector).await?;
+        let mut results = indexer.query(query, self, collector).await?;
 
-        let data = results
-            .docs
-            .map(|r| {
-                let mut name = HighlightedString::new(r.name);
+        let mut count = 0;
+        for r in results.docs {
+            let mut name = HighlightedString::new(r.name);
 
-                for r in &filter_regexes {
-                    name.apply_regex(r);
-                }
+            for r in &filter_regexes {
+                name.apply_regex(r);
+            }
 
-                QueryResult::RepositoryResult(RepositoryResultData {
-                    name,
-                    repo_ref: r.repo_ref,
-                })
+            let result = QueryResult::RepositoryResult(RepositoryResultData {
+                name,
+                repo_ref: r.repo_ref,
+            });
+
+            if sink(result).is_break() {
+                break;
+            }
+            count += 1;
+        }
+
+        let stats = ResultStats::default()
+            .with_repo_freqs(repo_stats_handle.extract(&mut results.metadata));
+
+        let total_count = total_count_handle.extract(&mut results.metadata);
+        let metadata = PagingMetadata::new(q.page, q.page_size, Some(total_count));
+
+        Ok(QuerySummary {
+            count,
+            metadata,
+            stats,
+        })
+    }
+
+    async fn explain(
+        &self,
+        indexer: &Indexer<Self::Index>,
+        query: &parser::QueryTree<'_>,
+    ) -> Result<ReaderExplanation> {
+        let filtered = self.filter(&indexer.source, query, Count);
+        count_candidates(self, "repo", "repo", indexer, query, filtered).await
+    }
+}
+
+#[async_trait]
+impl ExecuteQuery for CommitReader {
+    type Index = Commit;
+
+    async fn execute_with(
+        &self,
+        indexer: &Indexer<Self::Index>,
+        query: &parser::QueryTree<'_>,
+        q: &ApiQuery,
+        sink: &mut ResultSink<'_>,
+    ) -> Result<QuerySummary> {
+        let filter_regexes = query
+            .positive_terms()
+            .filter_map(|q| {
+                let message = q.target.as_ref()?.commit()?;
+                RegexBuilder::new(&message.regex_str())
+                    .multi_line(true)
+                    .case_insensitive(!q.is_case_sensitive())
+                    .build()
+                    .ok()
             })
-            .collect::<Vec<QueryResult>>();
+            .collect::<Vec<_>>();
+
+        let top_k = TopDocs::with_limit(q.limit()).and_offset(q.offset());
+
+        let repo_field = indexer.source.raw_repo_name;
+        let repo_stats_collector = FrequencyCollector(repo_field);
+        let total_count_collector = tantivy::collector::Count;
+
+        let mut metadata_collector = MultiCollector::new();
+        let repo_stats_handle = metadata_collector.add_collector(repo_stats_collector);
+        let total_count_handle = metadata_collector.add_collector(total_count_collector);
+
+        let collector = self.filter(&indexer.source, query, (top_k, metadata_collector));
+
+        let mut results = indexer.query(query, self, collector).await?;
+
+        let mut count = 0;
+        for c in results.docs {
+            let mut message = HighlightedString::new(c.message);
+
+            for regex in &filter_regexes {
+                message.apply_regex(regex);
+            }
+
+            let result = QueryResult::Commit(CommitResultData {
+                repo_name: c.repo_name,
+                repo_ref: c.repo_ref,
+                id: c.id,
+                author: c.author,
+                commit_unix_secs: c.commit_unix_secs,
+                message,
+                changed_paths: c.changed_paths,
+            });
+
+            if sink(result).is_break() {
+                break;
+            }
+            count += 1;
+        }
 
         let stats = ResultStats::default()
             .with_repo_freqs(repo_stats_handle.extract(&mut results.metadata));
@@ -565,14 +1293,20 @@ impl ExecuteQuery for RepoReader {
         let total_count = total_count_handle.extract(&mut results.metadata);
         let metadata = PagingMetadata::new(q.page, q.page_size, Some(total_count));
 
-        let response = QueryResponse {
-            count: data.len(),
-            data,
+        Ok(QuerySummary {
+            count,
             metadata,
             stats,
-        };
+        })
+    }
 
-        Ok(response)
+    async fn explain(
+        &self,
+        indexer: &Indexer<Self::Index>,
+        query: &parser::QueryTree<'_>,
+    ) -> Result<ReaderExplanation> {
+        let filtered = self.filter(&indexer.source, query, Count);
+        count_candidates(self, "commit", "commit", indexer, query, filtered).await
     }
 }
 
@@ -580,67 +1314,21 @@ impl ExecuteQuery for RepoReader {
 impl ExecuteQuery for OpenReader {
     type Index = File;
 
-    async fn execute(
+    async fn execute_with(
         &self,
         indexer: &Indexer<Self::Index>,
-        queries: &[parser::Query<'_>],
+        query: &parser::QueryTree<'_>,
         _q: &ApiQuery,
-    ) -> Result<QueryResponse> {
-        #[derive(Debug)]
-        struct Directive {
-            relative_path: String,
-            repo_name: String,
-        }
-
-        let open_directives = queries
-            .iter()
-            .filter(|q| self.query_matches(q))
-            .filter_map(|q| {
-                Some(Directive {
-                    relative_path: match q.path.as_ref() {
-                        None => "".into(),
-                        Some(parser::Literal::Plain(p)) => p.to_string(),
-                        Some(parser::Literal::Regex(..)) => return None,
-                    },
-                    repo_name: q.repo.as_ref()?.as_plain()?.into(),
-                })
-            })
-            .collect::<SmallVec<[_; 2]>>();
+        sink: &mut ResultSink<'_>,
+    ) -> Result<QuerySummary> {
+        let open_directives = OpenDirective::from_query(query);
 
         let top_docs = TopDocs::with_limit(50000);
         let empty_collector = MultiCollector::new();
 
-        let relative_paths = open_directives
-            .iter()
-            .map(|d| d.relative_path.to_owned())
-            .collect::<Vec<_>>();
-
-        let collector = BytesFilterCollector::new(
-            indexer.source.raw_relative_path,
-            move |b| {
-                let Ok(relative_path) = std::str::from_utf8(b) else {
-                    return false;
-                };
+        let collector = self.filter(&indexer.source, query, (top_docs, empty_collector));
 
-                // Check if *any* of the relative paths match. We can't compare repositories here
-                // because the `BytesFilterCollector` operates on one field. So we sort through this
-                // later. It's unlikely that a search will use more than one open query.
-                relative_paths.iter().any(|rp| {
-                    let rp = rp.trim_end_matches(|c| c != '/');
-
-                    matches!(
-                        // Trim trailing suffix and avoid returning results for an empty string
-                        // (this means that the document we are looking at is the folder itself; a
-                        // redundant result).
-                        relative_path.strip_prefix(rp).map(|p| p.trim_end_matches('/')),
-                        Some(p) if !p.is_empty() && !p.contains('/')
-                    )
-                })
-            },
-            (top_docs, empty_collector),
-        );
-
-        let results = indexer.query(queries.iter(), self, collector).await?;
+        let results = indexer.query(query, self, collector).await?;
 
         // Map of (repo_name, relative_path) -> (String, entry set)
         //
@@ -737,17 +1425,30 @@ impl ExecuteQuery for OpenReader {
                 })
             })
             .map(QueryResult::Directory)
-            .chain(files.into_iter().map(QueryResult::File))
-            .collect::<Vec<QueryResult>>();
+            .chain(files.into_iter().map(QueryResult::File));
 
-        let response = QueryResponse {
-            count: data.len(),
-            data,
+        let mut count = 0;
+        for result in data {
+            if sink(result).is_break() {
+                break;
+            }
+            count += 1;
+        }
+
+        Ok(QuerySummary {
+            count,
             metadata: PagingMetadata::default(),
             stats: ResultStats::default(),
-        };
+        })
+    }
 
-        Ok(response)
+    async fn explain(
+        &self,
+        indexer: &Indexer<Self::Index>,
+        query: &parser::QueryTree<'_>,
+    ) -> Result<ReaderExplanation> {
+        let filtered = self.filter(&indexer.source, query, Count);
+        count_candidates(self, "open", "file", indexer, query, filtered).await
     }
 }
 
@@ -816,6 +1517,7 @@ mod tests {
                     highlights: vec![51..56],
                     symbols: vec![],
                 }],
+                submodule: None,
             })],
             metadata: PagingMetadata {
                 page: 0,
@@ -829,4 +1531,126 @@ mod tests {
 
         assert_eq!(expected, observed);
     }
+
+    #[test]
+    fn deferred_pages_only_count_matches() {
+        let doc = |relative_path: &str, content: &str| ContentDocument {
+            relative_path: relative_path.into(),
+            repo_name: "local//bleep".into(),
+            lang: Some("Rust".into()),
+            line_end_indices: content.match_indices('\n').map(|(i, _)| i as u32).collect(),
+            content: content.into(),
+            ..Default::default()
+        };
+
+        // only every other candidate has a match
+        let docs = (0..5)
+            .flat_map(|i| {
+                [
+                    doc(
+                        &format!("unwrap{i}.rs"),
+                        "fn main() {\n    foo().unwrap();\n}\n",
+                    ),
+                    doc(
+                        &format!("expect{i}.rs"),
+                        "fn main() {\n    foo().expect(\"\");\n}\n",
+                    ),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let query = r#"struct:'(call_expression (field_expression field: (field_identifier) @f (#eq? @f "unwrap")))'"#;
+        let tree = parser::parse(query).unwrap();
+
+        let page = |page: usize| {
+            let q = serde_json::from_value::<ApiQuery>(serde_json::json!({
+                "q": query,
+                "page": page,
+                "page_size": 2,
+            }))
+            .unwrap();
+
+            let mut paths = Vec::new();
+            let summary =
+                ContentReader::snip_pages(&tree, docs.clone().into_iter(), &q, &mut |result| {
+                    let QueryResult::Snippets(file) = result else {
+                        panic!("expected snippets");
+                    };
+                    paths.push(file.relative_path);
+                    ControlFlow::Continue(())
+                })
+                .unwrap();
+
+            (paths, summary)
+        };
+
+        // snipping stops at the first match past the page
+        let (paths, summary) = page(0);
+        assert_eq!(paths, ["unwrap0.rs", "unwrap1.rs"]);
+        assert_eq!(summary.count, 2);
+        assert_eq!(summary.metadata.total_count, Some(3));
+        assert_eq!(summary.metadata.page_count, Some(2));
+        assert_eq!(summary.stats.lang, HashMap::from([("Rust".into(), 3)]));
+
+        let (paths, summary) = page(2);
+        assert_eq!(paths, ["unwrap4.rs"]);
+        assert_eq!(summary.count, 1);
+
+        let (paths, summary) = page(3);
+        assert!(paths.is_empty());
+        assert_eq!(summary.metadata.total_count, Some(5));
+    }
+
+    #[test]
+    fn deferred_queries() {
+        let deferred = |query: &str| ContentReader::is_deferred(&parser::parse(query).unwrap());
+
+        assert!(deferred("kind:struct"));
+        assert!(deferred("symbol:config kind:struct"));
+        assert!(deferred("struct:'(struct_item) @s'"));
+        assert!(!deferred("symbol:config"));
+        assert!(!deferred("config not (lang:rust)"));
+
+        // symbol kinds can't be excluded
+        assert!(parser::parse("config not (kind:struct)").is_err());
+    }
+
+    #[test]
+    fn exclusions_are_exact() {
+        let query = parser::parse(r"foo -path:tests -path:/^vendor\// -repo:/^bar$/").unwrap();
+        let filter = RawField::filter(
+            &[RawField::Content, RawField::Path, RawField::RepoName],
+            &query,
+        );
+
+        let matches = |path: &str, repo: &str| {
+            let values = [&b"foo"[..], path.as_bytes(), repo.as_bytes()];
+            filter.matches(&mut |check| check.check(&values))
+        };
+
+        // `latest/stsync.rs` has all trigrams of `tests`, but doesn't contain it
+        assert!(matches("latest/stsync.rs", "baz"));
+        assert!(!matches("src/tests/lib.rs", "baz"));
+
+        assert!(matches("src/vendor/lib.rs", "baz"));
+        assert!(!matches("vendor/lib.rs", "baz"));
+
+        assert!(matches("src/lib.rs", "foobar"));
+        assert!(!matches("src/lib.rs", "bar"));
+    }
+
+    #[test]
+    fn excluded_groups_check_lang() {
+        let query = parser::parse("foo not (bar lang:rust)").unwrap();
+        let filter = RawField::filter(&[RawField::Content, RawField::Lang], &query);
+
+        let matches = |content: &str, lang: &str| {
+            let values = [content.as_bytes(), lang.as_bytes()];
+            filter.matches(&mut |check| check.check(&values))
+        };
+
+        assert!(!matches("foo bar", "rust"));
+        assert!(matches("foo bar", "python"));
+        assert!(matches("foo", "rust"));
+    }
 }
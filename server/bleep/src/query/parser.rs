@@ -1,9 +1,8 @@
 use pest::{iterators::Pair, Parser};
 use regex::Regex;
-use smallvec::{smallvec, SmallVec};
-use std::{borrow::Cow, collections::HashSet, mem};
+use std::{borrow::Cow, collections::HashSet, mem, ops::Range};
 
-#[derive(Default, Clone, Debug, PartialEq, Eq)]
+#[derive(Default, Clone, Debug, PartialEq, Eq, serde::Serialize)]
 pub struct Query<'a> {
     pub open: Option<bool>,
     pub case_sensitive: Option<bool>,
@@ -15,19 +14,50 @@ pub struct Query<'a> {
     pub lang: Option<Cow<'a, str>>,
     pub branch: Option<Literal<'a>>,
     pub target: Option<Target<'a>>,
+
+    /// The kind of symbol definitions to look for, e.g. `struct` or `function`.
+    pub kind: Option<Cow<'a, str>>,
+
+    /// Only match files last changed before this unix timestamp.
+    pub before: Option<u64>,
+
+    /// Only match files last changed at or after this unix timestamp.
+    pub after: Option<u64>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+/// A boolean combination of query terms.
+///
+/// Labels that are simply joined together are merged into a single `Term`. For example,
+/// `repo:bloop ParseError` is parsed as one term, while `(repo:bloop or repo:google) ParseError`
+/// is an `And` of a `ParseError` term and an `Or` of two repository terms.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub enum Tree<T> {
+    And(Vec<Tree<T>>),
+    Or(Vec<Tree<T>>),
+    Not(Box<Tree<T>>),
+    Term(T),
+}
+
+/// A parsed query in the bloop query language.
+pub type QueryTree<'a> = Tree<Query<'a>>;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Serialize)]
 pub enum Target<'a> {
     Symbol(Literal<'a>),
     Content(Literal<'a>),
+
+    /// A tree-sitter query, matched against the syntax tree of each file.
+    Structural(Literal<'a>),
+
+    /// Text in the message of a commit.
+    Commit(Literal<'a>),
 }
 
 #[derive(Debug, PartialEq, Eq)]
 #[allow(clippy::large_enum_variant)]
 pub enum ParsedQuery<'a> {
     Semantic(SemanticQuery<'a>),
-    Grep(Vec<Query<'a>>),
+    Grep(QueryTree<'a>),
 }
 
 impl<'a> ParsedQuery<'a> {
@@ -46,6 +76,13 @@ pub struct SemanticQuery<'a> {
     pub langs: HashSet<Cow<'a, str>>,
     pub branch: HashSet<Literal<'a>>,
     pub target: Option<Literal<'a>>,
+
+    #[serde(default)]
+    pub not_repos: HashSet<Literal<'a>>,
+    #[serde(default)]
+    pub not_paths: HashSet<Literal<'a>>,
+    #[serde(default)]
+    pub not_langs: HashSet<Cow<'a, str>>,
 }
 
 impl<'a> SemanticQuery<'a> {
@@ -69,6 +106,18 @@ impl<'a> SemanticQuery<'a> {
         self.branch.iter().filter_map(|t| t.as_plain())
     }
 
+    pub fn not_repos(&'a self) -> impl Iterator<Item = Cow<'a, str>> {
+        self.not_repos.iter().filter_map(|t| t.as_plain())
+    }
+
+    pub fn not_paths(&'a self) -> impl Iterator<Item = Cow<'a, str>> {
+        self.not_paths.iter().filter_map(|t| t.as_plain())
+    }
+
+    pub fn not_langs(&'a self) -> impl Iterator<Item = Cow<'a, str>> {
+        self.not_langs.iter().cloned()
+    }
+
     // TODO (@calyptobai): This is a quirk of the current conversation logic. We take only the
     // first branch because the UX operates on a single "current" branch. We can likely update
     // `SemanticQuery` to remove multiple branches altogether.
@@ -95,6 +144,21 @@ impl<'a> SemanticQuery<'a> {
                 .collect(),
             branch: self.branch.into_iter().map(Literal::into_owned).collect(),
             target: self.target.map(Literal::into_owned),
+            not_repos: self
+                .not_repos
+                .into_iter()
+                .map(Literal::into_owned)
+                .collect(),
+            not_paths: self
+                .not_paths
+                .into_iter()
+                .map(Literal::into_owned)
+                .collect(),
+            not_langs: self
+                .not_langs
+                .into_iter()
+                .map(|c| c.into_owned().into())
+                .collect(),
         }
     }
 }
@@ -113,6 +177,9 @@ impl<'a> Query<'a> {
             path: rhs.path.or(self.path),
             lang: rhs.lang.or(self.lang),
             branch: rhs.branch.or(self.branch),
+            kind: rhs.kind.or(self.kind),
+            before: rhs.before.or(self.before),
+            after: rhs.after.or(self.after),
 
             target: match (self.target, rhs.target) {
                 (Some(Target::Content(lhs)), Some(Target::Content(rhs))) => {
@@ -125,46 +192,6 @@ impl<'a> Query<'a> {
         }
     }
 
-    /// Fetch all `merge`s of this `Query` with a list of query groups.
-    ///
-    /// This is useful to flatten out a nested tree of queries. For example, with the following input
-    /// string:
-    ///
-    /// ```text
-    /// (repo:enterprise-search or repo:query-planner) (org:bloop or org:google) ParseError
-    /// ```
-    ///
-    /// This method will effectively flatten out the combinators, resulting in the equivalent list
-    /// of queries:
-    ///
-    /// ```text
-    ///    (repo:enterprise-search org:bloop  ParseError)
-    /// or (repo:query-planner     org:bloop  ParseError)
-    /// or (repo:enterprise-search org:google ParseError)
-    /// or (repo:query-planner     org:google ParseError)
-    /// ```
-    ///
-    /// The input argument `iter` expects an iterator, where each item is another iterator over a
-    /// set of queries joined with `or`. Because a list of queries is effectively an `or`, you can
-    /// interpret the input as being a list of `or` groups that are `and`ed together.
-    fn cross<I>(self, mut iter: I) -> SmallVec<[Self; 1]>
-    where
-        I: Clone + Iterator,
-        I::Item: Iterator<Item = Self>,
-    {
-        if let Some(queries) = iter.next() {
-            let mut list = smallvec![];
-
-            for rhs in queries {
-                list.extend(self.clone().merge(rhs).cross(iter.clone()));
-            }
-
-            list
-        } else {
-            smallvec![self]
-        }
-    }
-
     pub fn is_case_sensitive(&self) -> bool {
         // defaults to false if unset
         self.case_sensitive.unwrap_or_default()
@@ -179,6 +206,166 @@ impl<'a> Query<'a> {
             self.target.as_mut().map(Target::make_regex);
         }
     }
+
+    pub fn into_owned(self) -> Query<'static> {
+        Query {
+            open: self.open,
+            case_sensitive: self.case_sensitive,
+            global_regex: self.global_regex,
+            org: self.org.map(Literal::into_owned),
+            repo: self.repo.map(Literal::into_owned),
+            path: self.path.map(Literal::into_owned),
+            lang: self.lang.map(|c| c.into_owned().into()),
+            branch: self.branch.map(Literal::into_owned),
+            target: self.target.map(Target::into_owned),
+            kind: self.kind.map(|c| c.into_owned().into()),
+            before: self.before,
+            after: self.after,
+        }
+    }
+
+    /// The range of last commit times allowed by `before:` and `after:` labels, if either is set.
+    pub fn commit_range(&self) -> Option<Range<u64>> {
+        if self.before.is_none() && self.after.is_none() {
+            return None;
+        }
+
+        Some(self.after.unwrap_or(0)..self.before.unwrap_or(u64::MAX))
+    }
+
+    /// Count the labels set on this query, not including flags like `case:` or `open:`.
+    pub fn label_count(&self) -> usize {
+        [
+            self.org.is_some(),
+            self.repo.is_some(),
+            self.path.is_some(),
+            self.lang.is_some(),
+            self.branch.is_some(),
+            self.target.is_some(),
+            self.kind.is_some(),
+            self.before.is_some(),
+            self.after.is_some(),
+        ]
+        .into_iter()
+        .filter(|set| *set)
+        .count()
+    }
+}
+
+impl<T> Tree<T> {
+    fn and(mut trees: Vec<Self>) -> Self {
+        if trees.len() == 1 {
+            trees.pop().unwrap()
+        } else {
+            Self::And(trees)
+        }
+    }
+
+    fn or(mut trees: Vec<Self>) -> Self {
+        if trees.len() == 1 {
+            trees.pop().unwrap()
+        } else {
+            Self::Or(trees)
+        }
+    }
+
+    /// The alternatives of a top-level `or`, or just this tree if it isn't one.
+    ///
+    /// For example, `foo or repo:bar` has the alternatives `foo` and `repo:bar`, while
+    /// `(foo or repo:bar) path:src` is a single alternative.
+    pub fn disjuncts(&self) -> &[Self] {
+        match self {
+            Self::Or(trees) => trees,
+            tree => std::slice::from_ref(tree),
+        }
+    }
+
+    /// Iterate over all terms in this tree, in order, including negated ones.
+    pub fn terms(&self) -> impl Iterator<Item = &T> {
+        self.terms_with_negation().map(|(_, term)| term)
+    }
+
+    /// Iterate over the terms in this tree that are not negated.
+    pub fn positive_terms(&self) -> impl Iterator<Item = &T> {
+        self.terms_with_negation()
+            .filter(|(negated, _)| !negated)
+            .map(|(_, term)| term)
+    }
+
+    /// Iterate over the terms in this tree that are negated, e.g. `tests` in `-path:tests`.
+    pub fn negated_terms(&self) -> impl Iterator<Item = &T> {
+        self.terms_with_negation()
+            .filter(|(negated, _)| *negated)
+            .map(|(_, term)| term)
+    }
+
+    fn terms_with_negation(&self) -> impl Iterator<Item = (bool, &T)> {
+        let mut stack = vec![(false, self)];
+
+        std::iter::from_fn(move || loop {
+            match stack.pop()? {
+                (negated, Self::And(trees) | Self::Or(trees)) => {
+                    stack.extend(trees.iter().rev().map(|t| (negated, t)))
+                }
+                (negated, Self::Not(tree)) => stack.push((!negated, tree)),
+                (negated, Self::Term(term)) => return Some((negated, term)),
+            }
+        })
+    }
+
+    fn for_each_term_mut(&mut self, f: &mut impl FnMut(&mut T)) {
+        match self {
+            Self::And(trees) | Self::Or(trees) => {
+                trees.iter_mut().for_each(|t| t.for_each_term_mut(f))
+            }
+            Self::Not(tree) => tree.for_each_term_mut(f),
+            Self::Term(term) => f(term),
+        }
+    }
+
+    /// Keep the alternatives of this tree that pass `keep`, see [`Tree::disjuncts`].
+    pub fn filter_disjuncts(&self, mut keep: impl FnMut(&Self) -> bool) -> Option<Self>
+    where
+        T: Clone,
+    {
+        let trees = self
+            .disjuncts()
+            .iter()
+            .filter(|tree| keep(tree))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        (!trees.is_empty()).then(|| Self::or(trees))
+    }
+
+    /// Map every term in this tree, preserving its structure.
+    pub fn map<U>(&self, f: &mut impl FnMut(&T) -> U) -> Tree<U> {
+        match self {
+            Self::And(trees) => Tree::And(trees.iter().map(|t| t.map(f)).collect()),
+            Self::Or(trees) => Tree::Or(trees.iter().map(|t| t.map(f)).collect()),
+            Self::Not(tree) => Tree::Not(Box::new(tree.map(f))),
+            Self::Term(term) => Tree::Term(f(term)),
+        }
+    }
+
+    /// Check whether a document may match this tree.
+    ///
+    /// `check` tests a single term against the document, returning `None` if it can't tell,
+    /// e.g. because the term refers to a field it doesn't have access to. Such terms are assumed
+    /// to match when that could only add to the results, so this returns `false` only for
+    /// documents that definitely don't match.
+    pub fn matches(&self, check: &mut impl FnMut(&T) -> Option<bool>) -> bool {
+        self.matches_inner(check, false)
+    }
+
+    fn matches_inner(&self, check: &mut impl FnMut(&T) -> Option<bool>, negated: bool) -> bool {
+        match self {
+            Self::And(trees) => trees.iter().all(|t| t.matches_inner(check, negated)),
+            Self::Or(trees) => trees.iter().any(|t| t.matches_inner(check, negated)),
+            Self::Not(tree) => !tree.matches_inner(check, !negated),
+            Self::Term(term) => check(term).unwrap_or(!negated),
+        }
+    }
 }
 
 impl<'a> Target<'a> {
@@ -187,6 +374,8 @@ impl<'a> Target<'a> {
         match self {
             Self::Symbol(lit) => lit,
             Self::Content(lit) => lit,
+            Self::Structural(lit) => lit,
+            Self::Commit(lit) => lit,
         }
     }
 
@@ -194,15 +383,40 @@ impl<'a> Target<'a> {
     pub fn symbol(&self) -> Option<&Literal<'_>> {
         match self {
             Self::Symbol(lit) => Some(lit),
-            Self::Content(_) => None,
+            _ => None,
         }
     }
 
     /// Get the content literal, if present
     pub fn content(&self) -> Option<&Literal<'_>> {
         match self {
-            Self::Symbol(_) => None,
             Self::Content(lit) => Some(lit),
+            _ => None,
+        }
+    }
+
+    /// Get the commit message literal, if present
+    pub fn commit(&self) -> Option<&Literal<'_>> {
+        match self {
+            Self::Commit(lit) => Some(lit),
+            _ => None,
+        }
+    }
+
+    /// Get the tree-sitter query source, if present
+    pub fn structural(&self) -> Option<&Cow<'_, str>> {
+        match self {
+            Self::Structural(Literal::Plain(src) | Literal::Regex(src)) => Some(src),
+            _ => None,
+        }
+    }
+
+    pub fn into_owned(self) -> Target<'static> {
+        match self {
+            Self::Symbol(lit) => Target::Symbol(lit.into_owned()),
+            Self::Content(lit) => Target::Content(lit.into_owned()),
+            Self::Structural(lit) => Target::Structural(lit.into_owned()),
+            Self::Commit(lit) => Target::Commit(lit.into_owned()),
         }
     }
 
@@ -210,6 +424,10 @@ impl<'a> Target<'a> {
         match self {
             Self::Symbol(lit) => lit.make_regex(),
             Self::Content(lit) => lit.make_regex(),
+            Self::Commit(lit) => lit.make_regex(),
+
+            // tree-sitter queries are never matched as text
+            Self::Structural(_) => {}
         }
     }
 }
@@ -226,6 +444,8 @@ pub enum ParseError {
     UnparsedToken(String),
     #[error("multiple mode designators")]
     MultiMode,
+    #[error("`before:` and `after:` can only be excluded on their own, or with `lang:`")]
+    MixedDateExclusion,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Hash, serde::Serialize, serde::Deserialize)]
@@ -270,6 +490,14 @@ impl<'a> Literal<'a> {
         }
     }
 
+    /// Turn a tag name into the name it is indexed under, alongside branch names.
+    fn tag(self) -> Self {
+        match self {
+            Self::Plain(name) => Self::Plain(format!("tags/{name}").into()),
+            Self::Regex(name) => Self::Regex(format!("tags/{name}").into()),
+        }
+    }
+
     pub fn regex(&self) -> Result<Regex, regex::Error> {
         Regex::new(&self.regex_str())
     }
@@ -369,11 +597,18 @@ enum Expr<'a> {
     Org(Literal<'a>),
     Repo(Literal<'a>),
     Symbol(Literal<'a>),
+    Structural(Literal<'a>),
+    Commit(Literal<'a>),
     Path(Literal<'a>),
     Lang(Cow<'a, str>),
+    Kind(Cow<'a, str>),
+    Before(u64),
+    After(u64),
     Content(Literal<'a>),
     Branch(Literal<'a>),
 
+    Not(Box<Expr<'a>>),
+
     CaseSensitive(bool),
     Open(bool),
     GlobalRegex(bool),
@@ -397,9 +632,27 @@ impl<'a> Expr<'a> {
             Rule::path => Path(Literal::from(pair.into_inner().next().unwrap())),
             Rule::repo => Repo(Literal::from(pair.into_inner().next().unwrap())),
             Rule::symbol => Symbol(Literal::from(pair.into_inner().next().unwrap())),
+            Rule::structural => Structural(Literal::from(pair.into_inner().next().unwrap())),
+            Rule::commit => Commit(Literal::from(pair.into_inner().next().unwrap())),
             Rule::org => Org(Literal::from(pair.into_inner().next().unwrap())),
             Rule::branch => Branch(Literal::from(pair.into_inner().next().unwrap())),
+            Rule::tag => Branch(Literal::from(pair.into_inner().next().unwrap()).tag()),
             Rule::lang => Lang(pair.into_inner().as_str().into()),
+            Rule::kind => Kind(pair.into_inner().as_str().to_ascii_lowercase().into()),
+            Rule::before => Before(parse_date(&pair).ok_or(pair)?),
+            Rule::after => After(parse_date(&pair).ok_or(pair)?),
+
+            Rule::negated_label | Rule::negated_group => {
+                let inner = pair.clone().into_inner().next().unwrap();
+                let expr = Self::parse(inner, false)?;
+
+                // Flags and other labels cannot be negated.
+                if !expr.is_negatable() {
+                    return Err(pair);
+                }
+
+                Not(Box::new(expr))
+            }
 
             Rule::open => {
                 let inner = pair.into_inner().next().unwrap();
@@ -485,10 +738,30 @@ impl<'a> Expr<'a> {
             _ => Err(pair)?,
         })
     }
+
+    /// Whether this expression only contains labels that can be negated.
+    fn is_negatable(&self) -> bool {
+        use Expr::*;
+
+        match self {
+            Or(exprs) | And(exprs) => exprs.iter().all(Self::is_negatable),
+            Not(expr) => expr.is_negatable(),
+            Repo(_) | Path(_) | Lang(_) | Content(_) | Before(_) | After(_) => true,
+            _ => false,
+        }
+    }
 }
 
-/// Parse an input query string into a list of top-level `Query`s.
-pub fn parse(query: &str) -> Result<Vec<Query<'_>>, ParseError> {
+/// Parse the `YYYY-MM-DD` date in a `before:` or `after:` label, as the unix timestamp of the start
+/// of that day in UTC.
+fn parse_date(pair: &Pair<'_, Rule>) -> Option<u64> {
+    let date = chrono::NaiveDate::parse_from_str(pair.clone().into_inner().as_str(), "%Y-%m-%d");
+    let timestamp = date.ok()?.and_hms_opt(0, 0, 0)?.timestamp();
+    u64::try_from(timestamp).ok()
+}
+
+/// Parse an input query string into a tree of `Query` terms.
+pub fn parse(query: &str) -> Result<QueryTree<'_>, ParseError> {
     let pair = PestParser::parse(Rule::query, query)
         .map_err(Box::new)?
         .next()
@@ -496,18 +769,33 @@ pub fn parse(query: &str) -> Result<Vec<Query<'_>>, ParseError> {
     let root =
         Expr::parse(pair, true).map_err(|pair| ParseError::UnparsedToken(pair.to_string()))?;
 
-    let mut qs = flatten(root);
+    let mut tree = build(root);
+
+    // Excluded text is checked against the raw contents of documents, which don't include commit
+    // dates, so a term like `not (foo before:2023-01-01)` would silently exclude nothing.
+    if tree.negated_terms().any(|q| {
+        q.commit_range().is_some()
+            && (q.target.is_some() || q.path.is_some() || q.repo.is_some() || q.branch.is_some())
+    }) {
+        return Err(ParseError::MixedDateExclusion);
+    }
 
     // Find and redistribute global options.
-    let global_regex = qs.iter().fold(None, |a, e| e.global_regex.or(a));
-    let case_sensitive = qs.iter().fold(None, |a, e| e.case_sensitive.or(a));
+    let global_regex = tree.terms().fold(None, |a, e| e.global_regex.or(a));
+    let case_sensitive = tree.terms().fold(None, |a, e| e.case_sensitive.or(a));
 
-    for q in qs.iter_mut() {
+    tree.for_each_term_mut(&mut |q| {
         q.set_global_regex(global_regex);
         q.case_sensitive = case_sensitive;
+    });
+
+    // Terms that only held global options don't constrain the query any further.
+    if let Tree::And(trees) = &mut tree {
+        trees.retain(|t| !matches!(t, Tree::Term(q) if q.label_count() == 0 && q.open.is_none()));
+        tree = Tree::and(mem::take(trees));
     }
 
-    Ok(qs.into_vec())
+    Ok(tree)
 }
 
 pub fn parse_nl(query: &str) -> Result<ParsedQuery<'_>, ParseError> {
@@ -517,6 +805,9 @@ pub fn parse_nl(query: &str) -> Result<ParsedQuery<'_>, ParseError> {
     let mut paths = HashSet::new();
     let mut langs = HashSet::new();
     let mut branch = HashSet::new();
+    let mut not_repos = HashSet::new();
+    let mut not_paths = HashSet::new();
+    let mut not_langs = HashSet::new();
     let mut target: Option<Literal> = None;
     let mut force_parsing_as = None;
     for pair in pairs {
@@ -533,10 +824,34 @@ pub fn parse_nl(query: &str) -> Result<ParsedQuery<'_>, ParseError> {
                 let item = Literal::from(pair.into_inner().next().unwrap());
                 let _ = branch.insert(item);
             }
+            Rule::tag => {
+                let item = Literal::from(pair.into_inner().next().unwrap()).tag();
+                let _ = branch.insert(item);
+            }
             Rule::lang => {
                 let item = super::languages::parse_alias(pair.into_inner().as_str().into());
                 let _ = langs.insert(item);
             }
+            Rule::negated_label => {
+                let label = pair.into_inner().next().unwrap();
+                match label.as_rule() {
+                    Rule::repo => {
+                        let item = Literal::from(label.into_inner().next().unwrap());
+                        let _ = not_repos.insert(item);
+                    }
+                    Rule::path => {
+                        let item = Literal::from(label.into_inner().next().unwrap());
+                        let _ = not_paths.insert(item);
+                    }
+                    Rule::lang => {
+                        let item =
+                            super::languages::parse_alias(label.into_inner().as_str().into());
+                        let _ = not_langs.insert(item);
+                    }
+                    // Content can't be excluded from a semantic search.
+                    _ => {}
+                }
+            }
             Rule::raw_text => {
                 let rhs = Literal::from(pair);
                 if let Some(t) = target {
@@ -569,72 +884,127 @@ pub fn parse_nl(query: &str) -> Result<ParsedQuery<'_>, ParseError> {
             langs,
             branch,
             target,
+            not_repos,
+            not_paths,
+            not_langs,
         })),
     }
 }
 
-fn flatten(root: Expr<'_>) -> SmallVec<[Query<'_>; 1]> {
+/// Build a query tree from a parsed expression, merging adjacent terms.
+fn build(root: Expr<'_>) -> QueryTree<'_> {
     match root {
-        Expr::Repo(repo) => smallvec![Query {
+        Expr::Repo(repo) => Tree::Term(Query {
             repo: Some(repo),
             ..Default::default()
-        }],
-        Expr::Branch(branch) => smallvec![Query {
+        }),
+        Expr::Branch(branch) => Tree::Term(Query {
             branch: Some(branch),
             ..Default::default()
-        }],
-        Expr::Org(org) => smallvec![Query {
+        }),
+        Expr::Org(org) => Tree::Term(Query {
             org: Some(org),
             ..Default::default()
-        }],
-        Expr::Path(path) => smallvec![Query {
+        }),
+        Expr::Path(path) => Tree::Term(Query {
             path: Some(path),
             ..Default::default()
-        }],
+        }),
 
-        Expr::Symbol(sym) => smallvec![Query {
+        Expr::Symbol(sym) => Tree::Term(Query {
             target: Some(Target::Symbol(sym)),
             ..Default::default()
-        }],
-        Expr::Lang(lang) => smallvec![Query {
+        }),
+        Expr::Structural(src) => Tree::Term(Query {
+            target: Some(Target::Structural(src)),
+            ..Default::default()
+        }),
+        Expr::Commit(message) => Tree::Term(Query {
+            target: Some(Target::Commit(message)),
+            ..Default::default()
+        }),
+        Expr::Lang(lang) => Tree::Term(Query {
             lang: Some(super::languages::parse_alias(lang)),
             ..Default::default()
-        }],
-        Expr::Content(lit) => smallvec![Query {
+        }),
+        Expr::Kind(kind) => Tree::Term(Query {
+            kind: Some(kind),
+            ..Default::default()
+        }),
+        Expr::Before(before) => Tree::Term(Query {
+            before: Some(before),
+            ..Default::default()
+        }),
+        Expr::After(after) => Tree::Term(Query {
+            after: Some(after),
+            ..Default::default()
+        }),
+        Expr::Content(lit) => Tree::Term(Query {
             target: Some(Target::Content(lit)),
             ..Default::default()
-        }],
+        }),
 
-        Expr::CaseSensitive(case_sensitive) => smallvec![Query {
+        Expr::CaseSensitive(case_sensitive) => Tree::Term(Query {
             case_sensitive: Some(case_sensitive),
             ..Default::default()
-        }],
-        Expr::Open(open) => smallvec![Query {
+        }),
+        Expr::Open(open) => Tree::Term(Query {
             open: Some(open),
             ..Default::default()
-        }],
-        Expr::GlobalRegex(flag) => smallvec![Query {
+        }),
+        Expr::GlobalRegex(flag) => Tree::Term(Query {
             global_regex: Some(flag),
             ..Default::default()
-        }],
-        Expr::GlobalMode(_) => smallvec![Query {
+        }),
+        Expr::GlobalMode(_) => Tree::Term(Query {
             // we don't propagate this flag down to the query level!
             ..Default::default()
-        }],
+        }),
+
+        Expr::Or(exprs) => Tree::or(
+            exprs
+                .into_iter()
+                .map(build)
+                .flat_map(|tree| match tree {
+                    Tree::Or(trees) => trees,
+                    tree => vec![tree],
+                })
+                .collect(),
+        ),
+
+        // Terms that are joined together are merged into one, while nested groups are kept as
+        // they are.
+        Expr::And(exprs) => {
+            let mut term = None::<Query>;
+            let mut trees = Vec::new();
+
+            for tree in exprs.into_iter().map(build) {
+                let children = match tree {
+                    Tree::And(trees) => trees,
+                    tree => vec![tree],
+                };
 
-        // Simple merge
-        Expr::Or(exprs) => {
-            let mut queries = smallvec![];
-            for e in exprs {
-                queries.extend(flatten(e));
+                for child in children {
+                    match child {
+                        Tree::Term(rhs) => {
+                            term = Some(match term {
+                                Some(lhs) => lhs.merge(rhs),
+                                None => rhs,
+                            })
+                        }
+                        child => trees.push(child),
+                    }
+                }
             }
-            queries
-        }
 
-        // A more complex cross merge.
-        Expr::And(els) => {
-            Query::default().cross(els.iter().cloned().map(flatten).map(SmallVec::into_iter))
+            trees.splice(0..0, term.map(Tree::Term));
+            Tree::and(trees)
         }
+
+        Expr::Not(expr) => match build(*expr) {
+            Tree::Not(tree) => *tree,
+            tree => Tree::Not(Box::new(tree)),
+        },
     }
 }
 
@@ -647,58 +1017,58 @@ mod tests {
     fn basic_parse() {
         assert_eq!(
             parse("ParseError").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 target: Some(Target::Content(Literal::Plain("ParseError".into()))),
                 ..Query::default()
-            }],
+            }),
         );
 
         assert_eq!(
             parse("org:bloopai repo:enterprise-search branch:origin/main ParseError").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 repo: Some(Literal::Plain("enterprise-search".into())),
                 org: Some(Literal::Plain("bloopai".into())),
                 branch: Some(Literal::Plain("origin/main".into())),
                 target: Some(Target::Content(Literal::Plain("ParseError".into()))),
                 ..Query::default()
-            }],
+            }),
         );
 
         assert_eq!(
             parse("org:bloopai repo:enterprise-search ParseError").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 repo: Some(Literal::Plain("enterprise-search".into())),
                 org: Some(Literal::Plain("bloopai".into())),
                 target: Some(Target::Content(Literal::Plain("ParseError".into()))),
                 ..Query::default()
-            }],
+            }),
         );
 
         assert_eq!(
             parse("content:ParseError").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 target: Some(Target::Content(Literal::Plain("ParseError".into()))),
                 ..Query::default()
-            }],
+            }),
         );
 
         // Here the last target operator takes precedence. Should we return an error instead?
         assert_eq!(
             parse("path:foo.c create_foo symbol:bar").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 path: Some(Literal::Plain("foo.c".into())),
                 target: Some(Target::Symbol(Literal::Plain("bar".into()))),
                 ..Query::default()
-            }],
+            }),
         );
 
         assert_eq!(
             parse("case:ignore Parse").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 case_sensitive: Some(false),
                 target: Some(Target::Content(Literal::Plain("Parse".into()))),
                 ..Query::default()
-            }],
+            }),
         );
     }
 
@@ -706,47 +1076,47 @@ mod tests {
     fn test_force_parsing_mode_from_language() {
         assert_eq!(
             parse("repo:foo ParseError or repo:bar mode:grep").unwrap(),
-            vec![
-                Query {
+            Tree::Or(vec![
+                Tree::Term(Query {
                     repo: Some(Literal::Plain("foo".into())),
                     target: Some(Target::Content(Literal::Plain("ParseError".into()))),
                     ..Query::default()
-                },
-                Query {
+                }),
+                Tree::Term(Query {
                     repo: Some(Literal::Plain("bar".into())),
                     ..Query::default()
-                },
-            ],
+                })
+            ]),
         );
 
         assert_eq!(
             parse_nl("repo:foo ParseError or repo:bar mode:grep"),
-            Ok(ParsedQuery::Grep(vec![
-                Query {
+            Ok(ParsedQuery::Grep(Tree::Or(vec![
+                Tree::Term(Query {
                     repo: Some(Literal::Plain("foo".into())),
                     target: Some(Target::Content(Literal::Plain("ParseError".into()))),
                     ..Query::default()
-                },
-                Query {
+                }),
+                Tree::Term(Query {
                     repo: Some(Literal::Plain("bar".into())),
                     ..Query::default()
-                },
-            ])),
+                })
+            ]))),
         );
 
         assert_eq!(
             parse("repo:foo ParseError or repo:bar").unwrap(),
-            vec![
-                Query {
+            Tree::Or(vec![
+                Tree::Term(Query {
                     repo: Some(Literal::Plain("foo".into())),
                     target: Some(Target::Content(Literal::Plain("ParseError".into()))),
                     ..Query::default()
-                },
-                Query {
+                }),
+                Tree::Term(Query {
                     repo: Some(Literal::Plain("bar".into())),
                     ..Query::default()
-                },
-            ],
+                })
+            ]),
         );
 
         assert_eq!(
@@ -764,33 +1134,33 @@ mod tests {
     fn intersection_parse() {
         assert_eq!(
             parse("repo:foo ParseError or repo:bar").unwrap(),
-            vec![
-                Query {
+            Tree::Or(vec![
+                Tree::Term(Query {
                     repo: Some(Literal::Plain("foo".into())),
                     target: Some(Target::Content(Literal::Plain("ParseError".into()))),
                     ..Query::default()
-                },
-                Query {
+                }),
+                Tree::Term(Query {
                     repo: Some(Literal::Plain("bar".into())),
                     ..Query::default()
-                },
-            ],
+                })
+            ]),
         );
 
         // Flip the intersection order.
         assert_eq!(
             parse("repo:bar or repo:foo ParseError").unwrap(),
-            vec![
-                Query {
+            Tree::Or(vec![
+                Tree::Term(Query {
                     repo: Some(Literal::Plain("bar".into())),
                     ..Query::default()
-                },
-                Query {
+                }),
+                Tree::Term(Query {
                     repo: Some(Literal::Plain("foo".into())),
                     target: Some(Target::Content(Literal::Plain("ParseError".into()))),
                     ..Query::default()
-                },
-            ],
+                })
+            ]),
         );
     }
 
@@ -798,14 +1168,13 @@ mod tests {
     fn complex_nested_combinators_expr() {
         // (((repo:foo xyz) or repo:abc) (repo:fred or repo:grub) org:bloop)
         //
-        // -> 4 independent terms [
-        //    (org:bloop repo:fred xyz),
-        //    (org:bloop repo:grub xyz),
-        //    (org:bloop repo:fred),
-        //    (org:bloop repo:grub),
+        // -> a tree of [
+        //    org:bloop
+        //    and ((repo:foo xyz) or repo:abc)
+        //    and (repo:fred or repo:grub)
         // ]
 
-        let terms = flatten(Expr::And(vec![
+        let tree = build(Expr::And(vec![
             Expr::Or(vec![
                 Expr::And(vec![
                     Expr::Repo(Literal::Plain("foo".into())),
@@ -821,31 +1190,34 @@ mod tests {
         ]));
 
         assert_eq!(
-            terms.into_vec(),
-            vec![
-                Query {
-                    repo: Some(Literal::Plain("fred".into())),
-                    org: Some(Literal::Plain("bloop".into())),
-                    target: Some(Target::Content(Literal::Plain("xyz".into()))),
-                    ..Query::default()
-                },
-                Query {
-                    repo: Some(Literal::Plain("grub".into())),
-                    org: Some(Literal::Plain("bloop".into())),
-                    target: Some(Target::Content(Literal::Plain("xyz".into()))),
-                    ..Query::default()
-                },
-                Query {
-                    repo: Some(Literal::Plain("fred".into())),
-                    org: Some(Literal::Plain("bloop".into())),
-                    ..Query::default()
-                },
-                Query {
-                    repo: Some(Literal::Plain("grub".into())),
+            tree,
+            Tree::And(vec![
+                Tree::Term(Query {
                     org: Some(Literal::Plain("bloop".into())),
                     ..Query::default()
-                },
-            ]
+                }),
+                Tree::Or(vec![
+                    Tree::Term(Query {
+                        repo: Some(Literal::Plain("foo".into())),
+                        target: Some(Target::Content(Literal::Plain("xyz".into()))),
+                        ..Query::default()
+                    }),
+                    Tree::Term(Query {
+                        repo: Some(Literal::Plain("abc".into())),
+                        ..Query::default()
+                    }),
+                ]),
+                Tree::Or(vec![
+                    Tree::Term(Query {
+                        repo: Some(Literal::Plain("fred".into())),
+                        ..Query::default()
+                    }),
+                    Tree::Term(Query {
+                        repo: Some(Literal::Plain("grub".into())),
+                        ..Query::default()
+                    }),
+                ]),
+            ])
         );
     }
 
@@ -853,74 +1225,273 @@ mod tests {
     fn complex_nested_combinators_parse() {
         assert_eq!(
             parse("(((repo:foo xyz) or repo:abc) (repo:fred or repo:grub) org:bloop)").unwrap(),
-            vec![
-                Query {
-                    repo: Some(Literal::Plain("fred".into())),
-                    org: Some(Literal::Plain("bloop".into())),
-                    target: Some(Target::Content(Literal::Plain("xyz".into()))),
-                    ..Query::default()
-                },
-                Query {
-                    repo: Some(Literal::Plain("grub".into())),
-                    org: Some(Literal::Plain("bloop".into())),
-                    target: Some(Target::Content(Literal::Plain("xyz".into()))),
-                    ..Query::default()
-                },
-                Query {
-                    repo: Some(Literal::Plain("fred".into())),
+            Tree::And(vec![
+                Tree::Term(Query {
                     org: Some(Literal::Plain("bloop".into())),
                     ..Query::default()
-                },
-                Query {
-                    repo: Some(Literal::Plain("grub".into())),
-                    org: Some(Literal::Plain("bloop".into())),
-                    ..Query::default()
-                },
-            ],
+                }),
+                Tree::Or(vec![
+                    Tree::Term(Query {
+                        repo: Some(Literal::Plain("foo".into())),
+                        target: Some(Target::Content(Literal::Plain("xyz".into()))),
+                        ..Query::default()
+                    }),
+                    Tree::Term(Query {
+                        repo: Some(Literal::Plain("abc".into())),
+                        ..Query::default()
+                    }),
+                ]),
+                Tree::Or(vec![
+                    Tree::Term(Query {
+                        repo: Some(Literal::Plain("fred".into())),
+                        ..Query::default()
+                    }),
+                    Tree::Term(Query {
+                        repo: Some(Literal::Plain("grub".into())),
+                        ..Query::default()
+                    }),
+                ]),
+            ]),
         );
     }
 
+    #[test]
+    fn many_groups_stay_linear() {
+        let query = (0..32)
+            .map(|i| format!("(repo:a{i} or repo:b{i})"))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let tree = parse(&query).unwrap();
+        assert_eq!(tree.terms().count(), 64);
+
+        let Tree::And(groups) = tree else {
+            panic!("expected an intersection")
+        };
+        assert_eq!(groups.len(), 32);
+    }
+
     #[test]
     fn complex_multiple_parse_types() {
         assert_eq!(
             parse("(repo:bloop or repo:google) Parser or repo:zoekt Parsing or (symbol:Compiler or (org:bloop repo:enterprise-search))").unwrap(),
-            vec![
-                Query {
-                    repo: Some(Literal::Plain("bloop".into())),
-                    target: Some(Target::Content(Literal::Plain("Parser".into()))),
-                    ..Query::default()
-                },
-                Query {
-                    repo: Some(Literal::Plain("google".into())),
-                    target: Some(Target::Content(Literal::Plain("Parser".into()))),
-                    ..Query::default()
-                },
-                Query {
+            Tree::Or(vec![
+                Tree::And(vec![
+                    Tree::Term(Query {
+                        target: Some(Target::Content(Literal::Plain("Parser".into()))),
+                        ..Query::default()
+                    }),
+                    Tree::Or(vec![
+                        Tree::Term(Query {
+                            repo: Some(Literal::Plain("bloop".into())),
+                            ..Query::default()
+                        }),
+                        Tree::Term(Query {
+                            repo: Some(Literal::Plain("google".into())),
+                            ..Query::default()
+                        }),
+                    ]),
+                ]),
+                Tree::Term(Query {
                     repo: Some(Literal::Plain("zoekt".into())),
                     target: Some(Target::Content(Literal::Plain("Parsing".into()))),
                     ..Query::default()
-                },
-                Query {
+                }),
+                Tree::Term(Query {
                     target: Some(Target::Symbol(Literal::Plain("Compiler".into()))),
                     ..Query::default()
-                },
-                Query {
+                }),
+                Tree::Term(Query {
                     repo: Some(Literal::Plain("enterprise-search".into())),
                     org: Some(Literal::Plain("bloop".into())),
                     ..Query::default()
-                },
-            ],
+                }),
+            ]),
         );
     }
 
+    #[test]
+    fn negated_labels() {
+        let not = |q| Tree::Not(Box::new(Tree::Term(q)));
+
+        assert_eq!(
+            parse("unwrap -path:tests -path:vendor -lang:rust -repo:bloop -content:expect")
+                .unwrap(),
+            Tree::And(vec![
+                Tree::Term(Query {
+                    target: Some(Target::Content(Literal::Plain("unwrap".into()))),
+                    ..Query::default()
+                }),
+                not(Query {
+                    path: Some(Literal::Plain("tests".into())),
+                    ..Query::default()
+                }),
+                not(Query {
+                    path: Some(Literal::Plain("vendor".into())),
+                    ..Query::default()
+                }),
+                not(Query {
+                    lang: Some("rust".into()),
+                    ..Query::default()
+                }),
+                not(Query {
+                    repo: Some(Literal::Plain("bloop".into())),
+                    ..Query::default()
+                }),
+                not(Query {
+                    target: Some(Target::Content(Literal::Plain("expect".into()))),
+                    ..Query::default()
+                }),
+            ]),
+        );
+
+        // A dash that is not followed by a negatable label is still a literal.
+        assert_eq!(
+            parse("-foo").unwrap(),
+            Tree::Term(Query {
+                target: Some(Target::Content(Literal::Plain("-foo".into()))),
+                ..Query::default()
+            }),
+        );
+
+        assert_eq!(
+            parse("-symbol:foo").unwrap(),
+            Tree::Term(Query {
+                target: Some(Target::Content(Literal::Plain("-symbol:foo".into()))),
+                ..Query::default()
+            }),
+        );
+    }
+
+    #[test]
+    fn negated_groups() {
+        let unwrap = || {
+            Tree::Term(Query {
+                target: Some(Target::Content(Literal::Plain("unwrap".into()))),
+                ..Query::default()
+            })
+        };
+
+        assert_eq!(
+            parse("unwrap not (path:tests or path:vendor)").unwrap(),
+            Tree::And(vec![
+                unwrap(),
+                Tree::Not(Box::new(Tree::Or(vec![
+                    Tree::Term(Query {
+                        path: Some(Literal::Plain("tests".into())),
+                        ..Query::default()
+                    }),
+                    Tree::Term(Query {
+                        path: Some(Literal::Plain("vendor".into())),
+                        ..Query::default()
+                    }),
+                ]))),
+            ]),
+        );
+
+        assert_eq!(
+            parse("unwrap not (path:tests lang:rust)").unwrap(),
+            Tree::And(vec![
+                unwrap(),
+                Tree::Not(Box::new(Tree::Term(Query {
+                    path: Some(Literal::Plain("tests".into())),
+                    lang: Some("rust".into()),
+                    ..Query::default()
+                }))),
+            ]),
+        );
+
+        // Content terms are negated as a whole.
+        assert_eq!(
+            parse("unwrap not (foo bar)").unwrap(),
+            Tree::And(vec![
+                unwrap(),
+                Tree::Not(Box::new(Tree::Term(Query {
+                    target: Some(Target::Content(Literal::Regex("foo\\s+bar".into()))),
+                    ..Query::default()
+                }))),
+            ]),
+        );
+
+        // Double negation.
+        assert_eq!(
+            parse("not (-path:foo)").unwrap(),
+            parse("path:foo").unwrap()
+        );
+
+        // `not` on its own is a regular literal.
+        assert_eq!(
+            parse("not found").unwrap(),
+            Tree::Term(Query {
+                target: Some(Target::Content(Literal::Regex("not\\s+found".into()))),
+                ..Query::default()
+            }),
+        );
+
+        // Only some labels can be negated.
+        assert!(parse("not (symbol:foo)").is_err());
+        assert!(parse("not (repo:foo or org:bar)").is_err());
+
+        // Exclusions are made into regexes along with the rest of the query.
+        assert_eq!(
+            parse("global_regex:true foo -path:bar").unwrap(),
+            Tree::And(vec![
+                Tree::Term(Query {
+                    global_regex: Some(true),
+                    target: Some(Target::Content(Literal::Regex("foo".into()))),
+                    ..Query::default()
+                }),
+                Tree::Not(Box::new(Tree::Term(Query {
+                    global_regex: Some(true),
+                    path: Some(Literal::Regex("bar".into())),
+                    ..Query::default()
+                }))),
+            ]),
+        );
+    }
+
+    #[test]
+    fn tree_matches() {
+        let tree = parse("foo not (bar path:tests) or quux").unwrap();
+
+        assert_eq!(
+            tree.positive_terms()
+                .filter_map(|q| q.target.as_ref())
+                .collect::<Vec<_>>(),
+            vec![
+                &Target::Content(Literal::Plain("foo".into())),
+                &Target::Content(Literal::Plain("quux".into())),
+            ]
+        );
+        assert_eq!(tree.negated_terms().count(), 1);
+
+        // Check terms against content only, so that `path:tests` is unknown.
+        let matches = |content: &str| {
+            tree.matches(&mut |q: &Query<'_>| {
+                let found = content.contains(q.target.as_ref()?.literal().regex_str().as_ref());
+                match q.path {
+                    Some(_) if found => None,
+                    _ => Some(found),
+                }
+            })
+        };
+
+        assert!(matches("foo"));
+        assert!(matches("quux"));
+        assert!(!matches("bar"));
+
+        // This might not be in `tests`, so the document can't be ruled out.
+        assert!(matches("foo bar"));
+    }
+
     #[test]
     fn slash_in_path() {
         assert_eq!(
             parse("path:foo/bar.js").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 path: Some(Literal::Plain("foo/bar.js".into())),
                 ..Query::default()
-            }],
+            }),
         );
     }
 
@@ -941,41 +1512,189 @@ mod tests {
     fn lang_path_filter() {
         assert_eq!(
             parse("lang:Rust path:server").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 path: Some(Literal::Plain("server".into())),
                 lang: Some("rust".into()),
                 ..Query::default()
-            }],
+            }),
+        );
+    }
+
+    #[test]
+    fn symbol_kind() {
+        assert_eq!(
+            parse("symbol:Parser kind:Struct").unwrap(),
+            Tree::Term(Query {
+                target: Some(Target::Symbol(Literal::Plain("Parser".into()))),
+                kind: Some("struct".into()),
+                ..Query::default()
+            }),
+        );
+
+        assert_eq!(
+            parse("(symbol:Config kind:struct) or (symbol:config kind:function)").unwrap(),
+            Tree::Or(vec![
+                Tree::Term(Query {
+                    target: Some(Target::Symbol(Literal::Plain("Config".into()))),
+                    kind: Some("struct".into()),
+                    ..Query::default()
+                }),
+                Tree::Term(Query {
+                    target: Some(Target::Symbol(Literal::Plain("config".into()))),
+                    kind: Some("function".into()),
+                    ..Query::default()
+                }),
+            ]),
+        );
+    }
+
+    #[test]
+    fn commit_dates() {
+        assert_eq!(
+            parse("after:2023-01-01 before:2023-02-01 path:src").unwrap(),
+            Tree::Term(Query {
+                path: Some(Literal::Plain("src".into())),
+                after: Some(1672531200),
+                before: Some(1675209600),
+                ..Query::default()
+            }),
+        );
+
+        assert_eq!(
+            parse("after:2023-01-01")
+                .unwrap()
+                .terms()
+                .next()
+                .unwrap()
+                .commit_range(),
+            Some(1672531200..u64::MAX),
+        );
+
+        // Dates that don't exist are rejected.
+        assert!(parse("after:2023-02-30").is_err());
+
+        // Dates can only be excluded along with labels the index matches exactly.
+        assert!(parse("foo not (before:2023-01-01 lang:rust)").is_ok());
+        assert!(matches!(
+            parse("foo not (bar before:2023-01-01)"),
+            Err(ParseError::MixedDateExclusion)
+        ));
+
+        // Anything that doesn't look like a date is searched for as text.
+        assert_eq!(
+            parse("before:yesterday").unwrap(),
+            Tree::Term(Query {
+                target: Some(Target::Content(Literal::Plain("before:yesterday".into()))),
+                ..Query::default()
+            }),
+        );
+    }
+
+    #[test]
+    fn tags() {
+        assert_eq!(
+            parse("tag:v1.2.0 Parser").unwrap(),
+            Tree::Term(Query {
+                branch: Some(Literal::Plain("tags/v1.2.0".into())),
+                target: Some(Target::Content(Literal::Plain("Parser".into()))),
+                ..Query::default()
+            }),
+        );
+
+        assert_eq!(
+            parse("tag:/v1\\.[0-9]+/").unwrap(),
+            Tree::Term(Query {
+                branch: Some(Literal::Regex("tags/v1\\.[0-9]+".into())),
+                ..Query::default()
+            }),
+        );
+
+        // `branch:` matches branches and tags alike
+        assert_eq!(
+            parse("branch:tags/v1.2.0").unwrap(),
+            Tree::Term(Query {
+                branch: Some(Literal::Plain("tags/v1.2.0".into())),
+                ..Query::default()
+            }),
+        );
+    }
+
+    #[test]
+    fn commit_target() {
+        assert_eq!(
+            parse("commit:retry path:src/backoff.rs").unwrap(),
+            Tree::Term(Query {
+                path: Some(Literal::Plain("src/backoff.rs".into())),
+                target: Some(Target::Commit(Literal::Plain("retry".into()))),
+                ..Query::default()
+            }),
+        );
+
+        assert_eq!(
+            parse("commit:/retr(y|ies)/ after:2023-01-01").unwrap(),
+            Tree::Term(Query {
+                target: Some(Target::Commit(Literal::Regex("retr(y|ies)".into()))),
+                after: Some(1672531200),
+                ..Query::default()
+            }),
+        );
+    }
+
+    #[test]
+    fn structural_target() {
+        assert_eq!(
+            parse(r#"lang:rust struct:'(call_expression function: (identifier) @f (#eq? @f "unwrap"))'"#)
+                .unwrap(),
+            Tree::Term(Query {
+                lang: Some("rust".into()),
+                target: Some(Target::Structural(Literal::Plain(
+                    r#"(call_expression function: (identifier) @f (#eq? @f "unwrap"))"#.into()
+                ))),
+                ..Query::default()
+            }),
+        );
+
+        // Tree-sitter queries are left alone by `global_regex`.
+        assert_eq!(
+            parse("global_regex:true struct:'(struct_item)'").unwrap(),
+            Tree::Term(Query {
+                global_regex: Some(true),
+                target: Some(Target::Structural(Literal::Plain("(struct_item)".into()))),
+                ..Query::default()
+            }),
         );
+
+        // They can't be used to exclude files.
+        assert!(parse("not (struct:'(struct_item)')").is_err());
     }
 
     #[test]
     fn enable_open() {
         assert_eq!(
             parse("open:true path:server/bleep/Cargo.toml").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 open: Some(true),
                 path: Some(Literal::Plain("server/bleep/Cargo.toml".into())),
                 ..Query::default()
-            }],
+            }),
         );
 
         assert_eq!(
             parse("open:false path:server/bleep/Cargo.toml").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 open: Some(false),
                 path: Some(Literal::Plain("server/bleep/Cargo.toml".into())),
                 ..Query::default()
-            }],
+            }),
         );
 
         assert_eq!(
             parse("path:server/bleep/Cargo.toml").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 open: None,
                 path: Some(Literal::Plain("server/bleep/Cargo.toml".into())),
                 ..Query::default()
-            }],
+            }),
         );
     }
 
@@ -983,20 +1702,20 @@ mod tests {
     fn special_chars() {
         assert_eq!(
             parse("foo\\nbar\\tquux").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 target: Some(Target::Content(Literal::Plain("foo\\nbar\\tquux".into()))),
                 ..Query::default()
-            }],
+            }),
         );
 
         assert_eq!(
             parse("/^\\b\\B\\w\\Wfoo\\d\\D$/").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 target: Some(Target::Content(Literal::Regex(
                     "^\\b\\B\\w\\Wfoo\\d\\D$".into()
                 ))),
                 ..Query::default()
-            }],
+            }),
         );
     }
 
@@ -1004,30 +1723,30 @@ mod tests {
     fn test_global_regex() {
         assert_eq!(
             parse("global_regex:true foo").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 global_regex: Some(true),
                 target: Some(Target::Content(Literal::Regex("foo".into()))),
                 ..Query::default()
-            }],
+            }),
         );
 
         // Don't conflict with per-term regexes.
         assert_eq!(
             parse("global_regex:true /foo/").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 global_regex: Some(true),
                 target: Some(Target::Content(Literal::Regex("foo".into()))),
                 ..Query::default()
-            }],
+            }),
         );
 
         // Lack of the flag should result in a `None` value.
         assert_eq!(
             parse("foo").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 target: Some(Target::Content(Literal::Plain("foo".into()))),
                 ..Query::default()
-            }],
+            }),
         );
 
         // Can only apply this flag at the top-level, not inside groups.
@@ -1036,39 +1755,36 @@ mod tests {
         // Later uses at the top-level override previous uses.
         assert_eq!(
             parse("global_regex:false org:bloopai repo:bloop path:server foo or repo:google bar global_regex:true").unwrap(),
-            vec![
-                Query {
+            Tree::Or(vec![Tree::Term(Query {
                     global_regex: Some(true),
                     org: Some(Literal::Regex("bloopai".into())),
                     repo: Some(Literal::Regex("bloop".into())),
                     path: Some(Literal::Regex("server".into())),
                     target: Some(Target::Content(Literal::Regex("foo".into()))),
                     ..Query::default()
-                },
-                Query {
+                }), Tree::Term(Query {
                     global_regex: Some(true),
                     repo: Some(Literal::Regex("google".into())),
                     target: Some(Target::Content(Literal::Regex("bar".into()))),
                     ..Query::default()
-                },
-            ],
+                })]),
         );
 
         // Make sure that later values of `false` override previous values of `true`.
         assert_eq!(
             parse("global_regex:true foo or bar global_regex:false").unwrap(),
-            vec![
-                Query {
+            Tree::Or(vec![
+                Tree::Term(Query {
                     global_regex: Some(false),
                     target: Some(Target::Content(Literal::Plain("foo".into()))),
                     ..Query::default()
-                },
-                Query {
+                }),
+                Tree::Term(Query {
                     global_regex: Some(false),
                     target: Some(Target::Content(Literal::Plain("bar".into()))),
                     ..Query::default()
-                },
-            ],
+                })
+            ]),
         );
     }
 
@@ -1078,18 +1794,18 @@ mod tests {
 
         assert_eq!(
             parse("foo or bar case:ignore").unwrap(),
-            vec![
-                Query {
+            Tree::Or(vec![
+                Tree::Term(Query {
                     case_sensitive: Some(false),
                     target: Some(Target::Content(Literal::Plain("foo".into()))),
                     ..Query::default()
-                },
-                Query {
+                }),
+                Tree::Term(Query {
                     case_sensitive: Some(false),
                     target: Some(Target::Content(Literal::Plain("bar".into()))),
                     ..Query::default()
-                },
-            ],
+                })
+            ]),
         );
 
         assert_eq!(
@@ -1107,24 +1823,24 @@ mod tests {
     fn or_prefix() {
         assert_eq!(
             parse("org").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 target: Some(Target::Content(Literal::Plain("org".into()))),
                 ..Query::default()
-            },],
+            }),
         );
 
         assert_eq!(
             parse("org or orange").unwrap(),
-            vec![
-                Query {
+            Tree::Or(vec![
+                Tree::Term(Query {
                     target: Some(Target::Content(Literal::Plain("org".into()))),
                     ..Query::default()
-                },
-                Query {
+                }),
+                Tree::Term(Query {
                     target: Some(Target::Content(Literal::Plain("orange".into()))),
                     ..Query::default()
-                },
-            ],
+                })
+            ]),
         );
     }
 
@@ -1132,24 +1848,24 @@ mod tests {
     fn or_suffix() {
         assert_eq!(
             parse("for").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 target: Some(Target::Content(Literal::Plain("for".into()))),
                 ..Query::default()
-            },],
+            }),
         );
 
         assert_eq!(
             parse("for or error").unwrap(),
-            vec![
-                Query {
+            Tree::Or(vec![
+                Tree::Term(Query {
                     target: Some(Target::Content(Literal::Plain("for".into()))),
                     ..Query::default()
-                },
-                Query {
+                }),
+                Tree::Term(Query {
                     target: Some(Target::Content(Literal::Plain("error".into()))),
                     ..Query::default()
-                },
-            ],
+                })
+            ]),
         );
     }
 
@@ -1157,14 +1873,12 @@ mod tests {
     fn test_complex_parse() {
         let mut q = parse(r#"(?:[a-z0-9!#$%&'*+\/=?^_`{|}~-]+(?:\.[a-z0-9!#$%&'*+\/=?^_`{|}~-]+)*|"(?:[\x01-\x08\x0b\x0c\x0e-\x1f\x21\x23-\x5b\x5d-\x7f]|\\[\x01-\x09\x0b\x0c\x0e-\x7f])*")@(?:(?:[a-z0-9](?:[a-z0-9-]*[a-z0-9])?\.)+[a-z0-9](?:[a-z0-9-]*[a-z0-9])?|\[(?:(?:(2(5[0-5]|[0-4][0-9])|1[0-9][0-9]|[1-9]?[0-9]))\.){3}(?:(2(5[0-5]|[0-4][0-9])|1[0-9][0-9]|[1-9]?[0-9])|[a-z0-9-]*[a-z0-9]:(?:[\x01-\x08\x0b\x0c\x0e-\x1f\x21-\x5a\x53-\x7f]|\\[\x01-\x09\x0b\x0c\x0e-\x7f])+)\])"#).unwrap();
 
+        let Tree::Term(q) = &mut q else {
+            panic!("expected a single term")
+        };
+
         // Make sure that this regex successfully compiles.
-        q[0].target
-            .take()
-            .unwrap()
-            .content()
-            .unwrap()
-            .regex()
-            .unwrap();
+        q.target.take().unwrap().content().unwrap().regex().unwrap();
     }
 
     #[test]
@@ -1176,7 +1890,8 @@ mod tests {
                 langs: ["tsx".into()].into(),
                 repos: [Literal::Plain("bloop".into())].into(),
                 paths: [].into(),
-                branch: [].into()
+                branch: [].into(),
+                ..Default::default()
             }),
         );
     }
@@ -1206,6 +1921,7 @@ mod tests {
                 ]
                 .into(),
                 paths: [Literal::Plain("server/bleep".into())].into(),
+                ..Default::default()
             })
         );
     }
@@ -1223,6 +1939,7 @@ mod tests {
                 repos: [Literal::Plain("bloop".into())].into(),
                 paths: [].into(),
                 branch: [].into(),
+                ..Default::default()
             })
         );
 
@@ -1238,6 +1955,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn nl_parse_negations() {
+        assert_eq!(
+            parse_nl("where do we retry requests? repo:bloop -path:tests -lang:js -content:foo")
+                .unwrap(),
+            ParsedQuery::Semantic(SemanticQuery {
+                target: Some(Literal::Plain("where do we retry requests?".into())),
+                repos: [Literal::Plain("bloop".into())].into(),
+                not_paths: [Literal::Plain("tests".into())].into(),
+                not_langs: ["javascript".into()].into(),
+                ..Default::default()
+            })
+        );
+    }
+
     // NL queries should permit arbitrary text in the `target` field, such as `(` and `|`
     #[test]
     fn nl_parse_arbitrary_text() {
@@ -1255,26 +1987,26 @@ mod tests {
     fn escape_characters() {
         assert_eq!(
             parse("'foo\\'bar'").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 target: Some(Target::Content(Literal::Plain("foo'bar".into()))),
                 ..Query::default()
-            }],
+            }),
         );
 
         assert_eq!(
             parse(r#""foo\"bar""#).unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 target: Some(Target::Content(Literal::Plain("foo\"bar".into()))),
                 ..Query::default()
-            }],
+            }),
         );
 
         assert_eq!(
             parse("/foo\\/bar/").unwrap(),
-            vec![Query {
+            Tree::Term(Query {
                 target: Some(Target::Content(Literal::Regex("foo/bar".into()))),
                 ..Query::default()
-            }],
+            }),
         );
     }
 }
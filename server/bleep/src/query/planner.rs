@@ -89,7 +89,7 @@ fn step(hir: Hir) -> Result<Fragment, Error> {
     Ok(fragment)
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
 pub enum Fragment {
     /// A dense fragment.
     ///
@@ -222,7 +222,7 @@ impl Display for Fragment {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Default, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Default, Copy, Clone, serde::Serialize)]
 pub enum Op {
     #[default]
     And,
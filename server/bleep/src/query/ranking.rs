@@ -27,7 +27,7 @@ impl ScoreSegmentTweaker<Score> for SegmentScorer {
             .unwrap()
             .as_secs()
             .saturating_sub(self.last_commit.get_val(doc))
-            .min(5_000_000) as f32;
+            .clamp(1, 5_000_000) as f32;
 
         score
     }
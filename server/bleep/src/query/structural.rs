@@ -0,0 +1,316 @@
+//! Narrowing down candidates for structural (tree-sitter) queries.
+//!
+//! A tree-sitter query can only be run once a file has been parsed, which requires knowing its
+//! language. Instead of parsing every file in the index, we pull out the text that any match of
+//! the query is guaranteed to contain, and look that up in the trigram index first.
+
+use super::planner::{self, Fragment, Op};
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token<'a> {
+    Open,
+    Close,
+    Str(String),
+    Atom(&'a str),
+}
+
+/// Plan a trigram lookup for a tree-sitter query.
+///
+/// Text is pulled from `#eq?` and `#match?` predicates, as well as anonymous nodes like `"fn"`.
+/// Anything that may be missing from a match, e.g. a node under an alternation or an optional
+/// quantifier, is skipped. A query that doesn't require any text is planned as a `Break`.
+pub fn plan(query: &str) -> Fragment {
+    let tokens = tokenize(query);
+    let mut patterns = Vec::new();
+    let mut rest = &tokens[..];
+
+    while !rest.is_empty() {
+        let len = unit_len(rest);
+        let (pattern, tail) = rest.split_at(len);
+        patterns.push(plan_pattern(pattern));
+        rest = tail;
+    }
+
+    // Top-level patterns are alternatives, so a single pattern without any text means that we
+    // can't narrow anything down.
+    if patterns.is_empty() || patterns.contains(&Fragment::Break) {
+        return Fragment::Break;
+    }
+
+    dense(Op::Or, patterns)
+}
+
+fn dense(op: Op, mut fragments: Vec<Fragment>) -> Fragment {
+    if fragments.len() == 1 {
+        fragments.pop().unwrap()
+    } else {
+        Fragment::Dense(op, fragments)
+    }
+}
+
+/// Plan a single top-level pattern.
+fn plan_pattern(tokens: &[Token<'_>]) -> Fragment {
+    let mut pattern = Pattern::default();
+    pattern.walk(tokens, false);
+
+    let mut required = pattern.strings;
+
+    for (name, args) in &pattern.predicates {
+        let (capture, text) = match &args[..] {
+            [Token::Atom(capture), Token::Str(text)] | [Token::Str(text), Token::Atom(capture)]
+                if capture.starts_with('@') =>
+            {
+                (&capture[1..], text)
+            }
+            _ => continue,
+        };
+
+        // If a capture isn't part of a match, tree-sitter considers its predicates satisfied.
+        let mut occurrences = pattern
+            .captures
+            .iter()
+            .filter(|(c, _)| *c == capture)
+            .peekable();
+
+        if occurrences.peek().is_none() || !occurrences.all(|(_, required)| *required) {
+            continue;
+        }
+
+        match *name {
+            "#eq?" if !text.is_empty() => required.push(Fragment::Literal(text.clone())),
+            "#match?" => match planner::plan(text) {
+                Ok(Fragment::Break) | Err(_) => {}
+                Ok(fragment) => required.push(fragment),
+            },
+            _ => {}
+        }
+    }
+
+    if required.is_empty() {
+        Fragment::Break
+    } else {
+        dense(Op::And, required)
+    }
+}
+
+#[derive(Default)]
+struct Pattern<'a, 't> {
+    /// Text of anonymous nodes that every match contains.
+    strings: Vec<Fragment>,
+
+    /// Captures, alongside whether they are always part of a match.
+    captures: Vec<(&'a str, bool)>,
+
+    /// Predicates, by name, alongside their arguments.
+    predicates: Vec<(&'a str, &'t [Token<'a>])>,
+}
+
+impl<'a, 't> Pattern<'a, 't> {
+    fn walk(&mut self, mut tokens: &'t [Token<'a>], optional: bool) {
+        while !tokens.is_empty() {
+            let len = unit_len(tokens);
+            let (unit, tail) = tokens.split_at(len);
+            tokens = tail;
+
+            // Split off trailing quantifiers and captures.
+            let suffix_start = unit
+                .iter()
+                .rposition(|t| !is_suffix(t))
+                .map(|i| i + 1)
+                .unwrap_or(0);
+            let (node, suffix) = unit.split_at(suffix_start);
+
+            let optional = optional
+                || suffix
+                    .iter()
+                    .any(|t| matches!(t, Token::Atom(a) if a.starts_with(['?', '*'])));
+
+            for token in suffix {
+                if let Token::Atom(a) = token {
+                    if let Some(capture) = a.strip_prefix('@') {
+                        self.captures.push((capture, !optional));
+                    }
+                }
+            }
+
+            match node {
+                // An alternation only requires one of its branches to match.
+                [Token::Atom("["), inner @ .., Token::Close] => self.walk(inner, true),
+
+                [Token::Open, Token::Atom(name), args @ .., Token::Close]
+                    if name.starts_with('#') =>
+                {
+                    self.predicates.push((name, args));
+                }
+
+                [Token::Open, inner @ .., Token::Close] => self.walk(inner, optional),
+
+                [Token::Str(text)] if !optional && !text.is_empty() => {
+                    self.strings.push(Fragment::Literal(text.clone()))
+                }
+
+                _ => {}
+            }
+        }
+    }
+}
+
+fn is_suffix(token: &Token<'_>) -> bool {
+    matches!(token, Token::Atom(a) if a.starts_with(['?', '*', '+', '@']))
+}
+
+/// The number of tokens that make up the next node, including quantifiers and captures that
+/// follow it.
+fn unit_len(tokens: &[Token<'_>]) -> usize {
+    let mut len = match tokens[0] {
+        Token::Open | Token::Atom("[") => {
+            let mut depth = 0;
+            tokens
+                .iter()
+                .position(|t| {
+                    match t {
+                        Token::Open | Token::Atom("[") => depth += 1,
+                        Token::Close => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                })
+                .map(|i| i + 1)
+                .unwrap_or(tokens.len())
+        }
+        _ => 1,
+    };
+
+    len += tokens[len..].iter().take_while(|t| is_suffix(t)).count();
+    len
+}
+
+/// Split a tree-sitter query into tokens, dropping comments.
+///
+/// Opening brackets are kept as `Atom("[")` so that they can be told apart from parentheses,
+/// while both closing brackets become `Close`.
+fn tokenize(query: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut chars = query.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '(' => tokens.push(Token::Open),
+            '[' => tokens.push(Token::Atom("[")),
+            ')' | ']' => tokens.push(Token::Close),
+            ';' => while chars.next_if(|(_, c)| *c != '\n').is_some() {},
+            '"' => {
+                let mut text = String::new();
+
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some((_, 'n')) => text.push('\n'),
+                            Some((_, 't')) => text.push('\t'),
+                            Some((_, 'r')) => text.push('\r'),
+                            Some((_, '0')) => text.push('\0'),
+                            Some((_, c)) => text.push(c),
+                            None => break,
+                        },
+                        c => text.push(c),
+                    }
+                }
+
+                tokens.push(Token::Str(text));
+            }
+            c if c.is_whitespace() => {}
+            _ => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) =
+                    chars.next_if(|(_, c)| !c.is_whitespace() && !"()[]\";".contains(*c))
+                {
+                    end = i + c.len_utf8();
+                }
+
+                tokens.push(Token::Atom(&query[start..end]));
+            }
+        }
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit(s: &str) -> Fragment {
+        Fragment::Literal(s.to_owned())
+    }
+
+    #[test]
+    fn predicates() {
+        assert_eq!(
+            plan(r#"(call_expression function: (identifier) @f (#eq? @f "unwrap"))"#),
+            lit("unwrap"),
+        );
+
+        assert_eq!(
+            plan(r#"((identifier) @f (#match? @f "get|set") (#eq? "Parser" @f))"#),
+            Fragment::Dense(
+                Op::And,
+                vec![
+                    Fragment::Dense(Op::Or, vec![lit("get"), lit("set")]),
+                    lit("Parser")
+                ]
+            ),
+        );
+
+        // Negative predicates don't tell us anything.
+        assert_eq!(
+            plan(r#"((identifier) @f (#not-eq? @f "unwrap"))"#),
+            Fragment::Break,
+        );
+    }
+
+    #[test]
+    fn anonymous_nodes() {
+        assert_eq!(
+            plan(r#"(function_item "async" name: (identifier))"#),
+            lit("async"),
+        );
+
+        assert_eq!(
+            plan(r#"(function_item "async"? name: (identifier))"#),
+            Fragment::Break,
+        );
+    }
+
+    #[test]
+    fn optional_captures() {
+        assert_eq!(
+            plan(r#"(call_expression (identifier)? @f (#eq? @f "unwrap"))"#),
+            Fragment::Break,
+        );
+
+        assert_eq!(
+            plan(r#"(call_expression [(identifier) @f (field_expression)] (#eq? @f "unwrap"))"#),
+            Fragment::Break,
+        );
+    }
+
+    #[test]
+    fn multiple_patterns() {
+        assert_eq!(
+            plan(
+                r#"
+                ; unwraps
+                ((identifier) @f (#eq? @f "unwrap"))
+                ((identifier) @f (#eq? @f "expect"))
+                "#
+            ),
+            Fragment::Dense(Op::Or, vec![lit("unwrap"), lit("expect")]),
+        );
+
+        assert_eq!(
+            plan(r#"((identifier) @f (#eq? @f "unwrap")) (struct_item)"#),
+            Fragment::Break,
+        );
+    }
+}
@@ -15,12 +15,16 @@ use tracing::{debug, error, warn};
 
 use crate::{
     background::SyncHandle,
-    remotes,
+    network, remotes,
     repo::{Backend, RepoError, RepoRef, Repository, SyncStatus},
     Application,
 };
 
+mod command;
+pub mod discovery;
+pub mod git;
 pub mod github;
+pub mod gitlab;
 
 type GitCreds = Account;
 
@@ -54,6 +58,9 @@ pub(crate) enum RemoteError {
     #[error("github access error: {0}")]
     GitHub(#[from] octocrab::Error),
 
+    #[error("gitlab access error: {0}")]
+    GitLab(#[from] reqwest::Error),
+
     #[error("anyhow: {0:?}")]
     Anyhow(#[from] anyhow::Error),
 
@@ -100,12 +107,43 @@ macro_rules! creds_callback(($auth:ident) => {{
     }
 }});
 
-async fn git_clone(auth: GitCreds, url: &str, target: &Path) -> Result<()> {
+async fn git_clone(auth: GitCreds, url: &str, repo: &Repository) -> Result<()> {
+    git_clone_with_config(auth, url, repo, vec![]).await
+}
+
+/// Clone with configuration overrides, as in `core.sshCommand=...`
+///
+/// Clones that aren't full go through the `git` command, as gix can't make them yet, and so do
+/// all clones when a proxy or extra certificates are configured.
+async fn git_clone_with_config(
+    auth: GitCreds,
+    url: &str,
+    repo: &Repository,
+    config: Vec<String>,
+) -> Result<()> {
     let url = url.to_owned();
-    let target = target.to_owned();
+    let target = repo.disk_path.to_owned();
+    let options = repo.clone_options.clone();
+    let submodules = repo
+        .index_submodules
+        .then(|| (auth.clone(), url.clone(), config.clone()));
 
     tokio::task::spawn_blocking(move || {
-        let clone = gix::prepare_clone_bare(url, target)?;
+        if !options.is_full() || network::requires_git_command() {
+            return command::clone(&auth, &url, &target, &options, &config);
+        }
+
+        let clone = if config.is_empty() {
+            gix::prepare_clone_bare(url, target)?
+        } else {
+            gix::clone::PrepareFetch::new(
+                url,
+                target,
+                gix::create::Kind::Bare,
+                Default::default(),
+                gix::open::Options::default().config_overrides(config),
+            )?
+        };
         let (_repo, _outcome) = clone
             .configure_connection(move |con| {
                 con.set_credentials(creds_callback!(auth));
@@ -115,18 +153,49 @@ async fn git_clone(auth: GitCreds, url: &str, target: &Path) -> Result<()> {
 
         Ok(())
     })
-    .await?
+    .await??;
+
+    if let Some((auth, url, config)) = submodules {
+        git_sync_submodules(auth, repo, url, config).await?;
+    }
+
+    Ok(())
+}
+
+/// Mirror the submodules of a clone, so they can be indexed along with it
+async fn git_sync_submodules(
+    auth: GitCreds,
+    repo: &Repository,
+    url: String,
+    config: Vec<String>,
+) -> Result<()> {
+    let disk_path = repo.disk_path.to_owned();
+    tokio::task::spawn_blocking(move || command::sync_submodules(&auth, &disk_path, &url, &config))
+        .await?
 }
 
 async fn git_pull(auth: GitCreds, repo: &Repository) -> Result<()> {
+    git_pull_with_config(auth, repo, vec![]).await
+}
+
+/// Fetch `refspecs` from the default remote, on top of the ones it's configured with
+async fn git_fetch(auth: GitCreds, repo: &Repository, refspecs: Vec<String>) -> Result<()> {
     use gix::remote::Direction;
 
     let disk_path = repo.disk_path.to_owned();
+    let options = repo.clone_options.clone();
     tokio::task::spawn_blocking(move || {
+        if !options.is_full() || network::requires_git_command() {
+            return command::fetch(&auth, &disk_path, &refspecs, &options);
+        }
+
         let repo = gix::open(disk_path)?;
         let remote = repo
             .find_default_remote(Direction::Fetch)
-            .context("no remote found")??;
+            .context("no remote found")??
+            .with_refspecs(refspecs.iter().map(String::as_str), Direction::Fetch)
+            .context("invalid refspec")?;
+
         remote
             .connect(Direction::Fetch)?
             .with_credentials(creds_callback!(auth))
@@ -138,11 +207,58 @@ async fn git_pull(auth: GitCreds, repo: &Repository) -> Result<()> {
     .await?
 }
 
+/// Pull with configuration overrides, as in `core.sshCommand=...`
+async fn git_pull_with_config(
+    auth: GitCreds,
+    repo: &Repository,
+    config: Vec<String>,
+) -> Result<()> {
+    use gix::remote::Direction;
+
+    let disk_path = repo.disk_path.to_owned();
+    let options = repo.clone_options.clone();
+    let submodules = repo
+        .index_submodules
+        .then(|| (auth.clone(), repo.remote.to_string(), config.clone()));
+
+    tokio::task::spawn_blocking(move || {
+        if !options.is_full() || network::requires_git_command() {
+            return command::pull(&auth, &disk_path, &options, &config);
+        }
+
+        let repo = if config.is_empty() {
+            gix::open(disk_path)?
+        } else {
+            gix::open_opts(
+                disk_path,
+                gix::open::Options::default().config_overrides(config),
+            )?
+        };
+        let remote = repo
+            .find_default_remote(Direction::Fetch)
+            .context("no remote found")??;
+        remote
+            .connect(Direction::Fetch)?
+            .with_credentials(creds_callback!(auth))
+            .prepare_fetch(gix::progress::Discard, Default::default())?
+            .receive(gix::progress::Discard, &false.into())?;
+
+        Ok(())
+    })
+    .await??;
+
+    if let Some((auth, url, config)) = submodules {
+        git_sync_submodules(auth, repo, url, config).await?;
+    }
+
+    Ok(())
+}
+
 pub(crate) fn gather_repo_roots(
     path: impl AsRef<Path>,
     exclude: Option<PathBuf>,
 ) -> std::collections::HashSet<RepoRef> {
-    const RECOGNIZED_VCS_DIRS: &[&str] = &[".git"];
+    const RECOGNIZED_VCS_DIRS: &[&str] = &[".git", ".hg"];
 
     let repos = Arc::new(scc::HashSet::new());
 
@@ -243,7 +359,13 @@ impl From<HashMap<Backend, BackendCredential>> for Backends {
 
 impl Backends {
     pub(crate) fn for_repo(&self, repo: &RepoRef) -> Option<BackendCredential> {
-        self.backends.read(&repo.backend(), |_, v| v.inner.clone())
+        let creds = self.backends.read(&repo.backend(), |_, v| v.inner.clone());
+
+        match repo.backend() {
+            // Generic remotes may be public, or use the system's SSH agent
+            Backend::Git => creds.or_else(|| Some(BackendCredential::Git(Default::default()))),
+            _ => creds,
+        }
     }
 
     pub(crate) fn remove(&self, backend: impl Borrow<Backend>) -> Option<BackendCredential> {
@@ -251,25 +373,64 @@ impl Backends {
     }
 
     pub(crate) fn github(&self) -> Option<github::State> {
-        self.backends.read(&Backend::Github, |_, v| {
-            let BackendCredential::Github(ref github) = v.inner;
-            github.clone()
-        })
+        self.backends
+            .read(&Backend::Github, |_, v| match v.inner {
+                BackendCredential::Github(ref github) => Some(github.clone()),
+                _ => None,
+            })
+            .flatten()
     }
 
     pub(crate) fn set_github(&self, gh: github::State) {
+        self.set(Backend::Github, BackendCredential::Github(gh));
+    }
+
+    pub(crate) fn github_updated(&self) -> Option<flume::Receiver<()>> {
+        self.updated(&Backend::Github)
+    }
+
+    pub(crate) fn gitlab(&self) -> Option<gitlab::State> {
+        self.backends
+            .read(&Backend::Gitlab, |_, v| match v.inner {
+                BackendCredential::Gitlab(ref gitlab) => Some(gitlab.clone()),
+                _ => None,
+            })
+            .flatten()
+    }
+
+    pub(crate) fn set_gitlab(&self, gl: gitlab::State) {
+        self.set(Backend::Gitlab, BackendCredential::Gitlab(gl));
+    }
+
+    pub(crate) fn gitlab_updated(&self) -> Option<flume::Receiver<()>> {
+        self.updated(&Backend::Gitlab)
+    }
+
+    pub(crate) fn git(&self) -> Option<git::State> {
+        self.backends
+            .read(&Backend::Git, |_, v| match v.inner {
+                BackendCredential::Git(ref git) => Some(git.clone()),
+                _ => None,
+            })
+            .flatten()
+    }
+
+    pub(crate) fn set_git(&self, git: git::State) {
+        self.set(Backend::Git, BackendCredential::Git(git));
+    }
+
+    fn set(&self, backend: Backend, cred: BackendCredential) {
         self.backends
-            .entry(Backend::Github)
+            .entry(backend)
             .and_modify(|existing| {
-                existing.inner = BackendCredential::Github(gh.clone());
+                existing.inner = cred.clone();
                 _ = existing.updated_tx.send(());
             })
-            .or_insert_with(|| BackendCredential::Github(gh).into());
+            .or_insert_with(|| cred.into());
     }
 
-    pub(crate) fn github_updated(&self) -> Option<flume::Receiver<()>> {
-        self.backends
-            .read(&Backend::Github, |_, v| v.updated.clone())
+    fn updated(&self, backend: &Backend) -> Option<flume::Receiver<()>> {
+        self.backends.read(backend, |_, v| v.updated.clone())
     }
 
     pub(crate) async fn serialize(&self) -> impl Serialize + Send + Sync {
@@ -295,31 +456,46 @@ impl Backends {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub(crate) enum BackendCredential {
     Github(github::State),
+    Gitlab(gitlab::State),
+    Git(git::State),
 }
 
 impl BackendCredential {
+    async fn clone_repo(&self, reporef: &RepoRef, repo: &Repository) -> Result<()> {
+        match self {
+            BackendCredential::Github(gh) => gh.auth.clone_repo(repo).await,
+            BackendCredential::Gitlab(gl) => gl.clone_repo(repo, reporef).await,
+            BackendCredential::Git(git) => git.clone_repo(repo, reporef).await,
+        }
+    }
+
+    async fn pull_repo(&self, reporef: &RepoRef, repo: &Repository) -> Result<()> {
+        match self {
+            BackendCredential::Github(gh) => gh.auth.pull_repo(repo).await,
+            BackendCredential::Gitlab(gl) => gl.pull_repo(repo, reporef).await,
+            BackendCredential::Git(git) => git.pull_repo(repo, reporef).await,
+        }
+    }
+
     #[tracing::instrument(fields(repo=%sync_handle.reporef), skip_all)]
     pub(crate) async fn sync(self, sync_handle: &SyncHandle) -> Result<()> {
-        let SyncHandle { app, .. } = sync_handle;
-
-        use BackendCredential::*;
+        let SyncHandle { app, reporef, .. } = sync_handle;
         let existing = sync_handle.sync_lock().await;
 
-        let Github(gh) = self;
         let mut synced = match existing {
             Err(err) => return Err(err),
             Ok(repo) if repo.last_index_unix_secs == 0 && repo.disk_path.exists() => {
                 // it is possible syncing was killed, but the repo is
                 // intact. pull if the dir exists, then quietly revert
                 // to cloning if that fails
-                if let Ok(success) = gh.auth.pull_repo(&repo).await {
+                if let Ok(success) = self.pull_repo(reporef, &repo).await {
                     Ok(success)
                 } else {
-                    gh.auth.clone_repo(&repo).await
+                    self.clone_repo(reporef, &repo).await
                 }
             }
-            Ok(repo) if repo.last_index_unix_secs == 0 => gh.auth.clone_repo(&repo).await,
-            Ok(repo) => gh.auth.pull_repo(&repo).await,
+            Ok(repo) if repo.last_index_unix_secs == 0 => self.clone_repo(reporef, &repo).await,
+            Ok(repo) => self.pull_repo(reporef, &repo).await,
         };
 
         let new_status = match synced {
@@ -335,7 +511,7 @@ impl BackendCredential {
                 let removed = tokio::fs::remove_dir_all(&repo.disk_path).await;
                 debug!(?removed, "removing recursively");
 
-                synced = gh.auth.clone_repo(&repo).await;
+                synced = self.clone_repo(reporef, &repo).await;
                 match synced {
                     Ok(_) => SyncStatus::Queued,
                     Err(ref err) => SyncStatus::Error {
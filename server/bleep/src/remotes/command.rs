@@ -0,0 +1,564 @@
+//! Clones and fetches through the `git` command, for the clone options and network settings gix
+//! doesn't support yet.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use crate::repo::{iterator::parse_gitmodules, CloneOptions};
+
+use super::*;
+
+/// Answers credential requests from the environment, so secrets stay out of the process list
+const CREDENTIAL_HELPER: &str = r#"!f() { test "$1" = get && printf 'username=%s\npassword=%s\n' "$BLOOP_GIT_USERNAME" "$BLOOP_GIT_PASSWORD"; }; f"#;
+
+/// Submodules can point anywhere, so they're only synced over the network
+const SUBMODULE_CONFIG: &[&str] = &[
+    "protocol.allow=never",
+    "protocol.file.allow=never",
+    "protocol.http.allow=always",
+    "protocol.https.allow=always",
+    "protocol.ssh.allow=always",
+];
+
+/// For commands that mustn't be given any credentials
+static NO_CREDS: GitCreds = GitCreds {
+    username: String::new(),
+    password: String::new(),
+};
+
+/// Treeless clones need one round to fetch the trees at the tips, and one for their blobs. Anything
+/// still missing after that is fetched lazily when it's read.
+const HYDRATE_ROUNDS: usize = 2;
+
+#[cfg(windows)]
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+struct Git<'a> {
+    auth: &'a GitCreds,
+    /// Only give `auth` to this scheme and host, as in `https://example.com`
+    scope: Option<String>,
+    config: &'a [String],
+    dir: &'a Path,
+}
+
+impl Git<'_> {
+    fn command(&self, args: &[&str]) -> Command {
+        let mut cmd = Command::new("git");
+        cmd.arg("-C").arg(self.dir);
+        for config in crate::network::git_config().iter().chain(self.config) {
+            cmd.arg("-c").arg(config);
+        }
+
+        cmd.args(args)
+            .envs(crate::network::git_env())
+            .env("GIT_TERMINAL_PROMPT", "0");
+
+        if !self.auth.username.is_empty() {
+            let helper = match self.scope {
+                Some(ref scope) => format!("credential.{scope}.helper"),
+                None => "credential.helper".to_owned(),
+            };
+
+            // The empty helper resets the list, so configured helpers don't answer first
+            cmd.env("GIT_CONFIG_COUNT", "2")
+                .env("GIT_CONFIG_KEY_0", "credential.helper")
+                .env("GIT_CONFIG_VALUE_0", "")
+                .env("GIT_CONFIG_KEY_1", helper)
+                .env("GIT_CONFIG_VALUE_1", CREDENTIAL_HELPER)
+                .env("BLOOP_GIT_USERNAME", &self.auth.username)
+                .env("BLOOP_GIT_PASSWORD", &self.auth.password);
+        }
+
+        #[cfg(windows)]
+        cmd.creation_flags(CREATE_NO_WINDOW);
+
+        cmd
+    }
+
+    fn run(&self, args: &[&str]) -> Result<String> {
+        self.run_with_input(args, None)
+    }
+
+    fn run_with_input(&self, args: &[&str], input: Option<&str>) -> Result<String> {
+        let mut child = self
+            .command(args)
+            .stdin(if input.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("failed to run git")?;
+
+        if let Some(input) = input {
+            let mut stdin = child.stdin.take().expect("stdin is piped");
+            stdin.write_all(input.as_bytes())?;
+        }
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(anyhow::format_err!(
+                "git {} failed: {}",
+                args.first().unwrap_or(&""),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// Clone `url` into a bare repository at `target`.
+///
+/// The layout matches a gix clone, with branches under `refs/remotes/origin`, and `HEAD`
+/// naming the remote's default branch.
+pub(super) fn clone(
+    auth: &GitCreds,
+    url: &str,
+    target: &Path,
+    options: &CloneOptions,
+    config: &[String],
+) -> Result<()> {
+    std::fs::create_dir_all(target)?;
+
+    let git = Git {
+        auth,
+        scope: None,
+        config,
+        dir: target,
+    };
+    git.run(&["init", "--bare", "--quiet"])?;
+    git.run(&["remote", "add", "origin", url])?;
+
+    let head = default_branch(&git.run(&["ls-remote", "--symref", "origin", "HEAD"])?)
+        .context("remote has no default branch")?;
+    git.run(&["symbolic-ref", "HEAD", &format!("refs/heads/{head}")])?;
+
+    if options.single_branch {
+        git.run(&["remote", "set-branches", "origin", &head])?;
+    }
+
+    if let Some(filter) = options.filter {
+        git.run(&["config", "remote.origin.promisor", "true"])?;
+        git.run(&["config", "remote.origin.partialclonefilter", filter.spec()])?;
+    }
+
+    run_fetch(&git, &[], options)
+}
+
+/// Fetch new commits into a repository made by [`clone`]
+pub(super) fn pull(
+    auth: &GitCreds,
+    repo: &Path,
+    options: &CloneOptions,
+    config: &[String],
+) -> Result<()> {
+    let git = Git {
+        auth,
+        scope: None,
+        config,
+        dir: repo,
+    };
+
+    run_fetch(&git, &["--prune"], options)
+}
+
+/// Fetch `refspecs` into a repository made by [`clone`], on top of the ones it's configured
+/// with
+pub(super) fn fetch(
+    auth: &GitCreds,
+    repo: &Path,
+    refspecs: &[String],
+    options: &CloneOptions,
+) -> Result<()> {
+    let git = Git {
+        auth,
+        scope: None,
+        config: &[],
+        dir: repo,
+    };
+
+    let refspecs = refspecs.iter().map(String::as_str).collect::<Vec<_>>();
+    run_fetch(&git, &refspecs, options)
+}
+
+/// Mirror the submodules listed in `.gitmodules` on the default branch of the repository at
+/// `repo` into `modules/<name>`, where the walker looks for them. Relative URLs are resolved
+/// against `url`, the parent's remote.
+///
+/// Submodules that fail to sync are skipped, so one unreachable submodule doesn't stop the
+/// parent from being indexed.
+pub(super) fn sync_submodules(
+    auth: &GitCreds,
+    repo: &Path,
+    url: &str,
+    config: &[String],
+) -> Result<()> {
+    let git = Git {
+        auth,
+        scope: None,
+        config,
+        dir: repo,
+    };
+    let config = submodule_config(config);
+
+    let head = git.run(&["symbolic-ref", "--short", "HEAD"])?;
+    let Ok(gitmodules) = git.run(&[
+        "show",
+        &format!("refs/remotes/origin/{}:.gitmodules", head.trim()),
+    ]) else {
+        debug!(?repo, "no submodules to sync");
+        return Ok(());
+    };
+
+    for module in parse_gitmodules(&gitmodules) {
+        let module_url = resolve_url(url, &module.url);
+        let target = repo.join("modules").join(&module.name);
+
+        let synced = if target.exists() {
+            submodule(auth, url, &module_url, &config, &target)
+                .run(&["fetch", "--quiet", "--prune", "origin"])
+        } else {
+            submodule(auth, url, &module_url, &config, repo).run(&[
+                "clone",
+                "--quiet",
+                "--mirror",
+                &module_url,
+                &target.to_string_lossy(),
+            ])
+        };
+
+        if let Err(err) = synced {
+            warn!(?err, name = %module.name, "failed to sync submodule; skipping");
+        }
+    }
+
+    Ok(())
+}
+
+/// Run `git` for the submodule at `module_url` in `dir`. It's only given the credentials of the
+/// parent at `url` if it has the same scheme and host, so other hosts never see them.
+fn submodule<'a>(
+    auth: &'a GitCreds,
+    url: &str,
+    module_url: &str,
+    config: &'a [String],
+    dir: &'a Path,
+) -> Git<'a> {
+    let scope =
+        credential_scope(url).filter(|scope| credential_scope(module_url).as_ref() == Some(scope));
+    Git {
+        auth: if scope.is_some() { auth } else { &NO_CREDS },
+        scope,
+        config,
+        dir,
+    }
+}
+
+fn submodule_config(config: &[String]) -> Vec<String> {
+    config
+        .iter()
+        .cloned()
+        .chain(SUBMODULE_CONFIG.iter().map(|c| c.to_string()))
+        .collect()
+}
+
+/// The scheme and host that git matches credentials by, as in `https://example.com`. scp-like
+/// and local URLs have none.
+fn credential_scope(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    let host = rest.split('/').next()?;
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+
+    (scheme != "file" && !host.is_empty())
+        .then(|| format!("{scheme}://{host}").to_ascii_lowercase())
+}
+
+/// Resolve a submodule URL like `../lib.git` against the URL of the parent repository, as git
+/// does. Absolute URLs are returned as they are.
+fn resolve_url(base: &str, url: &str) -> String {
+    if !url.starts_with("./") && !url.starts_with("../") {
+        return url.to_owned();
+    }
+
+    let mut base = base.trim_end_matches('/').to_owned();
+    let mut separator = '/';
+    let mut rest = url;
+    loop {
+        if let Some(next) = rest.strip_prefix("./") {
+            rest = next;
+        } else if let Some(next) = rest.strip_prefix("../") {
+            // scp-like URLs, as in `git@example.com:team/project.git`, end their host with `:`
+            if let Some(end) = base.rfind(['/', ':']) {
+                if base[end..].starts_with(':') {
+                    separator = ':';
+                }
+                base.truncate(end);
+            }
+            rest = next;
+        } else {
+            break;
+        }
+    }
+
+    format!("{base}{separator}{rest}")
+}
+
+fn run_fetch(git: &Git, extra_args: &[&str], options: &CloneOptions) -> Result<()> {
+    let depth = options.depth.map(|depth| format!("--depth={depth}"));
+
+    let mut args = vec!["fetch", "--quiet"];
+    args.extend(depth.as_deref());
+    args.push("origin");
+    args.extend(extra_args);
+    git.run(&args)?;
+
+    if options.filter.is_some() {
+        hydrate(git)?;
+    }
+
+    Ok(())
+}
+
+/// Fetch the objects the filter left out at the tip of each ref, so the tips can be indexed
+/// without a round trip to the remote for every file
+fn hydrate(git: &Git) -> Result<()> {
+    for _ in 0..HYDRATE_ROUNDS {
+        let missing = git
+            .run(&[
+                "rev-list",
+                "--objects",
+                "--no-walk",
+                "--all",
+                "--missing=print",
+            ])?
+            .lines()
+            .filter_map(|line| line.strip_prefix('?'))
+            .map(|id| format!("{id}\n"))
+            .collect::<String>();
+
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        git.run_with_input(
+            &[
+                "-c",
+                "fetch.negotiationAlgorithm=noop",
+                "fetch",
+                "--quiet",
+                "--no-tags",
+                "--no-write-fetch-head",
+                "--recurse-submodules=no",
+                "--filter=blob:none",
+                "--stdin",
+                "origin",
+            ],
+            Some(&missing),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Parse the branch `HEAD` points to from the output of `git ls-remote --symref`
+fn default_branch(ls_remote: &str) -> Option<String> {
+    ls_remote.lines().find_map(|line| {
+        let (target, name) = line.strip_prefix("ref: ")?.split_once('\t')?;
+        (name == "HEAD")
+            .then(|| target.strip_prefix("refs/heads/"))
+            .flatten()
+            .map(ToOwned::to_owned)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        repo::CloneFilter,
+        test_utils::{bare_repo_with_commit, git},
+    };
+
+    use std::num::NonZeroU32;
+    use tempdir::TempDir;
+
+    #[test]
+    fn submodule_urls() {
+        let gitmodules = r#"
+[submodule "vendor/lib"]
+	path = vendor/lib
+	url = ../lib.git
+[submodule "docs"]
+	path = docs/
+	url = https://example.com/docs.git
+[submodule "../escape"]
+	path = escape
+	url = https://example.com/escape.git
+[submodule "no-url"]
+	path = no-url
+"#;
+
+        let modules = parse_gitmodules(gitmodules);
+        assert_eq!(
+            modules
+                .iter()
+                .map(|m| (m.name.as_str(), m.path.as_str()))
+                .collect::<Vec<_>>(),
+            [("vendor/lib", "vendor/lib"), ("docs", "docs")]
+        );
+
+        let base = "https://example.com/team/project.git";
+        assert_eq!(
+            resolve_url(base, &modules[0].url),
+            "https://example.com/team/lib.git"
+        );
+        assert_eq!(
+            resolve_url(base, &modules[1].url),
+            "https://example.com/docs.git"
+        );
+        assert_eq!(
+            resolve_url("git@example.com:project.git", "../lib.git"),
+            "git@example.com:lib.git"
+        );
+        assert_eq!(
+            resolve_url("https://example.com/team/project", "./sub"),
+            "https://example.com/team/project/sub"
+        );
+    }
+
+    #[test]
+    fn submodule_credentials() {
+        let auth = GitCreds {
+            username: "user".into(),
+            password: "secret".into(),
+        };
+        let config = submodule_config(&[]);
+        let parent = "https://git.example.com/team/project.git";
+
+        let command = |module_url: &str| {
+            submodule(&auth, parent, module_url, &config, Path::new(".")).command(&["fetch"])
+        };
+        let env = |command: &Command, key: &str| {
+            command
+                .get_envs()
+                .find(|(k, _)| *k == key)
+                .and_then(|(_, v)| v)
+                .map(|v| v.to_string_lossy().into_owned())
+        };
+
+        let same_host = command("https://user@git.example.com/team/lib.git");
+        assert_eq!(env(&same_host, "BLOOP_GIT_PASSWORD").unwrap(), "secret");
+        assert_eq!(
+            env(&same_host, "GIT_CONFIG_KEY_1").unwrap(),
+            "credential.https://git.example.com.helper"
+        );
+        assert!(same_host
+            .get_args()
+            .any(|arg| arg == "protocol.file.allow=never"));
+
+        for foreign in [
+            "https://other.example.com/team/lib.git",
+            "http://git.example.com/team/lib.git",
+            "git@git.example.com:team/lib.git",
+            "file:///srv/git/lib.git",
+            "/srv/git/lib.git",
+        ] {
+            let command = command(foreign);
+            assert_eq!(env(&command, "BLOOP_GIT_PASSWORD"), None, "{foreign}");
+            assert_eq!(env(&command, "GIT_CONFIG_KEY_1"), None, "{foreign}");
+        }
+    }
+
+    #[test]
+    fn parse_default_branch() {
+        let output = "ref: refs/heads/main\tHEAD\n0123456789abcdef0123456789abcdef01234567\tHEAD\n";
+        assert_eq!(default_branch(output).as_deref(), Some("main"));
+        assert_eq!(default_branch("0123\tHEAD\n"), None);
+    }
+
+    #[test]
+    fn shallow_blobless_single_branch() {
+        let tmpdir = TempDir::new("test-git-command").unwrap();
+        let work = bare_repo_with_commit(tmpdir.path(), "served.git");
+        for content in ["two", "three"] {
+            std::fs::write(work.join("README.md"), content).unwrap();
+            git(&work, &["commit", "-q", "-am", content]);
+        }
+        git(&work, &["branch", "other"]);
+
+        let served = tmpdir.path().join("served.git");
+        git(&work, &["push", "-q", "../served.git", "main", "other"]);
+        git(&served, &["config", "uploadpack.allowFilter", "true"]);
+        git(
+            &served,
+            &["config", "uploadpack.allowAnySHA1InWant", "true"],
+        );
+
+        let options = CloneOptions {
+            depth: NonZeroU32::new(1),
+            filter: Some(CloneFilter::Blobless),
+            single_branch: true,
+        };
+        let auth = GitCreds {
+            username: "".into(),
+            password: "".into(),
+        };
+        let url = format!("file://{}", served.display());
+        let target = tmpdir.path().join("clone");
+        clone(&auth, &url, &target, &options, &[]).unwrap();
+
+        let repo = gix::open(&target).unwrap();
+        assert!(repo.find_reference("refs/remotes/origin/other").is_err());
+        assert_eq!(
+            repo.head_name().unwrap().unwrap().as_bstr(),
+            "refs/heads/main"
+        );
+
+        let tip = repo
+            .find_reference("refs/remotes/origin/main")
+            .unwrap()
+            .into_fully_peeled_id()
+            .unwrap()
+            .object()
+            .unwrap()
+            .into_commit();
+
+        // Only the tip is fetched, with the contents of its files
+        assert!(tip
+            .parent_ids()
+            .all(|id| repo.try_find_object(id).unwrap().is_none()));
+        let tree = tip.tree().unwrap();
+        let readme = tree
+            .decode()
+            .unwrap()
+            .entries
+            .iter()
+            .find(|entry| entry.filename == "README.md")
+            .unwrap()
+            .oid
+            .to_owned();
+        assert_eq!(repo.find_object(readme).unwrap().data, b"three");
+
+        std::fs::write(work.join("README.md"), "four").unwrap();
+        git(&work, &["commit", "-q", "-am", "four"]);
+        git(&work, &["push", "-q", "../served.git", "main"]);
+
+        pull(&auth, &target, &options, &[]).unwrap();
+        let repo = gix::open(&target).unwrap();
+        let tip = repo
+            .find_reference("refs/remotes/origin/main")
+            .unwrap()
+            .into_fully_peeled_id()
+            .unwrap()
+            .object()
+            .unwrap()
+            .into_commit();
+        assert_eq!(tip.message_raw_sloppy(), "four\n");
+    }
+}
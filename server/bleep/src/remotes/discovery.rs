@@ -0,0 +1,219 @@
+//! Rules for adding the repositories of a GitHub organization automatically, as they are created
+//! and deleted.
+
+use std::collections::HashSet;
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+use super::*;
+
+/// Rules for each organization, by name
+pub(crate) type DiscoveryRules = scc::HashMap<String, OrgRules>;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct OrgRules {
+    /// Glob patterns for the names of repositories to add, as in `service-*`. Every repository
+    /// is added when this is empty.
+    #[serde(default)]
+    pub include: Vec<String>,
+
+    /// Glob patterns for the names of repositories to leave out, even if they are included
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    /// Only add repositories with at least one of these topics
+    #[serde(default)]
+    pub topics: Vec<String>,
+
+    /// Add archived repositories
+    #[serde(default)]
+    pub archived: bool,
+
+    /// Add forks
+    #[serde(default)]
+    pub forks: bool,
+}
+
+/// The parts of a GitHub repository the rules look at
+struct Candidate<'a> {
+    name: &'a str,
+    archived: bool,
+    fork: bool,
+    topics: &'a [String],
+}
+
+struct Matcher {
+    rules: OrgRules,
+    include: GlobSet,
+    exclude: GlobSet,
+}
+
+impl OrgRules {
+    pub(crate) fn validate(&self) -> std::result::Result<(), globset::Error> {
+        self.matcher().map(|_| ())
+    }
+
+    fn matcher(&self) -> std::result::Result<Matcher, globset::Error> {
+        let globs = |patterns: &[String]| {
+            let mut set = GlobSetBuilder::new();
+            for pattern in patterns {
+                // GitHub names are case insensitive
+                set.add(GlobBuilder::new(pattern).case_insensitive(true).build()?);
+            }
+            set.build()
+        };
+
+        Ok(Matcher {
+            include: globs(&self.include)?,
+            exclude: globs(&self.exclude)?,
+            rules: self.clone(),
+        })
+    }
+}
+
+impl Matcher {
+    fn matches(&self, repo: &Candidate<'_>) -> bool {
+        let rules = &self.rules;
+
+        (rules.include.is_empty() || self.include.is_match(repo.name))
+            && !self.exclude.is_match(repo.name)
+            && (rules.archived || !repo.archived)
+            && (rules.forks || !repo.fork)
+            && (rules.topics.is_empty() || repo.topics.iter().any(|t| rules.topics.contains(t)))
+    }
+}
+
+/// Queue the `listed` repositories that match the rules of their organization, and remove the
+/// ones that are no longer listed from organizations with rules.
+///
+/// Repositories that stop matching the rules stay, so nothing added by hand is removed.
+pub(crate) async fn apply(
+    app: &Application,
+    rules: &DiscoveryRules,
+    listed: &[octocrab::models::Repository],
+) {
+    let mut matchers = HashMap::new();
+    rules.scan(|org, rules| match rules.matcher() {
+        Ok(matcher) => {
+            matchers.insert(org.to_lowercase(), matcher);
+        }
+        Err(err) => warn!(?err, %org, "invalid discovery rules"),
+    });
+
+    if matchers.is_empty() {
+        return;
+    }
+
+    let mut seen = HashSet::new();
+    let mut queue = vec![];
+
+    for repo in listed {
+        let Some(full_name) = repo.full_name.as_deref() else {
+            continue;
+        };
+
+        let Ok(reporef) = RepoRef::new(Backend::Github, full_name) else {
+            continue;
+        };
+
+        seen.insert(reporef.clone());
+
+        let Some(matcher) = org_of(&reporef).and_then(|org| matchers.get(&org)) else {
+            continue;
+        };
+
+        let candidate = Candidate {
+            name: &repo.name,
+            archived: repo.archived.unwrap_or_default(),
+            fork: repo.fork.unwrap_or_default(),
+            topics: repo.topics.as_deref().unwrap_or_default(),
+        };
+
+        if matcher.matches(&candidate) && !app.repo_pool.contains(&reporef) {
+            debug!(%reporef, "discovered new repository");
+            queue.push(reporef);
+        }
+    }
+
+    app.repo_pool
+        .for_each_async(|reporef, repo| {
+            let ruled = org_of(reporef).map_or(false, |org| matchers.contains_key(&org));
+
+            if ruled && !seen.contains(reporef) && repo.sync_status != SyncStatus::Removed {
+                debug!(%reporef, "repository is gone from its organization");
+                repo.mark_removed();
+                queue.push(reporef.clone());
+            }
+        })
+        .await;
+
+    if !queue.is_empty() {
+        app.write_index().enqueue_sync(queue).await;
+    }
+}
+
+fn org_of(reporef: &RepoRef) -> Option<String> {
+    if reporef.backend() != Backend::Github {
+        return None;
+    }
+
+    let (org, _) = reporef.name().split_once('/')?;
+    Some(org.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_rules() {
+        let rules = OrgRules {
+            include: vec!["service-*".into(), "api".into()],
+            exclude: vec!["*-legacy".into()],
+            ..Default::default()
+        };
+        let matcher = rules.matcher().unwrap();
+
+        let repo = |name| Candidate {
+            name,
+            archived: false,
+            fork: false,
+            topics: &[],
+        };
+
+        assert!(matcher.matches(&repo("service-auth")));
+        assert!(matcher.matches(&repo("API")));
+        assert!(!matcher.matches(&repo("service-auth-legacy")));
+        assert!(!matcher.matches(&repo("website")));
+        assert!(!matcher.matches(&Candidate {
+            archived: true,
+            ..repo("api")
+        }));
+        assert!(!matcher.matches(&Candidate {
+            fork: true,
+            ..repo("api")
+        }));
+
+        let topics = ["search".to_owned()];
+        let rules = OrgRules {
+            topics: topics.to_vec(),
+            forks: true,
+            ..Default::default()
+        };
+        let matcher = rules.matcher().unwrap();
+
+        assert!(!matcher.matches(&repo("api")));
+        assert!(matcher.matches(&Candidate {
+            topics: &topics,
+            fork: true,
+            ..repo("api")
+        }));
+
+        assert!(OrgRules {
+            include: vec!["[".into()],
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,191 @@
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+
+use crate::repo::Repository;
+
+use super::*;
+
+/// Remotes with no API, which are only reachable through git
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub(crate) struct State {
+    /// Credentials for each remote, by URL
+    pub repositories: HashMap<String, Auth>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub(crate) enum Auth {
+    /// Public remotes, or SSH remotes the system's SSH agent has a key for
+    #[default]
+    None,
+    Password {
+        username: String,
+        #[serde(serialize_with = "crate::config::serialize_secret_str")]
+        password: SecretString,
+    },
+    /// An access token, sent in place of a password
+    Token {
+        #[serde(serialize_with = "crate::config::serialize_secret_str")]
+        token: SecretString,
+    },
+    /// A private key file for SSH remotes, which has to be in `ssh_key_dir`
+    SshKey { path: PathBuf },
+}
+
+impl State {
+    pub(crate) fn auth(&self, reporef: &RepoRef) -> Auth {
+        self.repositories
+            .get(reporef.name())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub(crate) async fn clone_repo(&self, repo: &Repository, reporef: &RepoRef) -> Result<()> {
+        let auth = self.auth(reporef);
+        git_clone_with_config(auth.git_cred(), reporef.name(), repo, auth.git_config()).await
+    }
+
+    pub(crate) async fn pull_repo(&self, repo: &Repository, reporef: &RepoRef) -> Result<()> {
+        let auth = self.auth(reporef);
+        git_pull_with_config(auth.git_cred(), repo, auth.git_config()).await
+    }
+}
+
+impl Auth {
+    /// Check that an SSH key is a file inside `key_dir`, and refer to it by its canonical path so
+    /// that links can't point outside of it later.
+    pub(crate) fn confine_to(self, key_dir: &Path) -> anyhow::Result<Self> {
+        let Auth::SshKey { path } = self else {
+            return Ok(self);
+        };
+
+        let key_dir = key_dir
+            .canonicalize()
+            .with_context(|| format!("SSH key directory {} not found", key_dir.display()))?;
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("SSH key {} not found", path.display()))?;
+
+        if !path.starts_with(&key_dir) || !path.is_file() {
+            anyhow::bail!("SSH keys have to be files in {}", key_dir.display());
+        }
+
+        Ok(Auth::SshKey { path })
+    }
+
+    fn git_cred(&self) -> GitCreds {
+        match self {
+            Auth::Password { username, password } => GitCreds {
+                username: username.clone(),
+                password: password.expose_secret().into(),
+            },
+            // Servers generally ignore the username when given a token
+            Auth::Token { token } => GitCreds {
+                username: "git".into(),
+                password: token.expose_secret().into(),
+            },
+            Auth::None | Auth::SshKey { .. } => GitCreds {
+                username: "".into(),
+                password: "".into(),
+            },
+        }
+    }
+
+    fn git_config(&self) -> Vec<String> {
+        match self {
+            // git runs `core.sshCommand` through the shell
+            Auth::SshKey { path } => vec![format!(
+                "core.sshCommand=ssh -i {} -o IdentitiesOnly=yes",
+                shell_quote(&path.to_string_lossy())
+            )],
+            _ => vec![],
+        }
+    }
+}
+
+/// Quote a string as a single shell word, e.g. `it's` becomes `'it'\''s'`.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{bare_repo_with_commit, git};
+
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn clone_and_pull_file_url() {
+        let tmpdir = TempDir::new("test-git-remote").unwrap();
+        let work = bare_repo_with_commit(tmpdir.path(), "project.git");
+
+        let url = format!("file://{}", tmpdir.path().join("project.git").display());
+        let reporef = RepoRef::new(Backend::Git, &url).unwrap();
+        let repo = Repository::remote_at(&reporef, tmpdir.path().join("clone"));
+        assert_eq!(repo.remote.to_string(), url);
+
+        let state = State::default();
+        state.clone_repo(&repo, &reporef).await.unwrap();
+
+        let head = || {
+            gix::open(&repo.disk_path)
+                .unwrap()
+                .find_reference("refs/remotes/origin/main")
+                .unwrap()
+                .id()
+                .detach()
+        };
+        let first = head();
+
+        std::fs::write(work.join("README.md"), "hello again").unwrap();
+        git(&work, &["commit", "-q", "-am", "second"]);
+        git(&work, &["push", "-q", "../project.git", "main"]);
+
+        state.pull_repo(&repo, &reporef).await.unwrap();
+        assert_ne!(first, head());
+    }
+
+    #[test]
+    fn ssh_key_config() {
+        let auth = Auth::SshKey {
+            path: "/keys/id_ed25519".into(),
+        };
+
+        assert_eq!(
+            auth.git_config(),
+            ["core.sshCommand=ssh -i '/keys/id_ed25519' -o IdentitiesOnly=yes"]
+        );
+        assert!(Auth::None.git_config().is_empty());
+
+        let auth = Auth::SshKey {
+            path: "/keys/x'; touch pwned; '".into(),
+        };
+
+        assert_eq!(
+            auth.git_config(),
+            [r"core.sshCommand=ssh -i '/keys/x'\''; touch pwned; '\''' -o IdentitiesOnly=yes"]
+        );
+    }
+
+    #[test]
+    fn ssh_keys_confined() {
+        let tmpdir = TempDir::new("test-ssh-keys").unwrap();
+        let keys = tmpdir.path().join("keys");
+        std::fs::create_dir_all(&keys).unwrap();
+        std::fs::write(keys.join("id_ed25519"), "key").unwrap();
+        std::fs::write(tmpdir.path().join("secret"), "not a key").unwrap();
+
+        let key = |path: PathBuf| Auth::SshKey { path }.confine_to(&keys);
+
+        let Auth::SshKey { path } = key(keys.join("id_ed25519")).unwrap() else {
+            panic!("expected an SSH key");
+        };
+        assert_eq!(path, keys.canonicalize().unwrap().join("id_ed25519"));
+
+        assert!(key(keys.join("../secret")).is_err());
+        assert!(key(keys.join("missing")).is_err());
+        assert!(key(keys.clone()).is_err());
+        assert!(Auth::None.confine_to(&keys).is_ok());
+    }
+}
@@ -1,8 +1,9 @@
 use chrono::{DateTime, Utc};
 use jsonwebtoken::EncodingKey;
 use octocrab::{
+    auth::AppAuth,
     models::{Installation, InstallationToken},
-    Octocrab,
+    AuthState, Octocrab,
 };
 
 use secrecy::{ExposeSecret, SecretString};
@@ -12,6 +13,9 @@ use crate::repo::{GitRemote, RepoRemote, Repository};
 
 use super::*;
 
+/// Where the GitHub API is
+pub(crate) const GITHUB_API: &str = "https://api.github.com";
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub(crate) struct State {
     pub auth: Auth,
@@ -27,12 +31,12 @@ impl State {
         }
     }
 
-    pub fn client(&self) -> octocrab::Result<Octocrab> {
+    pub fn client(&self) -> Octocrab {
         self.auth.client()
     }
 
     pub(crate) async fn validate(&self) -> Result<Option<String>> {
-        let client = self.client()?;
+        let client = self.client();
 
         let username = match client.current().user().await {
             Ok(user) => Some(user.login),
@@ -130,26 +134,22 @@ impl Auth {
 impl Auth {
     pub(crate) async fn clone_repo(&self, repo: &Repository) -> Result<()> {
         self.check_repo(repo).await?;
-        git_clone(self.git_cred(), &repo.remote.to_string(), &repo.disk_path).await
+        git_clone(self.git_cred(), &repo.remote.to_string(), repo).await?;
+        self.sync_pull_requests(repo).await;
+        Ok(())
     }
 
     pub(crate) async fn pull_repo(&self, repo: &Repository) -> Result<()> {
         self.check_repo(repo).await?;
-        git_pull(self.git_cred(), repo).await
+        git_pull(self.git_cred(), repo).await?;
+        self.sync_pull_requests(repo).await;
+        Ok(())
     }
 
     pub async fn check_repo(&self, repo: &Repository) -> Result<()> {
-        let RepoRemote::Git(GitRemote {
-            ref address, ..
-        }) = repo.remote else {
-            return Err(RemoteError::NotSupported("github without git backend"));
-        };
-
-        let (org, reponame) = address
-            .split_once('/')
-            .ok_or(RemoteError::NotSupported("invalid repo address"))?;
+        let (org, reponame) = org_and_name(repo)?;
 
-        let response = self.client()?.repos(org, reponame).get().await;
+        let response = self.client().repos(org, reponame).get().await;
         match response {
             Err(octocrab::Error::GitHub { ref source, .. }) => match source.message.as_str() {
                 // GitHub API will send 403 for API-level issues, not object-level permissions
@@ -164,6 +164,62 @@ impl Auth {
         }
     }
 
+    /// Fetch the heads of open pull requests, and drop those of closed ones
+    ///
+    /// Pull request heads are kept as `refs/remotes/pr/<number>`, so they are indexed as
+    /// `pr/<number>` branches. Failing to update them doesn't fail the sync.
+    async fn sync_pull_requests(&self, repo: &Repository) {
+        if repo.clone_options.single_branch {
+            return;
+        }
+
+        let open = match self.open_pull_requests(repo).await {
+            Ok(open) => open,
+            Err(err) => {
+                warn!(?err, "failed to list open pull requests");
+                return;
+            }
+        };
+
+        if let Err(err) = remove_closed_pull_requests(repo, open.clone()).await {
+            warn!(?err, "failed to remove closed pull requests");
+        }
+
+        if open.is_empty() {
+            return;
+        }
+
+        let refspecs = pull_request_refspecs(&open);
+        if let Err(err) = git_fetch(self.git_cred(), repo, refspecs).await {
+            warn!(?err, "failed to fetch pull requests");
+        }
+    }
+
+    async fn open_pull_requests(&self, repo: &Repository) -> Result<Vec<u64>> {
+        let (org, reponame) = org_and_name(repo)?;
+        let client = self.client();
+
+        let mut results = vec![];
+        for page in 1.. {
+            let resp = client
+                .pulls(org, reponame)
+                .list()
+                .state(octocrab::params::State::Open)
+                .per_page(100)
+                .page(page)
+                .send()
+                .await?;
+
+            if resp.items.is_empty() {
+                break;
+            }
+
+            results.extend(resp.items.into_iter().map(|pr| pr.number));
+        }
+
+        Ok(results)
+    }
+
     fn git_cred(&self) -> GitCreds {
         use Auth::*;
         match self {
@@ -178,30 +234,22 @@ impl Auth {
         }
     }
 
-    fn client(&self) -> octocrab::Result<Octocrab> {
+    fn client(&self) -> Octocrab {
         use Auth::*;
-        match self.clone() {
-            OAuth {
-                access_token,
-                token_type,
-                scope,
-            } => {
-                let token = octocrab::auth::OAuth {
-                    access_token,
-                    token_type,
-                    scope,
-                };
-
-                Octocrab::builder().oauth(token).build()
-            }
-            App { token, .. } => Octocrab::builder()
-                .personal_token(token.expose_secret().to_string())
-                .build(),
-        }
+        let token = match self {
+            OAuth { access_token, .. } => access_token,
+            App { token, .. } => token,
+        };
+
+        network::github(
+            GITHUB_API,
+            [network::github_token(token.expose_secret())],
+            AuthState::None,
+        )
     }
 
     async fn list_repos(&self) -> Result<Vec<octocrab::models::Repository>> {
-        let gh_client = self.client().expect("failed to build github client");
+        let gh_client = self.client();
         let mut results = vec![];
         for page in 1.. {
             let mut resp = match self {
@@ -236,6 +284,54 @@ impl Auth {
     }
 }
 
+fn org_and_name(repo: &Repository) -> Result<(&str, &str)> {
+    let RepoRemote::Git(GitRemote { ref address, .. }) = repo.remote else {
+        return Err(RemoteError::NotSupported("github without git backend"));
+    };
+
+    address
+        .split_once('/')
+        .ok_or(RemoteError::NotSupported("invalid repo address"))
+}
+
+/// Where pull request heads are fetched to
+const PULL_REQUEST_REFS: &str = "refs/remotes/pr/";
+
+async fn remove_closed_pull_requests(repo: &Repository, open: Vec<u64>) -> Result<()> {
+    let disk_path = repo.disk_path.to_owned();
+    tokio::task::spawn_blocking(move || {
+        let repo = gix::open(disk_path)?;
+
+        let stale = repo
+            .references()
+            .context("failed to read references")?
+            .prefixed(PULL_REQUEST_REFS)
+            .context("failed to read references")?
+            .filter_map(Result::ok)
+            .filter(|r| {
+                let name = r.name().as_bstr().to_string();
+                !open
+                    .iter()
+                    .any(|pr| name == format!("{PULL_REQUEST_REFS}{pr}"))
+            })
+            .collect::<Vec<_>>();
+
+        for r in stale {
+            debug!(name = %r.name().as_bstr(), "removing closed pull request");
+            r.delete().context("failed to remove pull request")?;
+        }
+
+        Ok(())
+    })
+    .await?
+}
+
+fn pull_request_refspecs(open: &[u64]) -> Vec<String> {
+    open.iter()
+        .map(|pr| format!("+refs/pull/{pr}/head:{PULL_REQUEST_REFS}{pr}"))
+        .collect()
+}
+
 pub(crate) async fn refresh_github_installation_token(app: &Application) -> Result<()> {
     let privkey = std::fs::read(
         app.config
@@ -249,15 +345,18 @@ pub(crate) async fn refresh_github_installation_token(app: &Application) -> Resu
         .github_app_install_id
         .ok_or(RemoteError::Configuration("github_app_install_id"))?;
 
-    let octocrab = Octocrab::builder()
-        .app(
-            app.config
+    let octocrab = network::github(
+        GITHUB_API,
+        [],
+        AuthState::App(AppAuth {
+            app_id: app
+                .config
                 .github_app_id
                 .ok_or(RemoteError::Configuration("github_app_id"))?
                 .into(),
-            EncodingKey::from_rsa_pem(&privkey)?,
-        )
-        .build()?;
+            key: EncodingKey::from_rsa_pem(&privkey)?,
+        }),
+    );
 
     let installation: Installation = octocrab
         .get(format!("/app/installations/{install_id}"), None::<&()>)
@@ -274,3 +373,19 @@ pub(crate) async fn refresh_github_installation_token(app: &Application) -> Resu
     app.credentials.set_github(State::with_auth(auth));
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pull_request_refs() {
+        assert_eq!(
+            pull_request_refspecs(&[12, 1234]),
+            [
+                "+refs/pull/12/head:refs/remotes/pr/12",
+                "+refs/pull/1234/head:refs/remotes/pr/1234"
+            ]
+        );
+    }
+}
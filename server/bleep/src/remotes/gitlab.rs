@@ -0,0 +1,276 @@
+use chrono::{DateTime, Utc};
+use reqwest::{StatusCode, Url};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+
+use crate::repo::{RepoRef, Repository};
+
+use super::*;
+
+/// The header GitLab expects personal, group and project access tokens in
+const TOKEN_HEADER: &str = "PRIVATE-TOKEN";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct State {
+    /// Base URL of the GitLab instance, e.g. `https://gitlab.example.com`
+    pub url: String,
+    #[serde(serialize_with = "crate::config::serialize_secret_str")]
+    pub token: SecretString,
+    #[serde(skip)]
+    pub repositories: Arc<Vec<Project>>,
+}
+
+/// The parts of a GitLab project we care about
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct Project {
+    pub path_with_namespace: String,
+    pub http_url_to_repo: Option<String>,
+    pub ssh_url_to_repo: Option<String>,
+    pub last_activity_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    username: String,
+}
+
+impl State {
+    pub(crate) fn new(url: impl Into<String>, token: SecretString) -> Self {
+        Self {
+            url: url.into().trim_end_matches('/').to_owned(),
+            token,
+            repositories: Arc::default(),
+        }
+    }
+
+    /// The host repositories of this instance are named under
+    pub(crate) fn host(&self) -> Result<String> {
+        Url::parse(&self.url)
+            .ok()
+            .and_then(|url| url.host_str().map(ToOwned::to_owned))
+            .ok_or(RemoteError::Configuration("gitlab url"))
+    }
+
+    /// The `RepoRef` of a project on this instance
+    pub(crate) fn reporef(&self, project: &Project) -> Result<RepoRef> {
+        let name = format!("{}/{}", self.host()?, project.path_with_namespace);
+        Ok(RepoRef::new(Backend::Gitlab, &name)?)
+    }
+
+    /// Check the token, and return the name of the user it belongs to
+    pub(crate) async fn validate(&self) -> Result<String> {
+        let user: User = self.get("user", &[]).await?.json().await?;
+        Ok(user.username)
+    }
+
+    /// Get every project the token's user is a member of
+    pub async fn current_repo_list(&self) -> Result<Vec<Project>> {
+        let mut results = vec![];
+        for page in 1.. {
+            let page = page.to_string();
+            let projects: Vec<Project> = self
+                .get(
+                    "projects",
+                    &[
+                        ("membership", "true"),
+                        ("simple", "true"),
+                        ("per_page", "100"),
+                        ("page", &page),
+                    ],
+                )
+                .await?
+                .json()
+                .await?;
+
+            if projects.is_empty() {
+                break;
+            }
+
+            results.extend(projects);
+        }
+
+        Ok(results)
+    }
+
+    /// Create a new object with the updated repositories list
+    pub fn update_repositories(self, repos: Vec<Project>) -> Self {
+        Self {
+            repositories: repos.into(),
+            ..self
+        }
+    }
+
+    pub(crate) async fn clone_repo(&self, repo: &Repository, reporef: &RepoRef) -> Result<()> {
+        self.check_repo(reporef).await?;
+        git_clone(self.git_cred(), &self.clone_url(reporef), repo).await
+    }
+
+    pub(crate) async fn pull_repo(&self, repo: &Repository, reporef: &RepoRef) -> Result<()> {
+        self.check_repo(reporef).await?;
+        git_pull(self.git_cred(), repo).await
+    }
+
+    async fn check_repo(&self, reporef: &RepoRef) -> Result<()> {
+        // Project paths can be used in place of IDs, with the slashes encoded
+        let id = reporef.gitlab_path().replace('/', "%2F");
+        self.get(&format!("projects/{id}"), &[]).await?;
+        Ok(())
+    }
+
+    /// Clone through the instance URL, rather than the host in the `RepoRef`, so instances
+    /// behind a port or a path prefix work
+    fn clone_url(&self, reporef: &RepoRef) -> String {
+        format!("{}/{}.git", self.url, reporef.gitlab_path())
+    }
+
+    fn git_cred(&self) -> GitCreds {
+        GitCreds {
+            username: "oauth2".into(),
+            password: self.token.expose_secret().into(),
+        }
+    }
+
+    async fn get(&self, endpoint: &str, query: &[(&str, &str)]) -> Result<reqwest::Response> {
+        let response = crate::network::client()
+            .get(format!("{}/api/v4/{endpoint}", self.url))
+            .header(TOKEN_HEADER, self.token.expose_secret())
+            .query(query)
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Err(RemoteError::RemoteNotFound),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(RemoteError::PermissionDenied),
+            _ => Ok(response.error_for_status()?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{bare_repo_with_commit, git};
+
+    use axum::{extract::Query, http::HeaderMap, routing::get, Json, Router};
+    use std::net::SocketAddr;
+    use tempdir::TempDir;
+
+    const TOKEN: &str = "glpat-test";
+
+    async fn mock_gitlab() -> String {
+        async fn projects(
+            headers: HeaderMap,
+            Query(query): Query<HashMap<String, String>>,
+        ) -> std::result::Result<Json<serde_json::Value>, StatusCode> {
+            if headers.get(TOKEN_HEADER).map(|v| v.as_bytes()) != Some(TOKEN.as_bytes()) {
+                return Err(StatusCode::UNAUTHORIZED);
+            }
+
+            let projects = match query.get("page").map(String::as_str) {
+                Some("1") => serde_json::json!([
+                    {
+                        "path_with_namespace": "group/project",
+                        "http_url_to_repo": "https://gitlab.example.com/group/project.git",
+                        "last_activity_at": "2023-07-01T10:00:00Z",
+                    },
+                    {
+                        "path_with_namespace": "group/subgroup/project",
+                        "last_activity_at": "2023-07-02T10:00:00Z",
+                    },
+                ]),
+                _ => serde_json::json!([]),
+            };
+
+            Ok(Json(projects))
+        }
+
+        let app = Router::new()
+            .route("/api/v4/projects", get(projects))
+            .route(
+                "/api/v4/projects/group%2Fproject",
+                get(|| async { Json(serde_json::json!({})) }),
+            );
+
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        url
+    }
+
+    #[tokio::test]
+    async fn list_projects() {
+        let url = mock_gitlab().await;
+        let gitlab = State::new(url, TOKEN.to_owned().into());
+
+        let projects = gitlab.current_repo_list().await.unwrap();
+        let reporefs = projects
+            .iter()
+            .map(|p| gitlab.reporef(p).unwrap().to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            reporefs,
+            [
+                "127.0.0.1/group/project",
+                "127.0.0.1/group/subgroup/project"
+            ]
+        );
+
+        let unknown = "127.0.0.1/group/unknown".parse::<RepoRef>().unwrap();
+        assert!(matches!(
+            gitlab.check_repo(&unknown).await,
+            Err(RemoteError::RemoteNotFound)
+        ));
+
+        let gitlab = State::new(gitlab.url, "wrong".to_owned().into());
+        assert!(matches!(
+            gitlab.current_repo_list().await,
+            Err(RemoteError::PermissionDenied)
+        ));
+    }
+
+    #[tokio::test]
+    async fn clone_and_pull() {
+        let tmpdir = TempDir::new("test-gitlab-clone").unwrap();
+        let work = bare_repo_with_commit(tmpdir.path(), "served/group/project.git");
+        let served = tmpdir.path().join("served");
+
+        let reporef = "127.0.0.1/group/project".parse::<RepoRef>().unwrap();
+        let repo = Repository::remote_at(&reporef, tmpdir.path().join("clone"));
+
+        let api = State::new(mock_gitlab().await, TOKEN.to_owned().into());
+        api.check_repo(&reporef).await.unwrap();
+
+        // The mock server doesn't speak git, so the git operations go to a bare repo laid out
+        // the way the instance would serve it
+        let gitlab = State::new(
+            format!("file://{}", served.display()),
+            TOKEN.to_owned().into(),
+        );
+        git_clone(gitlab.git_cred(), &gitlab.clone_url(&reporef), &repo)
+            .await
+            .unwrap();
+
+        let head = || {
+            gix::open(&repo.disk_path)
+                .unwrap()
+                .find_reference("refs/remotes/origin/main")
+                .unwrap()
+                .id()
+                .detach()
+        };
+        let first = head();
+
+        std::fs::write(work.join("README.md"), "hello again").unwrap();
+        git(&work, &["commit", "-q", "-am", "second"]);
+        git(
+            &work,
+            &["push", "-q", "../served/group/project.git", "main"],
+        );
+
+        git_pull(gitlab.git_cred(), &repo).await.unwrap();
+        assert_ne!(first, head());
+    }
+}
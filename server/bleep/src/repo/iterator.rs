@@ -7,10 +7,15 @@ use tracing::warn;
 
 mod fs;
 mod git;
+mod hg;
 pub(super) mod language;
 
 pub use fs::FileWalker;
-pub use git::{BranchFilter, GitWalker};
+pub use git::{
+    commit_history, parse_gitmodules, BranchFilter, CommitInfo, GitWalker, Submodule,
+    WORKTREE_BRANCH,
+};
+pub use hg::HgWalker;
 
 use crate::background::SyncPipes;
 
@@ -54,17 +59,39 @@ impl RepoDirEntry {
             RepoDirEntry::Other => None,
         }
     }
+
+    /// The path of the submodule this entry comes from, relative to the repository root
+    pub fn submodule(&self) -> Option<&str> {
+        match self {
+            RepoDirEntry::Dir(d) => d.submodule.as_deref(),
+            RepoDirEntry::File(f) => f.submodule.as_deref(),
+            RepoDirEntry::Other => None,
+        }
+    }
+
+    /// The time this entry was last changed, if the walker could tell.
+    pub fn last_commit_unix_secs(&self) -> Option<u64> {
+        match self {
+            RepoDirEntry::Dir(d) => d.last_commit_unix_secs,
+            RepoDirEntry::File(f) => f.last_commit_unix_secs,
+            RepoDirEntry::Other => None,
+        }
+    }
 }
 
 pub struct RepoDir {
     pub path: String,
     pub branches: Vec<String>,
+    pub last_commit_unix_secs: Option<u64>,
+    pub submodule: Option<String>,
 }
 
 pub struct RepoFile {
     pub path: String,
     pub buffer: String,
     pub branches: Vec<String>,
+    pub last_commit_unix_secs: Option<u64>,
+    pub submodule: Option<String>,
 }
 
 #[derive(Hash, Eq, PartialEq)]
@@ -82,7 +109,10 @@ fn should_index<P: AsRef<Path>>(p: &P) -> bool {
     let path = p.as_ref();
 
     // TODO: Make this more robust
-    if path.components().any(|c| c.as_os_str() == ".git") {
+    if path
+        .components()
+        .any(|c| c.as_os_str() == ".git" || c.as_os_str() == ".hg")
+    {
         return false;
     }
 
@@ -188,6 +218,8 @@ mod test {
             // Ignore .git directory.
             (".git/HEAD", false),
             (".git/config", false),
+            (".hg/store/00changelog.i", false),
+            (".hgignore", true),
             (".gitignore", true),
             (".github/workflows/ci.yml", true),
         ];
@@ -2,7 +2,10 @@ use super::*;
 
 use tracing::warn;
 
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
 pub struct FileWalker {
     file_list: Vec<PathBuf>,
@@ -46,6 +49,13 @@ impl FileSource for FileWalker {
         self.file_list
             .into_par_iter()
             .filter_map(|entry_disk_path| {
+                // without any history, the modification time is the best guess we have
+                let last_commit_unix_secs = std::fs::metadata(&entry_disk_path)
+                    .and_then(|meta| meta.modified())
+                    .ok()
+                    .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+                    .map(|time| time.as_secs());
+
                 if entry_disk_path.is_file() {
                     let buffer = match std::fs::read_to_string(&entry_disk_path) {
                         Err(err) => {
@@ -58,11 +68,15 @@ impl FileSource for FileWalker {
                         buffer,
                         path: entry_disk_path.to_string_lossy().to_string(),
                         branches: vec![HEAD.into()],
+                        last_commit_unix_secs,
+                        submodule: None,
                     }))
                 } else if entry_disk_path.is_dir() {
                     Some(RepoDirEntry::Dir(RepoDir {
                         path: entry_disk_path.to_string_lossy().to_string(),
                         branches: vec![HEAD.into()],
+                        last_commit_unix_secs,
+                        submodule: None,
                     }))
                 } else {
                     Some(RepoDirEntry::Other)
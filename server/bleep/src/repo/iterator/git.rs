@@ -1,17 +1,31 @@
-use crate::repo::RepoRef;
+use crate::repo::{IndexedBranch, RepoRef};
 
 use super::*;
 
 use anyhow::Result;
-use gix::ThreadSafeRepository;
+use gix::{
+    bstr::{BStr, BString, ByteSlice},
+    objs::tree::EntryMode,
+    ObjectId, ThreadSafeRepository,
+};
 use regex::RegexSet;
-use tracing::{error, trace};
+use tracing::{debug, error, trace};
 
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque},
     path::Path,
 };
 
+mod worktree;
+
+use worktree::Worktree;
+pub use worktree::WORKTREE_BRANCH;
+
+/// The number of commits to look through when finding the last commit that changed each file.
+///
+/// Files that haven't changed in that many commits are dated to the oldest commit we looked at.
+const MAX_HISTORY_DEPTH: usize = 10_000;
+
 pub enum BranchFilter {
     All,
     Head,
@@ -19,13 +33,25 @@ pub enum BranchFilter {
 }
 
 impl BranchFilter {
-    fn filter(&self, is_head: bool, branch: &str) -> bool {
+    pub(super) fn filter(&self, is_head: bool, branch: &str) -> bool {
         match self {
             BranchFilter::All => true,
             BranchFilter::Select(patterns) => is_head || patterns.is_match(branch),
             BranchFilter::Head => is_head,
         }
     }
+
+    /// Tags are named `tags/<name>`, and are only selected by patterns that start with `tags/`,
+    /// as repositories tend to have a lot of them.
+    pub(super) fn filter_tag(&self, tag: &str) -> bool {
+        match self {
+            BranchFilter::Select(patterns) => patterns
+                .matches(tag)
+                .into_iter()
+                .any(|i| patterns.patterns()[i].starts_with("tags/")),
+            BranchFilter::All | BranchFilter::Head => false,
+        }
+    }
 }
 
 impl Default for BranchFilter {
@@ -35,13 +61,31 @@ impl Default for BranchFilter {
 }
 
 fn human_readable_branch_name(r: &gix::Reference<'_>) -> String {
-    use gix::bstr::ByteSlice;
     r.name().shorten().to_str_lossy().to_string()
 }
 
 pub struct GitWalker {
     git: ThreadSafeRepository,
     entries: HashMap<(String, FileType, gix::ObjectId), BTreeSet<String>>,
+    last_commits: HashMap<(String, gix::ObjectId), u64>,
+    branches: HashMap<String, IndexedBranch>,
+    changed: Option<Vec<String>>,
+
+    /// Submodules walked at their pinned commits, with their path relative to the root
+    submodules: Vec<(String, ThreadSafeRepository)>,
+    /// The submodule each entry from a submodule comes from, by path on disk
+    submodule_of: HashMap<String, usize>,
+
+    /// Entries of the working tree that are read from disk, by path on disk and object id
+    on_disk: HashSet<(String, ObjectId)>,
+}
+
+/// A submodule, as listed in `.gitmodules`
+#[derive(Debug, PartialEq, Eq)]
+pub struct Submodule {
+    pub name: String,
+    pub path: String,
+    pub url: String,
 }
 
 impl GitWalker {
@@ -49,6 +93,50 @@ impl GitWalker {
         reporef: &RepoRef,
         dir: impl AsRef<Path>,
         filter: impl Into<Option<BranchFilter>>,
+    ) -> Result<Self> {
+        Self::open(reporef, dir, filter, None, false, false)
+    }
+
+    /// Only walk the paths that changed since each branch was at its `indexed` commit.
+    ///
+    /// Every file is walked if the set of branches is different from the one that was indexed,
+    /// or if the history of any branch was rewritten since.
+    ///
+    /// With `submodules`, the files of submodules are walked too, at the commit each branch pins
+    /// them to. Every file is walked then, as changes inside submodules aren't tracked.
+    ///
+    /// With `worktree`, uncommitted changes in the working tree are walked as the
+    /// [`WORKTREE_BRANCH`] pseudo-branch, for local repositories.
+    pub fn open_incremental(
+        reporef: &RepoRef,
+        dir: impl AsRef<Path>,
+        filter: impl Into<Option<BranchFilter>>,
+        indexed: &HashMap<String, IndexedBranch>,
+        submodules: bool,
+        worktree: bool,
+    ) -> Result<Self> {
+        Self::open(reporef, dir, filter, Some(indexed), submodules, worktree)
+    }
+
+    /// The commit that each walked branch points to.
+    pub fn branches(&self) -> &HashMap<String, IndexedBranch> {
+        &self.branches
+    }
+
+    /// Paths on disk that changed since the branches were last indexed, including removed ones.
+    ///
+    /// This is `None` if every file is walked.
+    pub fn changed_paths(&self) -> Option<&[String]> {
+        self.changed.as_deref()
+    }
+
+    fn open(
+        reporef: &RepoRef,
+        dir: impl AsRef<Path>,
+        filter: impl Into<Option<BranchFilter>>,
+        indexed: Option<&HashMap<String, IndexedBranch>>,
+        submodules: bool,
+        worktree: bool,
     ) -> Result<Self> {
         let root_dir = dir.as_ref();
         let branches = filter.into().unwrap_or_default();
@@ -57,97 +145,144 @@ impl GitWalker {
             .open(dir.as_ref())?;
 
         let local_git = git.to_thread_local();
-        let mut head = local_git.head()?;
-
-        // HEAD name needs to be pinned to the remote pointer
-        //
-        // Otherwise the local branch will never advance to the
-        // remote's branch ref
-        //
-        // The easiest here is to check by name, and assume the
-        // default remote is `origin`, since we don't configure it
-        // otherwise.
-        let head_name = head.clone().try_into_referent().map(|r| {
-            if reporef.is_local() {
-                human_readable_branch_name(&r)
-            } else {
-                format!("origin/{}", human_readable_branch_name(&r))
+        let trees = branch_tips(&local_git, reporef, &branches)?;
+
+        let worktree = match worktree && reporef.is_local() {
+            true => Some(Worktree::read(&local_git, root_dir)?),
+            false => None,
+        };
+
+        let mut tips = trees.iter().map(|(_, _, id, _)| *id).collect::<Vec<_>>();
+        let mut walked = trees
+            .iter()
+            .map(|(is_head, branch, id, _)| {
+                let indexed = IndexedBranch {
+                    commit: id.to_string(),
+                    is_head: *is_head,
+                    changes: Default::default(),
+                };
+
+                (branch.clone(), indexed)
+            })
+            .collect::<HashMap<_, _>>();
+
+        if let Some(ref worktree) = worktree {
+            tips.push(worktree.head);
+            walked.insert(WORKTREE_BRANCH.to_owned(), worktree.indexed());
+        }
+
+        let changed = match indexed {
+            Some(_) if submodules => None,
+            Some(indexed) => {
+                let mut indexed = indexed.clone();
+                let indexed_worktree = indexed.remove(WORKTREE_BRANCH);
+
+                changed_paths(&local_git, &trees, &indexed)
+                    .and_then(|changed| match (changed, &worktree, indexed_worktree) {
+                        (Some(mut changed), Some(worktree), Some(old)) => {
+                            Ok(worktree.changed_since(&local_git, &old)?.map(|more| {
+                                changed.extend(more);
+                                changed
+                            }))
+                        }
+                        (changed, None, None) => Ok(changed),
+                        // the working tree was switched on or off since
+                        _ => Ok(None),
+                    })
+                    .unwrap_or_else(|err| {
+                        error!(
+                            ?err,
+                            "failed to diff against indexed commits; walking every file"
+                        );
+                        None
+                    })
             }
-        });
+            None => None,
+        };
 
-        let refs = local_git.references()?;
-        let trees = if head_name.is_none() && matches!(branches, BranchFilter::Head) {
-            // the current checkout is not a branch, so HEAD will not
-            // point to a real reference.
-            vec![(
-                true,
-                "HEAD".to_string(),
-                head.peel_to_commit_in_place()?.tree()?,
-            )]
-        } else {
-            refs.all()?
-                .filter_map(Result::ok)
-                // Check if it's HEAD
-                // Normalize the name of the branch for further steps
-                //
-                .map(|r| {
-                    let name = human_readable_branch_name(&r);
-                    (
-                        head_name
-                            .as_ref()
-                            .map(|head| head == &name)
-                            .unwrap_or_default(),
-                        name,
-                        r,
-                    )
-                })
-                .filter(|(_, name, _)| {
-                    if reporef.is_local() {
-                        true
-                    } else {
-                        // Only consider remote branches
-                        //
-                        name.starts_with("origin/")
+        let mut files = vec![];
+        let mut gitlinks = vec![];
+        for (is_head, branch, _, tree) in trees {
+            match &changed {
+                Some(changed) => {
+                    for path in changed {
+                        let path = path.as_bstr();
+                        if let Some((mode, oid)) = find_entry(&local_git, tree.id, path)? {
+                            let full_path = disk_path(root_dir, path);
+                            files.push((is_head, branch.clone(), full_path, mode, oid));
+                        }
                     }
-                })
-                // Apply branch filters, along whether it's HEAD
-                //
-                .filter(|(is_head, name, _)| branches.filter(*is_head, name))
-                .filter_map(|(is_head, branch, r)| -> Option<_> {
-                    Some((
-                        is_head,
-                        branch,
-                        r.into_fully_peeled_id()
-                            .ok()?
-                            .object()
-                            .ok()?
-                            .peel_to_tree()
-                            .ok()?,
-                    ))
-                })
-                .collect()
-        };
+                }
+                None => {
+                    for entry in tree.traverse().breadthfirst.files().unwrap() {
+                        let full_path = disk_path(root_dir, entry.filepath.as_bstr());
+                        trace!(?entry.filepath, ?full_path, "got path from gix");
+
+                        if submodules && entry.mode == EntryMode::Commit {
+                            gitlinks.push((
+                                is_head,
+                                branch.clone(),
+                                entry.filepath.clone(),
+                                entry.oid,
+                                tree.id,
+                            ));
+                        }
+
+                        files.push((is_head, branch.clone(), full_path, entry.mode, entry.oid));
+                    }
+                }
+            }
+        }
+
+        let mut on_disk = HashSet::new();
+        if let Some(ref worktree) = worktree {
+            for entry in worktree.entries(changed.as_ref()) {
+                let full_path = disk_path(root_dir, entry.path.as_bytes().as_bstr());
+                if entry.on_disk {
+                    on_disk.insert((full_path.clone(), entry.oid));
+                }
+
+                files.push((
+                    false,
+                    WORKTREE_BRANCH.to_owned(),
+                    full_path,
+                    entry.mode,
+                    entry.oid,
+                ));
+            }
+        }
+
+        let mut modules = SubmoduleRepos::default();
+        let mut module_tips = HashMap::<usize, Vec<ObjectId>>::new();
+        let mut submodule_of = HashMap::new();
+
+        for (is_head, branch, path, pinned, tree) in gitlinks {
+            let Some(index) = modules.open(&local_git, root_dir, tree, path.as_bstr()) else {
+                continue;
+            };
+
+            let module = modules.repos[index].1.to_thread_local();
+            let Ok(Some(commit)) = module
+                .try_find_object(pinned)
+                .map(|object| object.and_then(|o| o.try_into_commit().ok()))
+            else {
+                debug!(?path, %pinned, "pinned commit of submodule isn't fetched; skipping");
+                continue;
+            };
+
+            module_tips.entry(index).or_default().push(pinned);
+
+            let module_dir = root_dir.join(path.to_str_lossy().as_ref());
+            for entry in commit.tree()?.traverse().breadthfirst.files().unwrap() {
+                let full_path = disk_path(&module_dir, entry.filepath.as_bstr());
+                submodule_of.insert(full_path.clone(), index);
+                files.push((is_head, branch.clone(), full_path, entry.mode, entry.oid));
+            }
+        }
 
-        let entries = trees
+        let entries = files
             .into_iter()
-            .flat_map(|(is_head, branch, tree)| {
-                let files = tree.traverse().breadthfirst.files().unwrap().into_iter();
-
-                files
-                    .map(move |entry| {
-                        let strpath = String::from_utf8_lossy(entry.filepath.as_ref());
-                        let full_path = root_dir.join(strpath.as_ref());
-                        trace!(?strpath, ?full_path, "got path from gix");
-                        (
-                            is_head,
-                            branch.clone(),
-                            full_path.to_string_lossy().to_string(),
-                            entry.mode,
-                            entry.oid,
-                        )
-                    })
-                    .filter(|(_, _, path, _, _)| should_index(path))
-            })
+            .filter(|(_, _, path, _, _)| should_index(path))
             .fold(
                 HashMap::new(),
                 |mut acc, (is_head, branch, file, mode, oid)| {
@@ -169,8 +304,666 @@ impl GitWalker {
                 },
             );
 
-        Ok(Self { git, entries })
+        // files read from disk are dated by their modification time instead
+        let (module_pending, pending): (HashSet<_>, HashSet<_>) = entries
+            .keys()
+            .map(|(path, _, oid)| (path.clone(), *oid))
+            .filter(|key| !on_disk.contains(key))
+            .partition(|(path, _)| submodule_of.contains_key(path));
+
+        let mut last_commits = last_commit_times(&local_git, root_dir, tips, pending)
+            .unwrap_or_else(|err| {
+                error!(?err, "failed to walk history; files will be dated by HEAD");
+                HashMap::new()
+            });
+
+        // files in submodules are dated by the history of the submodule
+        for (index, tips) in module_tips {
+            let (path, module) = &modules.repos[index];
+            let pending = module_pending
+                .iter()
+                .filter(|(file, _)| submodule_of.get(file) == Some(&index))
+                .cloned()
+                .collect();
+
+            match last_commit_times(
+                &module.to_thread_local(),
+                &root_dir.join(path),
+                tips,
+                pending,
+            ) {
+                Ok(times) => last_commits.extend(times),
+                Err(err) => error!(?err, path, "failed to walk submodule history"),
+            }
+        }
+
+        Ok(Self {
+            git,
+            entries,
+            last_commits,
+            branches: walked,
+            changed: changed.map(|changed| {
+                changed
+                    .iter()
+                    .map(|path| disk_path(root_dir, path.as_bstr()))
+                    .collect()
+            }),
+            submodules: modules.repos,
+            submodule_of,
+            on_disk,
+        })
+    }
+}
+
+/// The repositories of the submodules found while walking, opened once each
+#[derive(Default)]
+struct SubmoduleRepos {
+    repos: Vec<(String, ThreadSafeRepository)>,
+    by_path: HashMap<BString, Option<usize>>,
+}
+
+impl SubmoduleRepos {
+    /// Open the repository of the submodule at `path`, as listed in the `.gitmodules` file of
+    /// `tree`.
+    ///
+    /// Git keeps submodules in `modules/<name>` in the parent's git directory, and so do our
+    /// clones. Older checkouts keep them in the submodule's directory instead.
+    fn open(
+        &mut self,
+        git: &gix::Repository,
+        root_dir: &Path,
+        tree: ObjectId,
+        path: &BStr,
+    ) -> Option<usize> {
+        if let Some(index) = self.by_path.get(path) {
+            return *index;
+        }
+
+        let relative_path = path.to_str_lossy().to_string();
+        let name = gitmodules(git, tree)
+            .into_iter()
+            .find(|module| module.path == relative_path)
+            .map(|module| module.name)
+            .unwrap_or_else(|| relative_path.clone());
+
+        let index = [
+            git.path().join("modules").join(&name),
+            root_dir.join(&relative_path),
+        ]
+        .into_iter()
+        .find_map(|dir| {
+            gix::open::Options::isolated()
+                .filter_config_section(|_| false)
+                .open(dir)
+                .ok()
+        })
+        .map(|repo| {
+            self.repos.push((relative_path.clone(), repo));
+            self.repos.len() - 1
+        });
+
+        if index.is_none() {
+            debug!(path = relative_path, "submodule isn't cloned; skipping");
+        }
+
+        self.by_path.insert(path.to_owned(), index);
+        index
+    }
+}
+
+/// The submodules listed in the `.gitmodules` file of `tree`
+fn gitmodules(git: &gix::Repository, tree: ObjectId) -> Vec<Submodule> {
+    let Ok(Some((_, oid))) = find_entry(git, tree, b".gitmodules".as_bstr()) else {
+        return vec![];
+    };
+
+    git.find_object(oid)
+        .map(|object| parse_gitmodules(&String::from_utf8_lossy(&object.data)))
+        .unwrap_or_default()
+}
+
+/// Parse the submodules from the contents of a `.gitmodules` file, leaving out ones without a
+/// path or URL, and ones with names that could point outside the `modules` directory.
+pub fn parse_gitmodules(contents: &str) -> Vec<Submodule> {
+    let mut modules = vec![];
+    let mut current: Option<(String, Option<String>, Option<String>)> = None;
+
+    let mut finish = |current: Option<(String, Option<String>, Option<String>)>| {
+        if let Some((name, Some(path), Some(url))) = current {
+            let unsafe_name = name.is_empty()
+                || Path::new(&name).is_absolute()
+                || name.split(['/', '\\']).any(|part| part == "..");
+
+            if !unsafe_name {
+                modules.push(Submodule { name, path, url });
+            }
+        }
+    };
+
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            finish(current.take());
+
+            current = section
+                .trim()
+                .strip_prefix("submodule")
+                .map(str::trim)
+                .and_then(|name| name.strip_prefix('"')?.strip_suffix('"'))
+                .map(|name| (name.to_owned(), None, None));
+
+            continue;
+        }
+
+        let (Some((_, path, url)), Some((key, value))) = (&mut current, line.split_once('='))
+        else {
+            continue;
+        };
+
+        let value = value.trim().trim_matches('"').to_owned();
+        match key.trim() {
+            "path" => *path = Some(value.trim_end_matches('/').to_owned()),
+            "url" => *url = Some(value),
+            _ => {}
+        }
+    }
+
+    finish(current);
+    modules
+}
+
+/// Resolve the branches selected by `filter` to the commit and tree they point to.
+fn branch_tips<'repo>(
+    git: &'repo gix::Repository,
+    reporef: &RepoRef,
+    filter: &BranchFilter,
+) -> Result<Vec<(bool, String, ObjectId, gix::Tree<'repo>)>> {
+    let mut head = git.head()?;
+
+    // HEAD name needs to be pinned to the remote pointer
+    //
+    // Otherwise the local branch will never advance to the
+    // remote's branch ref
+    //
+    // The easiest here is to check by name, and assume the
+    // default remote is `origin`, since we don't configure it
+    // otherwise.
+    let head_name = head.clone().try_into_referent().map(|r| {
+        if reporef.is_local() {
+            human_readable_branch_name(&r)
+        } else {
+            format!("origin/{}", human_readable_branch_name(&r))
+        }
+    });
+
+    let refs = git.references()?;
+    let tips = if head_name.is_none() && matches!(filter, BranchFilter::Head) {
+        // the current checkout is not a branch, so HEAD will not
+        // point to a real reference.
+        let commit = head.peel_to_commit_in_place()?;
+        vec![(true, "HEAD".to_string(), commit.id, commit.tree()?)]
+    } else {
+        refs.all()?
+            .filter_map(Result::ok)
+            // Check if it's HEAD
+            // Normalize the name of the branch for further steps
+            //
+            // Tags are indexed alongside branches, as `tags/<name>`
+            //
+            .map(|r| {
+                let is_tag = r.name().as_bstr().starts_with(b"refs/tags/");
+                let name = if is_tag {
+                    format!("tags/{}", human_readable_branch_name(&r))
+                } else {
+                    human_readable_branch_name(&r)
+                };
+
+                (
+                    head_name
+                        .as_ref()
+                        .map(|head| head == &name)
+                        .unwrap_or_default(),
+                    is_tag,
+                    name,
+                    r,
+                )
+            })
+            .filter(|(_, is_tag, name, _)| {
+                if reporef.is_local() || *is_tag {
+                    true
+                } else {
+                    // Only consider remote branches, and the heads of pull requests, which are
+                    // fetched as `refs/remotes/pr/<number>`
+                    //
+                    name.starts_with("origin/") || name.starts_with("pr/")
+                }
+            })
+            // Apply branch filters, along whether it's HEAD
+            //
+            .filter(|(is_head, is_tag, name, _)| {
+                if *is_tag {
+                    filter.filter_tag(name)
+                } else {
+                    filter.filter(*is_head, name)
+                }
+            })
+            .filter_map(|(is_head, _, branch, r)| -> Option<_> {
+                let commit = r
+                    .into_fully_peeled_id()
+                    .ok()?
+                    .object()
+                    .ok()?
+                    .try_into_commit()
+                    .ok()?;
+
+                Some((is_head, branch, commit.id, commit.tree().ok()?))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    Ok(tips)
+}
+
+fn disk_path(root_dir: &Path, path: &BStr) -> String {
+    root_dir
+        .join(path.to_str_lossy().as_ref())
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Find the paths that changed in any of the `branches` since they were at their `indexed`
+/// commits, relative to the repository root.
+///
+/// Returns `None` if the branches are not the ones that were indexed, or if any of them no
+/// longer contains the commit it was indexed at.
+fn changed_paths(
+    git: &gix::Repository,
+    branches: &[(bool, String, ObjectId, gix::Tree<'_>)],
+    indexed: &HashMap<String, IndexedBranch>,
+) -> Result<Option<HashSet<BString>>> {
+    if branches.len() != indexed.len() {
+        return Ok(None);
+    }
+
+    let mut changed = HashSet::new();
+
+    for (is_head, branch, tip, tree) in branches {
+        let Some(old) = indexed.get(branch).filter(|old| old.is_head == *is_head) else {
+            return Ok(None);
+        };
+
+        let Ok(old) = ObjectId::from_hex(old.commit.as_bytes()) else {
+            return Ok(None);
+        };
+
+        if old == *tip {
+            continue;
+        }
+
+        if !is_ancestor(git, old, *tip)? {
+            trace!(?branch, "history was rewritten");
+            return Ok(None);
+        }
+
+        let old_tree = git.find_object(old)?.try_into_commit()?.tree_id()?.detach();
+        diff_trees(
+            git,
+            Some(old_tree),
+            Some(tree.id),
+            &mut BString::default(),
+            &mut |path, _, _| {
+                changed.insert(path.to_owned());
+            },
+        )?;
+    }
+
+    Ok(Some(changed))
+}
+
+/// Whether `ancestor` is reachable from `commit`, looking at most `MAX_HISTORY_DEPTH` commits
+/// back.
+fn is_ancestor(git: &gix::Repository, ancestor: ObjectId, commit: ObjectId) -> Result<bool> {
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([commit]);
+
+    while let Some(id) = queue.pop_front() {
+        if id == ancestor {
+            return Ok(true);
+        }
+
+        if seen.len() == MAX_HISTORY_DEPTH {
+            break;
+        }
+
+        if seen.insert(id) {
+            // shallow clones end before the root commits
+            let Some(object) = git.try_find_object(id)? else {
+                continue;
+            };
+
+            queue.extend(object.try_into_commit()?.parent_ids().map(|id| id.detach()));
+        }
+    }
+
+    Ok(false)
+}
+
+/// The parents of `commit` that are in the repository, as shallow clones lack the parents of
+/// their oldest commits. The second value is whether any parent is missing.
+fn present_parents(
+    git: &gix::Repository,
+    commit: &gix::Commit<'_>,
+) -> Result<(Vec<ObjectId>, bool)> {
+    let mut parents = vec![];
+    let mut missing = false;
+
+    for id in commit.parent_ids() {
+        if git.try_find_object(id)?.is_some() {
+            parents.push(id.detach());
+        } else {
+            missing = true;
+        }
+    }
+
+    Ok((parents, missing))
+}
+
+/// Look up the entry at `path` in the tree `root`.
+fn find_entry(
+    git: &gix::Repository,
+    root: ObjectId,
+    path: &BStr,
+) -> Result<Option<(EntryMode, ObjectId)>> {
+    let mut found = (EntryMode::Tree, root);
+
+    for name in path.split_str("/") {
+        if !found.0.is_tree() {
+            return Ok(None);
+        }
+
+        match tree_entries(git, found.1)?
+            .into_iter()
+            .find(|(entry, _, _)| entry.as_slice() == name)
+        {
+            Some((_, mode, oid)) => found = (mode, oid),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(found))
+}
+
+/// A commit in the history of the walked branches.
+pub struct CommitInfo {
+    pub id: String,
+    pub author: String,
+    pub unix_secs: u64,
+    pub message: String,
+
+    /// The files changed by this commit, relative to the repository root
+    pub changed_paths: Vec<String>,
+}
+
+/// Read the history of the branches selected by `filter`, newest commit first.
+///
+/// At most `MAX_HISTORY_DEPTH` commits are read. The files changed by each commit are found by
+/// comparing it to its first parent. They are left empty where the clone lacks the parent or the
+/// trees to compare, as with shallow and partial clones.
+pub fn commit_history(
+    reporef: &RepoRef,
+    dir: impl AsRef<Path>,
+    filter: impl Into<Option<BranchFilter>>,
+) -> Result<Vec<CommitInfo>> {
+    let git = gix::open::Options::isolated()
+        .filter_config_section(|_| false)
+        .open(dir.as_ref())?
+        .to_thread_local();
+
+    let commit_time = |id: ObjectId| -> Result<u64> {
+        Ok(git.find_object(id)?.try_into_commit()?.time()?.seconds)
+    };
+
+    let mut seen = HashSet::new();
+    let mut queue = BinaryHeap::new();
+
+    for (_, _, tip, _) in branch_tips(&git, reporef, &filter.into().unwrap_or_default())? {
+        if seen.insert(tip) {
+            queue.push((commit_time(tip)?, tip));
+        }
+    }
+
+    let mut history = vec![];
+
+    while let Some((unix_secs, id)) = queue.pop() {
+        if history.len() == MAX_HISTORY_DEPTH {
+            break;
+        }
+
+        let commit = git.find_object(id)?.try_into_commit()?;
+        let (parents, missing_parents) = present_parents(&git, &commit)?;
+
+        let parent_tree = match parents.first() {
+            Some(&parent) => Some(
+                git.find_object(parent)?
+                    .try_into_commit()?
+                    .tree_id()?
+                    .detach(),
+            ),
+            None => None,
+        };
+
+        let mut changed_paths = vec![];
+        let diffed = if parents.is_empty() && missing_parents {
+            trace!(%id, "reached the end of a shallow clone");
+            Ok(())
+        } else {
+            diff_trees(
+                &git,
+                parent_tree,
+                Some(commit.tree_id()?.detach()),
+                &mut BString::default(),
+                &mut |path, previous, current| {
+                    // directories only change along with the files in them
+                    if [previous, current]
+                        .into_iter()
+                        .flatten()
+                        .all(|(mode, _)| mode.is_tree())
+                    {
+                        return;
+                    }
+
+                    changed_paths.push(path.to_str_lossy().to_string());
+                },
+            )
+        };
+
+        // partial clones lack the trees of older commits
+        if let Err(err) = diffed {
+            trace!(?err, %id, "failed to diff commit");
+            changed_paths.clear();
+        }
+
+        let author = commit.author()?;
+        history.push(CommitInfo {
+            id: id.to_string(),
+            author: format!("{} <{}>", author.name, author.email),
+            unix_secs,
+            message: commit.message_raw()?.to_str_lossy().to_string(),
+            changed_paths,
+        });
+
+        for parent in parents {
+            if seen.insert(parent) {
+                queue.push((commit_time(parent)?, parent));
+            }
+        }
+    }
+
+    Ok(history)
+}
+
+/// Find the time of the last commit that changed each of the `pending` entries.
+///
+/// Entries are keyed by their path on disk and object id, so a file is dated by the commit that
+/// introduced its current contents. The history is walked from `tips`, newest commit first, and
+/// each commit is compared to its first parent.
+fn last_commit_times(
+    git: &gix::Repository,
+    root_dir: &Path,
+    tips: Vec<ObjectId>,
+    mut pending: HashSet<(String, ObjectId)>,
+) -> Result<HashMap<(String, ObjectId), u64>> {
+    let commit_time = |id: ObjectId| -> Result<u64> {
+        Ok(git.find_object(id)?.try_into_commit()?.time()?.seconds)
+    };
+
+    let mut times = HashMap::new();
+    let mut seen = HashSet::new();
+    let mut queue = BinaryHeap::new();
+
+    for tip in tips {
+        if seen.insert(tip) {
+            queue.push((commit_time(tip)?, tip));
+        }
+    }
+
+    let mut oldest = None;
+    let mut depth = 0;
+
+    while let Some((time, id)) = queue.pop() {
+        if pending.is_empty() || depth == MAX_HISTORY_DEPTH {
+            break;
+        }
+
+        depth += 1;
+        oldest = Some(time);
+
+        let commit = git.find_object(id)?.try_into_commit()?;
+        let (parents, _) = present_parents(git, &commit)?;
+
+        let parent_tree = match parents.first() {
+            Some(&parent) => Some(
+                git.find_object(parent)?
+                    .try_into_commit()?
+                    .tree_id()?
+                    .detach(),
+            ),
+            None => None,
+        };
+
+        let diffed = diff_trees(
+            git,
+            parent_tree,
+            Some(commit.tree_id()?.detach()),
+            &mut BString::default(),
+            &mut |path, _, current| {
+                let Some((_, oid)) = current else {
+                    return;
+                };
+
+                let key = (disk_path(root_dir, path), oid);
+
+                if pending.remove(&key) {
+                    times.insert(key, time);
+                }
+            },
+        );
+
+        // partial clones lack the trees of older commits, so this is as far back as we can see
+        if let Err(err) = diffed {
+            trace!(?err, %id, "failed to diff commit");
+            break;
+        }
+
+        for parent in parents {
+            if seen.insert(parent) {
+                queue.push((commit_time(parent)?, parent));
+            }
+        }
     }
+
+    // whatever is left hasn't changed since the oldest commit we looked at
+    if let Some(oldest) = oldest {
+        times.extend(pending.into_iter().map(|key| (key, oldest)));
+    }
+
+    Ok(times)
+}
+
+/// Call `changed` with the path of every entry that differs between the `old` and `new` trees,
+/// including directories, along with the entry in each of them. Subtrees that are the same in
+/// both are skipped entirely.
+fn diff_trees(
+    git: &gix::Repository,
+    old: Option<ObjectId>,
+    new: Option<ObjectId>,
+    prefix: &mut BString,
+    changed: &mut impl FnMut(&BStr, Option<(EntryMode, ObjectId)>, Option<(EntryMode, ObjectId)>),
+) -> Result<()> {
+    let subtrees = |id: Option<ObjectId>| -> Result<BTreeMap<_, _>> {
+        Ok(match id {
+            Some(id) => tree_entries(git, id)?
+                .into_iter()
+                .map(|(name, mode, oid)| (name, (mode, oid)))
+                .collect(),
+            None => BTreeMap::new(),
+        })
+    };
+
+    let mut old_entries = subtrees(old)?;
+    let new_entries = subtrees(new)?;
+
+    let names = new_entries
+        .keys()
+        .chain(old_entries.keys())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+
+    for name in names {
+        let previous = old_entries.remove(&name);
+        let current = new_entries.get(&name).copied();
+        if previous.map(|(_, oid)| oid) == current.map(|(_, oid)| oid) {
+            continue;
+        }
+
+        let len = prefix.len();
+        if !prefix.is_empty() {
+            prefix.push(b'/');
+        }
+        prefix.extend_from_slice(&name);
+
+        changed(prefix.as_bstr(), previous, current);
+
+        let tree = |entry: Option<(EntryMode, ObjectId)>| {
+            entry.filter(|(mode, _)| mode.is_tree()).map(|(_, oid)| oid)
+        };
+
+        if tree(previous).is_some() || tree(current).is_some() {
+            diff_trees(git, tree(previous), tree(current), prefix, changed)?;
+        }
+
+        prefix.truncate(len);
+    }
+
+    Ok(())
+}
+
+fn tree_entries(
+    git: &gix::Repository,
+    id: ObjectId,
+) -> Result<Vec<(BString, EntryMode, ObjectId)>> {
+    let tree = git.find_object(id)?.try_into_tree()?;
+    let entries = tree
+        .decode()?
+        .entries
+        .into_iter()
+        .map(|entry| (entry.filename.to_owned(), entry.mode, entry.oid.to_owned()))
+        .collect();
+
+    Ok(entries)
 }
 
 impl FileSource for GitWalker {
@@ -184,7 +977,17 @@ impl FileSource for GitWalker {
             .into_par_iter()
             .filter_map(|((path, kind, oid), branches)| {
                 trace!(?path, "walking over path");
-                let git = self.git.to_thread_local();
+                if self.on_disk.contains(&(path.clone(), oid)) {
+                    return worktree::read_entry(path, kind, branches);
+                }
+
+                let submodule = self.submodule_of.get(&path).map(|&i| &self.submodules[i]);
+                let git = match submodule {
+                    Some((_, module)) => module.to_thread_local(),
+                    None => self.git.to_thread_local(),
+                };
+                let submodule = submodule.map(|(module_path, _)| module_path.clone());
+
                 let Ok(Some(object)) = git.try_find_object(oid) else {
                     error!(?path, ?branches, "can't find object for file");
                     return None;
@@ -194,6 +997,8 @@ impl FileSource for GitWalker {
                     return None;
                 }
 
+                let last_commit_unix_secs = self.last_commits.get(&(path.clone(), oid)).copied();
+
                 let entry = match kind {
                     FileType::File => {
                         let buffer = String::from_utf8_lossy(&object.data).to_string();
@@ -201,11 +1006,15 @@ impl FileSource for GitWalker {
                             path,
                             branches: branches.into_iter().collect(),
                             buffer,
+                            last_commit_unix_secs,
+                            submodule,
                         })
                     }
                     FileType::Dir => RepoDirEntry::Dir(RepoDir {
                         path,
                         branches: branches.into_iter().collect(),
+                        last_commit_unix_secs,
+                        submodule,
                     }),
                     FileType::Other => return None,
                 };
@@ -0,0 +1,423 @@
+//! Uncommitted changes in the working tree of a local repository
+//!
+//! The working tree is indexed as a pseudo-branch, [`WORKTREE_BRANCH`], made of the files of the
+//! checked-out commit, with modified and untracked files read from disk in their place. Ignored
+//! files are left out, as they are by git.
+
+use std::time::{Duration, SystemTime};
+
+use gix::index::entry::Mode;
+use rayon::prelude::*;
+
+use super::*;
+
+/// The name the working tree is indexed under
+pub const WORKTREE_BRANCH: &str = "(working tree)";
+
+pub(super) struct Worktree {
+    /// The checked-out commit
+    pub(super) head: ObjectId,
+
+    /// The entries of the checked-out commit, by path relative to the repository root
+    tree: HashMap<String, (EntryMode, ObjectId)>,
+
+    /// Files that differ from the checked-out commit, by path relative to the repository root.
+    /// Deleted files have no object id.
+    changes: BTreeMap<String, Option<ObjectId>>,
+}
+
+/// An entry of the working tree, along with whether it has to be read from disk
+pub(super) struct WorktreeEntry {
+    pub(super) path: String,
+    pub(super) mode: EntryMode,
+    pub(super) oid: ObjectId,
+    pub(super) on_disk: bool,
+}
+
+impl Worktree {
+    /// Compare the files in the working tree at `root_dir` to the checked-out commit.
+    ///
+    /// Tracked files are only hashed if their size or modification time changed since git's index
+    /// last recorded them, so edits between syncs only read the files that were touched.
+    pub(super) fn read(git: &gix::Repository, root_dir: &Path) -> Result<Self> {
+        let commit = git.head_commit()?;
+        let tree = commit
+            .tree()?
+            .traverse()
+            .breadthfirst
+            .files()
+            .unwrap()
+            .into_iter()
+            .map(|entry| {
+                (
+                    entry.filepath.to_str_lossy().to_string(),
+                    (entry.mode, entry.oid),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+
+        let changes = changes(root_dir, git.object_hash(), &tree, &stat_cache(git));
+        trace!(changes = changes.len(), "read working tree");
+
+        Ok(Self {
+            head: commit.id,
+            tree,
+            changes,
+        })
+    }
+
+    /// The state of the working tree, to find what changed when it's next indexed
+    pub(super) fn indexed(&self) -> IndexedBranch {
+        IndexedBranch {
+            commit: self.head.to_string(),
+            is_head: false,
+            changes: self
+                .changes
+                .iter()
+                .map(|(path, oid)| (path.clone(), oid.map(|o| o.to_string()).unwrap_or_default()))
+                .collect(),
+        }
+    }
+
+    /// The paths that changed since the working tree was `indexed`, relative to the repository
+    /// root.
+    ///
+    /// Returns `None` if the commit it was based on is gone.
+    pub(super) fn changed_since(
+        &self,
+        git: &gix::Repository,
+        indexed: &IndexedBranch,
+    ) -> Result<Option<HashSet<BString>>> {
+        let mut changed = HashSet::new();
+
+        let Ok(old) = ObjectId::from_hex(indexed.commit.as_bytes()) else {
+            return Ok(None);
+        };
+
+        if old != self.head {
+            let Some(object) = git.try_find_object(old)? else {
+                return Ok(None);
+            };
+
+            let old_tree = object.try_into_commit()?.tree_id()?.detach();
+            let new_tree = git
+                .find_object(self.head)?
+                .try_into_commit()?
+                .tree_id()?
+                .detach();
+            diff_trees(
+                git,
+                Some(old_tree),
+                Some(new_tree),
+                &mut BString::default(),
+                &mut |path, _, _| {
+                    changed.insert(path.to_owned());
+                },
+            )?;
+        }
+
+        let current = self.indexed().changes;
+        let paths = current.keys().chain(indexed.changes.keys());
+        for path in paths.filter(|p| current.get(*p) != indexed.changes.get(*p)) {
+            // directories with untracked files come and go with them
+            changed.extend(ancestors(path).map(BString::from));
+            changed.insert(path.as_str().into());
+        }
+
+        Ok(Some(changed))
+    }
+
+    /// The entries of the working tree, or only the ones at `changed` paths
+    pub(super) fn entries(&self, changed: Option<&HashSet<BString>>) -> Vec<WorktreeEntry> {
+        let selected = |path: &str| changed.map_or(true, |c| c.contains(path.as_bytes().as_bstr()));
+        let mut entries = vec![];
+
+        for (path, &(mode, oid)) in &self.tree {
+            if selected(path) && !self.changes.contains_key(path) {
+                entries.push(WorktreeEntry {
+                    path: path.clone(),
+                    mode,
+                    oid,
+                    on_disk: false,
+                });
+            }
+        }
+
+        for (path, oid) in &self.changes {
+            let Some(oid) = oid else {
+                continue;
+            };
+
+            if selected(path) {
+                entries.push(WorktreeEntry {
+                    path: path.clone(),
+                    mode: EntryMode::Blob,
+                    oid: *oid,
+                    on_disk: true,
+                });
+            }
+
+            // directories that only have untracked files in them aren't in the tree
+            for dir in ancestors(path) {
+                if self.tree.contains_key(dir) || !selected(dir) {
+                    continue;
+                }
+
+                entries.push(WorktreeEntry {
+                    path: dir.to_owned(),
+                    mode: EntryMode::Tree,
+                    oid: ObjectId::null(self.head.kind()),
+                    on_disk: true,
+                });
+            }
+        }
+
+        entries
+    }
+}
+
+/// Read an entry of the working tree from disk. Files are dated by their modification time.
+pub(super) fn read_entry(
+    path: String,
+    kind: FileType,
+    branches: BTreeSet<String>,
+) -> Option<RepoDirEntry> {
+    let last_commit_unix_secs = std::fs::metadata(&path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|time| time.as_secs());
+
+    let entry = match kind {
+        FileType::File => {
+            let data = std::fs::read(&path).ok()?;
+            if data.len() as u64 > MAX_FILE_LEN {
+                return None;
+            }
+
+            RepoDirEntry::File(RepoFile {
+                path,
+                buffer: String::from_utf8_lossy(&data).to_string(),
+                branches: branches.into_iter().collect(),
+                last_commit_unix_secs,
+                submodule: None,
+            })
+        }
+        FileType::Dir => RepoDirEntry::Dir(RepoDir {
+            path,
+            branches: branches.into_iter().collect(),
+            last_commit_unix_secs,
+            submodule: None,
+        }),
+        FileType::Other => return None,
+    };
+
+    Some(entry)
+}
+
+/// A file as git's index last recorded it
+struct Cached {
+    oid: ObjectId,
+    size: u32,
+    mtime: SystemTime,
+}
+
+impl Cached {
+    /// Whether the file is still the one that was recorded, going by its metadata
+    fn matches(&self, meta: &std::fs::Metadata) -> bool {
+        // the index only keeps the lower 32 bits of the size
+        meta.len() as u32 == self.size && meta.modified().map_or(false, |t| t == self.mtime)
+    }
+}
+
+/// The files recorded in git's index, by path relative to the repository root.
+///
+/// Files modified when the index was written could have changed again without their metadata
+/// changing, so they are left out, as git does with them.
+fn stat_cache(git: &gix::Repository) -> HashMap<String, Cached> {
+    let Ok(index) = git.open_index() else {
+        return HashMap::new();
+    };
+
+    let Ok(written) = std::fs::metadata(git.git_dir().join("index")).and_then(|m| m.modified())
+    else {
+        return HashMap::new();
+    };
+
+    index
+        .entries()
+        .iter()
+        .filter(|entry| {
+            entry.stage() == 0 && matches!(entry.mode, Mode::FILE | Mode::FILE_EXECUTABLE)
+        })
+        .filter_map(|entry| {
+            let mtime = SystemTime::UNIX_EPOCH
+                + Duration::new(entry.stat.mtime.secs.into(), entry.stat.mtime.nsecs);
+
+            (mtime < written).then(|| {
+                let cached = Cached {
+                    oid: entry.id,
+                    size: entry.stat.size,
+                    mtime,
+                };
+
+                (entry.path(&index).to_str_lossy().into_owned(), cached)
+            })
+        })
+        .collect()
+}
+
+/// Hash the tracked files and the untracked ones that aren't ignored, and keep the ones that
+/// differ from `tree`. Files that still match `cache` aren't read.
+///
+/// Symlinks, and files too large to be indexed, are left as they are committed. So are nested
+/// repositories, like submodules.
+fn changes(
+    root_dir: &Path,
+    hash_kind: gix::hash::Kind,
+    tree: &HashMap<String, (EntryMode, ObjectId)>,
+    cache: &HashMap<String, Cached>,
+) -> BTreeMap<String, Option<ObjectId>> {
+    let tracked = tree
+        .iter()
+        .filter(|(_, (mode, _))| matches!(mode, EntryMode::Blob | EntryMode::BlobExecutable))
+        .map(|(path, (_, oid))| (path.as_str(), *oid))
+        .collect::<HashMap<_, _>>();
+
+    let untracked = ignore::WalkBuilder::new(root_dir)
+        .standard_filters(true)
+        .hidden(false)
+        .filter_entry(|entry| {
+            entry.depth() == 0
+                || (entry.file_name() != ".git" && !entry.path().join(".git").exists())
+        })
+        .build()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().map_or(false, |t| t.is_file()))
+        .filter_map(|entry| relative_path(root_dir, entry.path()))
+        .filter(|path| !tracked.contains_key(path.as_str()));
+
+    tracked
+        .keys()
+        .map(|path| path.to_string())
+        .chain(untracked)
+        .filter(|path| should_index(path))
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .filter_map(|path| {
+            let current = match std::fs::symlink_metadata(root_dir.join(&path)) {
+                Ok(meta) if meta.is_file() && meta.len() <= MAX_FILE_LEN => {
+                    match cache.get(path.as_str()) {
+                        Some(cached) if cached.matches(&meta) => Some(cached.oid),
+                        _ => {
+                            let data = std::fs::read(root_dir.join(&path)).ok()?;
+                            Some(gix::objs::compute_hash(
+                                hash_kind,
+                                gix::objs::Kind::Blob,
+                                &data,
+                            ))
+                        }
+                    }
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+                _ => return None,
+            };
+
+            (tracked.get(path.as_str()) != current.as_ref()).then_some((path, current))
+        })
+        .collect()
+}
+
+/// The path of `path` relative to `root_dir`, separated by `/` as in git trees
+fn relative_path(root_dir: &Path, path: &Path) -> Option<String> {
+    let parts = path
+        .strip_prefix(root_dir)
+        .ok()?
+        .components()
+        .map(|c| c.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(parts.join("/"))
+}
+
+/// The directories `path` is in, innermost first
+fn ancestors(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/').rev().map(|(i, _)| &path[..i])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::git;
+
+    use tempdir::TempDir;
+
+    #[test]
+    fn working_tree_changes() {
+        let tmpdir = TempDir::new("test-worktree").unwrap();
+        let root = tmpdir.path();
+
+        git(root, &["init", "-q", "-b", "main"]);
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join(".gitignore"), "*.tmp\n").unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(root.join("src/lib.rs"), "mod a;").unwrap();
+        std::fs::write(root.join("README.md"), "hello").unwrap();
+        git(root, &["add", "."]);
+        git(root, &["commit", "-q", "-m", "first"]);
+
+        std::fs::write(root.join("src/main.rs"), "fn main() { todo!() }").unwrap();
+        std::fs::remove_file(root.join("README.md")).unwrap();
+        std::fs::create_dir_all(root.join("new/dir")).unwrap();
+        std::fs::write(root.join("new/dir/file.rs"), "struct A;").unwrap();
+        std::fs::write(root.join("scratch.tmp"), "ignored").unwrap();
+
+        let repo = gix::open(root).unwrap();
+        let worktree = Worktree::read(&repo, root).unwrap();
+        assert_eq!(
+            worktree
+                .changes
+                .iter()
+                .map(|(path, oid)| (path.as_str(), oid.is_some()))
+                .collect::<Vec<_>>(),
+            [
+                ("README.md", false),
+                ("new/dir/file.rs", true),
+                ("src/main.rs", true)
+            ]
+        );
+
+        let mut entries = worktree
+            .entries(None)
+            .into_iter()
+            .map(|e| (e.path, e.on_disk))
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(
+            entries,
+            [
+                (".gitignore".to_owned(), false),
+                ("new".to_owned(), true),
+                ("new/dir".to_owned(), true),
+                ("new/dir/file.rs".to_owned(), true),
+                ("src".to_owned(), false),
+                ("src/lib.rs".to_owned(), false),
+                ("src/main.rs".to_owned(), true),
+            ]
+        );
+
+        let indexed = worktree.indexed();
+        std::fs::write(root.join("src/lib.rs"), "mod b;").unwrap();
+        let worktree = Worktree::read(&repo, root).unwrap();
+
+        let mut changed = worktree
+            .changed_since(&repo, &indexed)
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|path| path.to_string())
+            .collect::<Vec<_>>();
+        changed.sort();
+        assert_eq!(changed, ["src", "src/lib.rs"]);
+    }
+}
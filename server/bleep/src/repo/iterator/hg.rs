@@ -0,0 +1,518 @@
+//! Mercurial repositories, read through the `hg` command.
+//!
+//! Named branches and bookmarks are walked like git branches, and tags as `tags/<name>`. Each
+//! walked revision is archived to a temporary directory, and files are identified across
+//! revisions by their file node, so a file that's the same on several branches is indexed once.
+
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{bail, Context, Result};
+use tracing::{error, trace, warn};
+
+use crate::repo::IndexedBranch;
+
+use super::*;
+
+/// The number of commits to look through when dating files, as for git repositories
+const MAX_HISTORY_DEPTH: usize = 10_000;
+
+/// The parent of the working directory in a repository with no commits
+const NULL_NODE: &str = "0000000000000000000000000000000000000000";
+
+const LOG_TEMPLATE: &str = "{node}\t{date|hgdate}\t{author|json}\t{desc|json}\t{files|json}\n";
+
+#[cfg(windows)]
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+static CHECKOUTS: AtomicUsize = AtomicUsize::new(0);
+
+pub struct HgWalker {
+    /// Each walked revision is archived in a subdirectory, named after its node
+    checkouts: PathBuf,
+    entries: HashMap<(String, FileType, String), HgEntry>,
+    branches: HashMap<String, IndexedBranch>,
+}
+
+struct HgEntry {
+    branches: BTreeSet<String>,
+
+    /// Where the contents are, for files
+    source: Option<PathBuf>,
+    last_commit_unix_secs: Option<u64>,
+}
+
+/// A branch, bookmark or tag selected for indexing
+struct Tip {
+    is_head: bool,
+    name: String,
+    node: String,
+}
+
+struct HgCommit {
+    node: String,
+    unix_secs: u64,
+    author: String,
+    message: String,
+    files: Vec<String>,
+}
+
+impl HgWalker {
+    pub fn is_repository(dir: impl AsRef<Path>) -> bool {
+        dir.as_ref().join(".hg").is_dir()
+    }
+
+    pub fn open_repository(
+        dir: impl AsRef<Path>,
+        filter: impl Into<Option<BranchFilter>>,
+    ) -> Result<Self> {
+        let root_dir = dir.as_ref();
+        let tips = tips(root_dir, &filter.into().unwrap_or_default())?;
+
+        let checkouts = std::env::temp_dir().join(format!(
+            "bloop-hg-{}-{}",
+            std::process::id(),
+            CHECKOUTS.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let mut walker = Self {
+            checkouts,
+            entries: HashMap::new(),
+            branches: tips
+                .iter()
+                .map(|tip| {
+                    let indexed = IndexedBranch {
+                        commit: tip.node.clone(),
+                        is_head: tip.is_head,
+                        changes: Default::default(),
+                    };
+
+                    (tip.name.clone(), indexed)
+                })
+                .collect(),
+        };
+
+        let nodes = tips
+            .iter()
+            .map(|tip| tip.node.as_str())
+            .collect::<BTreeSet<_>>();
+
+        std::fs::create_dir_all(&walker.checkouts)?;
+        for node in nodes {
+            let checkout = walker.checkouts.join(node);
+            hg(
+                root_dir,
+                &[
+                    "--config",
+                    "ui.archivemeta=false",
+                    "archive",
+                    "--type",
+                    "files",
+                    "--rev",
+                    node,
+                    &checkout.to_string_lossy(),
+                ],
+            )?;
+
+            let times = last_commit_times(root_dir, node).unwrap_or_else(|err| {
+                error!(?err, node, "failed to walk history; files will be undated");
+                HashMap::new()
+            });
+
+            let names = tips
+                .iter()
+                .filter(|tip| tip.node == node)
+                .flat_map(|tip| {
+                    tip.is_head
+                        .then(|| "HEAD".to_owned())
+                        .into_iter()
+                        .chain([tip.name.clone()])
+                })
+                .collect::<Vec<_>>();
+
+            for (path, file_node) in manifest(root_dir, node)? {
+                let full_path = disk_path(root_dir, &path);
+                if !should_index(&full_path) {
+                    continue;
+                }
+
+                let time = times.get(&path).copied();
+                walker.add(
+                    (full_path, FileType::File, file_node),
+                    &names,
+                    Some(checkout.join(&path)),
+                    time,
+                );
+
+                // directories are dated by the newest file in them
+                for dir in Path::new(&path).ancestors().skip(1) {
+                    if dir.as_os_str().is_empty() {
+                        break;
+                    }
+
+                    let full_path = disk_path(root_dir, &dir.to_string_lossy());
+                    walker.add(
+                        (full_path, FileType::Dir, String::new()),
+                        &names,
+                        None,
+                        time,
+                    );
+                }
+            }
+        }
+
+        Ok(walker)
+    }
+
+    /// The revision that each walked branch points to.
+    pub fn branches(&self) -> &HashMap<String, IndexedBranch> {
+        &self.branches
+    }
+
+    /// The time of the commit the working directory is at
+    pub fn head_commit_time(dir: impl AsRef<Path>) -> Result<u64> {
+        log(dir.as_ref(), ".", 1)?
+            .into_iter()
+            .find(|commit| commit.node != NULL_NODE)
+            .map(|commit| commit.unix_secs)
+            .context("repository has no commits")
+    }
+
+    /// The branch the working directory is on, and the last commit time of every branch,
+    /// bookmark and tag
+    pub fn list_branches(dir: impl AsRef<Path>) -> Result<(Option<String>, Vec<(String, u64)>)> {
+        let dir = dir.as_ref();
+        let tips = tips(dir, &BranchFilter::All)?;
+        let head = tips
+            .iter()
+            .find(|tip| tip.is_head)
+            .map(|tip| tip.name.clone());
+
+        let revset = tips
+            .iter()
+            .map(|tip| tip.node.as_str())
+            .collect::<Vec<_>>()
+            .join(" + ");
+
+        if revset.is_empty() {
+            return Ok((head, vec![]));
+        }
+
+        let times = log(dir, &revset, tips.len())?
+            .into_iter()
+            .map(|commit| (commit.node, commit.unix_secs))
+            .collect::<HashMap<_, _>>();
+
+        let branches = tips
+            .into_iter()
+            .filter_map(|tip| Some((tip.name, *times.get(&tip.node)?)))
+            .collect();
+
+        Ok((head, branches))
+    }
+
+    /// Read the history of the branches selected by `filter`, newest commit first, like
+    /// [`super::commit_history`] does for git.
+    pub fn commit_history(
+        dir: impl AsRef<Path>,
+        filter: impl Into<Option<BranchFilter>>,
+    ) -> Result<Vec<CommitInfo>> {
+        let dir = dir.as_ref();
+        let tips = tips(dir, &filter.into().unwrap_or_default())?;
+        if tips.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let nodes = tips
+            .iter()
+            .map(|tip| tip.node.as_str())
+            .collect::<Vec<_>>()
+            .join(" + ");
+
+        let history = log(
+            dir,
+            &format!("sort(ancestors({nodes}), -date)"),
+            MAX_HISTORY_DEPTH,
+        )?
+        .into_iter()
+        .map(|commit| CommitInfo {
+            id: commit.node,
+            author: commit.author,
+            unix_secs: commit.unix_secs,
+            message: commit.message,
+            changed_paths: commit.files,
+        })
+        .collect();
+
+        Ok(history)
+    }
+
+    fn add(
+        &mut self,
+        key: (String, FileType, String),
+        branches: &[String],
+        source: Option<PathBuf>,
+        time: Option<u64>,
+    ) {
+        let entry = self.entries.entry(key).or_insert_with(|| HgEntry {
+            branches: BTreeSet::new(),
+            source,
+            last_commit_unix_secs: None,
+        });
+
+        entry.branches.extend(branches.iter().cloned());
+        entry.last_commit_unix_secs = entry.last_commit_unix_secs.max(time);
+    }
+}
+
+impl Drop for HgWalker {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_dir_all(&self.checkouts) {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                warn!(?err, checkouts = ?self.checkouts, "failed to remove hg checkouts");
+            }
+        }
+    }
+}
+
+impl FileSource for HgWalker {
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn for_each(mut self, pipes: &SyncPipes, iterator: impl Fn(RepoDirEntry) + Sync + Send) {
+        use rayon::prelude::*;
+        std::mem::take(&mut self.entries)
+            .into_par_iter()
+            .filter_map(|((path, kind, _), entry)| {
+                trace!(?path, "walking over path");
+                let branches = entry.branches.into_iter().collect();
+                let last_commit_unix_secs = entry.last_commit_unix_secs;
+
+                match kind {
+                    FileType::File => {
+                        let source = entry.source?;
+                        if std::fs::metadata(&source).ok()?.len() > MAX_FILE_LEN {
+                            return None;
+                        }
+
+                        let buffer = match std::fs::read(&source) {
+                            Ok(data) => String::from_utf8_lossy(&data).to_string(),
+                            Err(err) => {
+                                error!(?err, ?path, "can't read archived file");
+                                return None;
+                            }
+                        };
+
+                        Some(RepoDirEntry::File(RepoFile {
+                            path,
+                            buffer,
+                            branches,
+                            last_commit_unix_secs,
+                            submodule: None,
+                        }))
+                    }
+                    FileType::Dir => Some(RepoDirEntry::Dir(RepoDir {
+                        path,
+                        branches,
+                        last_commit_unix_secs,
+                        submodule: None,
+                    })),
+                    FileType::Other => None,
+                }
+            })
+            .take_any_while(|_| !pipes.is_cancelled())
+            .for_each(iterator)
+    }
+}
+
+/// Resolve the branches, bookmarks and tags selected by `filter` to the revision they point to.
+///
+/// Bookmarks share names with branches, and a branch wins when both have the same name. The
+/// active bookmark is `HEAD` if there is one, and the branch of the working directory otherwise.
+fn tips(dir: &Path, filter: &BranchFilter) -> Result<Vec<Tip>> {
+    let current = hg(
+        dir,
+        &["log", "-r", ".", "-T", "{node}\t{branch}\t{activebookmark}"],
+    )?;
+
+    let head = match current.trim_end().split('\t').collect::<Vec<_>>()[..] {
+        [node, ..] if *node == NULL_NODE => None,
+        [_, _, bookmark] if !bookmark.is_empty() => Some(bookmark.to_owned()),
+        [_, branch, ..] => Some(branch.to_owned()),
+        _ => None,
+    };
+
+    let named = |args: &[&str]| -> Result<Vec<(String, String)>> {
+        Ok(hg(dir, args)?
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .map(|(name, node)| (name.to_owned(), node.to_owned()))
+            .collect())
+    };
+
+    let mut seen = HashSet::new();
+    let mut tips = vec![];
+
+    let branches = named(&["branches", "-T", "{branch}\t{node}\n"])?;
+    let bookmarks = named(&["bookmarks", "-T", "{bookmark}\t{node}\n"])?;
+    for (name, node) in branches.into_iter().chain(bookmarks) {
+        let is_head = head.as_ref() == Some(&name);
+        if seen.insert(name.clone()) && filter.filter(is_head, &name) {
+            tips.push(Tip {
+                is_head,
+                name,
+                node,
+            });
+        }
+    }
+
+    for (name, node) in named(&["tags", "-T", "{tag}\t{node}\n"])? {
+        // `tip` always points to the newest revision
+        if name == "tip" {
+            continue;
+        }
+
+        let name = format!("tags/{name}");
+        if filter.filter_tag(&name) {
+            tips.push(Tip {
+                is_head: false,
+                name,
+                node,
+            });
+        }
+    }
+
+    Ok(tips)
+}
+
+/// The files in revision `node`, with their file node
+fn manifest(dir: &Path, node: &str) -> Result<Vec<(String, String)>> {
+    // the hash is only filled in with `--debug`
+    let output = hg(
+        dir,
+        &["manifest", "--debug", "-r", node, "-T", "{hash}\t{path}\n"],
+    )?;
+
+    Ok(output
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .filter(|(hash, _)| hash.len() == 40 && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+        .map(|(hash, path)| (path.to_owned(), hash.to_owned()))
+        .collect())
+}
+
+/// Find the time of the last commit that changed each file in the history of `node`, by path
+/// relative to the repository root.
+///
+/// Files that haven't changed in `MAX_HISTORY_DEPTH` commits are left out.
+fn last_commit_times(dir: &Path, node: &str) -> Result<HashMap<String, u64>> {
+    let mut times = HashMap::new();
+    for commit in log(
+        dir,
+        &format!("sort(ancestors({node}), -date)"),
+        MAX_HISTORY_DEPTH,
+    )? {
+        for file in commit.files {
+            times.entry(file).or_insert(commit.unix_secs);
+        }
+    }
+
+    Ok(times)
+}
+
+fn log(dir: &Path, revset: &str, limit: usize) -> Result<Vec<HgCommit>> {
+    hg(
+        dir,
+        &[
+            "log",
+            "-r",
+            revset,
+            "--limit",
+            &limit.to_string(),
+            "-T",
+            LOG_TEMPLATE,
+        ],
+    )?
+    .lines()
+    .map(parse_log_line)
+    .collect()
+}
+
+fn parse_log_line(line: &str) -> Result<HgCommit> {
+    let mut fields = line.splitn(5, '\t');
+    let mut next = || fields.next().context("truncated hg log line");
+
+    let node = next()?.to_owned();
+
+    // `hgdate` is the time in seconds, and the offset of the time zone
+    let unix_secs = next()?
+        .split(' ')
+        .next()
+        .unwrap_or_default()
+        .parse::<i64>()
+        .context("invalid commit date")?
+        .max(0) as u64;
+
+    Ok(HgCommit {
+        node,
+        unix_secs,
+        author: serde_json::from_str(next()?)?,
+        message: serde_json::from_str(next()?)?,
+        files: serde_json::from_str(next()?)?,
+    })
+}
+
+fn hg(dir: &Path, args: &[&str]) -> Result<String> {
+    let mut cmd = Command::new("hg");
+    cmd.arg("--cwd")
+        .arg(dir)
+        .arg("--noninteractive")
+        .args(args)
+        // ignore the user's aliases and output settings
+        .env("HGPLAIN", "1")
+        .stdin(Stdio::null());
+
+    #[cfg(windows)]
+    cmd.creation_flags(CREATE_NO_WINDOW);
+
+    let output = cmd.output().context("failed to run hg")?;
+    if !output.status.success() {
+        bail!(
+            "hg {} failed: {}",
+            args.iter().find(|arg| !arg.starts_with('-')).unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn disk_path(root_dir: &Path, path: &str) -> String {
+    root_dir.join(path).to_string_lossy().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_log() {
+        let line = "0123456789abcdef0123456789abcdef01234567\t1690000000 -7200\t\"Jane Doe <jane@example.com>\"\t\"Fix the parser\\n\\nIt\\tbroke.\"\t[\"src/parser.rs\", \"README\"]";
+        let commit = parse_log_line(line).unwrap();
+
+        assert_eq!(commit.node, "0123456789abcdef0123456789abcdef01234567");
+        assert_eq!(commit.unix_secs, 1690000000);
+        assert_eq!(commit.author, "Jane Doe <jane@example.com>");
+        assert_eq!(commit.message, "Fix the parser\n\nIt\tbroke.");
+        assert_eq!(commit.files, ["src/parser.rs", "README"]);
+
+        assert!(parse_log_line("0123\t1690000000 0").is_err());
+    }
+}
@@ -1,37 +1,22 @@
-use std::{borrow::Cow, collections::HashMap, env, path::Path, sync::Arc};
+use std::{borrow::Cow, collections::HashMap, sync::Arc};
 
 use crate::{query::parser::SemanticQuery, Configuration};
 
-use ndarray::Axis;
-use ort::{
-    tensor::{FromArray, InputTensor, OrtOwnedTensor},
-    Environment, ExecutionProvider, GraphOptimizationLevel, LoggingLevel, SessionBuilder,
-};
-use qdrant_client::{
-    prelude::{QdrantClient, QdrantClientConfig},
-    qdrant::{
-        point_id::PointIdOptions, r#match::MatchValue, vectors::VectorsOptions, vectors_config,
-        with_payload_selector, with_vectors_selector, CollectionOperationResponse,
-        CreateCollection, Distance, FieldCondition, FieldType, Filter, Match, PointId,
-        RetrievedPoint, ScoredPoint, SearchPoints, Value, VectorParams, Vectors, VectorsConfig,
-        WithPayloadSelector, WithVectorsSelector,
-    },
-};
-
 use futures::{stream, StreamExt, TryStreamExt};
-use rayon::prelude::*;
 use thiserror::Error;
-use tracing::{debug, info, trace, warn};
+use tracing::{debug, info, warn};
 
 pub mod chunk;
+pub mod embedder;
 pub mod execute;
 mod schema;
+pub mod store;
 
+use embedder::Embedder;
 pub use schema::{Embedding, Payload};
+use store::{Condition, Filter, VectorStore};
 
-pub(crate) const COLLECTION_NAME: &str = "documents";
 pub(crate) const SCORE_THRESHOLD: f32 = 0.3;
-pub(crate) const EMBEDDING_DIM: usize = 384;
 
 #[derive(Error, Debug)]
 pub enum SemanticError {
@@ -54,263 +39,42 @@ pub enum SemanticError {
 
 #[derive(Clone)]
 pub struct Semantic {
-    qdrant: Arc<QdrantClient>,
-    tokenizer: Arc<tokenizers::Tokenizer>,
-    session: Arc<ort::Session>,
+    embedder: Arc<dyn Embedder>,
+    store: Arc<dyn VectorStore>,
     config: Arc<Configuration>,
 }
 
-macro_rules! val_str(($hash:ident, $val:expr) => { serde_json::from_value($hash.remove($val).unwrap()).unwrap() });
-macro_rules! val_parse_str(($hash:ident, $val:expr) => {
-    serde_json::from_value::<Cow<'_, str>>($hash.remove($val).unwrap())
-        .unwrap()
-        .parse()
-        .unwrap()
-});
-
-impl Payload {
-    pub fn from_qdrant(orig: ScoredPoint) -> Payload {
-        let ScoredPoint {
-            id,
-            payload,
-            score,
-            vectors,
-            ..
-        } = orig;
-
-        parse_payload(id, vectors, payload, score)
-    }
-
-    pub fn from_scroll(orig: RetrievedPoint) -> Payload {
-        let RetrievedPoint {
-            id,
-            payload,
-            vectors,
-            ..
-        } = orig;
-
-        parse_payload(id, vectors, payload, 0.0)
-    }
-
-    pub(crate) fn into_qdrant(self) -> HashMap<String, Value> {
-        HashMap::from([
-            ("lang".into(), self.lang.to_ascii_lowercase().into()),
-            ("repo_name".into(), self.repo_name.into()),
-            ("repo_ref".into(), self.repo_ref.into()),
-            ("relative_path".into(), self.relative_path.into()),
-            ("content_hash".into(), self.content_hash.into()),
-            ("snippet".into(), self.text.into()),
-            ("start_line".into(), self.start_line.to_string().into()),
-            ("end_line".into(), self.end_line.to_string().into()),
-            ("start_byte".into(), self.start_byte.to_string().into()),
-            ("end_byte".into(), self.end_byte.to_string().into()),
-            ("branches".into(), self.branches.into()),
-        ])
-    }
-}
-
-fn parse_payload(
-    id: Option<PointId>,
-    vectors: Option<Vectors>,
-    payload: HashMap<String, Value>,
-    score: f32,
-) -> Payload {
-    let Some(PointId { point_id_options: Some(PointIdOptions::Uuid(id)) }) = id
-    else {
-	// unless the db was corrupted/written by someone else,
-	// this shouldn't happen
-	unreachable!("corrupted db");
-    };
-
-    let embedding = match vectors {
-        None => None,
-        Some(Vectors {
-            vectors_options: Some(VectorsOptions::Vector(v)),
-        }) => Some(v.data),
-        _ => {
-            // this also should probably never happen
-            unreachable!("got non-vector value");
-        }
-    };
-
-    let mut converted = payload
-        .into_iter()
-        .map(|(key, value)| (key, kind_to_value(value.kind)))
-        .collect::<HashMap<String, serde_json::Value>>();
-
-    Payload {
-        lang: val_str!(converted, "lang"),
-        repo_name: val_str!(converted, "repo_name"),
-        repo_ref: val_str!(converted, "repo_ref"),
-        relative_path: val_str!(converted, "relative_path"),
-        content_hash: val_str!(converted, "content_hash"),
-        text: val_str!(converted, "snippet"),
-        branches: val_str!(converted, "branches"),
-        start_line: val_parse_str!(converted, "start_line"),
-        end_line: val_parse_str!(converted, "end_line"),
-        start_byte: val_parse_str!(converted, "start_byte"),
-        end_byte: val_parse_str!(converted, "end_byte"),
-
-        id: Some(id),
-        score: Some(score),
-        embedding,
-    }
-}
-
-fn kind_to_value(kind: Option<qdrant_client::qdrant::value::Kind>) -> serde_json::Value {
-    use qdrant_client::qdrant::value::Kind;
-    match kind {
-        Some(Kind::NullValue(_)) => serde_json::Value::Null,
-        Some(Kind::BoolValue(v)) => serde_json::Value::Bool(v),
-        Some(Kind::DoubleValue(v)) => {
-            serde_json::Value::Number(serde_json::Number::from_f64(v).unwrap())
-        }
-        Some(Kind::IntegerValue(v)) => serde_json::Value::Number(v.into()),
-        Some(Kind::StringValue(v)) => serde_json::Value::String(v),
-        Some(Kind::ListValue(v)) => serde_json::Value::Array(
-            v.values
-                .into_iter()
-                .map(|v| kind_to_value(v.kind))
-                .collect(),
-        ),
-        Some(Kind::StructValue(_v)) => todo!(),
-        None => serde_json::Value::Null,
-    }
-}
-
-fn collection_config() -> CreateCollection {
-    CreateCollection {
-        collection_name: COLLECTION_NAME.to_string(),
-        vectors_config: Some(VectorsConfig {
-            config: Some(vectors_config::Config::Params(VectorParams {
-                size: EMBEDDING_DIM as u64,
-                distance: Distance::Cosine.into(),
-                ..Default::default()
-            })),
-        }),
-        ..Default::default()
+impl Semantic {
+    /// Open the embedder and vector store chosen in `config`
+    pub async fn initialize(config: Arc<Configuration>) -> Result<Self, SemanticError> {
+        let embedder = embedder::open(&config).await?;
+        let store = store::open(&config, &*embedder).await?;
+        Ok(Self::new(embedder, store, config))
     }
-}
 
-impl Semantic {
-    pub async fn initialize(
-        model_dir: &Path,
-        qdrant_url: &str,
+    pub fn new(
+        embedder: Arc<dyn Embedder>,
+        store: Arc<dyn VectorStore>,
         config: Arc<Configuration>,
-    ) -> Result<Self, SemanticError> {
-        let qdrant = QdrantClient::new(Some(QdrantClientConfig::from_url(qdrant_url))).unwrap();
-
-        match qdrant.has_collection(COLLECTION_NAME).await {
-            Ok(false) => {
-                let CollectionOperationResponse { result, time } = qdrant
-                    .create_collection(&collection_config())
-                    .await
-                    .unwrap();
-
-                debug!(
-                    time,
-                    created = result,
-                    name = COLLECTION_NAME,
-                    "created qdrant collection"
-                );
-
-                assert!(result);
-            }
-            Ok(true) => {}
-            Err(_) => return Err(SemanticError::QdrantInitializationError),
-        }
-
-        qdrant
-            .create_field_index(COLLECTION_NAME, "repo_ref", FieldType::Text, None, None)
-            .await?;
-        qdrant
-            .create_field_index(COLLECTION_NAME, "content_hash", FieldType::Text, None, None)
-            .await?;
-        qdrant
-            .create_field_index(COLLECTION_NAME, "branches", FieldType::Text, None, None)
-            .await?;
-        qdrant
-            .create_field_index(
-                COLLECTION_NAME,
-                "relative_path",
-                FieldType::Text,
-                None,
-                None,
-            )
-            .await?;
-
-        if let Some(dylib_dir) = config.dylib_dir.as_ref() {
-            init_ort_dylib(dylib_dir);
-        }
-
-        let environment = Arc::new(
-            Environment::builder()
-                .with_name("Encode")
-                .with_log_level(LoggingLevel::Warning)
-                .with_execution_providers([ExecutionProvider::cpu()])
-                .with_telemetry(false)
-                .build()?,
-        );
-
-        let threads = if let Ok(v) = std::env::var("NUM_OMP_THREADS") {
-            str::parse(&v).unwrap_or(1)
-        } else {
-            1
-        };
-
-        Ok(Self {
-            qdrant: qdrant.into(),
-            tokenizer: tokenizers::Tokenizer::from_file(model_dir.join("tokenizer.json"))
-                .unwrap()
-                .into(),
-            session: SessionBuilder::new(&environment)?
-                .with_optimization_level(GraphOptimizationLevel::Level3)?
-                .with_intra_threads(threads)?
-                .with_model_from_file(model_dir.join("model.onnx"))?
-                .into(),
+    ) -> Self {
+        Self {
+            embedder,
+            store,
             config,
-        })
+        }
     }
 
     pub async fn health_check(&self) -> anyhow::Result<()> {
-        self.qdrant.health_check().await?;
-        Ok(())
+        self.store.health_check().await
+    }
+
+    /// Whether the vector store was created empty, so nothing embedded before is in it
+    pub fn store_is_new(&self) -> bool {
+        self.store.is_new()
     }
 
-    pub fn embed(&self, sequence: &str) -> anyhow::Result<Embedding> {
-        let tokenizer_output = self.tokenizer.encode(sequence, true).unwrap();
-
-        let input_ids = tokenizer_output.get_ids();
-        let attention_mask = tokenizer_output.get_attention_mask();
-        let token_type_ids = tokenizer_output.get_type_ids();
-        let length = input_ids.len();
-        trace!("embedding {} tokens {:?}", length, sequence);
-
-        let inputs_ids_array = ndarray::Array::from_shape_vec(
-            (1, length),
-            input_ids.iter().map(|&x| x as i64).collect(),
-        )?;
-
-        let attention_mask_array = ndarray::Array::from_shape_vec(
-            (1, length),
-            attention_mask.iter().map(|&x| x as i64).collect(),
-        )?;
-
-        let token_type_ids_array = ndarray::Array::from_shape_vec(
-            (1, length),
-            token_type_ids.iter().map(|&x| x as i64).collect(),
-        )?;
-
-        let outputs = self.session.run([
-            InputTensor::from_array(inputs_ids_array.into_dyn()),
-            InputTensor::from_array(attention_mask_array.into_dyn()),
-            InputTensor::from_array(token_type_ids_array.into_dyn()),
-        ])?;
-
-        let output_tensor: OrtOwnedTensor<f32, _> = outputs[0].try_extract().unwrap();
-        let sequence_embedding = &*output_tensor.view();
-        let pooled = sequence_embedding.mean_axis(Axis(1)).unwrap();
-        Ok(pooled.to_owned().as_slice().unwrap().to_vec())
+    pub async fn embed(&self, sequence: &str) -> anyhow::Result<Embedding> {
+        self.embedder.embed(sequence).await
     }
 
     pub async fn search_with<'a>(
@@ -319,30 +83,16 @@ impl Semantic {
         vector: Embedding,
         limit: u64,
         offset: u64,
-    ) -> anyhow::Result<Vec<ScoredPoint>> {
-        let response = self
-            .qdrant
-            .search_points(&SearchPoints {
-                limit,
+    ) -> anyhow::Result<Vec<Payload>> {
+        self.store
+            .search(
                 vector,
-                collection_name: COLLECTION_NAME.to_string(),
-                offset: Some(offset),
-                score_threshold: Some(SCORE_THRESHOLD),
-                with_payload: Some(WithPayloadSelector {
-                    selector_options: Some(with_payload_selector::SelectorOptions::Enable(true)),
-                }),
-                filter: Some(Filter {
-                    must: build_conditions(parsed_query),
-                    ..Default::default()
-                }),
-                with_vectors: Some(WithVectorsSelector {
-                    selector_options: Some(with_vectors_selector::SelectorOptions::Enable(true)),
-                }),
-                ..Default::default()
-            })
-            .await?;
-
-        Ok(response.result)
+                &build_filter(parsed_query),
+                SCORE_THRESHOLD,
+                limit,
+                offset,
+            )
+            .await
     }
 
     pub async fn batch_search_with<'a>(
@@ -351,8 +101,8 @@ impl Semantic {
         vectors: Vec<Embedding>,
         limit: u64,
         offset: u64,
-    ) -> anyhow::Result<Vec<ScoredPoint>> {
-        // FIXME: This method uses `search_points` internally, and not `search_batch_points`. It's
+    ) -> anyhow::Result<Vec<Payload>> {
+        // FIXME: With Qdrant, this method uses `search_points` internally, and not `search_batch_points`. It's
         // not clear why, but it seems that the `batch` variant of the `qdrant` calls leads to
         // HTTP2 errors on some deployment configurations. A typical example error:
         //
@@ -365,40 +115,19 @@ impl Semantic {
 
         // Queries should contain the same filters, so we get the first one
         let parsed_query = parsed_queries.first().unwrap();
-        let filters = &build_conditions(parsed_query);
+        let filter = &build_filter(parsed_query);
 
         let responses = stream::iter(vectors.into_iter())
             .map(|vector| async move {
-                let points = SearchPoints {
-                    limit,
-                    vector,
-                    collection_name: COLLECTION_NAME.to_string(),
-                    offset: Some(offset),
-                    score_threshold: Some(SCORE_THRESHOLD),
-                    with_payload: Some(WithPayloadSelector {
-                        selector_options: Some(with_payload_selector::SelectorOptions::Enable(
-                            true,
-                        )),
-                    }),
-                    filter: Some(Filter {
-                        must: filters.clone(),
-                        ..Default::default()
-                    }),
-                    with_vectors: Some(WithVectorsSelector {
-                        selector_options: Some(with_vectors_selector::SelectorOptions::Enable(
-                            true,
-                        )),
-                    }),
-                    ..Default::default()
-                };
-
-                self.qdrant.search_points(&points).await
+                self.store
+                    .search(vector, filter, SCORE_THRESHOLD, limit, offset)
+                    .await
             })
             .buffered(10)
             .try_collect::<Vec<_>>()
             .await?;
 
-        Ok(responses.into_iter().flat_map(|r| r.result).collect())
+        Ok(responses.into_iter().flatten().collect())
     }
 
     pub async fn search<'a>(
@@ -411,7 +140,7 @@ impl Semantic {
         let Some(query) = parsed_query.target() else {
             anyhow::bail!("no search target for query");
         };
-        let vector = self.embed(&query)?;
+        let vector = self.embed(&query).await?;
 
         // TODO: Remove the need for `retrieve_more`. It's here because:
         // In /q `limit` is the maximum number of results returned (the actual number will often be lower due to deduplication)
@@ -423,12 +152,7 @@ impl Semantic {
                 if retrieve_more { limit * 2 } else { limit }, // Retrieve double `limit` and deduplicate
                 offset,
             )
-            .await
-            .map(|raw| {
-                raw.into_iter()
-                    .map(Payload::from_qdrant)
-                    .collect::<Vec<_>>()
-            })?;
+            .await?;
         Ok(deduplicate_snippets(results, vector, limit))
     }
 
@@ -443,12 +167,16 @@ impl Semantic {
             anyhow::bail!("no search target for query");
         };
 
-        let vectors = parsed_queries
+        let targets = parsed_queries
             .iter()
-            .map(|q| self.embed(&q.target().unwrap()))
-            .collect::<anyhow::Result<Vec<_>>>()?;
+            .map(|q| q.target().unwrap())
+            .collect::<Vec<_>>();
+        let vectors = self
+            .embedder
+            .batch_embed(targets.iter().map(AsRef::as_ref).collect())
+            .await?;
 
-        tracing::trace!(?parsed_queries, "performing qdrant batch search");
+        tracing::trace!(?parsed_queries, "performing batch search");
 
         let result = self
             .batch_search_with(
@@ -459,12 +187,9 @@ impl Semantic {
             )
             .await;
 
-        tracing::trace!(?result, "qdrant batch search returned");
+        tracing::trace!(?result, "batch search returned");
 
-        let results = result?
-            .into_iter()
-            .map(Payload::from_qdrant)
-            .collect::<Vec<_>>();
+        let results = result?;
 
         // deduplicate with mmr with respect to the mean of query vectors
         // TODO: implement a more robust multi-vector deduplication strategy
@@ -473,7 +198,7 @@ impl Semantic {
     }
 
     #[allow(clippy::too_many_arguments)]
-    #[tracing::instrument(skip(self, repo_name, buffer, chunk_cache))]
+    #[tracing::instrument(skip(self, repo_name, buffer, syntax_tree, chunk_cache))]
     pub async fn insert_points_for_buffer(
         &self,
         repo_name: &str,
@@ -481,25 +206,33 @@ impl Semantic {
         relative_path: &str,
         buffer: &str,
         lang_str: &str,
+        syntax_tree: Option<&tree_sitter::Tree>,
         branches: &[String],
         chunk_cache: crate::cache::ChunkCache<'_>,
     ) {
-        let chunks = chunk::by_tokens(
-            repo_name,
-            relative_path,
-            buffer,
-            &self.tokenizer,
-            50..self.config.max_chunk_tokens,
-            15,
-            self.overlap_strategy(),
-        );
+        let chunks = match self.overlap_strategy() {
+            chunk::OverlapStrategy::Syntax => chunk::by_syntax(
+                repo_name,
+                relative_path,
+                buffer,
+                syntax_tree,
+                self.embedder.tokenizer(),
+                50..self.config.max_chunk_tokens,
+                15,
+            ),
+            strategy => chunk::by_tokens(
+                repo_name,
+                relative_path,
+                buffer,
+                self.embedder.tokenizer(),
+                50..self.config.max_chunk_tokens,
+                15,
+                strategy,
+            ),
+        };
         debug!(chunk_count = chunks.len(), "found chunks");
 
-        let embedder = |c: &str| {
-            debug!("generating embedding");
-            self.embed(c)
-        };
-        chunks.par_iter().for_each(|chunk| {
+        let chunks = chunks.iter().map(|chunk| {
             let data = format!("{repo_name}\t{relative_path}\n{}", chunk.data,);
             let payload = Payload {
                 repo_name: repo_name.to_owned(),
@@ -516,13 +249,17 @@ impl Semantic {
                 ..Default::default()
             };
 
-            let cached = chunk_cache.update_or_embed(&data, embedder, payload);
-            if let Err(err) = cached {
-                warn!(?err, %repo_name, %relative_path, "embedding failed");
-            }
+            (data, payload)
         });
 
-        match chunk_cache.commit(&self.qdrant).await {
+        let cached = chunk_cache
+            .update_or_embed(chunks.collect(), self.embedder.as_ref())
+            .await;
+        if let Err(err) = cached {
+            warn!(?err, %repo_name, %relative_path, "embedding failed");
+        }
+
+        match chunk_cache.commit(self.store.as_ref()).await {
             Ok((new, updated, deleted)) => {
                 info!(
                     repo_name,
@@ -540,22 +277,7 @@ impl Semantic {
         repo_ref: &str,
         paths: impl Iterator<Item = String>,
     ) {
-        let repo_filter = make_kv_keyword_filter("repo_ref", repo_ref).into();
-        let file_filter = paths
-            .map(|p| make_kv_keyword_filter("content_hash", &p).into())
-            .collect::<Vec<_>>();
-
-        let selector = Filter {
-            must: vec![repo_filter],
-            should: file_filter,
-            ..Default::default()
-        }
-        .into();
-
-        let _ = self
-            .qdrant
-            .delete_points(COLLECTION_NAME, &selector, None)
-            .await;
+        let _ = self.store.delete_by_hash(repo_ref, paths.collect()).await;
     }
 
     pub fn overlap_strategy(&self) -> chunk::OverlapStrategy {
@@ -563,131 +285,80 @@ impl Semantic {
     }
 }
 
-/// Initialize the `ORT_DYLIB_PATH` variable, consumed by the `ort` crate.
-///
-/// This doesn't do anything on Windows, as tauri on Windows will automatically bundle any `.dll`
-/// files found in the `target/$profile` folder. The `ort` crate by default will also copy the
-/// built dynamic library over to the `target/$profile` folder, when using the download strategy.
-fn init_ort_dylib(dylib_dir: impl AsRef<Path>) {
-    #[cfg(not(windows))]
-    {
-        #[cfg(target_os = "linux")]
-        let lib_name = "libonnxruntime.so";
-        #[cfg(target_os = "macos")]
-        let lib_name = "libonnxruntime.dylib";
-
-        let ort_dylib_path = dylib_dir.as_ref().join(lib_name);
-
-        if env::var("ORT_DYLIB_PATH").is_err() {
-            env::set_var("ORT_DYLIB_PATH", ort_dylib_path);
-        }
+// Exact match condition
+fn keyword(key: &'static str, value: &str) -> Condition {
+    Condition::Keyword {
+        key,
+        value: value.to_owned(),
     }
 }
 
-// Exact match filter
-pub(crate) fn make_kv_keyword_filter(key: &str, value: &str) -> FieldCondition {
-    let key = key.to_owned();
-    let value = value.to_owned();
-    FieldCondition {
+// Substring match condition
+fn text(key: &'static str, value: &str) -> Condition {
+    Condition::Text {
         key,
-        r#match: Some(Match {
-            match_value: MatchValue::Keyword(value).into(),
-        }),
-        ..Default::default()
+        value: value.to_owned(),
     }
 }
 
-// Substring match filter
-fn make_kv_text_filter(key: &str, value: &str) -> FieldCondition {
-    let key = key.to_owned();
-    let value = value.to_owned();
-    FieldCondition {
-        key,
-        r#match: Some(Match {
-            match_value: MatchValue::Text(value).into(),
-        }),
-        ..Default::default()
+fn repo_name_for_filter(repo: Cow<'_, str>) -> String {
+    if repo.contains('/') && !repo.starts_with("github.com/") {
+        format!("github.com/{repo}")
+    } else {
+        repo.to_string()
     }
 }
 
-fn build_conditions(query: &SemanticQuery<'_>) -> Vec<qdrant_client::qdrant::Condition> {
-    let repo_filter = {
-        let conditions = query
-            .repos()
-            .map(|r| {
-                if r.contains('/') && !r.starts_with("github.com/") {
-                    format!("github.com/{r}")
-                } else {
-                    r.to_string()
-                }
-            })
-            .map(|r| make_kv_keyword_filter("repo_name", r.as_ref()).into())
-            .collect::<Vec<_>>();
-        // one of the above repos should match
-        if conditions.is_empty() {
-            None
-        } else {
-            Some(Filter {
-                should: conditions,
-                ..Default::default()
-            })
-        }
-    };
-
-    let path_filter = {
-        let conditions = query
-            .paths()
-            .map(|r| make_kv_text_filter("relative_path", r.as_ref()).into())
-            .collect::<Vec<_>>();
-        if conditions.is_empty() {
-            None
-        } else {
-            Some(Filter {
-                should: conditions,
-                ..Default::default()
-            })
-        }
-    };
+fn build_filter(query: &SemanticQuery<'_>) -> Filter {
+    Filter {
+        must: build_conditions(query),
+        must_not: build_exclusions(query),
+    }
+}
 
-    let lang_filter = {
-        let conditions = query
-            .langs()
-            .map(|l| make_kv_keyword_filter("lang", l.as_ref()).into())
-            .collect::<Vec<_>>();
-        // one of the above langs should match
-        if conditions.is_empty() {
-            None
-        } else {
-            Some(Filter {
-                should: conditions,
-                ..Default::default()
-            })
-        }
-    };
+/// Build the groups of conditions returned points must match, one from each group.
+fn build_conditions(query: &SemanticQuery<'_>) -> Vec<Vec<Condition>> {
+    // one of the repos should match
+    let repos = query
+        .repos()
+        .map(repo_name_for_filter)
+        .map(|r| keyword("repo_name", r.as_ref()))
+        .collect::<Vec<_>>();
+
+    let paths = query
+        .paths()
+        .map(|p| text("relative_path", p.as_ref()))
+        .collect::<Vec<_>>();
+
+    // one of the langs should match
+    let langs = query
+        .langs()
+        .map(|l| keyword("lang", l.as_ref()))
+        .collect::<Vec<_>>();
+
+    let branches = query
+        .branch()
+        .map(|b| keyword("branches", b.as_ref()))
+        .collect::<Vec<_>>();
+
+    [repos, paths, langs, branches]
+        .into_iter()
+        .filter(|group| !group.is_empty())
+        .collect()
+}
 
-    let branch_filter = {
-        let conditions = query
-            .branch()
-            .map(|l| make_kv_keyword_filter("branches", l.as_ref()).into())
-            .collect::<Vec<_>>();
+/// Build the list of conditions that no returned point may match, e.g. for `-path:tests`.
+fn build_exclusions(query: &SemanticQuery<'_>) -> Vec<Condition> {
+    let repos = query
+        .not_repos()
+        .map(repo_name_for_filter)
+        .map(|r| keyword("repo_name", r.as_ref()));
 
-        if conditions.is_empty() {
-            None
-        } else {
-            Some(Filter {
-                should: conditions,
-                ..Default::default()
-            })
-        }
-    };
+    let paths = query.not_paths().map(|p| text("relative_path", p.as_ref()));
 
-    let filters: Vec<_> = [repo_filter, path_filter, lang_filter, branch_filter]
-        .into_iter()
-        .flatten()
-        .map(Into::into)
-        .collect();
+    let langs = query.not_langs().map(|l| keyword("lang", l.as_ref()));
 
-    filters
+    repos.chain(paths).chain(langs).collect()
 }
 
 fn dot(a: &[f32], b: &[f32]) -> f32 {
@@ -705,7 +376,7 @@ fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
 // Calculate the element-wise mean of the embeddings
 fn mean_pool(embeddings: Vec<Vec<f32>>) -> Vec<f32> {
     let len = embeddings.len() as f32;
-    let mut result = vec![0.0; EMBEDDING_DIM];
+    let mut result = vec![0.0; embeddings.first().map_or(0, Vec::len)];
     for embedding in embeddings {
         for (i, v) in embedding.iter().enumerate() {
             result[i] += v;
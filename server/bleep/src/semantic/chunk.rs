@@ -79,6 +79,8 @@ pub enum OverlapStrategy {
     ByLines(usize),
     /// A value > 0 and < 1 that indicates the target overlap in tokens.
     Partial(f64),
+    /// Don't overlap, chunk along the syntax tree instead (see [`by_syntax`])
+    Syntax,
 }
 
 impl Display for OverlapStrategy {
@@ -89,6 +91,7 @@ impl Display for OverlapStrategy {
                 (*p / 100.0).fmt(f)?;
                 f.write_char('%')
             }
+            Self::Syntax => f.write_str("syntax"),
         }
     }
 }
@@ -99,8 +102,11 @@ impl From<OverlapStrategy> for String {
     }
 }
 
-static OVERLAP_STRATEGY_VARIANTS: &[OverlapStrategy] =
-    &[OverlapStrategy::ByLines(1), OverlapStrategy::Partial(0.5)];
+static OVERLAP_STRATEGY_VARIANTS: &[OverlapStrategy] = &[
+    OverlapStrategy::ByLines(1),
+    OverlapStrategy::Partial(0.5),
+    OverlapStrategy::Syntax,
+];
 
 impl ValueEnum for OverlapStrategy {
     fn value_variants<'a>() -> &'a [Self] {
@@ -112,14 +118,17 @@ impl ValueEnum for OverlapStrategy {
             Some(PossibleValue::new("1"))
         } else if self == &OVERLAP_STRATEGY_VARIANTS[1] {
             Some(PossibleValue::new("50%"))
+        } else if self == &OVERLAP_STRATEGY_VARIANTS[2] {
+            Some(PossibleValue::new("syntax"))
         } else {
             None
         }
     }
 
     fn from_str(input: &str, _ignore_case: bool) -> Result<Self, String> {
-        Self::try_from(input)
-            .map_err(|_| String::from("overlap should be a number of lines or a percentage"))
+        Self::try_from(input).map_err(|_| {
+            String::from("overlap should be a number of lines, a percentage or `syntax`")
+        })
     }
 }
 
@@ -127,7 +136,9 @@ impl TryFrom<&'_ str> for OverlapStrategy {
     type Error = &'static str;
 
     fn try_from(input: &str) -> Result<Self, &'static str> {
-        Ok(if let Some(percentage) = input.strip_suffix('%') {
+        Ok(if input == "syntax" {
+            Self::Syntax
+        } else if let Some(percentage) = input.strip_suffix('%') {
             Self::Partial(
                 str::parse::<f64>(percentage).map_err(|_| "failure parsing overlap strategy")?
                     * 0.01,
@@ -144,6 +155,8 @@ impl OverlapStrategy {
         (match self {
             OverlapStrategy::ByLines(n) => max_tokens - n,
             OverlapStrategy::Partial(part) => ((max_tokens as f64) * part) as usize,
+            // only reached when falling back to token windows, overlap those by half
+            OverlapStrategy::Syntax => max_tokens / 2,
         })
         .max(1) // ensure we make forward progress
     }
@@ -158,6 +171,31 @@ impl Default for OverlapStrategy {
 /// This should take care of [CLS], [SEP] etc. which could be introduced during per-chunk tokenization
 pub const DEDUCT_SPECIAL_TOKENS: usize = 2;
 
+/// The number of tokens of code that fit in a chunk of `max_chunk_tokens`, once the repo and file
+/// name are prepended to it
+fn max_code_tokens(
+    repo: &str,
+    file: &str,
+    tokenizer: &Tokenizer,
+    max_chunk_tokens: usize,
+) -> Option<usize> {
+    let repo_plus_file = repo.to_owned() + "\t" + file + "\n";
+    let repo_tokens = match tokenizer.encode(repo_plus_file, true) {
+        Ok(encoding) => encoding.get_ids().len(),
+        Err(e) => {
+            error!("failure during encoding repo + file {:?}", e);
+            return None;
+        }
+    };
+
+    if max_chunk_tokens <= DEDUCT_SPECIAL_TOKENS + repo_tokens {
+        error!("too few tokens");
+        return None;
+    }
+
+    Some(max_chunk_tokens - DEDUCT_SPECIAL_TOKENS - repo_tokens)
+}
+
 fn add_token_range<'s>(
     chunks: &mut Vec<Chunk<'s>>,
     src: &'s str,
@@ -220,21 +258,9 @@ pub fn by_tokens<'s>(
         return Vec::new();
     }
 
-    let repo_plus_file = repo.to_owned() + "\t" + file + "\n";
-    let repo_tokens = match tokenizer.encode(repo_plus_file, true) {
-        Ok(encoding) => encoding.get_ids().len(),
-        Err(e) => {
-            error!("failure during encoding repo + file {:?}", e);
-            return Vec::new();
-        }
-    };
-
-    if token_bounds.end <= DEDUCT_SPECIAL_TOKENS + repo_tokens {
-        error!("too few tokens");
+    let Some(max_tokens) = max_code_tokens(repo, file, tokenizer, token_bounds.end) else {
         return Vec::new();
-    }
-
-    let max_tokens = token_bounds.end - DEDUCT_SPECIAL_TOKENS - repo_tokens;
+    };
     let max_newline_tokens = max_tokens * 3 / 4; //TODO: make this configurable
     let max_boundary_tokens = max_tokens * 7 / 8; //TODO: make this configurable
     debug!("max tokens reduced to {max_tokens}");
@@ -308,6 +334,152 @@ pub fn by_tokens<'s>(
     }
 }
 
+/// This splits the code along its syntax tree, so that chunks hold whole top-level definitions
+/// wherever they fit. Nodes with more than `max_tokens` are split into their children, and
+/// neighbouring nodes are merged for as long as they fit. Nodes that can't be split any further
+/// are cut at the last newline before `max_tokens`.
+///
+/// `tree` is the syntax tree of `src`, as parsed when the file was indexed. Chunks don't overlap.
+/// Files without a syntax tree, e.g. in languages without a tree-sitter grammar, are chunked
+/// with [`by_tokens`] instead.
+pub fn by_syntax<'s>(
+    repo: &str,
+    file: &str,
+    src: &'s str,
+    tree: Option<&tree_sitter::Tree>,
+    tokenizer: &Tokenizer,
+    token_bounds: Range<usize>,
+    max_lines: usize,
+) -> Vec<Chunk<'s>> {
+    let tree = match tree {
+        Some(tree) => tree,
+        None => {
+            debug!("no syntax tree, chunking by tokens");
+            return by_tokens(
+                repo,
+                file,
+                src,
+                tokenizer,
+                token_bounds,
+                max_lines,
+                OverlapStrategy::default(),
+            );
+        }
+    };
+
+    let min_tokens = token_bounds.start;
+    if src.len() < min_tokens {
+        return Vec::new();
+    }
+    let Ok(encoding) = tokenizer.encode(src, false) else {
+        warn!("Could not encode \"{}\"", src);
+        return by_lines(src, max_lines);
+    };
+
+    let offsets = encoding.get_offsets();
+    if offsets.len() < min_tokens {
+        return Vec::new();
+    }
+
+    let Some(max_tokens) = max_code_tokens(repo, file, tokenizer, token_bounds.end) else {
+        return Vec::new();
+    };
+
+    // the tokens of a byte range are those starting inside it, as a range of token indices
+    let tokens = |bytes: &Range<usize>| {
+        offsets.partition_point(|o| o.0 < bytes.start)..offsets.partition_point(|o| o.0 < bytes.end)
+    };
+
+    let mut units = Vec::new();
+    syntax_units(
+        tree.root_node(),
+        0..src.len(),
+        max_tokens,
+        &|bytes| tokens(bytes).len(),
+        &mut units,
+    );
+
+    // merge neighbours for as long as they fit
+    let mut groups: Vec<Range<usize>> = Vec::new();
+    for unit in units.into_iter().map(|bytes| tokens(&bytes)) {
+        match groups.last_mut() {
+            Some(group) if unit.end - group.start <= max_tokens => group.end = unit.end,
+            _ => groups.push(unit),
+        }
+    }
+
+    let mut chunks = Vec::new();
+    let (mut last_line, mut last_byte) = (0, 0);
+    for group in groups {
+        let mut start = group.start;
+        while start < group.end {
+            let limit = start + max_tokens;
+            let end = if limit >= group.end {
+                group.end
+            } else {
+                // cut oversized nodes before the last line break that fits, if there is one
+                (start + (max_tokens / 2).max(1)..=limit)
+                    .rfind(|&i| {
+                        src.get(offsets[i - 1].1..offsets[i].0)
+                            .map_or(false, |s| s.contains('\n'))
+                    })
+                    .unwrap_or(limit)
+            };
+
+            let (start_byte, end_byte) = (offsets[start].0, offsets[end - 1].1);
+            let start_point = point(src, start_byte, last_line, last_byte);
+            let end_point = point(src, end_byte, start_point.line, start_byte);
+            (last_line, last_byte) = (end_point.line, end_byte);
+            chunks.push(Chunk::new(
+                &src[start_byte..end_byte],
+                start_point,
+                end_point,
+            ));
+
+            start = end;
+        }
+    }
+
+    chunks
+}
+
+/// Collect the byte ranges `node` should be chunked in, each covering one of its children. The
+/// ranges are contiguous, so that the text between children isn't lost. Children with more than
+/// `max_tokens` are split recursively.
+fn syntax_units(
+    node: tree_sitter::Node<'_>,
+    bytes: Range<usize>,
+    max_tokens: usize,
+    count_tokens: &dyn Fn(&Range<usize>) -> usize,
+    units: &mut Vec<Range<usize>>,
+) {
+    let mut cursor = node.walk();
+    let children = node.children(&mut cursor).collect::<Vec<_>>();
+
+    let mut start = bytes.start;
+    for (i, child) in children.iter().enumerate() {
+        let end = children
+            .get(i + 1)
+            .map_or(bytes.end, |next| next.start_byte().clamp(start, bytes.end));
+        let unit = start..end;
+        start = end;
+
+        if unit.is_empty() {
+            continue;
+        }
+
+        if child.child_count() > 0 && count_tokens(&unit) > max_tokens {
+            syntax_units(*child, unit, max_tokens, count_tokens, units);
+        } else {
+            units.push(unit);
+        }
+    }
+
+    if children.is_empty() && !bytes.is_empty() {
+        units.push(bytes);
+    }
+}
+
 pub fn by_lines(src: &str, size: usize) -> Vec<Chunk<'_>> {
     let ends = std::iter::once(0)
         .chain(src.match_indices('\n').map(|(i, _)| i))
@@ -344,6 +516,7 @@ pub fn by_lines(src: &str, size: usize) -> Vec<Chunk<'_>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::intelligence::TreeSitterFile;
     use std::{env, path::PathBuf};
 
     fn minilm() -> Tokenizer {
@@ -484,6 +657,61 @@ mod tests {
         }
     }
 
+    #[test]
+    pub fn chunks_along_syntax() {
+        let tokenizer = minilm();
+        let max_lines = 15;
+
+        assert_eq!(
+            OverlapStrategy::try_from("syntax"),
+            Ok(OverlapStrategy::Syntax)
+        );
+        assert_eq!(OverlapStrategy::Syntax.to_string(), "syntax");
+
+        let file = TreeSitterFile::try_build(SRC.as_bytes(), "Rust").unwrap();
+        let chunks = super::by_syntax(
+            "bloop",
+            "src/config.rs",
+            SRC,
+            Some(file.tree()),
+            &tokenizer,
+            50..256,
+            max_lines,
+        );
+        assert!(chunks.len() > 1);
+
+        let mut last_end = 0;
+        for chunk in &chunks {
+            let range = chunk.range.start.byte..chunk.range.end.byte;
+            assert!(range.start >= last_end, "chunks overlap at {range:?}");
+            assert_eq!(chunk.data, &SRC[range.clone()]);
+            last_end = range.end;
+
+            let len = tokenizer.encode(chunk.data, false).unwrap().len();
+            assert!(
+                len.saturating_sub(256) < 10,
+                "chunk length ({len}) was not less than 256\n\n{}\n",
+                chunk.data
+            )
+        }
+
+        // small definitions are never cut in half
+        for item in [
+            "pub fn serialize_secret_opt_str<S>(",
+            "fn default_data_dir() -> PathBuf {",
+            "const fn default_port() -> u16 {",
+        ] {
+            let start = SRC.find(item).unwrap();
+            let end = start + SRC[start..].find("\n}\n").unwrap() + 2;
+            assert!(
+                chunks
+                    .iter()
+                    .any(|c| c.range.start.byte <= start && end <= c.range.end.byte),
+                "`{item}` was split across chunks"
+            );
+        }
+    }
+
     static SRC: &str = r#"
 use crate::{semantic::chunk::OverlapStrategy, state::StateSource};
 use anyhow::{Context, Result};
@@ -0,0 +1,493 @@
+//! Turning text into embeddings
+//!
+//! Embeddings come from an ONNX model on disk, by default the one in `model_dir`, or from an
+//! OpenAI-compatible `/embeddings` endpoint when `embedding_url` is set. Either way, the size of
+//! the embeddings is found when the embedder is opened, and the vector store follows it.
+
+use std::{env, path::Path, sync::Arc};
+
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use clap::ValueEnum;
+use ndarray::Axis;
+use ort::{
+    tensor::{FromArray, InputTensor, OrtOwnedTensor},
+    Environment, ExecutionProvider, GraphOptimizationLevel, LoggingLevel, SessionBuilder,
+};
+use rayon::prelude::*;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use tokenizers::Tokenizer;
+use tracing::{info, trace};
+
+use super::{Embedding, SemanticError};
+use crate::Configuration;
+
+/// Texts sent to an embedding API in one request
+const BATCH_SIZE: usize = 64;
+
+/// Text embedded to find the size of a model's embeddings
+const PROBE: &str = "fn main() {}";
+
+#[async_trait]
+pub trait Embedder: Send + Sync {
+    async fn embed(&self, sequence: &str) -> anyhow::Result<Embedding>;
+
+    async fn batch_embed(&self, sequences: Vec<&str>) -> anyhow::Result<Vec<Embedding>>;
+
+    /// The size of the embeddings
+    fn dimension(&self) -> usize;
+
+    /// Identifies the model and settings the embeddings come from, as embeddings made by
+    /// different ones can't be compared even when they're the same size
+    fn fingerprint(&self) -> String;
+
+    /// The tokenizer chunks are measured with
+    fn tokenizer(&self) -> &Tokenizer;
+}
+
+/// How the embeddings of each token are combined into one for the whole text
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Pooling {
+    /// The mean of all tokens
+    #[default]
+    Mean,
+    /// The first token, e.g. `[CLS]` for BERT models
+    Cls,
+}
+
+/// Open the embedder selected in `config`
+pub async fn open(config: &Configuration) -> Result<Arc<dyn Embedder>, SemanticError> {
+    let tokenizer = load_tokenizer(&config.model_dir)?;
+
+    let embedder: Arc<dyn Embedder> = match config.embedding_url {
+        Some(ref url) => Arc::new(
+            OpenAi::connect(
+                url,
+                config.embedding_model.clone(),
+                config.embedding_api_key.clone(),
+                tokenizer,
+                config.embedding_dim,
+            )
+            .await?,
+        ),
+        None => {
+            if let Some(dylib_dir) = config.dylib_dir.as_ref() {
+                init_ort_dylib(dylib_dir);
+            }
+
+            Arc::new(Onnx::new(
+                &config.model_dir,
+                tokenizer,
+                config.embedding_dim,
+                config.embedding_pooling.unwrap_or_default(),
+            )?)
+        }
+    };
+
+    info!(dimension = embedder.dimension(), "opened embedder");
+    Ok(embedder)
+}
+
+/// Load the `tokenizer.json` in `model_dir`
+pub fn load_tokenizer(model_dir: &Path) -> anyhow::Result<Tokenizer> {
+    Tokenizer::from_file(model_dir.join("tokenizer.json")).map_err(|err| anyhow!(err))
+}
+
+/// A local ONNX model, taking the token ids, attention mask and, if it has a third input, the
+/// token type ids of the text
+pub struct Onnx {
+    tokenizer: Tokenizer,
+    session: ort::Session,
+    pooling: Pooling,
+    dim: usize,
+
+    /// The hash of the model file
+    model_hash: String,
+}
+
+impl Onnx {
+    /// Load `model.onnx` from `model_dir`.
+    ///
+    /// If `dim` isn't given, it's found by embedding some text.
+    pub fn new(
+        model_dir: &Path,
+        tokenizer: Tokenizer,
+        dim: Option<usize>,
+        pooling: Pooling,
+    ) -> Result<Self, SemanticError> {
+        let environment = Arc::new(
+            Environment::builder()
+                .with_name("Encode")
+                .with_log_level(LoggingLevel::Warning)
+                .with_execution_providers([ExecutionProvider::cpu()])
+                .with_telemetry(false)
+                .build()?,
+        );
+
+        let threads = if let Ok(v) = std::env::var("NUM_OMP_THREADS") {
+            str::parse(&v).unwrap_or(1)
+        } else {
+            1
+        };
+
+        let model = model_dir.join("model.onnx");
+        let session = SessionBuilder::new(&environment)?
+            .with_optimization_level(GraphOptimizationLevel::Level3)?
+            .with_intra_threads(threads)?
+            .with_model_from_file(&model)?;
+
+        let mut onnx = Self {
+            tokenizer,
+            session,
+            pooling,
+            dim: 0,
+            model_hash: hash_file(&model)?,
+        };
+
+        onnx.dim = match dim {
+            Some(dim) => dim,
+            None => onnx.run(PROBE)?.len(),
+        };
+
+        Ok(onnx)
+    }
+
+    fn run(&self, sequence: &str) -> anyhow::Result<Embedding> {
+        let tokenizer_output = self.tokenizer.encode(sequence, true).unwrap();
+
+        let input_ids = tokenizer_output.get_ids();
+        let attention_mask = tokenizer_output.get_attention_mask();
+        let token_type_ids = tokenizer_output.get_type_ids();
+        let length = input_ids.len();
+        trace!("embedding {} tokens {:?}", length, sequence);
+
+        let inputs_ids_array = ndarray::Array::from_shape_vec(
+            (1, length),
+            input_ids.iter().map(|&x| x as i64).collect(),
+        )?;
+
+        let attention_mask_array = ndarray::Array::from_shape_vec(
+            (1, length),
+            attention_mask.iter().map(|&x| x as i64).collect(),
+        )?;
+
+        let token_type_ids_array = ndarray::Array::from_shape_vec(
+            (1, length),
+            token_type_ids.iter().map(|&x| x as i64).collect(),
+        )?;
+
+        let mut inputs = vec![
+            InputTensor::from_array(inputs_ids_array.into_dyn()),
+            InputTensor::from_array(attention_mask_array.into_dyn()),
+            InputTensor::from_array(token_type_ids_array.into_dyn()),
+        ];
+        inputs.truncate(self.session.inputs.len());
+
+        let outputs = self.session.run(inputs)?;
+
+        let output_tensor: OrtOwnedTensor<f32, _> = outputs[0].try_extract()?;
+        let sequence_embedding = &*output_tensor.view();
+        let pooled = match (sequence_embedding.ndim(), self.pooling) {
+            // the model pools the tokens itself
+            (2, _) => sequence_embedding.to_owned(),
+            (_, Pooling::Mean) => sequence_embedding.mean_axis(Axis(1)).unwrap(),
+            (_, Pooling::Cls) => sequence_embedding.index_axis(Axis(1), 0).to_owned(),
+        };
+
+        Ok(pooled.iter().copied().collect())
+    }
+}
+
+#[async_trait]
+impl Embedder for Onnx {
+    async fn embed(&self, sequence: &str) -> anyhow::Result<Embedding> {
+        checked(self.run(sequence)?, self.dim)
+    }
+
+    async fn batch_embed(&self, sequences: Vec<&str>) -> anyhow::Result<Vec<Embedding>> {
+        sequences
+            .par_iter()
+            .map(|sequence| checked(self.run(sequence)?, self.dim))
+            .collect()
+    }
+
+    fn dimension(&self) -> usize {
+        self.dim
+    }
+
+    fn fingerprint(&self) -> String {
+        format!("onnx:{}:{:?}:{}", self.model_hash, self.pooling, self.dim)
+    }
+
+    fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+}
+
+/// An OpenAI-compatible embeddings API.
+///
+/// The API doesn't say how it splits text into tokens, so chunks are measured with a local
+/// tokenizer.
+pub struct OpenAi {
+    http: reqwest::Client,
+    url: String,
+    model: Option<String>,
+    api_key: Option<SecretString>,
+    tokenizer: Tokenizer,
+    dim: usize,
+}
+
+#[derive(Serialize)]
+struct EmbeddingRequest<'a> {
+    input: &'a [&'a str],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Embedding,
+}
+
+impl OpenAi {
+    /// Use the API at `url`, e.g. `https://api.openai.com/v1`.
+    ///
+    /// If `dim` isn't given, it's found by embedding some text.
+    pub async fn connect(
+        url: &str,
+        model: Option<String>,
+        api_key: Option<SecretString>,
+        tokenizer: Tokenizer,
+        dim: Option<usize>,
+    ) -> anyhow::Result<Self> {
+        let mut openai = Self {
+            http: crate::network::client(),
+            url: format!("{}/embeddings", url.trim_end_matches('/')),
+            model,
+            api_key,
+            tokenizer,
+            dim: 0,
+        };
+
+        openai.dim = match dim {
+            Some(dim) => dim,
+            None => openai.request(&[PROBE]).await?[0].len(),
+        };
+
+        Ok(openai)
+    }
+
+    async fn request(&self, input: &[&str]) -> anyhow::Result<Vec<Embedding>> {
+        let mut request = self.http.post(&self.url).json(&EmbeddingRequest {
+            input,
+            model: self.model.as_deref(),
+        });
+
+        if let Some(ref api_key) = self.api_key {
+            request = request.bearer_auth(api_key.expose_secret());
+        }
+
+        let mut response = request
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<EmbeddingResponse>()
+            .await?;
+
+        if response.data.len() != input.len() {
+            bail!(
+                "asked for {} embeddings, got {}",
+                input.len(),
+                response.data.len()
+            );
+        }
+
+        response.data.sort_by_key(|d| d.index);
+        Ok(response.data.into_iter().map(|d| d.embedding).collect())
+    }
+}
+
+#[async_trait]
+impl Embedder for OpenAi {
+    async fn embed(&self, sequence: &str) -> anyhow::Result<Embedding> {
+        let mut embeddings = self.batch_embed(vec![sequence]).await?;
+        Ok(embeddings.remove(0))
+    }
+
+    async fn batch_embed(&self, sequences: Vec<&str>) -> anyhow::Result<Vec<Embedding>> {
+        let mut embeddings = Vec::with_capacity(sequences.len());
+        for batch in sequences.chunks(BATCH_SIZE) {
+            for embedding in self.request(batch).await? {
+                embeddings.push(checked(embedding, self.dim)?);
+            }
+        }
+
+        Ok(embeddings)
+    }
+
+    fn dimension(&self) -> usize {
+        self.dim
+    }
+
+    fn fingerprint(&self) -> String {
+        format!(
+            "openai:{}:{}:{}",
+            self.url,
+            self.model.as_deref().unwrap_or_default(),
+            self.dim
+        )
+    }
+
+    fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+}
+
+fn hash_file(path: &Path) -> anyhow::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().to_string())
+}
+
+/// Make sure `embedding` fits in a vector store made for `dim` dimensions
+fn checked(embedding: Embedding, dim: usize) -> anyhow::Result<Embedding> {
+    if embedding.len() != dim {
+        bail!(
+            "expected an embedding of {dim} dimensions, got {}",
+            embedding.len()
+        );
+    }
+
+    Ok(embedding)
+}
+
+/// Initialize the `ORT_DYLIB_PATH` variable, consumed by the `ort` crate.
+///
+/// This doesn't do anything on Windows, as tauri on Windows will automatically bundle any `.dll`
+/// files found in the `target/$profile` folder. The `ort` crate by default will also copy the
+/// built dynamic library over to the `target/$profile` folder, when using the download strategy.
+fn init_ort_dylib(dylib_dir: impl AsRef<Path>) {
+    #[cfg(not(windows))]
+    {
+        #[cfg(target_os = "linux")]
+        let lib_name = "libonnxruntime.so";
+        #[cfg(target_os = "macos")]
+        let lib_name = "libonnxruntime.dylib";
+
+        let ort_dylib_path = dylib_dir.as_ref().join(lib_name);
+
+        if env::var("ORT_DYLIB_PATH").is_err() {
+            env::set_var("ORT_DYLIB_PATH", ort_dylib_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::{
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Json, Router,
+    };
+    use std::{net::SocketAddr, path::PathBuf};
+
+    fn minilm() -> Tokenizer {
+        let model_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .join("model");
+        load_tokenizer(&model_dir).unwrap()
+    }
+
+    /// Embeds each input as `[length, index in the request, 1]`, and lists them in reverse
+    async fn stub_api() -> String {
+        async fn embeddings(
+            headers: HeaderMap,
+            Json(request): Json<serde_json::Value>,
+        ) -> Result<Json<serde_json::Value>, StatusCode> {
+            if headers.get("authorization").unwrap() != "Bearer key" {
+                return Err(StatusCode::UNAUTHORIZED);
+            }
+
+            assert_eq!(request["model"], "stub");
+            let data = request["input"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .enumerate()
+                .rev()
+                .map(|(index, input)| {
+                    let length = input.as_str().unwrap().len();
+                    serde_json::json!({
+                        "object": "embedding",
+                        "index": index,
+                        "embedding": [length as f32, index as f32, 1.0],
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            Ok(Json(serde_json::json!({ "object": "list", "data": data })))
+        }
+
+        let app = Router::new().route("/v1/embeddings", post(embeddings));
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let url = format!("http://{}/v1", server.local_addr());
+        tokio::spawn(server);
+
+        url
+    }
+
+    #[tokio::test]
+    async fn openai_embeddings() {
+        let url = stub_api().await;
+        let openai = OpenAi::connect(
+            &url,
+            Some("stub".to_owned()),
+            Some("key".to_owned().into()),
+            minilm(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(openai.dimension(), 3);
+        assert_eq!(
+            openai.fingerprint(),
+            format!("openai:{url}/embeddings:stub:3")
+        );
+
+        let sequences = (0..100).map(|i| "a".repeat(i)).collect::<Vec<_>>();
+        let embeddings = openai
+            .batch_embed(sequences.iter().map(String::as_str).collect())
+            .await
+            .unwrap();
+        assert_eq!(embeddings.len(), 100);
+        assert_eq!(embeddings[70], [70.0, (70 - BATCH_SIZE) as f32, 1.0]);
+
+        let unauthorized = OpenAi::connect(&url, Some("stub".to_owned()), None, minilm(), None);
+        assert!(unauthorized.await.is_err());
+
+        let wrong_dim = OpenAi::connect(
+            &url,
+            Some("stub".to_owned()),
+            Some("key".to_owned().into()),
+            minilm(),
+            Some(4),
+        )
+        .await
+        .unwrap();
+        assert!(wrong_dim.embed("fn main() {}").await.is_err());
+    }
+}
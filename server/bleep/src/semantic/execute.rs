@@ -53,6 +53,7 @@ pub async fn execute(
                 repo_ref,
                 snippets,
                 lang,
+                submodule: None,
             })
         })
         .collect::<Vec<_>>();
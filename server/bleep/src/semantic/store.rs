@@ -0,0 +1,107 @@
+//! Where the embeddings of code chunks are kept and searched
+//!
+//! [`Semantic`](super::Semantic) works with any [`VectorStore`]. Embeddings are kept in Qdrant
+//! when `qdrant_url` is set, or in an in-process store in the index directory with
+//! `embedded_vector_store`.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use super::{embedder::Embedder, Embedding, Payload, SemanticError};
+use crate::Configuration;
+
+mod embedded;
+mod qdrant;
+
+pub use embedded::Embedded;
+pub use qdrant::Qdrant;
+
+/// The embedding of a chunk, along with its payload
+pub struct Point {
+    pub id: String,
+    pub vector: Embedding,
+    pub payload: Payload,
+}
+
+/// Conditions on the payload of points
+#[derive(Default, Clone, Debug)]
+pub struct Filter {
+    /// Each group must have at least one matching condition
+    pub must: Vec<Vec<Condition>>,
+    /// No condition may match
+    pub must_not: Vec<Condition>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Condition {
+    /// The field is `value`, or for lists, one of the items is
+    Keyword { key: &'static str, value: String },
+    /// The field contains `value`
+    Text { key: &'static str, value: String },
+}
+
+#[async_trait]
+pub trait VectorStore: Send + Sync {
+    async fn health_check(&self) -> anyhow::Result<()>;
+
+    /// Whether the store was created empty when it was opened, so nothing that was embedded
+    /// before is in it
+    fn is_new(&self) -> bool;
+
+    /// Insert points, replacing any with the same id
+    async fn upsert(&self, points: Vec<Point>) -> anyhow::Result<()>;
+
+    async fn delete(&self, ids: Vec<String>) -> anyhow::Result<()>;
+
+    /// Delete the points of `repo_ref` with any of the content `hashes`
+    async fn delete_by_hash(&self, repo_ref: &str, hashes: Vec<String>) -> anyhow::Result<()>;
+
+    /// Replace the branches in the payload of the points with `ids`
+    async fn set_branches(&self, ids: Vec<String>, branches: Vec<String>) -> anyhow::Result<()>;
+
+    /// Find the points closest to `vector` that pass `filter`, most similar first.
+    ///
+    /// Only points with a cosine similarity of at least `threshold` are returned, along with
+    /// their score and embedding.
+    async fn search(
+        &self,
+        vector: Embedding,
+        filter: &Filter,
+        threshold: f32,
+        limit: u64,
+        offset: u64,
+    ) -> anyhow::Result<Vec<Payload>>;
+
+    /// Page through the points that pass `filter`, starting at the point with id `offset`.
+    ///
+    /// Returns the id the next page starts at, if there is one.
+    async fn scroll(
+        &self,
+        filter: &Filter,
+        offset: Option<String>,
+        limit: u32,
+    ) -> anyhow::Result<(Vec<Payload>, Option<String>)>;
+}
+
+/// Whether `config` selects a vector store
+pub fn is_configured(config: &Configuration) -> bool {
+    config.qdrant_url.is_some() || config.embedded_vector_store
+}
+
+/// Open the vector store selected in `config`, for the embeddings of `embedder`. Qdrant takes
+/// precedence over the embedded store.
+pub async fn open(
+    config: &Configuration,
+    embedder: &dyn Embedder,
+) -> Result<Arc<dyn VectorStore>, SemanticError> {
+    let dim = embedder.dimension();
+    let fingerprint = embedder.fingerprint();
+
+    if let Some(ref url) = config.qdrant_url {
+        return Ok(Arc::new(Qdrant::connect(url, dim, &fingerprint).await?));
+    }
+
+    let dir = config.index_dir.join("vectors");
+    Ok(Arc::new(Embedded::open(&dir, dim, &fingerprint)?))
+}
@@ -0,0 +1,851 @@
+//! An in-process vector store, so semantic search works without a Qdrant server
+//!
+//! Points are kept in memory, and searched through an HNSW graph[^hnsw] when too many of them
+//! pass the filter to compare the query to each one. Changes are appended to a log in the
+//! store's directory as they're made, and folded into a snapshot of the graph when the log gets
+//! large, or when the store is next opened.
+//!
+//! [^hnsw]: Malkov & Yashunin, "Efficient and robust approximate nearest neighbor search using
+//! Hierarchical Navigable Small World graphs", 2016
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use super::{Condition, Filter, Point, VectorStore};
+use crate::semantic::{Embedding, Payload};
+
+const SNAPSHOT: &str = "snapshot.bin";
+const LOG: &str = "changes.bin";
+
+/// The log is folded into the snapshot once it grows past this many bytes
+const LOG_LIMIT: u64 = 128 * 1024 * 1024;
+
+/// Links kept per node on the upper layers of the graph. The bottom layer keeps twice as many.
+const M: usize = 16;
+
+/// Candidates considered when linking a new node
+const EF_CONSTRUCTION: usize = 100;
+
+/// Candidates considered when searching
+const EF_SEARCH: usize = 64;
+
+/// Searches where at most this many points pass the filter compare the query to each of them
+const EXHAUSTIVE_LIMIT: usize = 20_000;
+
+/// Embeddings kept in the index directory
+pub struct Embedded {
+    state: Arc<State>,
+    new: bool,
+}
+
+/// Every operation takes locks and may write to disk, so it runs on a blocking thread with its
+/// own handle to the state
+struct State {
+    dir: PathBuf,
+    index: RwLock<Index>,
+    log: Mutex<Log>,
+}
+
+struct Log {
+    file: File,
+    len: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Change {
+    Upsert(Vec<StoredPoint>),
+    Delete(Vec<String>),
+    SetBranches {
+        ids: Vec<String>,
+        branches: Vec<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredPoint {
+    id: String,
+    /// Normalized, so that cosine similarity is a dot product
+    vector: Embedding,
+    payload: Payload,
+}
+
+impl Embedded {
+    /// Open the store in `dir`, creating it if it doesn't exist.
+    ///
+    /// A store of embeddings from a different embedder, going by its `fingerprint`, or with a
+    /// different dimension is started over.
+    pub fn open(dir: &Path, dim: usize, fingerprint: &str) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let snapshot = dir.join(SNAPSHOT);
+        let log = dir.join(LOG);
+
+        let existing = match read_snapshot(&snapshot) {
+            Ok(Some(index)) if index.dim == dim && index.embedder == fingerprint => Some(index),
+            Ok(Some(index)) => {
+                warn!(
+                    old = index.embedder,
+                    new = fingerprint,
+                    "embedder changed; starting over"
+                );
+                None
+            }
+            Ok(None) => None,
+            Err(err) => {
+                warn!(?err, "failed to read vector snapshot; starting over");
+                None
+            }
+        };
+
+        let new = existing.is_none();
+        let (mut index, replayed) = match existing {
+            Some(mut index) => {
+                let replayed = replay(&log, &mut index);
+                debug!(points = index.ids.len(), replayed, "opened vector store");
+                (index, replayed)
+            }
+            None => (Index::new(dim, fingerprint.to_owned()), 0),
+        };
+
+        // everything in the log is in the snapshot from here on
+        if new || replayed > 0 {
+            write_snapshot(dir, &mut index)?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&log)?;
+
+        Ok(Self {
+            state: Arc::new(State {
+                dir: dir.to_owned(),
+                index: RwLock::new(index),
+                log: Mutex::new(Log { file, len: 0 }),
+            }),
+            new,
+        })
+    }
+
+    /// Run `f` on a blocking thread, so that waiting for locks and disk doesn't hold up the
+    /// runtime
+    async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&State) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let state = Arc::clone(&self.state);
+        tokio::task::spawn_blocking(move || f(&state)).await?
+    }
+}
+
+impl State {
+    /// Log a change, then make it
+    fn write(&self, change: Change) -> Result<()> {
+        let mut log = self.log.lock().unwrap();
+        let record = bincode::serialize(&change)?;
+        log.file.write_all(&record)?;
+        log.len += record.len() as u64;
+
+        let mut index = self.index.write().unwrap();
+        index.apply(change);
+
+        if log.len > LOG_LIMIT {
+            write_snapshot(&self.dir, &mut index)?;
+            log.file.set_len(0)?;
+            log.len = 0;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl VectorStore for Embedded {
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn is_new(&self) -> bool {
+        self.new
+    }
+
+    async fn upsert(&self, points: Vec<Point>) -> Result<()> {
+        if points.is_empty() {
+            return Ok(());
+        }
+
+        self.blocking(|state| {
+            let points = points
+                .into_iter()
+                .map(|point| StoredPoint {
+                    id: point.id,
+                    vector: normalized(point.vector),
+                    payload: point.payload,
+                })
+                .collect();
+
+            state.write(Change::Upsert(points))
+        })
+        .await
+    }
+
+    async fn delete(&self, ids: Vec<String>) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        self.blocking(|state| state.write(Change::Delete(ids)))
+            .await
+    }
+
+    async fn delete_by_hash(&self, repo_ref: &str, hashes: Vec<String>) -> Result<()> {
+        let repo_ref = repo_ref.to_owned();
+        self.blocking(move |state| {
+            let hashes = hashes.into_iter().collect::<HashSet<_>>();
+            let ids = {
+                let index = state.index.read().unwrap();
+                index
+                    .ids
+                    .iter()
+                    .filter(|(_, &node)| {
+                        let payload = &index.nodes[node as usize].payload;
+                        payload.repo_ref == repo_ref && hashes.contains(&payload.content_hash)
+                    })
+                    .map(|(id, _)| id.clone())
+                    .collect::<Vec<_>>()
+            };
+
+            if ids.is_empty() {
+                return Ok(());
+            }
+
+            state.write(Change::Delete(ids))
+        })
+        .await
+    }
+
+    async fn set_branches(&self, ids: Vec<String>, branches: Vec<String>) -> Result<()> {
+        self.blocking(|state| state.write(Change::SetBranches { ids, branches }))
+            .await
+    }
+
+    async fn search(
+        &self,
+        vector: Embedding,
+        filter: &Filter,
+        threshold: f32,
+        limit: u64,
+        offset: u64,
+    ) -> Result<Vec<Payload>> {
+        let filter = filter.clone();
+        self.blocking(move |state| {
+            let query = normalized(vector);
+            let index = state.index.read().unwrap();
+
+            Ok(index
+                .search(&query, &filter, threshold, limit as usize, offset as usize)
+                .into_iter()
+                .map(|Scored(score, node)| index.payload(node, Some(score)))
+                .collect())
+        })
+        .await
+    }
+
+    async fn scroll(
+        &self,
+        filter: &Filter,
+        offset: Option<String>,
+        limit: u32,
+    ) -> Result<(Vec<Payload>, Option<String>)> {
+        let filter = filter.clone();
+        self.blocking(move |state| {
+            let index = state.index.read().unwrap();
+
+            let mut ids = index
+                .ids
+                .iter()
+                .filter(|(id, _)| offset.as_ref().map_or(true, |o| id >= &o))
+                .filter(|(_, &node)| matches(&filter, &index.nodes[node as usize].payload))
+                .collect::<Vec<_>>();
+            ids.sort_unstable();
+
+            let next = ids.get(limit as usize).map(|(id, _)| id.to_string());
+            let page = ids
+                .into_iter()
+                .take(limit as usize)
+                .map(|(_, &node)| index.payload(node, None))
+                .collect();
+
+            Ok((page, next))
+        })
+        .await
+    }
+}
+
+/// A navigable graph of points, in layers that get sparser towards the top
+#[derive(Serialize, Deserialize)]
+struct Index {
+    dim: usize,
+
+    /// The fingerprint of the embedder the vectors came from
+    embedder: String,
+
+    nodes: Vec<Node>,
+
+    /// The node searches start from, on the top layer
+    entry: Option<u32>,
+
+    /// How many nodes are deleted
+    deleted: usize,
+
+    /// The nodes of points that aren't deleted, by point id
+    #[serde(skip)]
+    ids: HashMap<String, u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Node {
+    id: String,
+    vector: Embedding,
+    payload: Payload,
+
+    /// Neighbours on each layer the node is on, from the bottom up
+    links: Vec<Vec<u32>>,
+
+    /// Deleted nodes are still walked through, but never returned
+    deleted: bool,
+}
+
+/// A node along with its similarity to a query, ordered by similarity
+#[derive(Clone, Copy, Debug)]
+struct Scored(f32, u32);
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
+
+impl Index {
+    fn new(dim: usize, embedder: String) -> Self {
+        Self {
+            dim,
+            embedder,
+            nodes: vec![],
+            entry: None,
+            deleted: 0,
+            ids: HashMap::new(),
+        }
+    }
+
+    fn apply(&mut self, change: Change) {
+        match change {
+            Change::Upsert(points) => {
+                for point in points {
+                    self.remove(&point.id);
+                    self.insert(point);
+                }
+            }
+            Change::Delete(ids) => {
+                for id in ids {
+                    self.remove(&id);
+                }
+            }
+            Change::SetBranches { ids, branches } => {
+                for id in ids {
+                    if let Some(&node) = self.ids.get(&id) {
+                        self.nodes[node as usize].payload.branches = branches.clone();
+                    }
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, id: &str) {
+        if let Some(node) = self.ids.remove(id) {
+            self.nodes[node as usize].deleted = true;
+            self.deleted += 1;
+        }
+    }
+
+    fn insert(&mut self, point: StoredPoint) {
+        self.insert_at(point, random_level(&mut rand::thread_rng()));
+    }
+
+    /// Insert a point, on the layers up to `level`
+    fn insert_at(&mut self, point: StoredPoint, level: usize) {
+        let node = self.nodes.len() as u32;
+        self.ids.insert(point.id.clone(), node);
+        self.nodes.push(Node {
+            id: point.id,
+            vector: point.vector,
+            payload: point.payload,
+            links: vec![vec![]; level + 1],
+            deleted: false,
+        });
+
+        let Some(mut entry) = self.entry else {
+            self.entry = Some(node);
+            return;
+        };
+
+        let query = self.nodes[node as usize].vector.clone();
+        let top = self.nodes[entry as usize].links.len() - 1;
+        for layer in (level + 1..=top).rev() {
+            entry = self.closest(&query, entry, layer);
+        }
+
+        for layer in (0..=level.min(top)).rev() {
+            let candidates = self.search_layer(&query, entry, EF_CONSTRUCTION, layer);
+            entry = candidates[0].1;
+
+            let neighbours = candidates
+                .into_iter()
+                .take(M)
+                .map(|Scored(_, n)| n)
+                .collect::<Vec<_>>();
+
+            for &n in &neighbours {
+                let links = &mut self.nodes[n as usize].links[layer];
+                links.push(node);
+                if links.len() > max_links(layer) {
+                    self.prune(n, layer);
+                }
+            }
+
+            self.nodes[node as usize].links[layer] = neighbours;
+        }
+
+        if level > top {
+            self.entry = Some(node);
+        }
+    }
+
+    /// Keep only the closest links of `node` on `layer`
+    fn prune(&mut self, node: u32, layer: usize) {
+        let vector = &self.nodes[node as usize].vector;
+        let mut links = self.nodes[node as usize].links[layer]
+            .iter()
+            .map(|&n| Scored(dot(vector, &self.nodes[n as usize].vector), n))
+            .collect::<Vec<_>>();
+
+        links.sort_unstable_by(|a, b| b.cmp(a));
+        links.truncate(max_links(layer));
+        self.nodes[node as usize].links[layer] = links.into_iter().map(|s| s.1).collect();
+    }
+
+    fn similarity(&self, node: u32, query: &[f32]) -> f32 {
+        dot(&self.nodes[node as usize].vector, query)
+    }
+
+    /// Walk from `entry` towards `query` on `layer`, until no neighbour is closer
+    fn closest(&self, query: &[f32], mut entry: u32, layer: usize) -> u32 {
+        let mut best = self.similarity(entry, query);
+        loop {
+            let mut moved = false;
+            for &n in &self.nodes[entry as usize].links[layer] {
+                let similarity = self.similarity(n, query);
+                if similarity > best {
+                    best = similarity;
+                    entry = n;
+                    moved = true;
+                }
+            }
+
+            if !moved {
+                return entry;
+            }
+        }
+    }
+
+    /// The `ef` nodes closest to `query` that can be reached from `entry` on `layer`, closest
+    /// first
+    fn search_layer(&self, query: &[f32], entry: u32, ef: usize, layer: usize) -> Vec<Scored> {
+        let first = Scored(self.similarity(entry, query), entry);
+        let mut visited = HashSet::from([entry]);
+        let mut candidates = BinaryHeap::from([first]);
+        let mut results = BinaryHeap::from([Reverse(first)]);
+
+        while let Some(Scored(similarity, node)) = candidates.pop() {
+            let worst = results.peek().map_or(f32::MIN, |Reverse(s)| s.0);
+            if results.len() >= ef && similarity < worst {
+                break;
+            }
+
+            for &n in &self.nodes[node as usize].links[layer] {
+                if !visited.insert(n) {
+                    continue;
+                }
+
+                let scored = Scored(self.similarity(n, query), n);
+                let worst = results.peek().map_or(f32::MIN, |Reverse(s)| s.0);
+                if results.len() < ef || scored.0 > worst {
+                    candidates.push(scored);
+                    results.push(Reverse(scored));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        let mut results = results.into_iter().map(|Reverse(s)| s).collect::<Vec<_>>();
+        results.sort_unstable_by(|a, b| b.cmp(a));
+        results
+    }
+
+    /// The `ef` nodes closest to `query` found through the graph, closest first, including
+    /// deleted ones
+    fn search_graph(&self, query: &[f32], ef: usize) -> Vec<Scored> {
+        let Some(mut entry) = self.entry else {
+            return vec![];
+        };
+
+        let top = self.nodes[entry as usize].links.len() - 1;
+        for layer in (1..=top).rev() {
+            entry = self.closest(query, entry, layer);
+        }
+
+        self.search_layer(query, entry, ef, 0)
+    }
+
+    fn search(
+        &self,
+        query: &[f32],
+        filter: &Filter,
+        threshold: f32,
+        limit: usize,
+        offset: usize,
+    ) -> Vec<Scored> {
+        let wanted = limit + offset;
+        let allowed = self
+            .nodes
+            .iter()
+            .map(|node| !node.deleted && matches(filter, &node.payload))
+            .collect::<Vec<_>>();
+
+        let mut scored = if allowed.iter().filter(|a| **a).count() <= EXHAUSTIVE_LIMIT {
+            allowed
+                .iter()
+                .enumerate()
+                .filter(|(_, allowed)| **allowed)
+                .map(|(node, _)| Scored(self.similarity(node as u32, query), node as u32))
+                .collect::<Vec<_>>()
+        } else {
+            // the graph search doesn't know about the filter, so look further until enough of
+            // what it finds passes
+            let mut ef = EF_SEARCH.max(wanted);
+            loop {
+                let found = self.search_graph(query, ef);
+                let exhausted = ef >= self.nodes.len()
+                    || found.last().map_or(true, |worst| worst.0 < threshold);
+
+                let found = found
+                    .into_iter()
+                    .filter(|s| allowed[s.1 as usize])
+                    .collect::<Vec<_>>();
+
+                if found.len() >= wanted || exhausted {
+                    break found;
+                }
+
+                ef *= 2;
+            }
+        };
+
+        scored.retain(|s| s.0 >= threshold);
+        scored.sort_unstable_by(|a, b| b.cmp(a));
+        scored.into_iter().skip(offset).take(limit).collect()
+    }
+
+    fn payload(&self, node: u32, score: Option<f32>) -> Payload {
+        let node = &self.nodes[node as usize];
+        Payload {
+            id: Some(node.id.clone()),
+            embedding: Some(node.vector.clone()),
+            score,
+            ..node.payload.clone()
+        }
+    }
+
+    /// Rebuild the graph without deleted nodes, once they make up most of it
+    fn compact(&mut self) {
+        if self.deleted * 2 <= self.nodes.len() {
+            return;
+        }
+
+        debug!(
+            nodes = self.nodes.len(),
+            deleted = self.deleted,
+            "compacting vector store"
+        );
+
+        let nodes = std::mem::take(&mut self.nodes);
+        *self = Index::new(self.dim, std::mem::take(&mut self.embedder));
+        for node in nodes.into_iter().filter(|n| !n.deleted) {
+            self.insert(StoredPoint {
+                id: node.id,
+                vector: node.vector,
+                payload: node.payload,
+            });
+        }
+    }
+}
+
+fn read_snapshot(path: &Path) -> Result<Option<Index>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut index: Index = bincode::deserialize_from(BufReader::new(file))?;
+    index.ids = index
+        .nodes
+        .iter()
+        .enumerate()
+        .filter(|(_, node)| !node.deleted)
+        .map(|(i, node)| (node.id.clone(), i as u32))
+        .collect();
+
+    Ok(Some(index))
+}
+
+/// Write the index to a new snapshot, replacing the old one once it's complete
+fn write_snapshot(dir: &Path, index: &mut Index) -> Result<()> {
+    index.compact();
+
+    let tmp = dir.join(format!("{SNAPSHOT}.tmp"));
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    bincode::serialize_into(&mut writer, &*index)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+
+    std::fs::rename(tmp, dir.join(SNAPSHOT))?;
+    Ok(())
+}
+
+/// Apply the changes in the log at `path` to `index`, returning how many there were
+fn replay(path: &Path, index: &mut Index) -> usize {
+    let Ok(file) = File::open(path) else {
+        return 0;
+    };
+
+    let mut reader = BufReader::new(file);
+    let mut replayed = 0;
+
+    // a change that was cut off while it was being written ends the log
+    while let Ok(change) = bincode::deserialize_from(&mut reader) {
+        index.apply(change);
+        replayed += 1;
+    }
+
+    replayed
+}
+
+fn matches(filter: &Filter, payload: &Payload) -> bool {
+    filter
+        .must
+        .iter()
+        .all(|group| group.iter().any(|c| condition_matches(c, payload)))
+        && !filter
+            .must_not
+            .iter()
+            .any(|c| condition_matches(c, payload))
+}
+
+fn condition_matches(condition: &Condition, payload: &Payload) -> bool {
+    let (Condition::Keyword { key, value } | Condition::Text { key, value }) = condition;
+    let field = match *key {
+        "lang" => &payload.lang,
+        "repo_name" => &payload.repo_name,
+        "repo_ref" => &payload.repo_ref,
+        "relative_path" => &payload.relative_path,
+        "content_hash" => &payload.content_hash,
+        "branches" => return payload.branches.contains(value),
+        _ => return false,
+    };
+
+    match condition {
+        Condition::Keyword { .. } => field == value,
+        Condition::Text { .. } => field.to_lowercase().contains(&value.to_lowercase()),
+    }
+}
+
+fn max_links(layer: usize) -> usize {
+    if layer == 0 {
+        2 * M
+    } else {
+        M
+    }
+}
+
+/// Pick the top layer of a new node, so that each layer has about `1 / M` of the nodes of the
+/// one below it
+fn random_level(rng: &mut impl Rng) -> usize {
+    let uniform = 1.0 - rng.gen::<f64>();
+    (-uniform.ln() / (M as f64).ln()).floor() as usize
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(ai, bi)| ai * bi).sum()
+}
+
+fn normalized(mut vector: Embedding) -> Embedding {
+    let norm = dot(&vector, &vector).sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+    vector
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{rngs::StdRng, SeedableRng};
+    use tempdir::TempDir;
+
+    fn point(id: &str, vector: Embedding, relative_path: &str, content_hash: &str) -> Point {
+        Point {
+            id: id.to_owned(),
+            vector,
+            payload: Payload {
+                repo_ref: "local//repo".to_owned(),
+                relative_path: relative_path.to_owned(),
+                content_hash: content_hash.to_owned(),
+                branches: vec!["main".to_owned()],
+                ..Default::default()
+            },
+        }
+    }
+
+    async fn search(store: &Embedded, filter: &Filter) -> Vec<String> {
+        store
+            .search(vec![1.0, 0.0, 0.0], filter, 0.3, 10, 0)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| p.id.unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn search_and_reopen() {
+        let tmpdir = TempDir::new("test-vectors").unwrap();
+        let store = Embedded::open(tmpdir.path(), 3, "model").unwrap();
+        assert!(store.is_new());
+
+        store
+            .upsert(vec![
+                point("a", vec![2.0, 0.0, 0.0], "src/a.rs", "h1"),
+                point("b", vec![0.9, 0.1, 0.0], "src/b.rs", "h2"),
+                point("c", vec![0.0, 1.0, 0.0], "docs/c.md", "h2"),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(search(&store, &Filter::default()).await, ["a", "b"]);
+
+        store
+            .set_branches(vec!["b".to_owned()], vec!["dev".to_owned()])
+            .await
+            .unwrap();
+        let dev = Filter {
+            must: vec![vec![Condition::Keyword {
+                key: "branches",
+                value: "dev".to_owned(),
+            }]],
+            ..Default::default()
+        };
+        assert_eq!(search(&store, &dev).await, ["b"]);
+
+        let not_b = Filter {
+            must_not: vec![Condition::Text {
+                key: "relative_path",
+                value: "B.RS".to_owned(),
+            }],
+            ..Default::default()
+        };
+        assert_eq!(search(&store, &not_b).await, ["a"]);
+
+        store
+            .delete_by_hash("local//repo", vec!["h1".to_owned()])
+            .await
+            .unwrap();
+        assert_eq!(search(&store, &Filter::default()).await, ["b"]);
+        drop(store);
+
+        let store = Embedded::open(tmpdir.path(), 3, "model").unwrap();
+        assert!(!store.is_new());
+        assert_eq!(search(&store, &dev).await, ["b"]);
+
+        let (page, next) = store.scroll(&Filter::default(), None, 1).await.unwrap();
+        assert_eq!(page[0].id.as_deref(), Some("b"));
+        assert_eq!(next.as_deref(), Some("c"));
+        drop(store);
+
+        assert!(Embedded::open(tmpdir.path(), 3, "other model")
+            .unwrap()
+            .is_new());
+        assert!(Embedded::open(tmpdir.path(), 4, "other model")
+            .unwrap()
+            .is_new());
+    }
+
+    #[test]
+    fn graph_search() {
+        let mut index = Index::new(8, String::new());
+        let mut rng = StdRng::seed_from_u64(8);
+        let random =
+            |rng: &mut StdRng| normalized((0..8).map(|_| rng.gen::<f32>() - 0.5).collect());
+
+        for i in 0..2000 {
+            let point = StoredPoint {
+                id: i.to_string(),
+                vector: random(&mut rng),
+                payload: Payload::default(),
+            };
+            let level = random_level(&mut rng);
+            index.insert_at(point, level);
+        }
+
+        let found = (0..50)
+            .map(|_| random(&mut rng))
+            .filter(|query| {
+                let exact = (0..index.nodes.len() as u32)
+                    .map(|n| Scored(index.similarity(n, query), n))
+                    .max()
+                    .unwrap();
+                index.search_graph(query, EF_SEARCH)[0] == exact
+            })
+            .count();
+
+        assert!(found >= 48, "found {found} of 50 nearest neighbours");
+    }
+}
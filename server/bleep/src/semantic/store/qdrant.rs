@@ -0,0 +1,511 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use async_trait::async_trait;
+use qdrant_client::{
+    prelude::{QdrantClient, QdrantClientConfig},
+    qdrant::{
+        point_id::PointIdOptions, r#match::MatchValue, vectors::VectorsOptions, vectors_config,
+        with_payload_selector, with_vectors_selector, CollectionOperationResponse,
+        CreateCollection, Distance, FieldCondition, FieldType, Match, PointId, PointStruct,
+        RetrievedPoint, ScoredPoint, ScrollPoints, SearchPoints, Value, VectorParams, Vectors,
+        VectorsConfig, WithPayloadSelector, WithVectorsSelector,
+    },
+};
+use tracing::{debug, warn};
+
+use super::{Condition, Filter, Point, VectorStore};
+use crate::semantic::{Embedding, Payload, SemanticError};
+
+const COLLECTION_NAME: &str = "documents";
+
+/// Holds a single point, whose payload is the fingerprint of the embedder that filled
+/// [`COLLECTION_NAME`]
+const EMBEDDER_COLLECTION_NAME: &str = "documents_embedder";
+
+/// Embeddings kept in a collection on a Qdrant server
+pub struct Qdrant {
+    client: QdrantClient,
+    new: bool,
+}
+
+impl Qdrant {
+    /// Connect to the server at `url`, creating the collection if it doesn't exist.
+    ///
+    /// A collection of embeddings from a different embedder, going by its `fingerprint`, or with
+    /// a different dimension is recreated.
+    pub async fn connect(url: &str, dim: usize, fingerprint: &str) -> Result<Self, SemanticError> {
+        let client = QdrantClient::new(Some(QdrantClientConfig::from_url(url))).unwrap();
+
+        let exists = match client.has_collection(COLLECTION_NAME).await {
+            Ok(exists) => exists,
+            Err(_) => return Err(SemanticError::QdrantInitializationError),
+        };
+
+        let mut new = !exists;
+        if exists {
+            let old_dim = collection_dimension(&client).await?;
+            let old = collection_fingerprint(&client).await?;
+            if old_dim.map_or(false, |old| old != dim as u64) || old.as_deref() != Some(fingerprint)
+            {
+                warn!(
+                    ?old,
+                    new = fingerprint,
+                    "embedder changed; recreating collection"
+                );
+                client.delete_collection(COLLECTION_NAME).await?;
+                new = true;
+            }
+        }
+
+        if new {
+            let CollectionOperationResponse { result, time } = client
+                .create_collection(&collection_config(dim))
+                .await
+                .unwrap();
+
+            debug!(
+                time,
+                created = result,
+                name = COLLECTION_NAME,
+                "created qdrant collection"
+            );
+
+            assert!(result);
+            set_collection_fingerprint(&client, fingerprint).await?;
+        }
+
+        client
+            .create_field_index(COLLECTION_NAME, "repo_ref", FieldType::Text, None, None)
+            .await?;
+        client
+            .create_field_index(COLLECTION_NAME, "content_hash", FieldType::Text, None, None)
+            .await?;
+        client
+            .create_field_index(COLLECTION_NAME, "branches", FieldType::Text, None, None)
+            .await?;
+        client
+            .create_field_index(
+                COLLECTION_NAME,
+                "relative_path",
+                FieldType::Text,
+                None,
+                None,
+            )
+            .await?;
+
+        Ok(Self { client, new })
+    }
+}
+
+#[async_trait]
+impl VectorStore for Qdrant {
+    async fn health_check(&self) -> anyhow::Result<()> {
+        self.client.health_check().await?;
+        Ok(())
+    }
+
+    fn is_new(&self) -> bool {
+        self.new
+    }
+
+    async fn upsert(&self, points: Vec<Point>) -> anyhow::Result<()> {
+        // qdrant doesn't like empty payloads.
+        if points.is_empty() {
+            return Ok(());
+        }
+
+        let points = points
+            .into_iter()
+            .map(|point| PointStruct {
+                id: Some(PointId::from(point.id)),
+                vectors: Some(point.vector.into()),
+                payload: point.payload.into_qdrant(),
+            })
+            .collect();
+
+        self.client
+            .upsert_points_blocking(COLLECTION_NAME, points, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, ids: Vec<String>) -> anyhow::Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let ids = ids.into_iter().map(PointId::from).collect::<Vec<_>>();
+        self.client
+            .delete_points(COLLECTION_NAME, &ids.into(), None)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_by_hash(&self, repo_ref: &str, hashes: Vec<String>) -> anyhow::Result<()> {
+        let repo_filter = make_kv_keyword_filter("repo_ref", repo_ref).into();
+        let file_filter = hashes
+            .iter()
+            .map(|h| make_kv_keyword_filter("content_hash", h).into())
+            .collect::<Vec<_>>();
+
+        let selector = qdrant_client::qdrant::Filter {
+            must: vec![repo_filter],
+            should: file_filter,
+            ..Default::default()
+        }
+        .into();
+
+        self.client
+            .delete_points(COLLECTION_NAME, &selector, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_branches(&self, ids: Vec<String>, branches: Vec<String>) -> anyhow::Result<()> {
+        let ids = ids
+            .into_iter()
+            .map(PointId::from)
+            .collect::<Vec<_>>()
+            .into();
+
+        let payload = qdrant_client::client::Payload::new_from_hashmap(
+            [("branches".to_string(), branches.into())].into(),
+        );
+
+        self.client
+            .set_payload_blocking(COLLECTION_NAME, &ids, payload, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn search(
+        &self,
+        vector: Embedding,
+        filter: &Filter,
+        threshold: f32,
+        limit: u64,
+        offset: u64,
+    ) -> anyhow::Result<Vec<Payload>> {
+        let response = self
+            .client
+            .search_points(&SearchPoints {
+                limit,
+                vector,
+                collection_name: COLLECTION_NAME.to_string(),
+                offset: Some(offset),
+                score_threshold: Some(threshold),
+                with_payload: Some(WithPayloadSelector {
+                    selector_options: Some(with_payload_selector::SelectorOptions::Enable(true)),
+                }),
+                filter: Some(to_qdrant_filter(filter)),
+                with_vectors: Some(WithVectorsSelector {
+                    selector_options: Some(with_vectors_selector::SelectorOptions::Enable(true)),
+                }),
+                ..Default::default()
+            })
+            .await?;
+
+        Ok(response
+            .result
+            .into_iter()
+            .map(Payload::from_qdrant)
+            .collect())
+    }
+
+    async fn scroll(
+        &self,
+        filter: &Filter,
+        offset: Option<String>,
+        limit: u32,
+    ) -> anyhow::Result<(Vec<Payload>, Option<String>)> {
+        let response = self
+            .client
+            .scroll(&ScrollPoints {
+                collection_name: COLLECTION_NAME.to_string(),
+                filter: Some(to_qdrant_filter(filter)),
+                offset: offset.map(PointId::from),
+                limit: Some(limit),
+                with_payload: Some(WithPayloadSelector {
+                    selector_options: Some(with_payload_selector::SelectorOptions::Enable(true)),
+                }),
+                with_vectors: Some(WithVectorsSelector {
+                    selector_options: Some(with_vectors_selector::SelectorOptions::Enable(true)),
+                }),
+                ..Default::default()
+            })
+            .await?;
+
+        let next = response
+            .next_page_offset
+            .and_then(|id| match id.point_id_options {
+                Some(PointIdOptions::Uuid(id)) => Some(id),
+                _ => None,
+            });
+
+        Ok((
+            response
+                .result
+                .into_iter()
+                .map(Payload::from_scroll)
+                .collect(),
+            next,
+        ))
+    }
+}
+
+macro_rules! val_str(($hash:ident, $val:expr) => { serde_json::from_value($hash.remove($val).unwrap()).unwrap() });
+macro_rules! val_parse_str(($hash:ident, $val:expr) => {
+    serde_json::from_value::<Cow<'_, str>>($hash.remove($val).unwrap())
+        .unwrap()
+        .parse()
+        .unwrap()
+});
+
+impl Payload {
+    fn from_qdrant(orig: ScoredPoint) -> Payload {
+        let ScoredPoint {
+            id,
+            payload,
+            score,
+            vectors,
+            ..
+        } = orig;
+
+        parse_payload(id, vectors, payload, score)
+    }
+
+    fn from_scroll(orig: RetrievedPoint) -> Payload {
+        let RetrievedPoint {
+            id,
+            payload,
+            vectors,
+            ..
+        } = orig;
+
+        parse_payload(id, vectors, payload, 0.0)
+    }
+
+    fn into_qdrant(self) -> HashMap<String, Value> {
+        HashMap::from([
+            ("lang".into(), self.lang.to_ascii_lowercase().into()),
+            ("repo_name".into(), self.repo_name.into()),
+            ("repo_ref".into(), self.repo_ref.into()),
+            ("relative_path".into(), self.relative_path.into()),
+            ("content_hash".into(), self.content_hash.into()),
+            ("snippet".into(), self.text.into()),
+            ("start_line".into(), self.start_line.to_string().into()),
+            ("end_line".into(), self.end_line.to_string().into()),
+            ("start_byte".into(), self.start_byte.to_string().into()),
+            ("end_byte".into(), self.end_byte.to_string().into()),
+            ("branches".into(), self.branches.into()),
+        ])
+    }
+}
+
+fn parse_payload(
+    id: Option<PointId>,
+    vectors: Option<Vectors>,
+    payload: HashMap<String, Value>,
+    score: f32,
+) -> Payload {
+    let Some(PointId {
+        point_id_options: Some(PointIdOptions::Uuid(id)),
+    }) = id
+    else {
+        // unless the db was corrupted/written by someone else,
+        // this shouldn't happen
+        unreachable!("corrupted db");
+    };
+
+    let embedding = match vectors {
+        None => None,
+        Some(Vectors {
+            vectors_options: Some(VectorsOptions::Vector(v)),
+        }) => Some(v.data),
+        _ => {
+            // this also should probably never happen
+            unreachable!("got non-vector value");
+        }
+    };
+
+    let mut converted = payload
+        .into_iter()
+        .map(|(key, value)| (key, kind_to_value(value.kind)))
+        .collect::<HashMap<String, serde_json::Value>>();
+
+    Payload {
+        lang: val_str!(converted, "lang"),
+        repo_name: val_str!(converted, "repo_name"),
+        repo_ref: val_str!(converted, "repo_ref"),
+        relative_path: val_str!(converted, "relative_path"),
+        content_hash: val_str!(converted, "content_hash"),
+        text: val_str!(converted, "snippet"),
+        branches: val_str!(converted, "branches"),
+        start_line: val_parse_str!(converted, "start_line"),
+        end_line: val_parse_str!(converted, "end_line"),
+        start_byte: val_parse_str!(converted, "start_byte"),
+        end_byte: val_parse_str!(converted, "end_byte"),
+
+        id: Some(id),
+        score: Some(score),
+        embedding,
+    }
+}
+
+fn kind_to_value(kind: Option<qdrant_client::qdrant::value::Kind>) -> serde_json::Value {
+    use qdrant_client::qdrant::value::Kind;
+    match kind {
+        Some(Kind::NullValue(_)) => serde_json::Value::Null,
+        Some(Kind::BoolValue(v)) => serde_json::Value::Bool(v),
+        Some(Kind::DoubleValue(v)) => {
+            serde_json::Value::Number(serde_json::Number::from_f64(v).unwrap())
+        }
+        Some(Kind::IntegerValue(v)) => serde_json::Value::Number(v.into()),
+        Some(Kind::StringValue(v)) => serde_json::Value::String(v),
+        Some(Kind::ListValue(v)) => serde_json::Value::Array(
+            v.values
+                .into_iter()
+                .map(|v| kind_to_value(v.kind))
+                .collect(),
+        ),
+        Some(Kind::StructValue(_v)) => todo!(),
+        None => serde_json::Value::Null,
+    }
+}
+
+/// The size of the vectors in the collection
+async fn collection_dimension(client: &QdrantClient) -> anyhow::Result<Option<u64>> {
+    let info = client.collection_info(COLLECTION_NAME).await?;
+    let config = info
+        .result
+        .and_then(|info| info.config)
+        .and_then(|config| config.params)
+        .and_then(|params| params.vectors_config)
+        .and_then(|vectors| vectors.config);
+
+    Ok(match config {
+        Some(vectors_config::Config::Params(params)) => Some(params.size),
+        _ => None,
+    })
+}
+
+/// The fingerprint of the embedder the collection was filled by, if it's known
+async fn collection_fingerprint(client: &QdrantClient) -> anyhow::Result<Option<String>> {
+    use qdrant_client::qdrant::value::Kind;
+
+    if !client.has_collection(EMBEDDER_COLLECTION_NAME).await? {
+        return Ok(None);
+    }
+
+    let response = client
+        .scroll(&ScrollPoints {
+            collection_name: EMBEDDER_COLLECTION_NAME.to_string(),
+            limit: Some(1),
+            with_payload: Some(WithPayloadSelector {
+                selector_options: Some(with_payload_selector::SelectorOptions::Enable(true)),
+            }),
+            ..Default::default()
+        })
+        .await?;
+
+    Ok(response
+        .result
+        .into_iter()
+        .next()
+        .and_then(|mut point| point.payload.remove("fingerprint"))
+        .and_then(|value| match value.kind {
+            Some(Kind::StringValue(fingerprint)) => Some(fingerprint),
+            _ => None,
+        }))
+}
+
+async fn set_collection_fingerprint(
+    client: &QdrantClient,
+    fingerprint: &str,
+) -> anyhow::Result<()> {
+    if !client.has_collection(EMBEDDER_COLLECTION_NAME).await? {
+        client
+            .create_collection(&CreateCollection {
+                collection_name: EMBEDDER_COLLECTION_NAME.to_string(),
+                ..collection_config(1)
+            })
+            .await?;
+    }
+
+    let point = PointStruct {
+        id: Some(PointId::from(0_u64)),
+        vectors: Some(vec![1.0_f32].into()),
+        payload: HashMap::from([("fingerprint".into(), fingerprint.to_string().into())]),
+    };
+
+    client
+        .upsert_points_blocking(EMBEDDER_COLLECTION_NAME, vec![point], None)
+        .await?;
+    Ok(())
+}
+
+fn collection_config(dim: usize) -> CreateCollection {
+    CreateCollection {
+        collection_name: COLLECTION_NAME.to_string(),
+        vectors_config: Some(VectorsConfig {
+            config: Some(vectors_config::Config::Params(VectorParams {
+                size: dim as u64,
+                distance: Distance::Cosine.into(),
+                ..Default::default()
+            })),
+        }),
+        ..Default::default()
+    }
+}
+
+fn to_qdrant_filter(filter: &Filter) -> qdrant_client::qdrant::Filter {
+    qdrant_client::qdrant::Filter {
+        // one of the conditions in each group should match
+        must: filter
+            .must
+            .iter()
+            .map(|group| {
+                qdrant_client::qdrant::Filter {
+                    should: group.iter().map(to_qdrant_condition).collect(),
+                    ..Default::default()
+                }
+                .into()
+            })
+            .collect(),
+        must_not: filter.must_not.iter().map(to_qdrant_condition).collect(),
+        ..Default::default()
+    }
+}
+
+fn to_qdrant_condition(condition: &Condition) -> qdrant_client::qdrant::Condition {
+    match condition {
+        Condition::Keyword { key, value } => make_kv_keyword_filter(key, value).into(),
+        Condition::Text { key, value } => make_kv_text_filter(key, value).into(),
+    }
+}
+
+// Exact match filter
+fn make_kv_keyword_filter(key: &str, value: &str) -> FieldCondition {
+    let key = key.to_owned();
+    let value = value.to_owned();
+    FieldCondition {
+        key,
+        r#match: Some(Match {
+            match_value: MatchValue::Keyword(value).into(),
+        }),
+        ..Default::default()
+    }
+}
+
+// Substring match filter
+fn make_kv_text_filter(key: &str, value: &str) -> FieldCondition {
+    let key = key.to_owned();
+    let value = value.to_owned();
+    FieldCondition {
+        key,
+        r#match: Some(Match {
+            match_value: MatchValue::Text(value).into(),
+        }),
+        ..Default::default()
+    }
+}
@@ -3,7 +3,12 @@ use regex::{Regex, RegexBuilder};
 use serde::Serialize;
 use smallvec::{smallvec, SmallVec};
 
-use crate::{indexes, symbol::Symbol};
+use crate::{
+    indexes,
+    intelligence::StructuralQueries,
+    query::parser::{Literal, QueryTree, Target},
+    symbol::Symbol,
+};
 use std::ops::Range;
 
 #[derive(Serialize, Debug, PartialEq, Eq)]
@@ -13,6 +18,9 @@ pub struct SnippedFile {
     pub repo_ref: String,
     pub lang: Option<String>,
     pub snippets: Vec<Snippet>,
+    /// The path of the submodule this file comes from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submodule: Option<String>,
 }
 
 #[derive(Serialize, Debug, PartialEq, Eq)]
@@ -54,9 +62,11 @@ impl Location {
             return Err(rhs);
         }
 
+        // Highlights of different targets may be nested, so the right-hand side doesn't
+        // necessarily extend this location.
         let offset = rhs.byte_range.start - self.byte_range.start;
-        self.line_range.end = rhs.line_range.end;
-        self.byte_range.end = rhs.byte_range.end;
+        self.line_range.end = self.line_range.end.max(rhs.line_range.end);
+        self.byte_range.end = self.byte_range.end.max(rhs.byte_range.end);
         self.highlights
             .extend(rhs.highlights.into_iter().map(|mut h| {
                 h.start += offset;
@@ -210,17 +220,112 @@ impl Snipper {
                 .collect::<Vec<_>>()
         };
 
-        Ok(if snippets.is_empty() {
-            None
-        } else {
-            Some(SnippedFile {
-                relative_path: doc.relative_path.clone(),
-                repo_name: doc.repo_name.clone(),
-                repo_ref: doc.repo_ref.clone(),
-                lang: doc.lang.clone(),
-                snippets,
+        Ok(snipped_file(doc, snippets))
+    }
+
+    /// Find snippets for all targets of a query in a document.
+    ///
+    /// Highlights for every target that isn't negated are gathered before building snippets, so
+    /// that matches of different targets which are close to each other share a snippet.
+    pub fn all_for_query(
+        &self,
+        query: &QueryTree<'_>,
+        doc: &indexes::reader::ContentDocument,
+    ) -> Result<Option<SnippedFile>> {
+        self.all_for_query_with(query, doc, &mut StructuralQueries::default())
+    }
+
+    /// Like [`Snipper::all_for_query`], reusing the tree-sitter queries in `queries`. Snipping many
+    /// documents for the same query should share them, so structural targets are only compiled
+    /// once per language.
+    pub fn all_for_query_with(
+        &self,
+        query: &QueryTree<'_>,
+        doc: &indexes::reader::ContentDocument,
+        queries: &mut StructuralQueries,
+    ) -> Result<Option<SnippedFile>> {
+        let symbols = doc.symbol_locations.list();
+        let mut highlights = Vec::new();
+        let mut symbol_highlights = Vec::new();
+
+        for term in query.positive_terms() {
+            // `kind:` limits symbol highlights to definitions of that kind
+            let of_kind = |sym: &Symbol| term.kind.as_deref().map_or(true, |kind| sym.kind == kind);
+
+            let Some(target) = &term.target else {
+                // a symbol kind on its own highlights every symbol of that kind
+                if term.kind.is_some() {
+                    symbol_highlights.extend(
+                        symbols
+                            .iter()
+                            .filter(|sym| of_kind(sym))
+                            .map(|sym| Range::<usize>::from(sym.range)),
+                    );
+                }
+
+                continue;
+            };
+
+            let regex = || {
+                RegexBuilder::new(&target.literal().regex_str())
+                    .multi_line(true)
+                    .case_insensitive(!term.is_case_sensitive())
+                    .build()
+            };
+
+            match target {
+                Target::Content(_) => {
+                    highlights.extend(regex()?.find_iter(&doc.content).map(|m| m.range()))
+                }
+
+                // symbol targets are limited to matches within symbols, just like in
+                // `all_for_doc`
+                Target::Symbol(_) => symbol_highlights.extend(
+                    regex()?
+                        .find_iter(&doc.content)
+                        .map(|m| m.range())
+                        .filter(|hl_range| {
+                            symbols.iter().filter(|sym| of_kind(sym)).any(|sym| {
+                                let sym_range: Range<usize> = sym.range.into();
+                                hl_range.start >= sym_range.start && hl_range.end <= sym_range.end
+                            })
+                        }),
+                ),
+
+                // structural targets highlight the nodes captured by the tree-sitter query, and
+                // files that the query can't be run against are left without highlights
+                Target::Structural(Literal::Plain(src) | Literal::Regex(src)) => highlights.extend(
+                    doc.structural_matches(src, queries)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|range| range.start.byte..range.end.byte),
+                ),
+
+                // commit messages aren't part of any file
+                Target::Commit(_) => {}
+            }
+        }
+
+        // limit symbols to only those containing a symbol highlight
+        let symbols = symbols
+            .into_iter()
+            .filter(|sym| {
+                symbol_highlights.iter().any(|hl_range| {
+                    hl_range.start >= sym.range.start.byte && hl_range.end <= sym.range.end.byte
+                })
             })
-        })
+            .collect::<Vec<_>>();
+
+        highlights.extend(symbol_highlights);
+        highlights.sort_by_key(|hl_range| (hl_range.start, hl_range.end));
+        highlights.dedup();
+
+        let snippets = self
+            .expand_many(highlights.into_iter(), &doc.content, &doc.line_end_indices)
+            .map(|loc| loc.reify(&doc.content, &symbols))
+            .collect::<Vec<_>>();
+
+        Ok(snipped_file(doc, snippets))
     }
 
     fn expand_many<'a>(
@@ -293,6 +398,24 @@ impl Snipper {
     }
 }
 
+fn snipped_file(
+    doc: &indexes::reader::ContentDocument,
+    snippets: Vec<Snippet>,
+) -> Option<SnippedFile> {
+    if snippets.is_empty() {
+        None
+    } else {
+        Some(SnippedFile {
+            relative_path: doc.relative_path.clone(),
+            repo_name: doc.repo_name.clone(),
+            repo_ref: doc.repo_ref.clone(),
+            lang: doc.lang.clone(),
+            snippets,
+            submodule: doc.submodule.clone(),
+        })
+    }
+}
+
 #[derive(Serialize)]
 pub struct HighlightedString {
     pub text: String,
@@ -611,6 +734,118 @@ mod tests {
             .is_some());
     }
 
+    #[test]
+    fn highlights_from_query() {
+        let (text, line_end_indices) = with_line_ends("foo bar\nbaz\n");
+        let doc = indexes::reader::ContentDocument {
+            content: text.into(),
+            line_end_indices,
+            ..Default::default()
+        };
+
+        let highlights = |query: &str| {
+            let query = crate::query::parser::parse(query).unwrap();
+            Snipper::default()
+                .all_for_query(&query, &doc)
+                .unwrap()
+                .map(|file| {
+                    file.snippets
+                        .into_iter()
+                        .map(|s| s.highlights)
+                        .collect::<Vec<_>>()
+                })
+        };
+
+        assert_eq!(highlights("foo or bar"), Some(vec![vec![0..3, 4..7]]));
+        assert_eq!(
+            highlights("(foo or baz) -content:bar"),
+            Some(vec![vec![0..3], vec![0..3]])
+        );
+        assert_eq!(highlights("quux"), None);
+    }
+
+    #[test]
+    fn highlights_by_symbol_kind() {
+        let (text, line_end_indices) = with_line_ends("struct Config;\nfn config() {}\n");
+        let graph = crate::intelligence::TreeSitterFile::try_build(text.as_bytes(), "Rust")
+            .and_then(crate::intelligence::TreeSitterFile::scope_graph)
+            .unwrap();
+        let doc = indexes::reader::ContentDocument {
+            content: text.into(),
+            lang: Some("Rust".into()),
+            line_end_indices,
+            symbol_locations: crate::symbol::SymbolLocations::TreeSitter(graph),
+            ..Default::default()
+        };
+
+        let highlights = |query: &str| {
+            let query = crate::query::parser::parse(query).unwrap();
+            Snipper::default()
+                .all_for_query(&query, &doc)
+                .unwrap()
+                .map(|file| {
+                    file.snippets
+                        .into_iter()
+                        .map(|s| s.highlights)
+                        .collect::<Vec<_>>()
+                })
+        };
+
+        assert_eq!(
+            highlights("symbol:config"),
+            Some(vec![vec![7..13], vec![3..9]])
+        );
+        assert_eq!(
+            highlights("symbol:config kind:struct"),
+            Some(vec![vec![7..13]])
+        );
+        assert_eq!(
+            highlights("symbol:config kind:function"),
+            Some(vec![vec![3..9]])
+        );
+        assert_eq!(highlights("kind:function"), Some(vec![vec![3..9]]));
+        assert_eq!(highlights("symbol:config kind:enum"), None);
+    }
+
+    #[test]
+    fn highlights_from_structural_query() {
+        let (text, line_end_indices) = with_line_ends("fn main() {\n    foo().unwrap();\n}\n");
+        let doc = indexes::reader::ContentDocument {
+            content: text.into(),
+            lang: Some("Rust".into()),
+            line_end_indices,
+            ..Default::default()
+        };
+
+        let highlights = |query: &str| {
+            let query = crate::query::parser::parse(query).unwrap();
+            Snipper::default()
+                .context(0, 0)
+                .all_for_query(&query, &doc)
+                .unwrap()
+                .map(|file| {
+                    file.snippets
+                        .into_iter()
+                        .map(|s| s.highlights)
+                        .collect::<Vec<_>>()
+                })
+        };
+
+        assert_eq!(
+            highlights(
+                r#"struct:'(call_expression (field_expression field: (field_identifier) @f (#eq? @f "unwrap")))'"#
+            ),
+            Some(vec![vec![10..16]])
+        );
+
+        // queries that don't fit the grammar of the file don't match
+        assert_eq!(highlights("struct:'(no_such_node) @n'"), None);
+        assert_eq!(
+            highlights(r#"struct:'((identifier) @f (#eq? @f "expect"))'"#),
+            None
+        );
+    }
+
     #[test]
     fn test_highlighted_string() {
         let mut s = HighlightedString::new("foo bar quux");
@@ -2,20 +2,25 @@ use crate::{
     remotes::{gather_repo_roots, BackendCredential},
     repo::{Backend, RepoError, RepoRef, Repository},
 };
+use aes_gcm::{aead::Aead, Aes256Gcm, Key, KeyInit, Nonce};
 use anyhow::Result;
 use clap::Args;
 use rand::Rng;
 use relative_path::RelativePath;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
+    io::Write,
     ops::Deref,
     path::{Path, PathBuf},
     sync::Arc,
 };
-use tracing::debug;
+use tracing::{debug, info};
 
 include!(concat!(env!("OUT_DIR"), "/schema_version.rs"));
 
+/// Hex encoded key for the credentials store, which takes precedence over the key file
+const CREDENTIALS_KEY_ENV_VAR: &str = "BLOOP_CREDENTIALS_KEY";
+
 pub(crate) type RepositoryPool = Arc<scc::HashMap<RepoRef, Repository>>;
 
 #[derive(Serialize, Deserialize, Args, Debug, Clone, Default, PartialEq)]
@@ -36,6 +41,12 @@ pub struct StateSource {
     #[serde(default)]
     credentials: Option<PathBuf>,
 
+    /// Key file for encrypting the credentials store. Generated if it doesn't exist, unless
+    /// the key is set in the `BLOOP_CREDENTIALS_KEY` environment variable.
+    #[clap(long)]
+    #[serde(default)]
+    credentials_key: Option<PathBuf>,
+
     /// Version of the current schema
     #[clap(short, long)]
     #[serde(default)]
@@ -106,6 +117,9 @@ impl StateSource {
         self.credentials
             .get_or_insert_with(|| dir.join("credentials.json"));
 
+        self.credentials_key
+            .get_or_insert_with(|| dir.join("credentials_key.bin"));
+
         self.version_file
             .get_or_insert_with(|| dir.join("version.json"));
 
@@ -226,17 +240,83 @@ impl StateSource {
         }
     }
 
+    /// Read the credentials store, encrypting it first if it was stored in plain text
     pub(crate) fn initialize_credentials(
         &self,
     ) -> Result<std::collections::HashMap<Backend, BackendCredential>, RepoError> {
-        read_file_or_default(self.credentials.as_ref().unwrap())
+        let path = self.credentials.as_ref().unwrap();
+        if !path.exists() {
+            return Ok(Default::default());
+        }
+
+        let contents = std::fs::read(path)?;
+        let Ok(sealed) = serde_json::from_slice::<SealedCredentials>(&contents) else {
+            let creds = serde_json::from_slice(&contents)?;
+            self.save_credentials(&creds)?;
+
+            info!(?path, "encrypted plaintext credentials");
+            return Ok(creds);
+        };
+
+        let key = self.credentials_key()?.ok_or_else(|| {
+            RepoError::MissingCredentialsKey(self.credentials_key.clone().unwrap_or_default())
+        })?;
+
+        Ok(serde_json::from_slice(&sealed.open(&key)?)?)
     }
 
+    /// Encrypt and write the credentials store, generating a key if there is none
     pub(crate) fn save_credentials(&self, creds: impl Serialize) -> Result<(), RepoError> {
-        match self.credentials {
-            None => Err(RepoError::NoSourceGiven),
-            Some(ref path) => pretty_write_file(path, &creds),
+        let Some(ref path) = self.credentials else {
+            return Err(RepoError::NoSourceGiven);
+        };
+
+        let key = match self.credentials_key()? {
+            Some(key) => key,
+            None => self.generate_credentials_key()?,
+        };
+
+        let sealed = SealedCredentials::seal(&key, &serde_json::to_vec(&creds)?)?;
+        pretty_write_file(path, &sealed)
+    }
+
+    /// The credentials key from the environment, or the key file if it exists
+    fn credentials_key(&self) -> Result<Option<[u8; 32]>, RepoError> {
+        let key = match std::env::var(CREDENTIALS_KEY_ENV_VAR) {
+            Ok(key) => hex::decode(key.trim()).map_err(|_| RepoError::InvalidCredentialsKey)?,
+            Err(_) => match self.credentials_key {
+                Some(ref path) if path.exists() => std::fs::read(path)?,
+                _ => return Ok(None),
+            },
+        };
+
+        key.try_into()
+            .map(Some)
+            .map_err(|_| RepoError::InvalidCredentialsKey)
+    }
+
+    fn generate_credentials_key(&self) -> Result<[u8; 32], RepoError> {
+        let Some(ref path) = self.credentials_key else {
+            return Err(RepoError::NoSourceGiven);
+        };
+
+        let mut key = [0; 32];
+        rand::thread_rng().fill(&mut key);
+
+        // only the owner can ever read the key, and an existing key is never overwritten
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
         }
+
+        options.open(path)?.write_all(&key)?;
+
+        info!(?path, "generated credentials key");
+        Ok(key)
     }
 
     pub fn index_version_mismatch(&self) -> bool {
@@ -263,6 +343,44 @@ impl StateSource {
     }
 }
 
+/// The credentials store, encrypted with AES-256-GCM
+#[derive(Serialize, Deserialize)]
+struct SealedCredentials {
+    /// Hex encoded
+    nonce: String,
+    /// Hex encoded, with the authentication tag
+    ciphertext: String,
+}
+
+impl SealedCredentials {
+    fn seal(key: &[u8; 32], plaintext: &[u8]) -> Result<Self, RepoError> {
+        let mut nonce = [0; 12];
+        rand::thread_rng().fill(&mut nonce);
+
+        let ciphertext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| anyhow::format_err!("failed to encrypt credentials"))?;
+
+        Ok(Self {
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    fn open(&self, key: &[u8; 32]) -> Result<Vec<u8>, RepoError> {
+        let nonce = hex::decode(&self.nonce)
+            .ok()
+            .filter(|nonce| nonce.len() == 12)
+            .ok_or(RepoError::DecryptCredentials)?;
+        let ciphertext =
+            hex::decode(&self.ciphertext).map_err(|_| RepoError::DecryptCredentials)?;
+
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| RepoError::DecryptCredentials)
+    }
+}
+
 pub fn pretty_write_file<T: Serialize + ?Sized>(
     path: impl AsRef<Path>,
     val: &T,
@@ -357,6 +475,7 @@ mod test {
             directory: Some(path.to_path_buf()),
             state_file: Some(path.join("state.json")),
             credentials: None,
+            credentials_key: None,
             version_file: None,
             cookie_key: None,
         }
@@ -384,4 +503,50 @@ mod test {
 
         assert_eq!(found_repos, expected_repos);
     }
+
+    #[test]
+    fn encrypt_credentials() {
+        let tmpdir = TempDir::new("test-credentials").unwrap();
+        let path = tmpdir.path();
+
+        let mut source = StateSource::default();
+        source.set_default_dir(path);
+
+        let plaintext = r#"{
+            "gitlab": {
+                "Gitlab": { "url": "https://gitlab.example.com", "token": "glpat-secret" }
+            }
+        }"#;
+        std::fs::write(path.join("credentials.json"), plaintext).unwrap();
+
+        let creds = source.initialize_credentials().unwrap();
+        assert!(matches!(
+            creds.get(&Backend::Gitlab),
+            Some(BackendCredential::Gitlab(_))
+        ));
+
+        // the plaintext file is migrated on first read
+        let stored = std::fs::read_to_string(path.join("credentials.json")).unwrap();
+        assert!(!stored.contains("glpat-secret"));
+        assert_eq!(source.initialize_credentials().unwrap().len(), 1);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let key = std::fs::metadata(path.join("credentials_key.bin")).unwrap();
+            assert_eq!(key.permissions().mode() & 0o777, 0o600);
+        }
+
+        std::fs::remove_file(path.join("credentials_key.bin")).unwrap();
+        assert!(matches!(
+            source.initialize_credentials(),
+            Err(RepoError::MissingCredentialsKey(_))
+        ));
+
+        std::fs::write(path.join("credentials_key.bin"), [0; 32]).unwrap();
+        assert!(matches!(
+            source.initialize_credentials(),
+            Err(RepoError::DecryptCredentials)
+        ));
+    }
 }
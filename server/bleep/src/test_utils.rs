@@ -0,0 +1,39 @@
+//! Helpers for tests that work with real git repositories
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Run git in `dir` as a test user, failing the test if it fails
+pub(crate) fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {args:?} failed");
+}
+
+/// Create a repository in `root/work` with a `README.md` committed to `main`, and a bare clone of
+/// it at `root/bare` to clone from. Returns the path of the working repository, which changes can
+/// be pushed from.
+pub(crate) fn bare_repo_with_commit(root: &Path, bare: &str) -> PathBuf {
+    let work = root.join("work");
+    std::fs::create_dir_all(&work).unwrap();
+
+    git(&work, &["init", "-q", "-b", "main"]);
+    std::fs::write(work.join("README.md"), "hello").unwrap();
+    git(&work, &["add", "README.md"]);
+    git(&work, &["commit", "-q", "-m", "first"]);
+
+    let bare = root.join(bare);
+    std::fs::create_dir_all(bare.parent().unwrap()).unwrap();
+    git(
+        root,
+        &["clone", "-q", "--bare", "work", &bare.to_string_lossy()],
+    );
+
+    work
+}
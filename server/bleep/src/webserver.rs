@@ -17,7 +17,9 @@ pub mod answer;
 mod autocomplete;
 mod config;
 mod file;
+mod git;
 mod github;
+mod gitlab;
 mod hoverable;
 mod index;
 mod intelligence;
@@ -25,6 +27,7 @@ pub mod middleware;
 mod query;
 pub mod repos;
 mod semantic;
+mod webhook;
 
 pub type Router<S = Application> = axum::Router<S>;
 
@@ -44,6 +47,8 @@ pub async fn start(app: Application) -> anyhow::Result<()> {
         .route("/config", get(config::get).put(config::put))
         // querying
         .route("/q", get(query::handle))
+        .route("/q/explain", get(query::explain))
+        .route("/q/stream", get(query::stream))
         // autocomplete
         .route("/autocomplete", get(autocomplete::handle))
         // indexing
@@ -75,7 +80,22 @@ pub async fn start(app: Application) -> anyhow::Result<()> {
         api = api
             .route("/remotes/github/login", get(github::login))
             .route("/remotes/github/logout", get(github::logout))
-            .route("/remotes/github/status", get(github::status));
+            .route("/remotes/github/status", get(github::status))
+            .route(
+                "/remotes/github/discovery",
+                get(github::discovery).put(github::set_discovery),
+            );
+    }
+
+    if app.env.allow(Feature::GitlabTokenAuth) {
+        api = api
+            .route("/remotes/gitlab/login", post(gitlab::login))
+            .route("/remotes/gitlab/logout", get(gitlab::logout))
+            .route("/remotes/gitlab/status", get(gitlab::status));
+    }
+
+    if app.env.allow(Feature::GitRemotes) {
+        api = api.route("/remotes/git", post(git::add));
     }
 
     api = api.route("/panic", get(|| async { panic!("dead") }));
@@ -88,6 +108,13 @@ pub async fn start(app: Application) -> anyhow::Result<()> {
         api = middleware::local_user(middleware::sentry_layer(api), app.clone());
     }
 
+    // Webhooks are authenticated by their signature
+    if app.config.webhook_secret.is_some() {
+        api = api
+            .route("/webhook", post(webhook::generic))
+            .route("/webhook/github", post(webhook::github));
+    }
+
     api = api.route("/health", get(health));
 
     let api = api
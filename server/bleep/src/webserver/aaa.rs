@@ -17,7 +17,7 @@ use axum::{
     TypedHeader,
 };
 use axum_extra::extract::cookie::{Cookie, PrivateCookieJar, SameSite};
-use octocrab::Octocrab;
+use octocrab::{AuthState, Octocrab};
 use rand::{distributions::Alphanumeric, Rng};
 use secrecy::{ExposeSecret, SecretString};
 use serde::Deserialize;
@@ -195,7 +195,7 @@ pub(super) async fn authorized(
         .await
         .unwrap();
 
-    let octocrab = make_octocrab(&gh_token).expect("bad token received from github");
+    let octocrab = make_octocrab(&gh_token);
     let user_name = get_username(&octocrab)
         .await
         .expect("can't retrieve user name");
@@ -239,7 +239,6 @@ pub(super) fn router(router: Router, app: Application) -> Router {
         .layer(Extension(Arc::new(AuthLayer::default())))
 }
 
-#[derive(Default)]
 pub(crate) struct AuthLayer {
     /// Logins that have been initiated, but not completed.
     ///
@@ -250,6 +249,15 @@ pub(crate) struct AuthLayer {
     client: reqwest::Client,
 }
 
+impl Default for AuthLayer {
+    fn default() -> Self {
+        Self {
+            initialized_login: Default::default(),
+            client: crate::network::client(),
+        }
+    }
+}
+
 impl AuthLayer {
     fn clean_old_states(&self) {
         const MAX_AGE: Duration = Duration::seconds(60 * 5);
@@ -316,7 +324,7 @@ async fn user_auth(
         return Ok((
             User::Authenticated {
                 login: auth_cookie.user_id,
-                crab: Arc::new(move || make_octocrab(&auth_cookie.github_token)),
+                crab: Arc::new(move || Ok(make_octocrab(&auth_cookie.github_token))),
             },
             jar,
         ));
@@ -359,7 +367,7 @@ async fn user_auth(
         };
 
         // An octocrab instance based on the user's access token.
-        let octocrab = make_octocrab(&auth_cookie.github_token)?;
+        let octocrab = make_octocrab(&auth_cookie.github_token);
         let user_name = get_username(&octocrab).await?;
 
         // https://docs.github.com/en/rest/orgs/members?apiVersion=2022-11-28#check-organization-membership-for-a-user
@@ -394,7 +402,7 @@ async fn user_auth(
     Ok((
         User::Authenticated {
             login: user_name,
-            crab: Arc::new(move || make_octocrab(&auth_cookie.github_token)),
+            crab: Arc::new(move || Ok(make_octocrab(&auth_cookie.github_token))),
         },
         jar.add(cookie),
     ))
@@ -409,12 +417,14 @@ async fn get_username(octocrab: &Octocrab) -> Result<String, anyhow::Error> {
         .login)
 }
 
-fn make_octocrab(github_token: &GithubAuthToken) -> Result<Octocrab, anyhow::Error> {
-    let octocrab = Octocrab::builder()
-        .personal_token(github_token.access_token.expose_secret().clone())
-        .build()
-        .context("failed to build octocrab instance")?;
-    Ok(octocrab)
+fn make_octocrab(github_token: &GithubAuthToken) -> Octocrab {
+    crate::network::github(
+        remotes::github::GITHUB_API,
+        [crate::network::github_token(
+            github_token.access_token.expose_secret(),
+        )],
+        AuthState::None,
+    )
 }
 
 async fn bot_auth(
@@ -178,7 +178,7 @@ pub struct Client {
 impl Client {
     pub fn new(base_url: &str) -> Self {
         Self {
-            http: reqwest::Client::new(),
+            http: crate::network::client(),
             base_url: base_url.to_owned(),
             max_retries: 5,
 
@@ -25,15 +25,17 @@ pub(super) async fn handle(
     api_params.page = 0;
     api_params.page_size = 3;
 
-    let queries = parser::parse(&api_params.q).map_err(Error::user)?;
+    let query = parser::parse(&api_params.q).map_err(Error::user)?;
     let mut autocomplete_results = vec![];
 
     // Only execute prefix search on flag names if there is a non-regex content target.
-    // Always matches against the last query.
+    // Always matches against the last term of the query.
     //
     //      `la repo:bloop or sy` -> search with prefix `sy`
     //      `repo:bloop re path:src` -> search with prefix `re`
-    if let Some(Target::Content(Literal::Plain(q))) = queries.last().unwrap().target.clone() {
+    if let Some(Target::Content(Literal::Plain(q))) =
+        query.terms().last().and_then(|q| q.target.clone())
+    {
         autocomplete_results.append(
             &mut complete_flag(&q)
                 .map(|f| QueryResult::Flag(f.to_string()))
@@ -58,9 +60,9 @@ pub(super) async fn handle(
 
     // If no flags completion, run a search with full query
     if autocomplete_results.is_empty() {
-        let contents = ContentReader.execute(&indexes.file, &queries, &api_params);
-        let repos = RepoReader.execute(&indexes.repo, &queries, &api_params);
-        let files = FileReader.execute(&indexes.file, &queries, &api_params);
+        let contents = ContentReader.execute(&indexes.file, &query, &api_params);
+        let repos = RepoReader.execute(&indexes.repo, &query, &api_params);
+        let files = FileReader.execute(&indexes.file, &query, &api_params);
 
         autocomplete_results = stream::iter([contents, repos, files])
             // Buffer several readers at the same time. The exact number is not important; this is
@@ -108,8 +110,9 @@ pub(super) struct AutocompleteResponse {
 
 impl super::ApiResponse for AutocompleteResponse {}
 
-const QUERY_FLAGS: &[&str; 8] = &[
-    "repo", "path", "content", "symbol", "lang", "case", "or", "open",
+const QUERY_FLAGS: &[&str; 14] = &[
+    "repo", "path", "content", "symbol", "struct", "commit", "kind", "tag", "before", "after",
+    "lang", "case", "or", "open",
 ];
 
 // List of common languages
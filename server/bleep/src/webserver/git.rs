@@ -0,0 +1,58 @@
+use super::{
+    prelude::*,
+    repos::{self, ReposResponse},
+};
+use crate::{
+    remotes::git,
+    repo::{Backend, CloneOptions, RepoRef},
+    Application,
+};
+
+use axum::Json;
+use tracing::error;
+
+#[derive(Deserialize)]
+pub(super) struct AddRemote {
+    /// Any git URL, as in `https://git.example.com/team/project.git`,
+    /// `ssh://git@git.example.com/team/project` or `file:///srv/git/project.git`
+    url: String,
+    #[serde(default)]
+    auth: git::Auth,
+    #[serde(default)]
+    clone_options: CloneOptions,
+}
+
+/// Add a repository on a remote with no API, and start syncing it
+//
+pub(super) async fn add(
+    Extension(app): Extension<Application>,
+    Json(AddRemote {
+        url,
+        auth,
+        clone_options,
+    }): Json<AddRemote>,
+) -> Result<impl IntoResponse> {
+    let reporef = RepoRef::new(Backend::Git, &url).map_err(Error::user)?;
+    let auth = auth
+        .confine_to(&app.config.ssh_key_dir)
+        .map_err(Error::user)?;
+
+    let mut state = app.credentials.git().unwrap_or_default();
+    match auth {
+        git::Auth::None => state.repositories.remove(&url),
+        auth => state.repositories.insert(url, auth),
+    };
+    app.credentials.set_git(state);
+
+    app.config
+        .source
+        .save_credentials(&app.credentials.serialize().await)
+        .map_err(|err| {
+            error!(?err, "failed to save credentials to disk");
+            Error::internal("failed to save changes")
+        })?;
+
+    repos::set_clone_options(&app, reporef.clone(), clone_options).await;
+    app.write_index().enqueue_sync(vec![reporef]).await;
+    Ok(json(ReposResponse::SyncQueued))
+}
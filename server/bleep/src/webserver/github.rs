@@ -1,19 +1,29 @@
 use super::prelude::*;
-use crate::{repo::Backend, Application};
-
+use crate::{
+    network,
+    remotes::discovery::{self, OrgRules},
+    repo::Backend,
+    Application,
+};
+
+use axum::Json;
 use either::Either;
-use octocrab::{auth::DeviceCodes, Octocrab};
-use reqwest::header::ACCEPT;
+use octocrab::{auth::DeviceCodes, AuthState, Octocrab};
+use reqwest::header::{HeaderValue, ACCEPT};
 use secrecy::SecretString;
 use tracing::{debug, error, warn};
 
-use std::time::{Duration, Instant};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
 
 #[derive(Serialize)]
 #[serde(rename_all = "snake_case")]
 pub(super) enum GithubResponse {
     AuthenticationNeeded { url: String, code: String },
     Status(GithubCredentialStatus),
+    Discovery(HashMap<String, OrgRules>),
 }
 
 impl super::ApiResponse for GithubResponse {}
@@ -37,8 +47,6 @@ pub(super) async fn status(Extension(app): Extension<Application>) -> impl IntoR
             // We just initialized this above.
             .expect("github credentials were not initialized")
             .client()
-            // We know the builder won't fail as we just built it above.
-            .expect("failed to get octocrab client")
             .current()
             .user()
             .await
@@ -84,12 +92,11 @@ pub(super) async fn login(Extension(app): Extension<Application>) -> impl IntoRe
         }
     };
 
-    let github = octocrab::Octocrab::builder()
-        .base_uri("https://github.com/")
-        .unwrap()
-        .add_header(ACCEPT, "application/json".to_string())
-        .build()
-        .map_err(|_| Error::internal("failed to build octocrab client"))?;
+    let github = network::github(
+        "https://github.com/",
+        [(ACCEPT, HeaderValue::from_static("application/json"))],
+        AuthState::None,
+    );
 
     let codes = github
         .authenticate_as_device(&client_id, ["public_repo", "repo", "read:org"])
@@ -194,3 +201,48 @@ async fn poll_for_oauth_token(
 
     debug!("github auth complete");
 }
+
+/// Get the rules for adding the repositories of GitHub organizations automatically
+//
+pub(super) async fn discovery(Extension(app): Extension<Application>) -> impl IntoResponse {
+    json(GithubResponse::Discovery(discovery_rules(&app)))
+}
+
+/// Replace the rules for adding the repositories of GitHub organizations automatically, and
+/// apply them to the repositories listed last
+//
+pub(super) async fn set_discovery(
+    Extension(app): Extension<Application>,
+    Json(rules): Json<HashMap<String, OrgRules>>,
+) -> Result<impl IntoResponse> {
+    for (org, org_rules) in &rules {
+        org_rules
+            .validate()
+            .map_err(|err| Error::user(format!("invalid rules for `{org}`: {err}")))?;
+    }
+
+    app.github_discovery.retain(|_, _| false);
+    for (org, org_rules) in rules {
+        _ = app.github_discovery.insert(org, org_rules);
+    }
+
+    app.github_discovery.store().map_err(|err| {
+        error!(?err, "failed to save discovery rules to disk");
+        Error::internal("failed to save changes")
+    })?;
+
+    if let Some(github) = app.credentials.github() {
+        discovery::apply(&app, &app.github_discovery, &github.repositories).await;
+    }
+
+    Ok(json(GithubResponse::Discovery(discovery_rules(&app))))
+}
+
+fn discovery_rules(app: &Application) -> HashMap<String, OrgRules> {
+    let mut rules = HashMap::new();
+    app.github_discovery.scan(|org, org_rules| {
+        rules.insert(org.clone(), org_rules.clone());
+    });
+
+    rules
+}
@@ -0,0 +1,87 @@
+use super::prelude::*;
+use crate::{remotes::gitlab, repo::Backend, Application};
+
+use axum::Json;
+use secrecy::SecretString;
+use tracing::{error, warn};
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum GitlabResponse {
+    Status(GitlabCredentialStatus),
+}
+
+impl super::ApiResponse for GitlabResponse {}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum GitlabCredentialStatus {
+    Ok,
+    Missing,
+}
+
+#[derive(Deserialize)]
+pub(super) struct Login {
+    /// Base URL of the GitLab instance, e.g. `https://gitlab.example.com`
+    url: String,
+    /// Personal, group or project access token with the `read_api` and `read_repository` scopes
+    token: SecretString,
+}
+
+/// Get the status of the GitLab token authentication
+//
+pub(super) async fn status(Extension(app): Extension<Application>) -> impl IntoResponse {
+    let status = if app.credentials.gitlab().is_some() {
+        GitlabCredentialStatus::Ok
+    } else {
+        GitlabCredentialStatus::Missing
+    };
+
+    json(GitlabResponse::Status(status))
+}
+
+/// Connect to a GitLab instance with an access token
+//
+pub(super) async fn login(
+    Extension(app): Extension<Application>,
+    Json(Login { url, token }): Json<Login>,
+) -> Result<impl IntoResponse> {
+    let gitlab = gitlab::State::new(url, token);
+
+    gitlab.host().map_err(Error::user)?;
+
+    if let Err(err) = gitlab.validate().await {
+        warn!(?err, "failed to validate GitLab token");
+        return Err(Error::user(format!(
+            "failed to validate GitLab token: {err}"
+        )));
+    }
+
+    app.credentials.set_gitlab(gitlab);
+    save_credentials(&app).await?;
+
+    Ok(json(GitlabResponse::Status(GitlabCredentialStatus::Ok)))
+}
+
+/// Remove GitLab credentials
+//
+pub(super) async fn logout(Extension(app): Extension<Application>) -> Result<impl IntoResponse> {
+    if app.credentials.remove(&Backend::Gitlab).is_none() {
+        return Ok(json(GitlabResponse::Status(
+            GitlabCredentialStatus::Missing,
+        )));
+    }
+
+    save_credentials(&app).await?;
+    Ok(json(GitlabResponse::Status(GitlabCredentialStatus::Ok)))
+}
+
+async fn save_credentials(app: &Application) -> Result<()> {
+    app.config
+        .source
+        .save_credentials(&app.credentials.serialize().await)
+        .map_err(|err| {
+            error!(?err, "failed to save credentials to disk");
+            Error::internal("failed to save changes")
+        })
+}
@@ -79,7 +79,7 @@ async fn local_user_mw<B>(
                 login: user,
                 crab: Arc::new(move || {
                     let gh = app.credentials.github().context("no github")?;
-                    Ok(gh.client()?)
+                    Ok(gh.client())
                 }),
             })
             .unwrap_or_else(|| User::Unknown),
@@ -1,7 +1,15 @@
-use axum::extract::State;
+use axum::{
+    extract::State,
+    response::{sse, Sse},
+};
+use futures::StreamExt;
 
 use super::prelude::*;
-use crate::{db::QueryLog, query::execute::ApiQuery, Application};
+use crate::{
+    db::QueryLog,
+    query::execute::{ApiQuery, QueryEvent},
+    Application,
+};
 
 pub(super) async fn handle(
     Query(api_params): Query<ApiQuery>,
@@ -16,3 +24,36 @@ pub(super) async fn handle(
         .map(json)
         .map_err(super::Error::from)
 }
+
+/// Run a query, streaming each result as an SSE `result` event as soon as it is ready
+///
+/// Once every result has been sent, a final `done` event carries the paging metadata and stats.
+/// If the query fails part-way through, an `error` event is sent instead.
+pub(super) async fn stream(
+    Query(api_params): Query<ApiQuery>,
+    Extension(indexes): Extension<Arc<Indexes>>,
+    State(app): State<Application>,
+) -> impl IntoResponse {
+    QueryLog::new(&app.sql).insert(&api_params.q).await?;
+
+    let events = Arc::new(api_params)
+        .stream(indexes)?
+        .map(|event| match event {
+            QueryEvent::Result(result) => sse::Event::default().event("result").json_data(result),
+            QueryEvent::Done(summary) => sse::Event::default().event("done").json_data(summary),
+            QueryEvent::Error(err) => Ok(sse::Event::default().event("error").data(err)),
+        });
+
+    Ok::<_, super::Error>(Sse::new(events))
+}
+
+pub(super) async fn explain(
+    Query(api_params): Query<ApiQuery>,
+    Extension(indexes): Extension<Arc<Indexes>>,
+) -> impl IntoResponse {
+    Arc::new(api_params)
+        .explain(indexes)
+        .await
+        .map(json)
+        .map_err(super::Error::from)
+}
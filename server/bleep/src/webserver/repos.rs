@@ -1,8 +1,16 @@
-use std::{collections::HashSet, hash::Hash, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    time::Duration,
+};
 
 use crate::{
     background::QueuedRepoStatus,
-    repo::{Backend, BranchFilter, RepoRef, Repository, SyncStatus},
+    periodic::QuietHours,
+    repo::{
+        iterator::{HgWalker, WORKTREE_BRANCH},
+        Backend, BranchFilter, CloneOptions, RepoRef, Repository, SyncSchedule, SyncStatus,
+    },
     state::RepositoryPool,
     Application,
 };
@@ -36,6 +44,9 @@ pub(crate) struct Repo {
     pub(super) most_common_lang: Option<String>,
     pub(super) branch_filter: BranchFilter,
     pub(super) branches: Vec<Branch>,
+    pub(super) sync_schedule: SyncSchedule,
+    pub(super) index_submodules: bool,
+    pub(super) index_working_tree: bool,
 }
 
 impl From<(&RepoRef, &Repository)> for Repo {
@@ -43,8 +54,25 @@ impl From<(&RepoRef, &Repository)> for Repo {
         use crate::repo::BranchFilter::*;
         let (head, branches) = 'branch_list: {
             let default = ("HEAD".to_string(), vec![]);
-            let Ok(git) = gix::open(&repo.disk_path)
-            else {
+
+            if HgWalker::is_repository(&repo.disk_path) {
+                let Ok((head, branches)) = HgWalker::list_branches(&repo.disk_path) else {
+                    break 'branch_list default;
+                };
+
+                let mut branches = branches
+                    .into_iter()
+                    .map(|(name, last_commit_unix_secs)| Branch {
+                        name,
+                        last_commit_unix_secs,
+                    })
+                    .collect::<Vec<_>>();
+
+                branches.sort_by_key(|b| b.last_commit_unix_secs);
+                break 'branch_list (head.unwrap_or(default.0), branches);
+            }
+
+            let Ok(git) = gix::open(&repo.disk_path) else {
                 break 'branch_list default;
             };
 
@@ -76,6 +104,12 @@ impl From<(&RepoRef, &Repository)> for Repo {
                 .filter_map(Result::ok)
                 .filter_map(|mut r| {
                     let name = r.name().shorten().to_str_lossy().to_string();
+                    let name = if r.name().as_bstr().starts_with(b"refs/tags/") {
+                        format!("tags/{name}")
+                    } else {
+                        name
+                    };
+
                     let last_commit_unix_secs = r
                         .peel_to_id_in_place()
                         .ok()?
@@ -92,10 +126,26 @@ impl From<(&RepoRef, &Repository)> for Repo {
                         last_commit_unix_secs,
                     })
                 })
-                .filter(|b| b.name != "origin/HEAD" && b.name.starts_with("origin/"))
+                .filter(|b| {
+                    b.name != "origin/HEAD"
+                        && (b.name.starts_with("origin/")
+                            || b.name.starts_with("pr/")
+                            // a repo can have thousands of tags, only list the ones selected for
+                            // indexing
+                            || (b.name.starts_with("tags/")
+                                && repo.indexed_branches.contains_key(&b.name)))
+                })
                 .collect::<Vec<_>>();
 
             branches.sort_by_key(|b| b.last_commit_unix_secs);
+
+            if key.is_local() && repo.index_working_tree {
+                branches.push(Branch {
+                    name: WORKTREE_BRANCH.to_owned(),
+                    last_commit_unix_secs: repo.last_index_unix_secs,
+                });
+            }
+
             (head, branches)
         };
 
@@ -134,6 +184,9 @@ impl From<(&RepoRef, &Repository)> for Repo {
             most_common_lang: repo.most_common_lang.clone(),
             branch_filter,
             branches,
+            sync_schedule: repo.sync_schedule.clone(),
+            index_submodules: repo.index_submodules,
+            index_working_tree: repo.index_working_tree,
         }
     }
 }
@@ -155,6 +208,31 @@ impl Repo {
             most_common_lang: None,
             branch_filter: crate::repo::BranchFilter::Select(vec![]),
             branches: vec![],
+            sync_schedule: SyncSchedule::default(),
+            index_submodules: false,
+            index_working_tree: false,
+        }
+    }
+
+    pub(crate) fn from_gitlab(
+        local_duplicates: Vec<RepoRef>,
+        repo_ref: RepoRef,
+        origin: &crate::remotes::gitlab::Project,
+    ) -> Self {
+        Repo {
+            provider: Backend::Gitlab,
+            name: repo_ref.display_name(),
+            repo_ref,
+            sync_status: SyncStatus::Uninitialized,
+            local_duplicates,
+            last_update: origin.last_activity_at,
+            last_index: None,
+            most_common_lang: None,
+            branch_filter: crate::repo::BranchFilter::Select(vec![]),
+            branches: vec![],
+            sync_schedule: SyncSchedule::default(),
+            index_submodules: false,
+            index_working_tree: false,
         }
     }
 }
@@ -179,6 +257,7 @@ pub(crate) enum ReposResponse {
     SyncQueue(Vec<QueuedRepoStatus>),
     SyncQueued,
     Deleted,
+    QuietHours(Option<QuietHours>),
 }
 
 impl super::ApiResponse for ReposResponse {}
@@ -200,6 +279,10 @@ pub(super) fn router() -> Router {
         .route("/status", get(index_status))
         .route("/indexed", indexed)
         .route("/sync", get(sync).delete(delete_sync))
+        .route("/schedule", put(set_schedule))
+        .route("/submodules", put(set_submodules))
+        .route("/working-tree", put(set_working_tree))
+        .route("/quiet-hours", get(quiet_hours).put(set_quiet_hours))
 }
 
 /// Get a stream of status notifications about the indexing of each repository
@@ -314,6 +397,107 @@ pub(super) async fn sync(
     Ok(json(ReposResponse::SyncQueued))
 }
 
+/// Set when a repository is synced in the background
+//
+pub(super) async fn set_schedule(
+    Query(RepoParams { repo }): Query<RepoParams>,
+    State(app): State<Application>,
+    Json(schedule): Json<SyncSchedule>,
+) -> Result<impl IntoResponse> {
+    let updated = app
+        .repo_pool
+        .update_async(&repo, |k, v| {
+            v.sync_schedule = schedule;
+            Repo::from((k, &*v))
+        })
+        .await
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "Can't find repository"))?;
+
+    app.config
+        .source
+        .save_pool(app.repo_pool.clone())
+        .map_err(Error::internal)?;
+
+    Ok(json(ReposResponse::Item(updated)))
+}
+
+/// Set whether a repository's submodules are indexed along with it
+//
+pub(super) async fn set_submodules(
+    Query(RepoParams { repo }): Query<RepoParams>,
+    State(app): State<Application>,
+    Json(index_submodules): Json<bool>,
+) -> Result<impl IntoResponse> {
+    let updated = app
+        .repo_pool
+        .update_async(&repo, |k, v| {
+            if v.index_submodules != index_submodules {
+                v.index_submodules = index_submodules;
+                // Walk every file again to pick up or drop the submodules
+                v.indexed_branches.clear();
+            }
+            Repo::from((k, &*v))
+        })
+        .await
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "Can't find repository"))?;
+
+    app.config
+        .source
+        .save_pool(app.repo_pool.clone())
+        .map_err(Error::internal)?;
+
+    app.write_index().enqueue_sync(vec![repo]).await;
+    Ok(json(ReposResponse::Item(updated)))
+}
+
+/// Set whether uncommitted changes are indexed for a local repository
+//
+pub(super) async fn set_working_tree(
+    Query(RepoParams { repo }): Query<RepoParams>,
+    State(app): State<Application>,
+    Json(index_working_tree): Json<bool>,
+) -> Result<impl IntoResponse> {
+    if !repo.is_local() {
+        return Err(Error::user("only local repositories have a working tree"));
+    }
+
+    let updated = app
+        .repo_pool
+        .update_async(&repo, |k, v| {
+            v.index_working_tree = index_working_tree;
+            Repo::from((k, &*v))
+        })
+        .await
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "Can't find repository"))?;
+
+    app.config
+        .source
+        .save_pool(app.repo_pool.clone())
+        .map_err(Error::internal)?;
+
+    app.write_index().enqueue_sync(vec![repo]).await;
+    Ok(json(ReposResponse::Item(updated)))
+}
+
+/// Get the daily window when repositories aren't synced in the background
+//
+pub(super) async fn quiet_hours(State(app): State<Application>) -> impl IntoResponse {
+    json(ReposResponse::QuietHours(*app.quiet_hours.read().unwrap()))
+}
+
+/// Set the daily window when repositories aren't synced in the background, or clear it with
+/// `null`. Syncs asked for through the API or webhooks still run.
+//
+pub(super) async fn set_quiet_hours(
+    State(app): State<Application>,
+    Json(quiet_hours): Json<Option<QuietHours>>,
+) -> Result<impl IntoResponse> {
+    *app.quiet_hours.write().unwrap() = quiet_hours;
+    app.quiet_hours.store().map_err(Error::internal)?;
+
+    Ok(json(ReposResponse::QuietHours(quiet_hours)))
+}
+
 /// Synchronize a repo by its id
 pub(super) async fn delete_sync(
     Query(RepoParams { repo }): Query<RepoParams>,
@@ -361,13 +545,45 @@ pub(super) async fn available(State(app): State<Application>) -> impl IntoRespon
         })
         .collect::<HashSet<_>>();
 
-    let repos = list_unique_repos(app.repo_pool.clone(), unknown_github).await;
+    let unknown_gitlab = app
+        .credentials
+        .gitlab()
+        .map(|gl| {
+            gl.repositories
+                .iter()
+                .filter_map(|project| {
+                    let reporef = gl.reporef(project).ok()?;
+                    let urls = [&project.ssh_url_to_repo, &project.http_url_to_repo]
+                        .map(|url| url.as_deref().unwrap_or_default().to_lowercase());
+
+                    let mut local_duplicates = vec![];
+                    app.repo_pool.scan(|k, v| {
+                        if urls.contains(&v.remote.to_string().to_lowercase()) {
+                            local_duplicates.push(k.clone())
+                        }
+                    });
+
+                    Some(Repo::from_gitlab(local_duplicates, reporef, project))
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let repos = list_unique_repos(
+        app.repo_pool.clone(),
+        unknown_github.into_iter().chain(unknown_gitlab).collect(),
+    )
+    .await;
     (StatusCode::OK, Json(ReposResponse::List(repos)))
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub(super) struct SetIndexed {
     indexed: Vec<RepoRef>,
+    /// Clone options for remote repositories in `indexed`. They apply the next time the
+    /// repository is cloned.
+    #[serde(default)]
+    clone_options: HashMap<RepoRef, CloneOptions>,
 }
 
 /// Update the list of repositories that are currently being indexed.
@@ -380,6 +596,12 @@ pub(super) async fn set_indexed(
 ) -> impl IntoResponse {
     let mut repo_list = new_list.indexed.into_iter().collect::<HashSet<_>>();
 
+    for (reporef, options) in new_list.clone_options {
+        if reporef.is_remote() && repo_list.contains(&reporef) {
+            set_clone_options(&app, reporef, options).await;
+        }
+    }
+
     app.with_analytics(|analytics| {
         analytics.track_synced_repos(repo_list.len(), user.login(), app.org_name());
     });
@@ -400,6 +622,18 @@ pub(super) async fn set_indexed(
     json(ReposResponse::SyncQueued)
 }
 
+/// Set the clone options of a remote repository, adding it to the pool if it's new
+pub(super) async fn set_clone_options(app: &Application, reporef: RepoRef, options: CloneOptions) {
+    app.repo_pool
+        .entry_async(reporef.clone())
+        .await
+        .and_modify(|repo| repo.clone_options = options.clone())
+        .or_insert_with(|| Repository {
+            clone_options: options,
+            ..Repository::remote_from(&reporef, &app.config.source)
+        });
+}
+
 #[derive(Deserialize)]
 pub(super) struct ScanRequest {
     /// The path to scan
@@ -451,7 +685,7 @@ async fn list_unique_repos(repo_pool: RepositoryPool, other: HashSet<Repo>) -> V
 mod test {
     use std::collections::HashSet;
 
-    use crate::repo::{GitProtocol, GitRemote, RepoRef, RepoRemote::Git, Repository, SyncStatus};
+    use crate::repo::{RepoRef, Repository, SyncStatus};
 
     use super::{list_unique_repos, Repo, RepositoryPool};
 
@@ -462,17 +696,13 @@ mod test {
             .insert(
                 RepoRef::try_from("github.com/test/test").unwrap(),
                 Repository {
-                    disk_path: "/repo".into(),
-                    remote: Git(GitRemote {
-                        protocol: GitProtocol::Https,
-                        host: "github.com".into(),
-                        address: "test/test".into(),
-                    }),
                     sync_status: SyncStatus::Done,
                     last_commit_unix_secs: 123456,
                     last_index_unix_secs: 123456,
-                    most_common_lang: None,
-                    branch_filter: Default::default(),
+                    ..Repository::remote_at(
+                        &RepoRef::try_from("github.com/test/test").unwrap(),
+                        "/repo",
+                    )
                 },
             )
             .unwrap();
@@ -480,17 +710,13 @@ mod test {
             .insert(
                 RepoRef::try_from("local//code/test2").unwrap(),
                 Repository {
-                    disk_path: "/repo2".into(),
-                    remote: Git(GitRemote {
-                        protocol: GitProtocol::Https,
-                        host: "github.com".into(),
-                        address: "test/test2".into(),
-                    }),
                     sync_status: SyncStatus::Done,
                     last_commit_unix_secs: 123456,
                     last_index_unix_secs: 123456,
-                    most_common_lang: None,
-                    branch_filter: Default::default(),
+                    ..Repository::remote_at(
+                        &RepoRef::try_from("github.com/test/test2").unwrap(),
+                        "/repo2",
+                    )
                 },
             )
             .unwrap();
@@ -500,17 +726,12 @@ mod test {
             (
                 &RepoRef::try_from("github.com/test/test").unwrap(),
                 &Repository {
-                    disk_path: "/unused".into(),
-                    remote: Git(GitRemote {
-                        protocol: GitProtocol::Https,
-                        host: "github.com".into(),
-                        address: "test/test".into(),
-                    }),
                     sync_status: SyncStatus::Uninitialized,
                     last_commit_unix_secs: 123456,
-                    last_index_unix_secs: 0,
-                    most_common_lang: None,
-                    branch_filter: Default::default(),
+                    ..Repository::remote_at(
+                        &RepoRef::try_from("github.com/test/test").unwrap(),
+                        "/unused",
+                    )
                 },
             )
                 .into(),
@@ -519,17 +740,12 @@ mod test {
         let mut ghrepo_2: Repo = (
             &RepoRef::try_from("github.com/test/test2").unwrap(),
             &Repository {
-                disk_path: "/unused".into(),
-                remote: Git(GitRemote {
-                    protocol: GitProtocol::Https,
-                    host: "github.com".into(),
-                    address: "test/test2".into(),
-                }),
                 sync_status: SyncStatus::Uninitialized,
                 last_commit_unix_secs: 123456,
-                last_index_unix_secs: 0,
-                most_common_lang: None,
-                branch_filter: Default::default(),
+                ..Repository::remote_at(
+                    &RepoRef::try_from("github.com/test/test2").unwrap(),
+                    "/unused",
+                )
             },
         )
             .into();
@@ -31,7 +31,7 @@ pub(super) async fn complex_search(
             .map(json)
             .map_err(super::Error::from),
         Err(err) => {
-            error!(?err, "qdrant query failed");
+            error!(?err, "vector search failed");
             Err(Error::new(ErrorKind::UpstreamService, "error"))
         }
     }
@@ -0,0 +1,222 @@
+use super::{prelude::*, Response};
+use crate::{repo::RepoRef, state::RepositoryPool, Application};
+
+use axum::{body::Bytes, extract::State, http::HeaderMap, Json};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use tracing::{debug, info};
+
+/// GitHub's header for the HMAC-SHA256 of the body, as `sha256=<hex>`. The generic form is
+/// signed the same way.
+const SIGNATURE_HEADER: &str = "X-Hub-Signature-256";
+const GITHUB_EVENT_HEADER: &str = "X-GitHub-Event";
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum WebhookResponse {
+    Ignored,
+    Queued { repos: usize },
+}
+
+impl super::ApiResponse for WebhookResponse {}
+
+#[derive(Deserialize)]
+struct GithubPush {
+    repository: GithubRepository,
+}
+
+#[derive(Deserialize)]
+struct GithubRepository {
+    full_name: String,
+    clone_url: Option<String>,
+    ssh_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GenericPush {
+    /// The pushed repository, as in `github.com/org/repo`
+    #[serde(default)]
+    repo: Option<RepoRef>,
+    /// The URL of the pushed remote, for remotes indexed under another name
+    #[serde(default)]
+    url: Option<String>,
+}
+
+/// Sync the repositories a GitHub `push` event is for
+//
+pub(super) async fn github(
+    State(app): State<Application>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse> {
+    verify_signature(app.config.webhook_secret.as_ref(), &headers, &body)?;
+
+    let event = headers
+        .get(GITHUB_EVENT_HEADER)
+        .and_then(|v| v.to_str().ok());
+
+    if event != Some("push") {
+        debug!(?event, "ignoring webhook event");
+        return Ok(json(WebhookResponse::Ignored));
+    }
+
+    let GithubPush { repository } = parse(&body)?;
+    let reporef = format!("github.com/{}", repository.full_name).parse().ok();
+    let urls = [repository.clone_url, repository.ssh_url]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    queue(&app, matching_repos(&app.repo_pool, reporef, &urls)).await
+}
+
+/// Sync the repositories named in a push notification from any other source
+//
+pub(super) async fn generic(
+    State(app): State<Application>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse> {
+    verify_signature(app.config.webhook_secret.as_ref(), &headers, &body)?;
+
+    let GenericPush { repo, url } = parse(&body)?;
+    if repo.is_none() && url.is_none() {
+        return Err(Error::user("either `repo` or `url` must be set"));
+    }
+
+    let urls = url.into_iter().collect::<Vec<_>>();
+    queue(&app, matching_repos(&app.repo_pool, repo, &urls)).await
+}
+
+async fn queue(app: &Application, repos: Vec<RepoRef>) -> Result<Json<Response<'static>>> {
+    if repos.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "no indexed repository matches the push",
+        ));
+    }
+
+    info!(?repos, "webhook triggered sync");
+    let queued = app.write_index().enqueue_sync(repos).await;
+    Ok(json(WebhookResponse::Queued { repos: queued }))
+}
+
+fn parse<'de, T: Deserialize<'de>>(body: &'de [u8]) -> Result<T> {
+    serde_json::from_slice(body).map_err(|err| Error::user(format!("invalid payload: {err}")))
+}
+
+fn verify_signature(secret: Option<&SecretString>, headers: &HeaderMap, body: &[u8]) -> Result<()> {
+    let secret = secret.ok_or_else(|| {
+        Error::new(
+            ErrorKind::Configuration,
+            "missing webhook_secret configuration option",
+        )
+    })?;
+
+    let unauthorized = |message| Error::user(message).with_status(StatusCode::UNAUTHORIZED);
+    let signature = headers
+        .get(SIGNATURE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("sha256="))
+        .and_then(|v| hex::decode(v).ok())
+        .ok_or_else(|| unauthorized("missing or malformed signature"))?;
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC takes keys of any size");
+    mac.update(body);
+
+    // `verify_slice` compares in constant time
+    mac.verify_slice(&signature)
+        .map_err(|_| unauthorized("invalid signature"))
+}
+
+/// Find the repositories in the pool that are either `reporef`, or cloned from one of `urls`
+fn matching_repos(
+    repo_pool: &RepositoryPool,
+    reporef: Option<RepoRef>,
+    urls: &[String],
+) -> Vec<RepoRef> {
+    let normalize = |url: &str| {
+        url.trim_end_matches('/')
+            .trim_end_matches(".git")
+            .to_lowercase()
+    };
+
+    let urls = urls.iter().map(|url| normalize(url)).collect::<Vec<_>>();
+    let mut matches = vec![];
+    repo_pool.scan(|k, v| {
+        if Some(k) == reporef.as_ref() || urls.contains(&normalize(&v.remote.to_string())) {
+            matches.push(k.clone());
+        }
+    });
+
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{Repository, SyncStatus};
+
+    #[test]
+    fn signature() {
+        // The example from GitHub's documentation on validating webhook deliveries
+        let secret = SecretString::new("It's a Secret to Everybody".into());
+        let body = b"Hello, World!";
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            SIGNATURE_HEADER,
+            "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17"
+                .parse()
+                .unwrap(),
+        );
+
+        assert!(verify_signature(Some(&secret), &headers, body).is_ok());
+        assert!(verify_signature(Some(&secret), &headers, b"Hello, World?").is_err());
+        assert!(verify_signature(Some(&secret), &HeaderMap::new(), body).is_err());
+        assert!(verify_signature(None, &headers, body).is_err());
+    }
+
+    #[test]
+    fn match_repos() {
+        let repo_pool = RepositoryPool::default();
+        for name in [
+            "github.com/org/repo",
+            "git/ssh://git@git.example.com/team/project",
+        ] {
+            let reporef = name.parse::<RepoRef>().unwrap();
+            let repo = Repository {
+                sync_status: SyncStatus::Done,
+                ..Repository::remote_at(&reporef, "/tmp/repo")
+            };
+            repo_pool.insert(reporef, repo).unwrap();
+        }
+
+        let github = "github.com/org/repo".parse::<RepoRef>().unwrap();
+        assert_eq!(
+            matching_repos(&repo_pool, Some(github.clone()), &[]),
+            [github.clone()]
+        );
+        assert_eq!(
+            matching_repos(
+                &repo_pool,
+                None,
+                &["https://github.com/Org/repo.git/".to_owned()]
+            ),
+            [github]
+        );
+        assert_eq!(
+            matching_repos(
+                &repo_pool,
+                None,
+                &["ssh://git@git.example.com/team/project.git".to_owned()]
+            ),
+            ["git/ssh://git@git.example.com/team/project"
+                .parse::<RepoRef>()
+                .unwrap()]
+        );
+        assert!(matching_repos(&repo_pool, None, &["https://example.com/x".to_owned()]).is_empty());
+    }
+}